@@ -4,6 +4,8 @@ pub type VirtAddr = usize;
 pub type PhysAddr = usize;
 
 pub const PAGE_SIZE: usize = 1 << 12;
+/// Size of huge pages, which may be supported by page tables
+pub const HUGE_PAGE_SIZE: usize = 1 << 21;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Page {
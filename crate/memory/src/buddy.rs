@@ -0,0 +1,325 @@
+//! Buddy system allocator of physical frames
+//!
+//! Free frames are kept in blocks of 2^order frames aligned to their size.
+//! A request is served by the smallest free block big enough, split in halves
+//! as needed, and a freed block is merged with its buddy, the other half of
+//! the block it was split from, whenever the buddy is free as well.
+//!
+//! The free blocks of each order are linked into a list through the first bytes
+//! of the blocks themselves, so the only static state is a bitmap of free block heads.
+
+use core::marker::PhantomData;
+use core::ops::Range;
+
+/// Blocks are at most 2^MAX_ORDER frames
+const MAX_ORDER: usize = 20;
+
+/// Information kept in the first bytes of a free block.
+/// Frames in the list are stored plus one, so that 0 means none
+/// and the allocator is all zeros initially.
+#[repr(C)]
+struct FreeBlock {
+    order: usize,
+    prev: usize,
+    next: usize,
+}
+
+/// Where the allocator reaches the free frames, to link them into lists
+pub trait FrameMemory {
+    /// Virtual address of the first byte of `frame`
+    fn frame_addr(frame: usize) -> usize;
+}
+
+/// A buddy allocator of frames numbered below `MAX_FRAMES`
+pub struct BuddyFrameAlloc<M: FrameMemory> {
+    /// The first free block of each order, plus one
+    heads: [usize; MAX_ORDER + 1],
+    /// Whether each frame is the head of a free block
+    free: [u64; MAX_FRAMES / 64],
+    /// Number of frames inserted
+    total: usize,
+    /// Number of free frames
+    available: usize,
+    memory: PhantomData<M>,
+}
+
+// x86_64 support up to 1T memory
+#[cfg(target_arch = "x86_64")]
+const MAX_FRAMES: usize = 256 << 20;
+
+// RISCV, ARM, MIPS has 1G memory
+#[cfg(not(target_arch = "x86_64"))]
+const MAX_FRAMES: usize = 1 << 20;
+
+impl<M: FrameMemory> BuddyFrameAlloc<M> {
+    pub const DEFAULT: Self = BuddyFrameAlloc {
+        heads: [0; MAX_ORDER + 1],
+        free: [0; MAX_FRAMES / 64],
+        total: 0,
+        available: 0,
+        memory: PhantomData,
+    };
+
+    /// Add the frames in `range` as free
+    pub fn insert(&mut self, range: Range<usize>) {
+        let end = range.end.min(MAX_FRAMES);
+        self.total += end.saturating_sub(range.start);
+        self.free_range(range.start..end);
+    }
+
+    /// Number of frames managed
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Number of frames free
+    pub fn available(&self) -> usize {
+        self.available
+    }
+
+    /// Free the frames in `range`, in blocks as big as possible
+    fn free_range(&mut self, range: Range<usize>) {
+        let mut frame = range.start;
+        let end = range.end;
+        while frame < end {
+            // the biggest aligned block starting here and fitting in the range
+            let mut order = (frame.trailing_zeros() as usize).min(MAX_ORDER);
+            while frame + (1 << order) > end {
+                order -= 1;
+            }
+            self.free_block(frame, order);
+            frame += 1 << order;
+        }
+    }
+
+    /// Allocate a frame
+    pub fn alloc(&mut self) -> Option<usize> {
+        self.alloc_order(0)
+    }
+
+    /// Allocate `size` contiguous frames, the first of which is aligned to `2^align_log2`
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        if size == 0 {
+            return None;
+        }
+        let order = size.next_power_of_two().trailing_zeros() as usize;
+        let order = order.max(align_log2);
+        let frame = self.alloc_order(order)?;
+        // give back the frames beyond `size`
+        self.free_range(frame + size..frame + (1 << order));
+        Some(frame)
+    }
+
+    /// Free a frame, which may be any frame of a contiguous allocation
+    pub fn dealloc(&mut self, frame: usize) {
+        self.free_block(frame, 0);
+    }
+
+    /// Allocate a block of `2^order` frames
+    fn alloc_order(&mut self, order: usize) -> Option<usize> {
+        if order > MAX_ORDER {
+            return None;
+        }
+        let mut current = (order..=MAX_ORDER).find(|&i| self.heads[i] != 0)?;
+        let frame = self.heads[current] - 1;
+        self.remove(frame, current);
+        self.available -= 1 << order;
+        // split it, freeing the upper halves
+        while current > order {
+            current -= 1;
+            self.push(frame + (1 << current), current);
+        }
+        Some(frame)
+    }
+
+    /// Free a block of `2^order` frames, merging it with its free buddies
+    fn free_block(&mut self, mut frame: usize, mut order: usize) {
+        assert!(!self.is_free_head(frame), "frame {:#x} freed twice", frame);
+        self.available += 1 << order;
+        while order < MAX_ORDER {
+            let buddy = frame ^ (1 << order);
+            if buddy >= MAX_FRAMES || !self.is_free_head(buddy) || self.block(buddy).order != order
+            {
+                break;
+            }
+            self.remove(buddy, order);
+            frame &= !(1 << order);
+            order += 1;
+        }
+        self.push(frame, order);
+    }
+
+    fn is_free_head(&self, frame: usize) -> bool {
+        self.free[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    fn set_free_head(&mut self, frame: usize, value: bool) {
+        if value {
+            self.free[frame / 64] |= 1 << (frame % 64);
+        } else {
+            self.free[frame / 64] &= !(1 << (frame % 64));
+        }
+    }
+
+    fn block(&self, frame: usize) -> &'static mut FreeBlock {
+        unsafe { &mut *(M::frame_addr(frame) as *mut FreeBlock) }
+    }
+
+    /// Link the free block at `frame` into the list of `order`
+    fn push(&mut self, frame: usize, order: usize) {
+        let next = self.heads[order];
+        *self.block(frame) = FreeBlock {
+            order,
+            prev: 0,
+            next,
+        };
+        if next != 0 {
+            self.block(next - 1).prev = frame + 1;
+        }
+        self.heads[order] = frame + 1;
+        self.set_free_head(frame, true);
+    }
+
+    /// Unlink the free block at `frame` from the list of `order`
+    fn remove(&mut self, frame: usize, order: usize) {
+        let block = self.block(frame);
+        let (prev, next) = (block.prev, block.next);
+        if prev != 0 {
+            self.block(prev - 1).next = next;
+        } else {
+            self.heads[order] = next;
+        }
+        if next != 0 {
+            self.block(next - 1).prev = prev;
+        }
+        self.set_free_head(frame, false);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PAGE_SIZE;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use core::cell::Cell;
+    use std::alloc::{alloc_zeroed, Layout};
+
+    /// Number of frames in the memory of a test
+    const FRAMES: usize = 64;
+
+    thread_local! {
+        /// Address of the memory of the running test
+        static MEMORY: Cell<usize> = Cell::new(0);
+    }
+
+    struct MockMemory;
+
+    impl FrameMemory for MockMemory {
+        fn frame_addr(frame: usize) -> usize {
+            assert!(frame < FRAMES, "frame {:#x} out of memory", frame);
+            MEMORY.with(|memory| memory.get()) + frame * PAGE_SIZE
+        }
+    }
+
+    type Memory = Vec<[usize; PAGE_SIZE / 8]>;
+
+    /// Create an allocator with the frames in `range` free,
+    /// and the memory to keep while it is used
+    fn new_alloc(range: Range<usize>) -> (Box<BuddyFrameAlloc<MockMemory>>, Memory) {
+        let mut memory = vec![[0; PAGE_SIZE / 8]; FRAMES];
+        MEMORY.with(|m| m.set(memory.as_mut_ptr() as usize));
+        // too big for the stack, while all zeros is `DEFAULT`
+        let layout = Layout::new::<BuddyFrameAlloc<MockMemory>>();
+        let mut ba: Box<BuddyFrameAlloc<MockMemory>> =
+            unsafe { Box::from_raw(alloc_zeroed(layout).cast()) };
+        ba.insert(range);
+        (ba, memory)
+    }
+
+    #[test]
+    fn alloc_dealloc() {
+        let (mut ba, _memory) = new_alloc(0..FRAMES);
+        assert_eq!(ba.total(), FRAMES);
+        assert_eq!(ba.available(), FRAMES);
+
+        let mut frames: Vec<usize> = (0..FRAMES).map(|_| ba.alloc().unwrap()).collect();
+        assert_eq!(ba.alloc(), None);
+        assert_eq!(ba.available(), 0);
+        frames.sort();
+        frames.dedup();
+        assert_eq!(frames.len(), FRAMES);
+
+        for frame in frames {
+            ba.dealloc(frame);
+        }
+        assert_eq!(ba.available(), FRAMES);
+        // all merged back into one block
+        assert_eq!(ba.alloc_contiguous(FRAMES, 0), Some(0));
+    }
+
+    #[test]
+    fn split_merge() {
+        let (mut ba, _memory) = new_alloc(0..8);
+        assert_eq!(ba.alloc(), Some(0));
+        assert_eq!(ba.alloc(), Some(1));
+        assert_eq!(ba.alloc_contiguous(4, 0), Some(4));
+        assert_eq!(ba.alloc_contiguous(2, 0), Some(2));
+        assert_eq!(ba.alloc(), None);
+
+        // merged with its buddy once both are free
+        ba.dealloc(1);
+        assert_eq!(ba.alloc_contiguous(2, 0), None);
+        ba.dealloc(0);
+        assert_eq!(ba.alloc_contiguous(2, 0), Some(0));
+        assert_eq!(ba.available(), 0);
+    }
+
+    #[test]
+    fn contiguous() {
+        let (mut ba, _memory) = new_alloc(0..16);
+        assert_eq!(ba.alloc_contiguous(0, 0), None);
+        assert_eq!(ba.alloc_contiguous(32, 0), None);
+
+        // the frames beyond the size are given back
+        assert_eq!(ba.alloc_contiguous(3, 0), Some(0));
+        assert_eq!(ba.available(), 13);
+        assert_eq!(ba.alloc(), Some(3));
+
+        let frame = ba.alloc_contiguous(2, 3).unwrap();
+        assert_eq!(frame % 8, 0);
+        assert_eq!(ba.available(), 10);
+
+        // any frame of a contiguous allocation is freed alone
+        for i in 0..2 {
+            ba.dealloc(frame + i);
+        }
+        for i in 0..4 {
+            ba.dealloc(i);
+        }
+        assert_eq!(ba.available(), 16);
+        assert_eq!(ba.alloc_contiguous(16, 4), Some(0));
+    }
+
+    #[test]
+    fn unaligned_range() {
+        let (mut ba, _memory) = new_alloc(3..13);
+        assert_eq!(ba.total(), 10);
+        assert_eq!(ba.available(), 10);
+        assert_eq!(ba.alloc_contiguous(8, 0), None);
+        for _ in 0..10 {
+            let frame = ba.alloc().unwrap();
+            assert!(frame >= 3 && frame < 13);
+        }
+        assert_eq!(ba.alloc(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "freed twice")]
+    fn double_free() {
+        let (mut ba, _memory) = new_alloc(0..4);
+        let frame = ba.alloc().unwrap();
+        ba.dealloc(frame);
+        ba.dealloc(frame);
+    }
+}
@@ -7,6 +7,7 @@ use log::*;
 extern crate alloc;
 
 mod addr;
+pub mod buddy;
 pub mod cow;
 pub mod memory_set;
 pub mod no_mmu;
@@ -3,6 +3,7 @@ use super::*;
 #[derive(Debug, Clone)]
 pub struct ByFrame<T: FrameAllocator> {
     allocator: T,
+    frames: CowFrames,
 }
 
 impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
@@ -18,8 +19,11 @@ impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
 
     fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
         let target = pt.get_entry(addr).expect("fail to get entry").target();
-        self.allocator.dealloc(target);
         pt.unmap(addr);
+        pt.flush_tlb_others(addr, addr + PAGE_SIZE);
+        if self.frames.release(target) {
+            self.allocator.dealloc(target);
+        }
     }
 
     fn clone_map(
@@ -29,18 +33,38 @@ impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
         addr: VirtAddr,
         attr: &MemoryAttr,
     ) {
-        self.map(pt, addr, attr);
-        let data = src_pt.get_page_slice_mut(addr);
-        pt.get_page_slice_mut(addr).copy_from_slice(data);
+        // copy on write
+        self.frames.share(pt, src_pt, addr, attr);
     }
 
-    fn handle_page_fault(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) -> bool {
+    fn moved(&self, _offset: isize) -> Option<Box<dyn MemoryHandler>> {
+        Some(self.box_clone())
+    }
+
+    fn protect(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.frames.protect(pt, addr, attr);
+    }
+
+    fn handle_page_fault_ext(
+        &self,
+        pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        access: super::AccessType,
+    ) -> bool {
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        if access.write && !entry.writable() {
+            self.frames.copy_on_write(pt, addr, &self.allocator);
+            return true;
+        }
         false
     }
 }
 
 impl<T: FrameAllocator> ByFrame<T> {
     pub fn new(allocator: T) -> Self {
-        ByFrame { allocator }
+        ByFrame {
+            allocator,
+            frames: CowFrames::new(),
+        }
     }
 }
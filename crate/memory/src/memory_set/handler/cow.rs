@@ -0,0 +1,214 @@
+//! Frames shared copy-on-write between forked page tables
+//!
+//! When a page table is cloned, present pages are mapped to the same frame
+//! read-only in both page tables, and the first write to either of them
+//! copies the frame. The number of page tables referencing a shared frame
+//! is kept here, so that the last one can take over the frame without copying.
+
+use super::*;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+/// Reference counts of shared frames, only kept while a frame has
+/// more than one user. It is shared among all clones of a handler.
+#[derive(Debug, Clone)]
+pub struct CowFrames(Arc<Mutex<BTreeMap<PhysAddr, usize>>>);
+
+impl CowFrames {
+    pub fn new() -> Self {
+        CowFrames(Arc::new(Mutex::new(BTreeMap::new())))
+    }
+
+    /// Map the page at `addr` of `src_pt` to the same frame in `pt`,
+    /// and make it read-only in both of them.
+    /// The TLB of other CPUs using `src_pt` is left to be flushed by the caller.
+    pub fn share(
+        &self,
+        pt: &mut dyn PageTable,
+        src_pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        attr: &MemoryAttr,
+    ) {
+        let entry = src_pt.get_entry(addr).expect("failed to get entry");
+        let target = entry.target();
+        entry.set_writable(false);
+        entry.update();
+        let entry = pt.map(addr, target);
+        attr.apply(entry);
+        entry.set_writable(false);
+        entry.update();
+        *self.0.lock().entry(target).or_insert(1) += 1;
+    }
+
+    /// Whether the frame is still shared with other page tables
+    pub fn is_shared(&self, target: PhysAddr) -> bool {
+        self.0.lock().contains_key(&target)
+    }
+
+    /// Drop a reference to the frame.
+    /// Return true if it was the last one, so the frame should be freed.
+    pub fn release(&self, target: PhysAddr) -> bool {
+        let mut frames = self.0.lock();
+        match frames.get_mut(&target) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 1 {
+                    frames.remove(&target);
+                }
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Handle a write to the read-only page at `addr`:
+    /// copy the frame if it is still shared, then make the page writable
+    pub fn copy_on_write(
+        &self,
+        pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        allocator: &impl FrameAllocator,
+    ) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        let target = entry.target();
+        let execute = entry.execute();
+        if self.release(target) {
+            entry.set_writable(true);
+            entry.update();
+            return;
+        }
+        let data = pt.get_page_slice_mut(addr);
+        let frame = allocator.alloc().expect("failed to alloc frame");
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.set_target(frame);
+        entry.set_writable(true);
+        entry.update();
+        pt.get_page_slice_mut(addr).copy_from_slice(data);
+        pt.flush_cache_copy_user(addr, addr + PAGE_SIZE, execute);
+        // the other CPUs may still read the frame shared
+        pt.flush_tlb_others(addr, addr + PAGE_SIZE);
+    }
+
+    /// Change the attribute of the present page at `addr`,
+    /// keeping it read-only while the frame is shared
+    pub fn protect(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        if let Some(entry) = pt.get_entry(addr) {
+            attr.apply(entry);
+            if entry.present() && self.is_shared(entry.target()) {
+                entry.set_writable(false);
+                entry.update();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::paging::MockPageTable;
+
+    // Each mock page table has its own memory, so data is only checked
+    // in the page table writing it.
+
+    /// Map a frame written with `data` at `addr` of `pt`
+    fn map_frame(pt: &mut MockPageTable, allocator: &MockFrameAllocator, addr: VirtAddr, data: u8) {
+        let frame = allocator.alloc().unwrap();
+        pt.map(addr, frame);
+        pt.write(addr, data);
+    }
+
+    #[test]
+    fn share() {
+        let allocator = MockFrameAllocator::new();
+        let frames = CowFrames::new();
+        let attr = MemoryAttr::default().user();
+        let mut parent = MockPageTable::new();
+        let mut child = MockPageTable::new();
+        map_frame(&mut parent, &allocator, 0x1000, 1);
+        let frame = parent.get_entry(0x1000).unwrap().target();
+
+        frames.share(&mut child, &mut parent, 0x1000, &attr);
+        assert!(frames.is_shared(frame));
+        assert_eq!(child.get_entry(0x1000).unwrap().target(), frame);
+        assert!(child.get_entry(0x1000).unwrap().user());
+        assert!(!child.get_entry(0x1000).unwrap().writable());
+        assert!(!parent.get_entry(0x1000).unwrap().writable());
+    }
+
+    #[test]
+    fn release() {
+        let allocator = MockFrameAllocator::new();
+        let frames = CowFrames::new();
+        let attr = MemoryAttr::default();
+        let mut parent = MockPageTable::new();
+        let mut child1 = MockPageTable::new();
+        let mut child2 = MockPageTable::new();
+        map_frame(&mut parent, &allocator, 0x1000, 1);
+        let frame = parent.get_entry(0x1000).unwrap().target();
+        // a frame not shared is freed by its only user
+        assert!(frames.release(0x2000));
+
+        frames.share(&mut child1, &mut parent, 0x1000, &attr);
+        frames.share(&mut child2, &mut parent, 0x1000, &attr);
+        assert!(!frames.release(frame));
+        assert!(frames.is_shared(frame));
+        assert!(!frames.release(frame));
+        assert!(!frames.is_shared(frame));
+        assert!(frames.release(frame));
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let allocator = MockFrameAllocator::new();
+        let frames = CowFrames::new();
+        let attr = MemoryAttr::default();
+        let mut parent = MockPageTable::new();
+        let mut child = MockPageTable::new();
+        map_frame(&mut parent, &allocator, 0x1000, 1);
+        let frame = parent.get_entry(0x1000).unwrap().target();
+        frames.share(&mut child, &mut parent, 0x1000, &attr);
+        let free = allocator.free_count();
+
+        // the first writer gets a copy
+        frames.copy_on_write(&mut child, 0x1000, &allocator);
+        assert_eq!(allocator.free_count(), free - 1);
+        let entry = child.get_entry(0x1000).unwrap();
+        assert_ne!(entry.target(), frame);
+        assert!(entry.writable());
+        child.write(0x1000, 2);
+        assert_eq!(parent.read(0x1000), 1);
+        assert!(!frames.is_shared(frame));
+
+        // the last one takes over the frame without copying
+        frames.copy_on_write(&mut parent, 0x1000, &allocator);
+        assert_eq!(allocator.free_count(), free - 1);
+        let entry = parent.get_entry(0x1000).unwrap();
+        assert_eq!(entry.target(), frame);
+        assert!(entry.writable());
+        assert_eq!(parent.read(0x1000), 1);
+    }
+
+    #[test]
+    fn protect() {
+        let allocator = MockFrameAllocator::new();
+        let frames = CowFrames::new();
+        let attr = MemoryAttr::default();
+        let mut parent = MockPageTable::new();
+        let mut child = MockPageTable::new();
+        map_frame(&mut parent, &allocator, 0x1000, 1);
+        frames.share(&mut child, &mut parent, 0x1000, &attr);
+
+        // still read-only while shared
+        frames.protect(&mut child, 0x1000, &attr.execute());
+        let entry = child.get_entry(0x1000).unwrap();
+        assert!(entry.execute());
+        assert!(!entry.writable());
+
+        frames.copy_on_write(&mut child, 0x1000, &allocator);
+        frames.protect(&mut parent, 0x1000, &attr);
+        assert!(parent.get_entry(0x1000).unwrap().writable());
+        frames.protect(&mut parent, 0x1000, &attr.readonly());
+        assert!(!parent.get_entry(0x1000).unwrap().writable());
+    }
+}
@@ -1,11 +1,18 @@
 use super::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A frame filled with zeros, mapped read-only to pages which are read
+/// before they are ever written. It is allocated on first use and never freed.
+static ZERO_FRAME: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Debug, Clone)]
-pub struct Delay<T: FrameAllocator> {
+pub struct Delay<T: FrameAllocator, S: Swapper = NoSwap> {
     allocator: T,
+    frames: CowFrames,
+    swapper: S,
 }
 
-impl<T: FrameAllocator> MemoryHandler for Delay<T> {
+impl<T: FrameAllocator, S: Swapper> MemoryHandler for Delay<T, S> {
     fn box_clone(&self) -> Box<dyn MemoryHandler> {
         Box::new(self.clone())
     }
@@ -18,13 +25,22 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
 
     fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
         let entry = pt.get_entry(addr).expect("failed to get entry");
-        if entry.present() {
-            self.allocator.dealloc(entry.target());
+        let target = entry.target();
+        let present = entry.present();
+        if !present && entry.swapped() {
+            self.swapper.swap_free(target / PAGE_SIZE);
+            entry.set_swapped(false);
         }
 
         // PageTable::unmap requires page to be present
         entry.set_present(true);
         pt.unmap(addr);
+        if present {
+            pt.flush_tlb_others(addr, addr + PAGE_SIZE);
+            if target != zero_frame() && self.frames.release(target) {
+                self.allocator.dealloc(target);
+            }
+        }
     }
 
     fn clone_map(
@@ -35,20 +51,97 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         attr: &MemoryAttr,
     ) {
         let entry = src_pt.get_entry(addr).expect("failed to get entry");
-        if entry.present() {
-            // eager map and copy data
-            let data = src_pt.get_page_slice_mut(addr);
+        if entry.present() && entry.target() == zero_frame() {
+            let entry = pt.map(addr, zero_frame());
+            attr.apply(entry);
+            entry.set_writable(false);
+            entry.update();
+        } else if entry.present() {
+            // copy on write
+            self.frames.share(pt, src_pt, addr, attr);
+        } else if entry.swapped() {
+            // read the page from swap, leaving it there for `src_pt`
+            let slot = entry.target() / PAGE_SIZE;
             let target = self.allocator.alloc().expect("failed to alloc frame");
             let entry = pt.map(addr, target);
             attr.apply(entry);
-            pt.get_page_slice_mut(addr).copy_from_slice(data);
-            pt.flush_cache_copy_user(addr, addr + data.len(), attr.execute);
+            let data = pt.get_page_slice_mut(addr);
+            self.swapper.swap_in(slot, data);
+            pt.flush_cache_copy_user(addr, addr + PAGE_SIZE, attr.execute);
         } else {
             // delay map
             self.map(pt, addr, attr);
         }
     }
 
+    fn discard(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.unmap(pt, addr);
+        self.map(pt, addr, attr);
+    }
+
+    fn moved(&self, _offset: isize) -> Option<Box<dyn MemoryHandler>> {
+        Some(self.box_clone())
+    }
+
+    fn protect(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.frames.protect(pt, addr, attr);
+        if let Some(entry) = pt.get_entry(addr) {
+            if entry.present() && entry.target() == zero_frame() {
+                entry.set_writable(false);
+                entry.update();
+            }
+        }
+    }
+
+    fn swap_out(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> bool {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        let target = entry.target();
+        // frames shared with others stay in memory
+        if !entry.present() || target == zero_frame() || self.frames.is_shared(target) {
+            return false;
+        }
+        let data = pt.get_page_slice_mut(addr);
+        // no CPU may write the page while it is copied
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.set_present(false);
+        entry.update();
+        pt.flush_tlb_others(addr, addr + PAGE_SIZE);
+        let slot = match self.swapper.swap_out(data) {
+            Some(slot) => slot,
+            None => {
+                let entry = pt.get_entry(addr).expect("failed to get entry");
+                entry.set_present(true);
+                entry.update();
+                return false;
+            }
+        };
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.set_swapped(true);
+        entry.set_target(slot * PAGE_SIZE);
+        entry.update();
+        self.allocator.dealloc(target);
+        true
+    }
+
+    fn swap_in(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> bool {
+        let frame = match self.allocator.alloc() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        let slot = entry.target() / PAGE_SIZE;
+        let execute = entry.execute();
+        entry.set_target(frame);
+        entry.set_present(true);
+        entry.set_swapped(false);
+        entry.update();
+        let data = pt.get_page_slice_mut(addr);
+        self.swapper.swap_in(slot, data);
+        self.swapper.swap_free(slot);
+        pt.flush_cache_copy_user(addr, addr + PAGE_SIZE, execute);
+        true
+    }
+
     fn handle_page_fault_ext(
         &self,
         pt: &mut dyn PageTable,
@@ -61,11 +154,59 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
             if access.check_access(entry) {
                 return true;
             }
+            if access.write && entry.target() == zero_frame() {
+                entry.set_writable(true);
+                self.map_zeroed(pt, addr);
+                return true;
+            }
+            if access.write && !entry.writable() {
+                self.frames.copy_on_write(pt, addr, &self.allocator);
+                return true;
+            }
             // permisison check failed.
             error!("Permission check failed at 0x{:x}.", addr);
             return false;
         }
+        if entry.swapped() {
+            return self.swap_in(pt, addr);
+        }
+        if !access.write {
+            self.map_zero_frame(pt, addr);
+            return true;
+        }
+        self.map_zeroed(pt, addr);
+        true
+    }
+}
+
+fn zero_frame() -> PhysAddr {
+    ZERO_FRAME.load(Ordering::Acquire)
+}
+
+impl<T: FrameAllocator> Delay<T> {
+    pub fn new(allocator: T) -> Self {
+        Delay {
+            allocator,
+            frames: CowFrames::new(),
+            swapper: NoSwap,
+        }
+    }
+}
+
+impl<T: FrameAllocator, S: Swapper> Delay<T, S> {
+    /// Create a handler whose pages can be swapped out with `swapper`
+    pub fn with_swapper(allocator: T, swapper: S) -> Self {
+        Delay {
+            allocator,
+            frames: CowFrames::new(),
+            swapper,
+        }
+    }
+
+    /// Map a newly allocated frame filled with zeros to `addr`
+    fn map_zeroed(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> PhysAddr {
         let frame = self.allocator.alloc().expect("failed to alloc frame");
+        let entry = pt.get_entry(addr).expect("failed to get entry");
         entry.set_target(frame);
         entry.set_present(true);
         entry.update();
@@ -76,12 +217,31 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
             *x = 0;
         }
         pt.flush_cache_copy_user(addr, addr + len, false);
-        true
+        frame
     }
-}
 
-impl<T: FrameAllocator> Delay<T> {
-    pub fn new(allocator: T) -> Self {
-        Delay { allocator }
+    /// Map the zero frame to `addr` read-only, allocating it if necessary
+    fn map_zero_frame(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
+        let mut zero = zero_frame();
+        if zero == 0 {
+            let frame = self.map_zeroed(pt, addr);
+            zero = match ZERO_FRAME.compare_exchange(0, frame, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => frame,
+                Err(other) => {
+                    // allocated by someone else in the meantime
+                    let entry = pt.get_entry(addr).expect("failed to get entry");
+                    entry.set_target(other);
+                    entry.update();
+                    self.allocator.dealloc(frame);
+                    other
+                }
+            };
+        }
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.set_target(zero);
+        entry.set_present(true);
+        entry.set_writable(false);
+        entry.update();
     }
 }
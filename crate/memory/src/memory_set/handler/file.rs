@@ -7,14 +7,47 @@ pub struct File<F, T> {
     pub mem_start: usize,
     pub file_start: usize,
     pub file_end: usize,
+    /// Whether dirty pages are written back to the file (MAP_SHARED)
+    pub shared: bool,
     pub allocator: T,
+    /// Private pages shared copy-on-write after fork
+    pub frames: CowFrames,
 }
 
 pub trait Read: Clone + Send + Sync + 'static {
     fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+
+    /// Get the frame caching the page of the file at `offset`,
+    /// which is mapped directly by shared mappings until `put_frame`.
+    /// Return None if the page is not cached.
+    fn get_frame(&self, _offset: usize) -> Option<PhysAddr> {
+        None
+    }
+
+    /// Stop mapping `frame` got from `get_frame`.
+    /// Return false if it is not a frame of the cache.
+    fn put_frame(&self, _offset: usize, _frame: PhysAddr) -> bool {
+        false
+    }
+
+    /// Mark the cached page in `frame` dirty.
+    /// Return false if it is not a frame of the cache.
+    fn set_dirty(&self, _offset: usize, _frame: PhysAddr) -> bool {
+        false
+    }
 }
 
-impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
+pub trait Write: Clone + Send + Sync + 'static {
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize;
+
+    /// Write the data of the file cached in memory to the storage.
+    /// Return false on error.
+    fn sync(&self) -> bool {
+        true
+    }
+}
+
+impl<F: Read + Write, T: FrameAllocator> MemoryHandler for File<F, T> {
     fn box_clone(&self) -> Box<dyn MemoryHandler> {
         Box::new(self.clone())
     }
@@ -26,14 +59,21 @@ impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
     }
 
     fn unmap(&self, pt: &mut dyn PageTable, addr: usize) {
+        self.write_back(pt, addr);
         let entry = pt.get_entry(addr).expect("failed to get entry");
-        if entry.present() {
-            self.allocator.dealloc(entry.target());
-        }
+        let target = entry.target();
+        let present = entry.present();
 
         // PageTable::unmap requires page to be present
         entry.set_present(true);
         pt.unmap(addr);
+        if present {
+            pt.flush_tlb_others(addr, addr + PAGE_SIZE);
+            let cached = self.shared && self.file.put_frame(self.file_offset(addr), target);
+            if !cached && self.frames.release(target) {
+                self.allocator.dealloc(target);
+            }
+        }
     }
 
     fn clone_map(
@@ -43,21 +83,46 @@ impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
         addr: usize,
         attr: &MemoryAttr,
     ) {
+        if self.shared {
+            // the child reads the latest data from the file
+            self.write_back(src_pt, addr);
+            self.map(pt, addr, attr);
+            return;
+        }
         let entry = src_pt.get_entry(addr).expect("failed to get entry");
-        if entry.present() && !attr.readonly {
-            // eager map and copy data
-            let data = src_pt.get_page_slice_mut(addr);
-            let target = self.allocator.alloc().expect("failed to alloc frame");
-            let entry = pt.map(addr, target);
-            attr.apply(entry);
-            pt.get_page_slice_mut(addr).copy_from_slice(data);
-            pt.flush_cache_copy_user(addr, addr + data.len(), attr.execute);
+        if entry.present() {
+            // copy on write
+            self.frames.share(pt, src_pt, addr, attr);
         } else {
             // delay map
             self.map(pt, addr, attr);
         }
     }
 
+    fn discard(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.unmap(pt, addr);
+        self.map(pt, addr, attr);
+    }
+
+    fn moved(&self, offset: isize) -> Option<Box<dyn MemoryHandler>> {
+        Some(Box::new(File {
+            mem_start: (self.mem_start as isize + offset) as usize,
+            ..self.clone()
+        }))
+    }
+
+    fn protect(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.frames.protect(pt, addr, attr);
+    }
+
+    fn sync(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
+        self.write_back(pt, addr);
+    }
+
+    fn sync_storage(&self) -> bool {
+        !self.shared || self.file.sync()
+    }
+
     fn handle_page_fault_ext(
         &self,
         pt: &mut dyn PageTable,
@@ -71,6 +136,10 @@ impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
             if access.check_access(entry) {
                 return true;
             }
+            if access.write && !entry.writable() && !self.shared {
+                self.frames.copy_on_write(pt, addr, &self.allocator);
+                return true;
+            }
             // permisison check failed.
             error!(
                 "Permission check failed at 0x{:x}, access = {:?}.",
@@ -79,6 +148,16 @@ impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
             return false;
         }
         let execute = entry.execute();
+        if self.shared {
+            if let Some(frame) = self.file.get_frame(self.file_offset(addr)) {
+                // map the page cache, so that other users of the file see the writes
+                entry.set_target(frame);
+                entry.set_present(true);
+                entry.update();
+                pt.flush_cache_copy_user(addr, addr + PAGE_SIZE, execute);
+                return true;
+            }
+        }
         let frame = self.allocator.alloc().expect("failed to alloc frame");
         entry.set_target(frame);
         entry.set_present(true);
@@ -90,10 +169,14 @@ impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
     }
 }
 
-impl<F: Read, T: FrameAllocator> File<F, T> {
+impl<F: Read + Write, T: FrameAllocator> File<F, T> {
+    fn file_offset(&self, addr: VirtAddr) -> usize {
+        addr + self.file_start - self.mem_start
+    }
+
     fn fill_data(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> usize {
         let data = pt.get_page_slice_mut(addr);
-        let file_offset = addr + self.file_start - self.mem_start;
+        let file_offset = self.file_offset(addr);
         let read_size = (self.file_end as isize - file_offset as isize)
             .min(PAGE_SIZE as isize)
             .max(0) as usize;
@@ -103,6 +186,32 @@ impl<F: Read, T: FrameAllocator> File<F, T> {
         }
         read_size
     }
+
+    /// Write the page at `addr` back to the file if it is shared and dirty
+    fn write_back(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
+        if !self.shared {
+            return;
+        }
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if !entry.present() || !entry.dirty() {
+            return;
+        }
+        entry.clear_dirty();
+        entry.update();
+        let target = entry.target();
+        // writes through the TLB of other CPUs would not mark it dirty again
+        pt.flush_tlb_others(addr, addr + PAGE_SIZE);
+        let file_offset = self.file_offset(addr);
+        if self.file.set_dirty(file_offset, target) {
+            // written back with the page cache
+            return;
+        }
+        let write_size = (self.file_end as isize - file_offset as isize)
+            .min(PAGE_SIZE as isize)
+            .max(0) as usize;
+        let data = pt.get_page_slice_mut(addr);
+        self.file.write_at(file_offset, &data[..write_size]);
+    }
 }
 
 impl<F, T> Debug for File<F, T> {
@@ -111,6 +220,7 @@ impl<F, T> Debug for File<F, T> {
             .field("mem_start", &self.mem_start)
             .field("file_start", &self.file_start)
             .field("file_end", &self.file_end)
+            .field("shared", &self.shared)
             .finish()
     }
 }
@@ -0,0 +1,108 @@
+use super::*;
+
+/// Number of frames in a huge page
+const HUGE_PAGE_FRAMES: usize = HUGE_PAGE_SIZE / PAGE_SIZE;
+
+/// Eagerly map huge pages, or normal pages where huge pages can not be
+/// allocated or mapped. Areas must be aligned to `HUGE_PAGE_SIZE`.
+#[derive(Debug, Clone)]
+pub struct Huge<T: FrameAllocator> {
+    allocator: T,
+}
+
+impl<T: FrameAllocator> MemoryHandler for Huge<T> {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        if addr % HUGE_PAGE_SIZE == 0 && self.map_huge(pt, addr, attr) {
+            return;
+        }
+        // the rest of a huge page mapped already
+        if pt.get_entry(addr).map_or(false, |entry| entry.huge()) {
+            return;
+        }
+        let target = self.allocator.alloc().expect("failed to allocate frame");
+        let entry = pt.map(addr, target);
+        attr.apply(entry);
+        pt.get_page_slice_mut(addr).iter_mut().for_each(|x| *x = 0);
+    }
+
+    fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
+        let entry = match pt.get_entry(addr) {
+            Some(entry) => entry,
+            // the rest of a huge page unmapped already
+            None => return,
+        };
+        let target = entry.target();
+        if entry.huge() {
+            pt.unmap_huge(addr);
+            pt.flush_tlb_others(addr, addr + HUGE_PAGE_SIZE);
+            for i in 0..HUGE_PAGE_FRAMES {
+                self.allocator.dealloc(target + i * PAGE_SIZE);
+            }
+        } else if entry.present() {
+            pt.unmap(addr);
+            pt.flush_tlb_others(addr, addr + PAGE_SIZE);
+            self.allocator.dealloc(target);
+        }
+    }
+
+    fn clone_map(
+        &self,
+        pt: &mut dyn PageTable,
+        src_pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        attr: &MemoryAttr,
+    ) {
+        // the pages may be mapped differently in `pt`, so copy them one by one
+        self.map(pt, addr, attr);
+        let data = src_pt.get_page_slice_mut(addr);
+        pt.get_page_slice_mut(addr).copy_from_slice(data);
+    }
+
+    fn page_size(&self) -> usize {
+        HUGE_PAGE_SIZE
+    }
+
+    fn handle_page_fault_ext(
+        &self,
+        _pt: &mut dyn PageTable,
+        _addr: VirtAddr,
+        _access: super::AccessType,
+    ) -> bool {
+        false
+    }
+}
+
+impl<T: FrameAllocator> Huge<T> {
+    pub fn new(allocator: T) -> Self {
+        Huge { allocator }
+    }
+
+    /// Try to map a huge page filled with zeros at `addr`
+    fn map_huge(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) -> bool {
+        let target = match self
+            .allocator
+            .alloc_contiguous(HUGE_PAGE_FRAMES, HUGE_PAGE_FRAMES.trailing_zeros() as usize)
+        {
+            Some(target) => target,
+            None => return false,
+        };
+        match pt.map_huge(addr, target) {
+            Some(entry) => attr.apply(entry),
+            None => {
+                for i in 0..HUGE_PAGE_FRAMES {
+                    self.allocator.dealloc(target + i * PAGE_SIZE);
+                }
+                return false;
+            }
+        }
+        for i in 0..HUGE_PAGE_FRAMES {
+            let data = pt.get_page_slice_mut(addr + i * PAGE_SIZE);
+            data.iter_mut().for_each(|x| *x = 0);
+        }
+        true
+    }
+}
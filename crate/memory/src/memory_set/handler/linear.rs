@@ -18,6 +18,7 @@ impl MemoryHandler for Linear {
 
     fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
         pt.unmap(addr);
+        pt.flush_tlb_others(addr, addr + PAGE_SIZE);
     }
 
     fn clone_map(
@@ -0,0 +1,45 @@
+//! Mock Frame Allocator
+//!
+//! An mock implementation of the FrameAllocator, handing out the frames of `MockPageTable`.
+//! Used to test memory handlers.
+
+use super::*;
+use alloc::collections::BTreeSet;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+/// Frames of the mock page table, frame 0 is never handed out
+const FRAME_COUNT: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct MockFrameAllocator(Arc<Mutex<BTreeSet<PhysAddr>>>);
+
+impl FrameAllocator for MockFrameAllocator {
+    fn alloc(&self) -> Option<PhysAddr> {
+        let mut frames = self.0.lock();
+        let frame = *frames.iter().next()?;
+        frames.remove(&frame);
+        Some(frame)
+    }
+    fn alloc_contiguous(&self, _size: usize, _align_log2: usize) -> Option<PhysAddr> {
+        unimplemented!()
+    }
+    fn dealloc(&self, target: PhysAddr) {
+        assert!(
+            self.0.lock().insert(target),
+            "frame {:#x} freed twice",
+            target
+        );
+    }
+}
+
+impl MockFrameAllocator {
+    pub fn new() -> Self {
+        let frames = (1..FRAME_COUNT).map(|i| i * PAGE_SIZE).collect();
+        MockFrameAllocator(Arc::new(Mutex::new(frames)))
+    }
+    /// Number of frames not allocated
+    pub fn free_count(&self) -> usize {
+        self.0.lock().len()
+    }
+}
@@ -0,0 +1,39 @@
+//! Mock Swapper
+//!
+//! An mock implementation of the Swapper, keeping the pages in memory.
+//! Used to test swapping.
+
+use super::*;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+#[derive(Debug, Clone, Default)]
+pub struct MockSwapper(Arc<Mutex<BTreeMap<usize, Vec<u8>>>>);
+
+impl Swapper for MockSwapper {
+    fn swap_out(&self, data: &[u8]) -> Option<usize> {
+        let mut slots = self.0.lock();
+        let slot = (0..).find(|slot| !slots.contains_key(slot)).unwrap();
+        slots.insert(slot, data.to_vec());
+        Some(slot)
+    }
+    fn swap_in(&self, slot: usize, data: &mut [u8]) {
+        data.copy_from_slice(&self.0.lock()[&slot]);
+    }
+    fn swap_free(&self, slot: usize) {
+        assert!(
+            self.0.lock().remove(&slot).is_some(),
+            "slot {} freed twice",
+            slot
+        );
+    }
+}
+
+impl MockSwapper {
+    /// Number of slots in use
+    pub fn used_count(&self) -> usize {
+        self.0.lock().len()
+    }
+}
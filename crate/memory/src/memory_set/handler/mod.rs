@@ -60,6 +60,51 @@ pub trait MemoryHandler: Debug + Send + Sync + 'static {
         attr: &MemoryAttr,
     );
 
+    /// Change the attribute of the mapped page `addr`
+    fn protect(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        if let Some(entry) = pt.get_entry(addr) {
+            attr.apply(entry);
+        }
+    }
+
+    /// Free the frame of the mapped page `addr` if it can be filled again
+    /// on the next page fault, so that it looks like a page never touched.
+    /// Pages of other kinds are left as they are.
+    fn discard(&self, _pt: &mut dyn PageTable, _addr: VirtAddr, _attr: &MemoryAttr) {}
+
+    /// Get the handler of the area after it is moved by `offset` bytes along with its pages.
+    /// Return None if the pages can not be moved.
+    fn moved(&self, _offset: isize) -> Option<Box<dyn MemoryHandler>> {
+        None
+    }
+
+    /// Size of the pages mapped by the handler.
+    /// Areas must be split at multiples of it.
+    fn page_size(&self) -> usize {
+        PAGE_SIZE
+    }
+
+    /// Write the mapped page `addr` back to the file it maps if it is dirty.
+    fn sync(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) {}
+
+    /// Wait for the pages written back by `sync` to reach the storage.
+    /// Return false if they can not be written.
+    fn sync_storage(&self) -> bool {
+        true
+    }
+
+    /// Write the page `addr` to swap and free its frame.
+    /// Return false if the page can not be swapped out.
+    fn swap_out(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) -> bool {
+        false
+    }
+
+    /// Read the swapped out page `addr` back into a new frame and free its slot.
+    /// Return false if there is no frame for it.
+    fn swap_in(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) -> bool {
+        false
+    }
+
     /// Handle page fault on `addr`
     /// Return true if success, false if error
     fn handle_page_fault(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> bool {
@@ -90,15 +135,53 @@ pub trait FrameAllocator: Debug + Clone + Send + Sync + 'static {
     fn dealloc(&self, target: PhysAddr);
 }
 
+/// Storage of swapped out pages, addressed by slot numbers
+pub trait Swapper: Debug + Clone + Send + Sync + 'static {
+    /// Write a page to a free slot, return the slot or None if swap is full
+    fn swap_out(&self, data: &[u8]) -> Option<usize>;
+    /// Read the page in `slot`, which is still kept in it
+    fn swap_in(&self, slot: usize, data: &mut [u8]);
+    /// Free `slot` for other pages
+    fn swap_free(&self, slot: usize);
+}
+
+/// Swapper for memory which is never swapped out
+#[derive(Debug, Clone)]
+pub struct NoSwap;
+
+impl Swapper for NoSwap {
+    fn swap_out(&self, _data: &[u8]) -> Option<usize> {
+        None
+    }
+    fn swap_in(&self, _slot: usize, _data: &mut [u8]) {
+        unreachable!()
+    }
+    fn swap_free(&self, _slot: usize) {
+        unreachable!()
+    }
+}
+
 mod byframe;
+mod cow;
 mod delay;
 mod file;
+mod huge;
 mod linear;
+#[cfg(test)]
+mod mock_frame_allocator;
+#[cfg(test)]
+mod mock_swapper;
 mod shared;
 //mod swap;
 
 pub use self::byframe::ByFrame;
+pub use self::cow::CowFrames;
 pub use self::delay::Delay;
-pub use self::file::{File, Read};
+pub use self::file::{File, Read, Write};
+pub use self::huge::Huge;
 pub use self::linear::Linear;
+#[cfg(test)]
+pub use self::mock_frame_allocator::MockFrameAllocator;
+#[cfg(test)]
+pub use self::mock_swapper::MockSwapper;
 pub use self::shared::{Shared, SharedGuard};
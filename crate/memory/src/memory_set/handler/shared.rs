@@ -31,7 +31,7 @@ impl<T: FrameAllocator> SharedGuard<T> {
     }
 
     pub fn alloc(&mut self, virt_addr: usize) -> Option<usize> {
-        let phys_addr = self.allocator.alloc().expect("failed to allocate frame");
+        let phys_addr = self.allocator.alloc()?;
         self.target.insert(virt_addr, phys_addr);
         Some(phys_addr)
     }
@@ -62,6 +62,9 @@ impl<T: FrameAllocator> Drop for SharedGuard<T> {
     }
 }
 
+/// Frames of the area are shared by all clones of the handler,
+/// so they are not copied on fork,
+/// and freed along with the guard when the last clone is dropped.
 #[derive(Debug, Clone)]
 pub struct Shared<T: FrameAllocator> {
     allocator: T,
@@ -104,7 +107,11 @@ impl<T: FrameAllocator> MemoryHandler for Shared<T> {
 
     fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
         // free physical memory done when guard destroyed
+        // PageTable::unmap requires page to be present
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.set_present(true);
         pt.unmap(addr);
+        pt.flush_tlb_others(addr, addr + PAGE_SIZE);
     }
 
     fn clone_map(
@@ -120,16 +127,26 @@ impl<T: FrameAllocator> MemoryHandler for Shared<T> {
         attr.apply(entry);
     }
 
-    fn handle_page_fault(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> bool {
+    fn handle_page_fault_ext(
+        &self,
+        pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        access: super::AccessType,
+    ) -> bool {
         let entry = pt.get_entry(addr).expect("failed to get entry");
         let addr_offset = addr - self.start_virt_addr.lock().unwrap();
-        let phys_addr_opt = self.guard.lock().get(addr_offset);
+        // held until the frame is filled, so that all sharers get the same one
+        let mut guard = self.guard.lock();
+        let phys_addr_opt = guard.get(addr_offset);
         if entry.present() {
-            // not a delay case
-            return false;
+            // not a delay case, maybe mapped by another thread in the meantime
+            return access.check_access(entry);
         } else if phys_addr_opt.is_none() {
             // physical memory not alloced.
-            let frame = self.guard.lock().alloc(addr_offset).unwrap();
+            let frame = match guard.alloc(addr_offset) {
+                Some(frame) => frame,
+                None => return false,
+            };
             entry.set_target(frame);
             entry.set_present(true);
             entry.update();
@@ -150,6 +167,19 @@ impl<T: FrameAllocator> MemoryHandler for Shared<T> {
         }
         true
     }
+
+    fn moved(&self, offset: isize) -> Option<Box<dyn MemoryHandler>> {
+        // offsets into the guard stay the same, only the start moves
+        let start = self
+            .start_virt_addr
+            .lock()
+            .map(|s| (s as isize + offset) as usize);
+        Some(Box::new(Shared {
+            allocator: self.allocator.clone(),
+            start_virt_addr: Arc::new(Mutex::new(start)),
+            guard: self.guard.clone(),
+        }))
+    }
 }
 
 impl<T: FrameAllocator> Shared<T> {
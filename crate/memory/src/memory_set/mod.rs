@@ -1,6 +1,6 @@
 //! Memory management structures
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeSet, vec::Vec};
 use core::fmt::{Debug, Error, Formatter};
 use core::mem::size_of;
 
@@ -23,13 +23,28 @@ pub struct MemoryArea {
 }
 
 impl MemoryArea {
+    /// Start address of the memory area
+    pub fn start_addr(&self) -> VirtAddr {
+        self.start_addr
+    }
+    /// End address of the memory area
+    pub fn end_addr(&self) -> VirtAddr {
+        self.end_addr
+    }
+    /// Attributes of the memory area
+    pub fn attr(&self) -> MemoryAttr {
+        self.attr
+    }
     /// Test whether a virtual address is in the memory area
     pub fn contains(&self, addr: VirtAddr) -> bool {
         addr >= self.start_addr && addr < self.end_addr
     }
     /// Check the array is within the readable memory.
-    /// Return the size of space covered in the area.
+    /// Return the size of space covered in the area, none if the user can not access it.
     fn check_read_array<S>(&self, ptr: *const S, count: usize) -> usize {
+        if !self.attr.user {
+            return 0;
+        }
         // page align
         let min_bound = (ptr as usize).max(Page::of_addr(self.start_addr).start_address());
         let max_bound = unsafe { ptr.add(count) as usize }
@@ -101,6 +116,15 @@ impl MemoryAttr {
         self.mmio = value;
         self
     }
+    pub fn is_user(&self) -> bool {
+        self.user
+    }
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+    pub fn is_execute(&self) -> bool {
+        self.execute
+    }
     /// Apply the attributes to page table entry, then update it.
     /// NOTE: You may need to set present manually.
     pub fn apply(&self, entry: &mut dyn Entry) {
@@ -119,6 +143,13 @@ impl MemoryAttr {
 pub struct MemorySet<T: PageTableExt> {
     areas: Vec<MemoryArea>,
     page_table: T,
+    /// Where the clock hand of swapping stopped
+    swap_clock: VirtAddr,
+    /// Pages locked in memory by mlock, which are never swapped out
+    locked: BTreeSet<VirtAddr>,
+    /// Whether areas pushed later are locked as well (mlockall with MCL_FUTURE),
+    /// their pages being mapped on fault as usual
+    lock_future: bool,
 }
 
 impl<T: PageTableExt> MemorySet<T> {
@@ -127,6 +158,9 @@ impl<T: PageTableExt> MemorySet<T> {
         MemorySet {
             areas: Vec::new(),
             page_table: T::new(),
+            swap_clock: 0,
+            locked: BTreeSet::new(),
+            lock_future: false,
         }
     }
     /// Create a new `MemorySet` for kernel remap
@@ -134,6 +168,9 @@ impl<T: PageTableExt> MemorySet<T> {
         MemorySet {
             areas: Vec::new(),
             page_table: T::new_bare(),
+            swap_clock: 0,
+            locked: BTreeSet::new(),
+            lock_future: false,
         }
     }
     /// Check the pointer is within the readable memory
@@ -187,7 +224,7 @@ impl<T: PageTableExt> MemorySet<T> {
             .expect("failed to find free area ???")
     }
     /// Test if [`start_addr`, `end_addr`) is a free area
-    fn test_free_area(&self, start_addr: usize, end_addr: usize) -> bool {
+    pub fn test_free_area(&self, start_addr: usize, end_addr: usize) -> bool {
         self.areas
             .iter()
             .find(|area| area.is_overlap_with(start_addr, end_addr))
@@ -217,12 +254,19 @@ impl<T: PageTableExt> MemorySet<T> {
             name,
         };
         area.map(&mut self.page_table);
-        // keep order by start address
+        self.insert_area(area);
+        if self.lock_future {
+            self.lock(start_addr, end_addr, true);
+        }
+    }
+
+    /// Insert an area, keeping order by start address
+    fn insert_area(&mut self, area: MemoryArea) {
         let idx = self
             .areas
             .iter()
             .enumerate()
-            .find(|(_, other)| start_addr < other.start_addr)
+            .find(|(_, other)| area.start_addr < other.start_addr)
             .map(|(i, _)| i)
             .unwrap_or(self.areas.len());
         self.areas.insert(idx, area);
@@ -235,6 +279,7 @@ impl<T: PageTableExt> MemorySet<T> {
             if self.areas[i].start_addr == start_addr && self.areas[i].end_addr == end_addr {
                 let area = self.areas.remove(i);
                 area.unmap(&mut self.page_table);
+                self.unlock(start_addr, end_addr);
                 return;
             }
         }
@@ -245,6 +290,7 @@ impl<T: PageTableExt> MemorySet<T> {
     /// and split existed ones when necessary.
     pub fn pop_with_split(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
         assert!(start_addr <= end_addr, "invalid memory area");
+        self.unlock(start_addr, end_addr);
         let mut i = 0;
         while i < self.areas.len() {
             if self.areas[i].is_overlap_with(start_addr, end_addr) {
@@ -328,11 +374,614 @@ impl<T: PageTableExt> MemorySet<T> {
         }
     }
 
+    /// Extend the area `[start_addr, end_addr)` to `new_end`,
+    /// or return false if there is no such area or the range after it is not free.
+    pub fn grow(&mut self, start_addr: VirtAddr, end_addr: VirtAddr, new_end: VirtAddr) -> bool {
+        assert!(end_addr <= new_end, "invalid memory area");
+        if !self.test_free_area(end_addr, new_end) {
+            return false;
+        }
+        let Self {
+            ref mut page_table,
+            ref mut areas,
+            ..
+        } = self;
+        let area = areas
+            .iter_mut()
+            .find(|area| area.contains(start_addr) && area.end_addr == end_addr);
+        match area {
+            Some(area) => {
+                for page in Page::range_of(end_addr, new_end) {
+                    area.handler
+                        .map(page_table, page.start_address(), &area.attr);
+                }
+                area.end_addr = new_end;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Extend the area starting at `start_addr` down to `new_start`,
+    /// or return false if there is no such area or the range below it is not free.
+    pub fn grow_down(&mut self, start_addr: VirtAddr, new_start: VirtAddr) -> bool {
+        assert!(new_start <= start_addr, "invalid memory area");
+        if !self.test_free_area(new_start, start_addr) {
+            return false;
+        }
+        let Self {
+            ref mut page_table,
+            ref mut areas,
+            ..
+        } = self;
+        let area = areas.iter_mut().find(|area| area.start_addr == start_addr);
+        match area {
+            Some(area) => {
+                for page in Page::range_of(new_start, start_addr) {
+                    area.handler
+                        .map(page_table, page.start_address(), &area.attr);
+                }
+                area.start_addr = new_start;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Test whether `[start_addr, end_addr)` is inside one area
+    /// whose pages can be moved to `new_start`, ignoring what is mapped there
+    pub fn can_relocate(
+        &self,
+        start_addr: VirtAddr,
+        end_addr: VirtAddr,
+        new_start: VirtAddr,
+    ) -> bool {
+        self.relocated_handler(start_addr, end_addr, new_start)
+            .is_some()
+    }
+
+    fn relocated_handler(
+        &self,
+        start_addr: VirtAddr,
+        end_addr: VirtAddr,
+        new_start: VirtAddr,
+    ) -> Option<(usize, Box<dyn MemoryHandler>)> {
+        let i = self
+            .areas
+            .iter()
+            .position(|area| area.start_addr <= start_addr && end_addr <= area.end_addr)?;
+        let handler = self.areas[i]
+            .handler
+            .moved(new_start as isize - start_addr as isize)?;
+        Some((i, handler))
+    }
+
+    /// Move `[start_addr, end_addr)` to the free range starting at `new_start`
+    /// along with its mapped pages, and split the old area when necessary.
+    /// Return false if the range is not inside one area or its pages can not be moved.
+    pub fn relocate(
+        &mut self,
+        start_addr: VirtAddr,
+        end_addr: VirtAddr,
+        new_start: VirtAddr,
+    ) -> bool {
+        assert!(start_addr < end_addr, "invalid memory area");
+        let new_end = new_start + (end_addr - start_addr);
+        if !self.test_free_area(new_start, new_end) {
+            return false;
+        }
+        let (i, handler) = match self.relocated_handler(start_addr, end_addr, new_start) {
+            Some(found) => found,
+            None => return false,
+        };
+        let area = self.areas.remove(i);
+        if end_addr < area.end_addr {
+            let right_area = MemoryArea {
+                start_addr: end_addr,
+                end_addr: area.end_addr,
+                attr: area.attr,
+                handler: area.handler.box_clone(),
+                name: area.name,
+            };
+            self.areas.insert(i, right_area);
+        }
+        if area.start_addr < start_addr {
+            let left_area = MemoryArea {
+                start_addr: area.start_addr,
+                end_addr: start_addr,
+                attr: area.attr,
+                handler: area.handler.box_clone(),
+                name: area.name,
+            };
+            self.areas.insert(i, left_area);
+        }
+        for page in Page::range_of(start_addr, end_addr) {
+            let addr = page.start_address();
+            let new_addr = addr - start_addr + new_start;
+            // the dirty bit is not moved along with the entry
+            area.handler.sync(&mut self.page_table, addr);
+            move_entry(&mut self.page_table, addr, new_addr);
+            if self.locked.remove(&addr) {
+                self.locked.insert(new_addr);
+            }
+        }
+        self.page_table.flush_tlb_others(start_addr, end_addr);
+        self.insert_area(MemoryArea {
+            start_addr: new_start,
+            end_addr: new_end,
+            attr: area.attr,
+            handler,
+            name: area.name,
+        });
+        true
+    }
+
+    /// Test whether areas overlapping `[start_addr, end_addr)` can be split at its ends,
+    /// which must be aligned to the pages of the areas
+    pub fn can_split(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        self.areas
+            .iter()
+            .filter(|area| area.is_overlap_with(start_addr, end_addr))
+            .all(|area| {
+                let size = area.handler.page_size();
+                start_addr % size == 0 && end_addr % size == 0
+            })
+    }
+
+    /// Test whether `[start_addr, end_addr)` is covered by areas without holes
+    pub fn is_mapped(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        // areas are sorted by start address
+        let mut covered = start_addr;
+        for area in self.areas.iter() {
+            if area.is_overlap_with(start_addr, end_addr) {
+                if area.start_addr > covered {
+                    return false;
+                }
+                covered = covered.max(area.end_addr);
+            }
+        }
+        covered >= end_addr
+    }
+
+    /// Free the frames of `[start_addr, end_addr)` which can be filled again on page fault
+    pub fn discard(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ..
+        } = self;
+        for area in areas.iter() {
+            if !area.is_overlap_with(start_addr, end_addr) {
+                continue;
+            }
+            let start = area.start_addr.max(start_addr);
+            let end = area.end_addr.min(end_addr);
+            for page in Page::range_of(start, end) {
+                area.handler
+                    .discard(page_table, page.start_address(), &area.attr);
+            }
+        }
+    }
+
+    /// Write the dirty pages of shared file mappings in `[start_addr, end_addr)`
+    /// back to the files. If `wait`, also write the files to the storage.
+    /// Return false if the storage reports an error.
+    pub fn sync(&mut self, start_addr: VirtAddr, end_addr: VirtAddr, wait: bool) -> bool {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ..
+        } = self;
+        let mut ok = true;
+        for area in areas.iter() {
+            if !area.is_overlap_with(start_addr, end_addr) {
+                continue;
+            }
+            let start = area.start_addr.max(start_addr);
+            let end = area.end_addr.min(end_addr);
+            for page in Page::range_of(start, end) {
+                area.handler.sync(page_table, page.start_address());
+            }
+            if wait {
+                ok &= area.handler.sync_storage();
+            }
+        }
+        ok
+    }
+
+    /// Map the pages of `[start_addr, end_addr)` which are not present yet,
+    /// as if they were read
+    pub fn prefault(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ..
+        } = self;
+        for area in areas.iter() {
+            if !area.is_overlap_with(start_addr, end_addr) {
+                continue;
+            }
+            let start = area.start_addr.max(start_addr);
+            let end = area.end_addr.min(end_addr);
+            for page in Page::range_of(start, end) {
+                let addr = page.start_address();
+                let present = page_table
+                    .get_entry(addr)
+                    .map_or(true, |entry| entry.present());
+                if !present {
+                    area.handler.handle_page_fault_ext(
+                        page_table,
+                        addr,
+                        handler::AccessType::read(true),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Lock the pages of `[start_addr, end_addr)` in memory,
+    /// mapping those not present yet unless `on_fault` is set.
+    /// Return false without locking anything if the range is not fully mapped.
+    pub fn lock(&mut self, start_addr: VirtAddr, end_addr: VirtAddr, on_fault: bool) -> bool {
+        if !self.is_mapped(start_addr, end_addr) {
+            return false;
+        }
+        if !on_fault {
+            self.prefault(start_addr, end_addr);
+        }
+        for page in Page::range_of(start_addr, end_addr) {
+            self.locked.insert(page.start_address());
+        }
+        true
+    }
+
+    /// Unlock the pages of `[start_addr, end_addr)`, so they can be swapped out again
+    pub fn unlock(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        for page in Page::range_of(start_addr, end_addr) {
+            self.locked.remove(&page.start_address());
+        }
+    }
+
+    /// Lock all pages currently mapped, with `lock`
+    pub fn lock_all(&mut self, on_fault: bool) {
+        let ranges: Vec<_> = self
+            .areas
+            .iter()
+            .map(|area| (area.start_addr, area.end_addr))
+            .collect();
+        for (start, end) in ranges {
+            self.lock(start, end, on_fault);
+        }
+    }
+
+    /// Unlock all pages, and stop locking future areas
+    pub fn unlock_all(&mut self) {
+        self.locked.clear();
+        self.lock_future = false;
+    }
+
+    /// Set whether areas pushed later are locked
+    pub fn set_lock_future(&mut self, lock_future: bool) {
+        self.lock_future = lock_future;
+    }
+
+    /// Whether areas pushed later are locked
+    pub fn lock_future(&self) -> bool {
+        self.lock_future
+    }
+
+    /// Number of locked pages in `[start_addr, end_addr)`
+    pub fn locked_pages(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> usize {
+        self.locked.range(start_addr..end_addr).count()
+    }
+
+    /// Number of locked pages in the whole set
+    pub fn total_locked_pages(&self) -> usize {
+        self.locked.len()
+    }
+
+    /// Number of pages in all areas
+    pub fn total_pages(&self) -> usize {
+        self.areas
+            .iter()
+            .map(|area| (area.end_addr - area.start_addr) / PAGE_SIZE)
+            .sum()
+    }
+
+    /// Number of pages mapped to frames in all areas
+    pub fn resident_pages(&mut self) -> usize {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ..
+        } = self;
+        let mut count = 0;
+        for area in areas.iter() {
+            for page in Page::range_of(area.start_addr, area.end_addr) {
+                if let Some(entry) = page_table.get_entry(page.start_address()) {
+                    if entry.present() {
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// Change the attribute of `[start_addr, end_addr)` to `attr`,
+    /// and split existed areas when necessary.
+    /// Entries already in the page table are updated, and flushed from the TLB of all CPUs.
+    /// Return false without changing anything if the range is not fully mapped.
+    pub fn protect(&mut self, start_addr: VirtAddr, end_addr: VirtAddr, attr: MemoryAttr) -> bool {
+        assert!(start_addr <= end_addr, "invalid memory area");
+        if start_addr == end_addr {
+            return true;
+        }
+        if !self.is_mapped(start_addr, end_addr) {
+            return false;
+        }
+
+        let mut i = 0;
+        while i < self.areas.len() {
+            if !self.areas[i].is_overlap_with(start_addr, end_addr) || self.areas[i].attr == attr {
+                i += 1;
+                continue;
+            }
+            let area = self.areas.remove(i);
+            let mid_start = area.start_addr.max(start_addr);
+            let mid_end = area.end_addr.min(end_addr);
+            if area.start_addr < mid_start {
+                let left_area = MemoryArea {
+                    start_addr: area.start_addr,
+                    end_addr: mid_start,
+                    attr: area.attr,
+                    handler: area.handler.box_clone(),
+                    name: area.name,
+                };
+                self.areas.insert(i, left_area);
+                i += 1;
+            }
+            // lazily mapped pages keep the attribute of their non-present entry
+            for page in Page::range_of(mid_start, mid_end) {
+                area.handler
+                    .protect(&mut self.page_table, page.start_address(), &attr);
+            }
+            let mid_area = MemoryArea {
+                start_addr: mid_start,
+                end_addr: mid_end,
+                attr,
+                handler: area.handler.box_clone(),
+                name: area.name,
+            };
+            self.areas.insert(i, mid_area);
+            i += 1;
+            if mid_end < area.end_addr {
+                let right_area = MemoryArea {
+                    start_addr: mid_end,
+                    end_addr: area.end_addr,
+                    attr: area.attr,
+                    handler: area.handler,
+                    name: area.name,
+                };
+                self.areas.insert(i, right_area);
+                i += 1;
+            }
+        }
+        self.page_table.flush_tlb_others(start_addr, end_addr);
+        true
+    }
+
+    /// Swap out a page which is not accessed recently with the clock algorithm.
+    /// Pages are scanned from where the last scan stopped, and the ones accessed
+    /// since the last scan get a second chance by clearing their accessed bit.
+    /// Return false if no page can be swapped out.
+    pub fn swap_out(&mut self) -> bool {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ref mut swap_clock,
+            ref locked,
+            ..
+        } = self;
+        let pages = || {
+            areas.iter().flat_map(|area| {
+                Page::range_of(area.start_addr, area.end_addr)
+                    .map(move |page| (area, page.start_address()))
+            })
+        };
+        let clock = *swap_clock;
+        let round = || {
+            pages()
+                .filter(move |&(_, addr)| addr >= clock)
+                .chain(pages().filter(move |&(_, addr)| addr < clock))
+        };
+        // the range of entries whose accessed bit is cleared, flushed from
+        // the TLB of other CPUs at last so that they set it again
+        let mut aged: Option<(VirtAddr, VirtAddr)> = None;
+        let mut found = false;
+        // every page loses its second chance in the first round
+        for (area, addr) in round().chain(round()) {
+            if locked.contains(&addr) {
+                continue;
+            }
+            let entry = match page_table.get_entry(addr) {
+                Some(entry) if entry.present() => entry,
+                _ => continue,
+            };
+            if entry.accessed() {
+                entry.clear_accessed();
+                entry.update();
+                aged = Some(aged.map_or((addr, addr + PAGE_SIZE), |(start, end)| {
+                    (start.min(addr), end.max(addr + PAGE_SIZE))
+                }));
+                continue;
+            }
+            // flushed by the handler before the frame is freed
+            if area.handler.swap_out(page_table, addr) {
+                *swap_clock = addr + PAGE_SIZE;
+                found = true;
+                break;
+            }
+        }
+        if let Some((start, end)) = aged {
+            page_table.flush_tlb_others(start, end);
+        }
+        found
+    }
+
+    /// Read all the pages swapped out back into memory, e.g. before the swap area is removed.
+    /// Return false if there are not enough frames.
+    pub fn swap_in_all(&mut self) -> bool {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ..
+        } = self;
+        for area in areas.iter() {
+            for page in Page::range_of(area.start_addr, area.end_addr) {
+                let addr = page.start_address();
+                let swapped = match page_table.get_entry(addr) {
+                    Some(entry) => entry.swapped(),
+                    None => false,
+                };
+                if swapped && !area.handler.swap_in(page_table, addr) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
     /// Get iterator of areas
     pub fn iter(&self) -> impl Iterator<Item = &MemoryArea> {
         self.areas.iter()
     }
 
+    /// Copy the page at `addr` into `buf`, e.g. for a core dump.
+    /// Pages swapped out are read back, while other pages not present, never touched,
+    /// are left unread. Return false if the page is not copied.
+    pub fn read_page(&mut self, addr: VirtAddr, buf: &mut [u8]) -> bool {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ..
+        } = self;
+        let area = match areas.iter().find(|area| area.contains(addr)) {
+            Some(area) => area,
+            None => return false,
+        };
+        let addr = Page::of_addr(addr).start_address();
+        let swapped = match page_table.get_entry(addr) {
+            Some(entry) if entry.present() => false,
+            Some(entry) if entry.swapped() => true,
+            _ => return false,
+        };
+        if swapped
+            && !area.handler.handle_page_fault_ext(
+                page_table,
+                addr,
+                handler::AccessType::read(true),
+            )
+        {
+            return false;
+        }
+        let len = buf.len().min(PAGE_SIZE);
+        buf[..len].copy_from_slice(&page_table.get_page_slice_mut(addr)[..len]);
+        true
+    }
+
+    /// Read the memory at `addr` into `buf` regardless of the protection,
+    /// e.g. for a debugger. Pages not present are brought in.
+    /// Return false if some page can not be read.
+    pub fn read_force(&mut self, addr: VirtAddr, buf: &mut [u8]) -> bool {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ..
+        } = self;
+        let mut pos = 0;
+        while pos < buf.len() {
+            let cur = addr + pos;
+            let area = match areas.iter().find(|area| area.contains(cur)) {
+                Some(area) => area,
+                None => return false,
+            };
+            let page = Page::of_addr(cur).start_address();
+            let offset = cur - page;
+            let len = (PAGE_SIZE - offset).min(buf.len() - pos);
+            let present = page_table
+                .get_entry(page)
+                .map_or(false, |entry| entry.present());
+            if !present
+                && !area.handler.handle_page_fault_ext(
+                    page_table,
+                    page,
+                    handler::AccessType::read(true),
+                )
+            {
+                return false;
+            }
+            buf[pos..pos + len]
+                .copy_from_slice(&page_table.get_page_slice_mut(page)[offset..offset + len]);
+            pos += len;
+        }
+        true
+    }
+
+    /// Write `data` to the memory at `addr` regardless of the protection,
+    /// e.g. breakpoints set by a debugger in the code.
+    /// Private pages are copied on write as if the area were writable,
+    /// then made read-only again. Return false if some page can not be written.
+    pub fn write_force(&mut self, addr: VirtAddr, data: &[u8]) -> bool {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ..
+        } = self;
+        let mut pos = 0;
+        while pos < data.len() {
+            let cur = addr + pos;
+            let area = match areas.iter().find(|area| area.contains(cur)) {
+                Some(area) => area,
+                None => return false,
+            };
+            let page = Page::of_addr(cur).start_address();
+            let offset = cur - page;
+            let len = (PAGE_SIZE - offset).min(data.len() - pos);
+            let present = page_table
+                .get_entry(page)
+                .map_or(false, |entry| entry.present());
+            // bring the page in, then get a private copy of it
+            if !present
+                && !area.handler.handle_page_fault_ext(
+                    page_table,
+                    page,
+                    handler::AccessType::read(true),
+                )
+            {
+                return false;
+            }
+            if !area.handler.handle_page_fault_ext(
+                page_table,
+                page,
+                handler::AccessType::write(true),
+            ) {
+                return false;
+            }
+            page_table.get_page_slice_mut(page)[offset..offset + len]
+                .copy_from_slice(&data[pos..pos + len]);
+            let entry = page_table.get_entry(page).expect("failed to get entry");
+            let execute = entry.execute();
+            if area.attr.readonly {
+                entry.set_writable(false);
+                entry.update();
+            }
+            page_table.flush_cache_copy_user(page, page + PAGE_SIZE, execute);
+            pos += len;
+        }
+        true
+    }
+
     /// Execute function `f` with the associated page table
     pub unsafe fn with(&self, f: impl FnOnce()) {
         self.page_table.with(f);
@@ -358,6 +1007,7 @@ impl<T: PageTableExt> MemorySet<T> {
             area.unmap(page_table);
         }
         areas.clear();
+        self.locked.clear();
     }
 
     /// Get physical address of the page of given virtual `addr`
@@ -379,19 +1029,38 @@ impl<T: PageTableExt> MemorySet<T> {
     pub fn handle_page_fault_ext(&mut self, addr: VirtAddr, access: handler::AccessType) -> bool {
         let area = self.areas.iter().find(|area| area.contains(addr));
         match area {
-            Some(area) => area
-                .handler
-                .handle_page_fault_ext(&mut self.page_table, addr, access),
-            None => false,
+            Some(area)
+                if area.attr.user
+                    && !Self::write_denied(&mut self.page_table, area, addr, access.write) =>
+            {
+                area.handler
+                    .handle_page_fault_ext(&mut self.page_table, addr, access)
+            }
+            _ => false,
         }
     }
     pub fn handle_page_fault(&mut self, addr: VirtAddr) -> bool {
         let area = self.areas.iter().find(|area| area.contains(addr));
         match area {
-            Some(area) => area.handler.handle_page_fault(&mut self.page_table, addr),
-            None => false,
+            Some(area)
+                if area.attr.user
+                    && !Self::write_denied(&mut self.page_table, area, addr, true) =>
+            {
+                area.handler.handle_page_fault(&mut self.page_table, addr)
+            }
+            _ => false,
         }
     }
+    /// Faults in areas the user can not access, like PROT_NONE, are never handled.
+    /// A page fault on a present page in a read-only area may be a write,
+    /// which must fail here since handlers resolve writes to copy-on-write pages
+    fn write_denied(page_table: &mut T, area: &MemoryArea, addr: VirtAddr, write: bool) -> bool {
+        write
+            && area.attr.readonly
+            && page_table
+                .get_entry(addr)
+                .map_or(false, |entry| entry.present())
+    }
 
     pub fn clone(&mut self) -> Self {
         let mut new_page_table = T::new();
@@ -410,13 +1079,44 @@ impl<T: PageTableExt> MemorySet<T> {
                 );
             }
         }
+        // pages shared copy-on-write are read-only now
+        if let (Some(first), Some(last)) = (areas.first(), areas.last()) {
+            page_table.flush_tlb_others(first.start_addr, last.end_addr);
+        }
         MemorySet {
             areas: areas.clone(),
             page_table: new_page_table,
+            swap_clock: 0,
+            // memory locks are not inherited
+            locked: BTreeSet::new(),
+            lock_future: false,
         }
     }
 }
 
+/// Move the entry of `addr` to `new_addr`, keeping its frame and flags
+fn move_entry(pt: &mut dyn PageTable, addr: VirtAddr, new_addr: VirtAddr) {
+    let entry = pt.get_entry(addr).expect("failed to get entry");
+    let target = entry.target();
+    let present = entry.present();
+    let writable = entry.writable();
+    let user = entry.user();
+    let execute = entry.execute();
+    let mmio = entry.mmio();
+    let swapped = entry.swapped();
+    // PageTable::unmap requires page to be present
+    entry.set_present(true);
+    pt.unmap(addr);
+    let entry = pt.map(new_addr, target);
+    entry.set_writable(writable);
+    entry.set_user(user);
+    entry.set_execute(execute);
+    entry.set_mmio(mmio);
+    entry.set_swapped(swapped);
+    entry.set_present(present);
+    entry.update();
+}
+
 impl<T: PageTableExt> Drop for MemorySet<T> {
     fn drop(&mut self) {
         self.clear();
@@ -428,3 +1128,322 @@ impl<T: PageTableExt> Debug for MemorySet<T> {
         f.debug_list().entries(self.areas.iter()).finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::handler::*;
+    use super::*;
+
+    fn areas(ms: &MemorySet<MockPageTable>) -> Vec<(VirtAddr, VirtAddr)> {
+        ms.iter()
+            .map(|area| (area.start_addr(), area.end_addr()))
+            .collect()
+    }
+
+    fn entry(ms: &mut MemorySet<MockPageTable>, addr: VirtAddr) -> &mut dyn Entry {
+        ms.get_page_table_mut().get_entry(addr).unwrap()
+    }
+
+    #[test]
+    fn fork() {
+        let allocator = MockFrameAllocator::new();
+        let free = allocator.free_count();
+        let mut ms = MemorySet::<MockPageTable>::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(
+            0x1000,
+            0x3000,
+            attr,
+            ByFrame::new(allocator.clone()),
+            "data",
+        );
+        ms.get_page_table_mut().write(0x1000, 1);
+        assert_eq!(allocator.free_count(), free - 2);
+
+        let mut child = ms.clone();
+        assert_eq!(areas(&child), areas(&ms));
+        assert_eq!(allocator.free_count(), free - 2);
+        for &addr in [0x1000, 0x2000].iter() {
+            assert_eq!(
+                entry(&mut child, addr).target(),
+                entry(&mut ms, addr).target()
+            );
+            assert!(!entry(&mut child, addr).writable());
+            assert!(!entry(&mut ms, addr).writable());
+        }
+
+        // the child copies the page it writes
+        assert!(child.handle_page_fault_ext(0x1000, AccessType::write(true)));
+        assert_eq!(allocator.free_count(), free - 3);
+        assert_ne!(
+            entry(&mut child, 0x1000).target(),
+            entry(&mut ms, 0x1000).target()
+        );
+        child.get_page_table_mut().write(0x1000, 2);
+        assert_eq!(ms.get_page_table_mut().read(0x1000), 1);
+        // a read-only area is not copied
+        child.protect(0x2000, 0x3000, attr.readonly());
+        assert!(!child.handle_page_fault_ext(0x2000, AccessType::write(true)));
+
+        // the frames left to the parent are not freed with the child
+        drop(child);
+        assert_eq!(allocator.free_count(), free - 2);
+        assert!(ms.handle_page_fault_ext(0x2000, AccessType::write(true)));
+        assert_eq!(allocator.free_count(), free - 2);
+        assert!(entry(&mut ms, 0x2000).writable());
+
+        drop(ms);
+        assert_eq!(allocator.free_count(), free);
+    }
+
+    #[test]
+    fn protect() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MemorySet::<MockPageTable>::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(
+            0x1000,
+            0x5000,
+            attr,
+            ByFrame::new(allocator.clone()),
+            "data",
+        );
+
+        assert!(ms.protect(0x2000, 0x3000, attr.readonly()));
+        assert_eq!(
+            areas(&ms),
+            [(0x1000, 0x2000), (0x2000, 0x3000), (0x3000, 0x5000)]
+        );
+        assert!(ms.iter().nth(1).unwrap().attr().is_readonly());
+        assert!(entry(&mut ms, 0x1000).writable());
+        assert!(!entry(&mut ms, 0x2000).writable());
+        assert!(entry(&mut ms, 0x3000).writable());
+
+        // nothing is changed if the range is not fully mapped
+        assert!(!ms.protect(0x4000, 0x6000, attr.readonly()));
+        assert!(entry(&mut ms, 0x4000).writable());
+        assert_eq!(areas(&ms).len(), 3);
+
+        assert!(ms.protect(0x1000, 0x5000, attr));
+        assert!(ms.iter().all(|area| area.attr() == attr));
+        assert!(entry(&mut ms, 0x2000).writable());
+    }
+
+    #[test]
+    fn pop_with_split() {
+        let allocator = MockFrameAllocator::new();
+        let free = allocator.free_count();
+        let mut ms = MemorySet::<MockPageTable>::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(
+            0x1000,
+            0x6000,
+            attr,
+            ByFrame::new(allocator.clone()),
+            "data",
+        );
+        ms.push(
+            0x8000,
+            0x9000,
+            attr,
+            ByFrame::new(allocator.clone()),
+            "data",
+        );
+
+        // superset
+        ms.pop_with_split(0x2000, 0x3000);
+        assert_eq!(
+            areas(&ms),
+            [(0x1000, 0x2000), (0x3000, 0x6000), (0x8000, 0x9000)]
+        );
+        assert!(!entry(&mut ms, 0x2000).present());
+        assert_eq!(allocator.free_count(), free - 5);
+        // prefix, and postfix
+        ms.pop_with_split(0x5000, 0x9000);
+        assert_eq!(areas(&ms), [(0x1000, 0x2000), (0x3000, 0x5000)]);
+        ms.pop_with_split(0x3000, 0x4000);
+        assert_eq!(areas(&ms), [(0x1000, 0x2000), (0x4000, 0x5000)]);
+        // subset
+        ms.pop_with_split(0x0, 0x3000);
+        assert_eq!(areas(&ms), [(0x4000, 0x5000)]);
+        assert_eq!(allocator.free_count(), free - 1);
+    }
+
+    #[test]
+    fn relocate() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MemorySet::<MockPageTable>::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(
+            0x1000,
+            0x4000,
+            attr,
+            ByFrame::new(allocator.clone()),
+            "data",
+        );
+        ms.push(
+            0x6000,
+            0x7000,
+            attr,
+            ByFrame::new(allocator.clone()),
+            "data",
+        );
+        ms.get_page_table_mut().write(0x2000, 7);
+        assert!(ms.lock(0x2000, 0x3000, false));
+        let frame = entry(&mut ms, 0x2000).target();
+
+        // the range must be free, and inside one area
+        assert!(!ms.relocate(0x2000, 0x3000, 0x6000));
+        assert!(!ms.relocate(0x3000, 0x7000, 0x8000));
+
+        assert!(ms.relocate(0x2000, 0x3000, 0x8000));
+        assert_eq!(
+            areas(&ms),
+            [
+                (0x1000, 0x2000),
+                (0x3000, 0x4000),
+                (0x6000, 0x7000),
+                (0x8000, 0x9000)
+            ]
+        );
+        assert!(!entry(&mut ms, 0x2000).present());
+        assert_eq!(entry(&mut ms, 0x8000).target(), frame);
+        assert!(entry(&mut ms, 0x8000).user());
+        assert_eq!(ms.get_page_table_mut().read(0x8000), 7);
+        assert_eq!(ms.locked_pages(0x2000, 0x3000), 0);
+        assert_eq!(ms.locked_pages(0x8000, 0x9000), 1);
+    }
+
+    #[test]
+    fn relocate_shared() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MemorySet::<MockPageTable>::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x1000, 0x3000, attr, Shared::new(allocator.clone()), "shm");
+        assert!(ms.handle_page_fault_ext(0x1000, AccessType::write(true)));
+        let mut child = ms.clone();
+        assert!(child.handle_page_fault_ext(0x2000, AccessType::write(true)));
+
+        assert!(ms.can_relocate(0x1000, 0x3000, 0x8000));
+        let frame = entry(&mut ms, 0x1000).target();
+        assert!(ms.relocate(0x1000, 0x3000, 0x8000));
+        assert_eq!(areas(&ms), [(0x8000, 0xa000)]);
+        assert_eq!(entry(&mut ms, 0x8000).target(), frame);
+        // pages faulted in after the move are still shared
+        assert!(ms.handle_page_fault_ext(0x9000, AccessType::read(true)));
+        assert_eq!(
+            entry(&mut ms, 0x9000).target(),
+            entry(&mut child, 0x2000).target()
+        );
+    }
+
+    #[test]
+    fn shared_out_of_memory() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MemorySet::<MockPageTable>::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(0x1000, 0x2000, attr, Shared::new(allocator.clone()), "shm");
+        while allocator.alloc().is_some() {}
+        assert!(!ms.handle_page_fault_ext(0x1000, AccessType::read(true)));
+        assert!(!entry(&mut ms, 0x1000).present());
+    }
+
+    #[test]
+    fn lock() {
+        let allocator = MockFrameAllocator::new();
+        let mut ms = MemorySet::<MockPageTable>::new();
+        let attr = MemoryAttr::default().user();
+        ms.push(
+            0x1000,
+            0x3000,
+            attr,
+            ByFrame::new(allocator.clone()),
+            "data",
+        );
+        ms.push(
+            0x4000,
+            0x5000,
+            attr,
+            ByFrame::new(allocator.clone()),
+            "data",
+        );
+
+        // a range with a hole is not locked at all
+        assert!(!ms.lock(0x1000, 0x5000, false));
+        assert_eq!(ms.total_locked_pages(), 0);
+
+        assert!(ms.lock(0x1000, 0x3000, false));
+        assert_eq!(ms.locked_pages(0x1000, 0x3000), 2);
+        ms.unlock(0x1000, 0x2000);
+        assert_eq!(ms.locked_pages(0x1000, 0x3000), 1);
+        // unmapped pages are unlocked
+        ms.pop_with_split(0x2000, 0x3000);
+        assert_eq!(ms.total_locked_pages(), 0);
+
+        ms.lock_all(false);
+        ms.set_lock_future(true);
+        ms.push(
+            0x6000,
+            0x8000,
+            attr,
+            ByFrame::new(allocator.clone()),
+            "data",
+        );
+        assert_eq!(ms.total_locked_pages(), 4);
+        // locks are not inherited
+        assert_eq!(ms.clone().total_locked_pages(), 0);
+
+        ms.unlock_all();
+        assert_eq!(ms.total_locked_pages(), 0);
+        assert!(!ms.lock_future());
+    }
+
+    #[test]
+    fn swap_out() {
+        let allocator = MockFrameAllocator::new();
+        let swapper = MockSwapper::default();
+        let free = allocator.free_count();
+        let mut ms = MemorySet::<MockPageTable>::new();
+        let attr = MemoryAttr::default().user();
+        let handler = Delay::with_swapper(allocator.clone(), swapper.clone());
+        ms.push(0x1000, 0x4000, attr, handler, "data");
+        for &addr in [0x1000, 0x2000, 0x3000].iter() {
+            assert!(ms.handle_page_fault_ext(addr, AccessType::write(true)));
+            ms.get_page_table_mut().write(addr, (addr >> 12) as u8);
+        }
+        assert!(ms.lock(0x2000, 0x3000, true));
+        assert_eq!(allocator.free_count(), free - 3);
+
+        // pages accessed get a second chance, and locked pages are kept
+        assert!(ms.swap_out());
+        assert!(!entry(&mut ms, 0x1000).present());
+        assert!(entry(&mut ms, 0x1000).swapped());
+        assert!(ms.swap_out());
+        assert!(entry(&mut ms, 0x2000).present());
+        assert!(entry(&mut ms, 0x3000).swapped());
+        assert!(!ms.swap_out());
+        assert_eq!(swapper.used_count(), 2);
+        assert_eq!(allocator.free_count(), free - 1);
+
+        // swapped in on fault
+        assert!(ms.handle_page_fault_ext(0x1000, AccessType::read(true)));
+        assert!(entry(&mut ms, 0x1000).present());
+        assert_eq!(ms.get_page_table_mut().read(0x1000), 1);
+        assert_eq!(swapper.used_count(), 1);
+
+        // frames shared with a child stay in memory
+        let child = ms.clone();
+        assert!(!ms.swap_out());
+        drop(child);
+
+        // all read back, e.g. for swapoff
+        assert!(ms.swap_in_all());
+        assert_eq!(swapper.used_count(), 0);
+        assert!(entry(&mut ms, 0x3000).present());
+        assert_eq!(ms.get_page_table_mut().read(0x3000), 3);
+
+        drop(ms);
+        assert_eq!(swapper.used_count(), 0);
+        assert_eq!(allocator.free_count(), free);
+    }
+}
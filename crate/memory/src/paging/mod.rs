@@ -19,6 +19,17 @@ pub trait PageTable {
     /// Unmap a page of virual address `addr`
     fn unmap(&mut self, addr: VirtAddr);
 
+    /// Map a huge page of virtual address `addr` to the frames starting at `target`,
+    /// both aligned to `HUGE_PAGE_SIZE`.
+    /// Return None if the huge page can not be mapped, e.g. not supported.
+    fn map_huge(&mut self, _addr: VirtAddr, _target: PhysAddr) -> Option<&mut dyn Entry> {
+        None
+    }
+
+    /// Unmap a huge page of virtual address `addr` mapped by `map_huge`.
+    /// Nothing is mapped by the default `map_huge`, so there is nothing to do.
+    fn unmap_huge(&mut self, _addr: VirtAddr) {}
+
     /// Get the page table entry of a page of virual address `addr`
     /// If its page do not exist, return `None`
     fn get_entry(&mut self, addr: VirtAddr) -> Option<&mut dyn Entry>;
@@ -29,6 +40,11 @@ pub trait PageTable {
     /// When copied user data (in page fault handler)，maybe need to flush I/D cache.
     fn flush_cache_copy_user(&mut self, start: VirtAddr, end: VirtAddr, execute: bool);
 
+    /// Invalidate the TLB entries of `[start, end)` on the other CPUs using this page table,
+    /// after its entries are changed, as `Entry::update` only does it on this CPU.
+    /// Frames unmapped must not be freed before it.
+    fn flush_tlb_others(&self, _start: VirtAddr, _end: VirtAddr) {}
+
     /// Read data from virtual address `addr`
     /// Used for testing with mock
     fn read(&mut self, _addr: VirtAddr) -> u8 {
@@ -85,6 +101,11 @@ pub trait Entry {
     fn set_execute(&mut self, value: bool);
     fn mmio(&self) -> u8;
     fn set_mmio(&mut self, value: u8);
+
+    /// Whether the entry maps a huge page
+    fn huge(&self) -> bool {
+        false
+    }
 }
 
 /// Extra methods of `PageTable` for non-trait-object usage
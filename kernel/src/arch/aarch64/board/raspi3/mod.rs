@@ -5,12 +5,19 @@ use bcm2837::{addr::bus_to_phys, atags::Atags};
 pub mod mailbox;
 pub mod timer;
 
+use crate::consts::SMP_CORES;
 use crate::drivers::gpu::fb::{self, ColorDepth, ColorFormat, FramebufferInfo, FramebufferResult};
 use crate::drivers::mmc::bcm2835_sdhci;
+use crate::memory::{kernel_offset, phys_to_virt};
+use aarch64::{asm, cache::*};
+use core::{cmp, mem};
 
 pub const BOARD_NAME: &'static str = "Raspberry Pi 3";
 pub const PERIPHERALS_START: usize = bcm2837::addr::PERIPHERALS_START;
 pub const PERIPHERALS_END: usize = bcm2837::addr::PERIPHERALS_END;
+pub const MEMORY_START: usize = 0;
+/// The memory shared with the GPU up to the peripherals is mapped as well
+pub const MEMORY_END: usize = PERIPHERALS_START;
 pub const CPU_NUM: usize = 4;
 
 /// BCM2837 spin table (ref: linux/arch/arm/boot/dts/bcm2837.dtsi)
@@ -35,6 +42,34 @@ pub fn init() {
     crate::drivers::console::init();
 }
 
+pub fn init_other() {
+    // Do nothing
+}
+
+/// Write `slave_startup` address to the spin table to start other CPUs.
+pub unsafe fn start_others() {
+    extern "C" {
+        fn others_start();
+    }
+    for i in 0..cmp::min(CPU_NUM, *SMP_CORES) {
+        if i == 0 {
+            continue;
+        }
+        let release_addr = phys_to_virt(CPU_SPIN_TABLE[i]) as *mut usize;
+        let entry_addr = kernel_offset(others_start as usize);
+        *release_addr = entry_addr;
+        DCache::<CleanAndInvalidate, PoC>::flush_area(
+            release_addr as usize,
+            mem::size_of::<usize>(),
+            SY,
+        );
+        asm::sev();
+    }
+}
+
+/// Idle CPUs are woken up by the periodic tick, without IPIs
+pub fn send_ipi(_cpu_id: usize) {}
+
 /// Returns the (start address, end address) of the physical memory on this
 /// system if it can be determined. If it cannot, `None` is returned.
 ///
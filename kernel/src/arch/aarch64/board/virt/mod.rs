@@ -0,0 +1,100 @@
+//! QEMU virt machine
+//!
+//! The devices are found in the device tree QEMU places at the start of RAM,
+//! as the kernel is loaded by the generic loader rather than `-kernel`.
+
+pub mod timer;
+
+use crate::consts::SMP_CORES;
+use crate::drivers::{bus::virtio_mmio, irq::gic, serial::pl011};
+use crate::memory::{kernel_offset, phys_to_virt};
+use core::cmp;
+use device_tree::util::SliceRead;
+use device_tree::DeviceTree;
+
+pub const BOARD_NAME: &'static str = "QEMU virt";
+/// The GIC, the UART and the virtio-mmio devices
+pub const PERIPHERALS_START: usize = 0x0800_0000;
+pub const PERIPHERALS_END: usize = 0x0a00_4000;
+pub const MEMORY_START: usize = 0x4000_0000;
+/// RAM ends no later than this, up to where the boot page table maps
+pub const MEMORY_END: usize = 0x1_0000_0000;
+pub const CPU_NUM: usize = 4;
+
+const DTB_ADDR: usize = MEMORY_START;
+
+/// The software generated interrupt to wake up other CPUs
+const IPI_SGI: usize = 0;
+
+const PSCI_CPU_ON: usize = 0xc400_0003;
+
+/// Probe the devices, the serial port among them, before other initializations.
+pub fn early_init() {
+    pl011::driver_init();
+    gic::driver_init();
+    virtio_mmio::driver_init();
+    crate::drivers::device_tree::init(phys_to_virt(DTB_ADDR));
+}
+
+pub fn early_final() {
+    // Do nothing
+}
+
+/// Initialize the timer and the console of the first CPU
+pub fn init() {
+    timer::init();
+    crate::drivers::console::init();
+}
+
+/// Initialize the interrupts of other CPUs
+pub fn init_other() {
+    gic::init_cpu();
+    timer::init();
+}
+
+/// Returns the (start address, end address) of the physical memory on this
+/// system if it can be determined. If it cannot, `None` is returned.
+///
+/// The device tree is parsed on the heap, which does not need the frames.
+pub fn probe_memory() -> Option<(usize, usize)> {
+    let header = unsafe { &*(phys_to_virt(DTB_ADDR) as *const [u32; 2]) };
+    let size = u32::from_be(header[1]) as usize;
+    let data = unsafe { core::slice::from_raw_parts(phys_to_virt(DTB_ADDR) as *const u8, size) };
+    let dt = DeviceTree::load(data).ok()?;
+    let memory = dt
+        .root
+        .children
+        .iter()
+        .find(|node| node.name.starts_with("memory"))?;
+    let reg = memory.prop_raw("reg")?.as_slice();
+    let start = reg.read_be_u64(0).ok()? as usize;
+    let size = reg.read_be_u64(8).ok()? as usize;
+    Some((start, cmp::min(start + size, MEMORY_END)))
+}
+
+/// Start other CPUs at `others_startup` by PSCI through the hypervisor call.
+pub unsafe fn start_others() {
+    extern "C" {
+        fn others_startup();
+    }
+    let entry = kernel_offset(others_startup as usize);
+    for i in 1..cmp::min(CPU_NUM, *SMP_CORES) {
+        // the MPIDR of CPU i
+        psci_call(PSCI_CPU_ON, i, entry, 0);
+    }
+}
+
+unsafe fn psci_call(func: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
+    let ret: usize;
+    llvm_asm!("hvc #0"
+        : "={x0}"(ret)
+        : "{x0}"(func), "{x1}"(arg0), "{x2}"(arg1), "{x3}"(arg2)
+        : "memory"
+        : "volatile");
+    ret
+}
+
+/// Wake up CPU `cpu_id` by a software generated interrupt
+pub fn send_ipi(cpu_id: usize) {
+    gic::send_sgi(cpu_id, IPI_SGI);
+}
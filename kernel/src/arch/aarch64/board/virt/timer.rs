@@ -0,0 +1,30 @@
+//! The EL1 physical timer of the ARM generic timer, at PPI 14 (interrupt 30) of each CPU
+
+use super::super::timer::counter_frequency;
+use crate::consts::USEC_PER_TICK;
+use log::*;
+
+const CNTP_CTL_ENABLE: u64 = 1 << 0;
+const CNTP_CTL_IMASK: u64 = 1 << 1;
+const CNTP_CTL_ISTATUS: u64 = 1 << 2;
+
+/// Initialization timer of this CPU.
+pub fn init() {
+    set_next();
+    unsafe { llvm_asm!("msr cntp_ctl_el0, $0" :: "r"(CNTP_CTL_ENABLE) :: "volatile") };
+    info!("timer: init end");
+}
+
+/// Set next timer interrupt to a tick from now.
+pub fn set_next() {
+    let interval = counter_frequency() * USEC_PER_TICK as u64 / 1_000_000;
+    unsafe { llvm_asm!("msr cntp_tval_el0, $0" :: "r"(interval) :: "volatile") };
+}
+
+/// Is interrupt pending
+pub fn is_pending() -> bool {
+    let ctl: u64;
+    unsafe { llvm_asm!("mrs $0, cntp_ctl_el0" : "=r"(ctl) ::: "volatile") };
+    ctl & (CNTP_CTL_ENABLE | CNTP_CTL_IMASK | CNTP_CTL_ISTATUS)
+        == CNTP_CTL_ENABLE | CNTP_CTL_ISTATUS
+}
@@ -1,4 +1,3 @@
-use super::board::{PERIPHERALS_END, PERIPHERALS_START};
 use crate::memory::phys_to_virt;
 use aarch64::paging::{memory_attribute::*, PageTableAttribute as Attr, PageTableFlags as EF};
 use aarch64::paging::{Page, PageTable, PhysFrame, Size1GiB, Size2MiB, Size4KiB};
@@ -7,6 +6,7 @@ use aarch64::{barrier, cache, regs::*, translation};
 
 global_asm!(include_str!("entry.S"));
 
+#[cfg(feature = "board_raspi3")]
 #[link_section = ".text.boot"]
 fn map_2mib(p2: &mut PageTable, start: usize, end: usize, flag: EF, attr: Attr) {
     let aligned_start = align_down(start as u64, ALIGN_2MIB);
@@ -25,7 +25,6 @@ extern "C" fn create_init_paging() {
     let p3 = unsafe { &mut *(page_table_lvl3 as *mut PageTable) };
     let p2 = unsafe { &mut *(page_table_lvl2 as *mut PageTable) };
     let frame_lvl3 = PhysFrame::<Size4KiB>::of_addr(page_table_lvl3 as u64);
-    let frame_lvl2 = PhysFrame::<Size4KiB>::of_addr(page_table_lvl2 as u64);
     p4.zero();
     p3.zero();
     p2.zero();
@@ -36,6 +35,15 @@ extern "C" fn create_init_paging() {
     // 0x8000_0000_0000 ~ 0x8080_0000_0000
     p4[256].set_frame(frame_lvl3, EF::default_table(), Attr::new(0, 0, 0));
 
+    map_board(p3, p2, block_flags);
+}
+
+#[cfg(feature = "board_raspi3")]
+#[link_section = ".text.boot"]
+fn map_board(p3: &mut PageTable, p2: &mut PageTable, block_flags: EF) {
+    use super::board::{PERIPHERALS_END, PERIPHERALS_START};
+    let frame_lvl2 = PhysFrame::<Size4KiB>::of_addr(page_table_lvl2 as u64);
+
     // 0x0000_0000 ~ 0x4000_0000
     p3[0].set_frame(frame_lvl2, EF::default_table(), Attr::new(0, 0, 0));
     // 0x4000_0000 ~ 0x8000_0000
@@ -63,6 +71,27 @@ extern "C" fn create_init_paging() {
     );
 }
 
+#[cfg(feature = "board_virt")]
+#[link_section = ".text.boot"]
+fn map_board(p3: &mut PageTable, _p2: &mut PageTable, block_flags: EF) {
+    use super::board::{MEMORY_END, MEMORY_START};
+
+    // device memory (0x0000_0000 ~ 0x4000_0000)
+    p3[0].set_block::<Size1GiB>(
+        PhysAddr::new(0),
+        block_flags | EF::PXN,
+        MairDevice::attr_value(),
+    );
+    // normal memory (0x4000_0000 ~ 0x1_0000_0000)
+    for addr in (MEMORY_START..MEMORY_END).step_by(ALIGN_1GIB as usize) {
+        p3[addr / ALIGN_1GIB as usize].set_block::<Size1GiB>(
+            PhysAddr::new(addr as u64),
+            block_flags,
+            MairNormal::attr_value(),
+        );
+    }
+}
+
 #[no_mangle]
 #[link_section = ".text.boot"]
 extern "C" fn enable_mmu() {
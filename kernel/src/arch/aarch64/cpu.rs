@@ -1,37 +1,18 @@
-use crate::consts::SMP_CORES;
-use crate::memory::{kernel_offset, phys_to_virt};
-use aarch64::{asm, cache::*};
-use core::{cmp, mem};
+use aarch64::asm;
 
-pub use super::board::{CPU_NUM, CPU_SPIN_TABLE};
+pub use super::board::{start_others, CPU_NUM};
 
 pub fn halt() {
     asm::wfi();
 }
 
-pub fn id() -> usize {
-    asm::cpuid()
+/// Wake up CPU `cpu_id` if it is idle
+pub fn send_ipi(cpu_id: usize) {
+    super::board::send_ipi(cpu_id);
 }
 
-/// Write `slave_startup` address to the spin table to start other CPUs.
-pub unsafe fn start_others() {
-    extern "C" {
-        fn others_start();
-    }
-    for i in 0..cmp::min(CPU_NUM, *SMP_CORES) {
-        if i == 0 {
-            continue;
-        }
-        let release_addr = phys_to_virt(CPU_SPIN_TABLE[i]) as *mut usize;
-        let entry_addr = kernel_offset(others_start as usize);
-        *release_addr = entry_addr;
-        DCache::<CleanAndInvalidate, PoC>::flush_area(
-            release_addr as usize,
-            mem::size_of::<usize>(),
-            SY,
-        );
-        asm::sev();
-    }
+pub fn id() -> usize {
+    asm::cpuid()
 }
 
 pub unsafe fn exit_in_qemu(_error_code: u8) -> ! {
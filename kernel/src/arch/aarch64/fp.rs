@@ -11,4 +11,6 @@ impl FpState {
     pub fn save(&mut self) {}
 
     pub fn restore(&self) {}
+
+    pub fn load(&mut self) {}
 }
@@ -1,5 +1,7 @@
 use super::syndrome::{Fault, Syndrome};
+use crate::signal::{Signal, BUS_ADRALN, BUS_ADRERR, ILL_ILLOPC};
 use aarch64::regs::*;
+use trapframe::UserContext;
 
 pub fn is_page_fault(trap: usize) -> bool {
     // 2: from lower el, sync error
@@ -30,7 +32,32 @@ pub const Timer: usize = 0x10002;
 pub const Syscall: usize = 0x00002;
 
 pub fn is_syscall(trap: usize) -> bool {
-    trap == Syscall
+    if trap != Syscall {
+        return false;
+    }
+    // other sync errors from lower el are faults
+    let esr = ESR_EL1.get() as u32;
+    match Syndrome::from(esr) {
+        Syndrome::Svc(_) => true,
+        _ => false,
+    }
+}
+
+pub fn is_breakpoint(trap: usize) -> bool {
+    if trap != Syscall {
+        return false;
+    }
+    // determine by esr as well
+    let esr = ESR_EL1.get() as u32;
+    match Syndrome::from(esr) {
+        Syndrome::Brk(_) => true,
+        _ => false,
+    }
+}
+
+/// The FPU state is not switched lazily
+pub fn is_fp_unavailable(_trap: usize) -> bool {
+    false
 }
 
 pub fn is_intr(trap: usize) -> bool {
@@ -44,3 +71,28 @@ pub fn is_timer_intr(trap: usize) -> bool {
 pub fn is_reserved_inst(trap: usize) -> bool {
     false
 }
+
+/// The signal for a fault of user code other than a page fault,
+/// with its si_code and si_addr
+pub fn fault_signal(context: &UserContext, trap: usize) -> Option<(Signal, i32, usize)> {
+    if trap != Syscall {
+        return None;
+    }
+    let esr = ESR_EL1.get() as u32;
+    let far = FAR_EL1.get() as usize;
+    Some(match Syndrome::from(esr) {
+        Syndrome::Unknown | Syndrome::IllegalExecutionState | Syndrome::MsrMrsSystem => {
+            (Signal::SIGILL, ILL_ILLOPC, context.elr)
+        }
+        Syndrome::PCAlignmentFault => (Signal::SIGBUS, BUS_ADRALN, context.elr),
+        Syndrome::SpAlignmentFault => (Signal::SIGBUS, BUS_ADRALN, context.sp),
+        Syndrome::DataAbort {
+            kind: Fault::Alignment,
+            level: _,
+        } => (Signal::SIGBUS, BUS_ADRALN, far),
+        Syndrome::DataAbort { .. } | Syndrome::InstructionAbort { .. } => {
+            (Signal::SIGBUS, BUS_ADRERR, far)
+        }
+        _ => return None,
+    })
+}
@@ -61,13 +61,16 @@ pub fn enable_irq(irq: usize) {
     // TODO
 }
 
+/// Sleep until an interrupt comes and handle it.
+/// `wfi` wakes up on a pending interrupt even if it is masked,
+/// so one coming before it is not missed.
 pub fn wait_for_interrupt() {
     use aarch64::regs::*;
     let daif = DAIF.get();
+    aarch64::asm::wfi();
     unsafe {
         llvm_asm!("msr daifclr, #2");
     }
-    aarch64::asm::wfe();
     DAIF.set(daif);
 }
 
@@ -7,6 +7,7 @@ use crate::sync::SpinNoIrqLock as Mutex;
 use aarch64::paging::frame::PhysFrame as Frame;
 use aarch64::regs::*;
 use aarch64::translation::{local_invalidate_tlb_all, ttbr_el1_write};
+use core::ops::Range;
 use log::*;
 use rcore_memory::PAGE_SIZE;
 
@@ -14,8 +15,9 @@ static KERNEL_MEMORY_SET: Mutex<Option<MemorySet>> = Mutex::new(None);
 
 /// Memory initialization.
 pub fn init() {
-    init_frame_allocator();
+    // the memory may be probed on the heap
     init_heap();
+    init_frame_allocator();
     map_kernel();
     info!("memory: init end");
 }
@@ -27,7 +29,6 @@ pub fn init_other() {
 }
 
 fn init_frame_allocator() {
-    use bitmap_allocator::BitAlloc;
     use core::ops::Range;
 
     let end = super::board::probe_memory()
@@ -94,7 +95,11 @@ fn map_kernel() {
     );
 
     let page_table = ms.get_page_table_mut();
-    page_table.map_physical_memory(0, super::board::PERIPHERALS_START);
+    page_table.map_physical_memory(super::board::MEMORY_START, super::board::MEMORY_END);
+    page_table.map_physical_mmio(
+        super::board::PERIPHERALS_START,
+        super::board::PERIPHERALS_END,
+    );
     unsafe { page_table.activate_as_kernel() };
     *KERNEL_MEMORY_SET.lock() = Some(ms);
 
@@ -138,6 +143,25 @@ pub fn set_page_table(vmtoken: usize) {
     local_invalidate_tlb_all();
 }
 
+/// Invalidate the TLB entries of `range` on other CPUs.
+/// The invalidation is broadcast to the inner shareable domain, so it is sent to all
+/// the CPUs instead of those in `mask`, without interrupting them.
+pub fn flush_tlb_others(_mask: usize, range: Range<usize>) {
+    const MAX_PAGES: usize = 32;
+    unsafe {
+        llvm_asm!("dsb ishst" :::: "volatile");
+        if (range.end - range.start) / PAGE_SIZE > MAX_PAGES {
+            llvm_asm!("tlbi vmalle1is" :::: "volatile");
+        } else {
+            for addr in range.step_by(PAGE_SIZE) {
+                // of any ASID
+                llvm_asm!("tlbi vaae1is, $0" :: "r"(addr >> 12) :: "volatile");
+            }
+        }
+        llvm_asm!("dsb ish; isb" :::: "volatile");
+    }
+}
+
 pub fn get_page_fault_addr() -> usize {
     FAR_EL1.get() as usize
 }
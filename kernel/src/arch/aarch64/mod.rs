@@ -19,6 +19,10 @@ pub mod timer;
 #[path = "board/raspi3/mod.rs"]
 pub mod board;
 
+#[cfg(feature = "board_virt")]
+#[path = "board/virt/mod.rs"]
+pub mod board;
+
 static AP_CAN_INIT: AtomicBool = AtomicBool::new(false);
 
 /// The entry point of kernel
@@ -60,6 +64,6 @@ pub extern "C" fn others_start() -> ! {
         trapframe::init();
     }
     memory::init_other();
-    //timer::init();
+    board::init_other();
     crate::kmain();
 }
@@ -7,7 +7,7 @@ use aarch64::paging::{
     mapper::{MappedPageTable, Mapper},
     memory_attribute::*,
     page_table::{PageTable as Aarch64PageTable, PageTableEntry, PageTableFlags as EF},
-    FrameAllocator, FrameDeallocator, Page as PageAllSizes, Size2MiB, Size4KiB,
+    FrameAllocator, FrameDeallocator, Page as PageAllSizes, PageTableAttribute, Size2MiB, Size4KiB,
 };
 use aarch64::translation::{invalidate_tlb_vaddr, local_invalidate_tlb_all};
 use aarch64::translation::{ttbr_el1_read, ttbr_el1_write};
@@ -91,6 +91,10 @@ impl PageTable for PageTableImpl {
             }
         }
     }
+
+    fn flush_tlb_others(&self, start: usize, end: usize) {
+        crate::memory::shootdown_tlb(self.token(), start..end);
+    }
 }
 
 fn frame_to_page_table(frame: Frame) -> *mut Aarch64PageTable {
@@ -261,10 +265,18 @@ impl PageTableImpl {
     /// to virtual space [phys_to_virt(start), phys_to_virt(end))
     pub fn map_physical_memory(&mut self, start: usize, end: usize) {
         info!("mapping physical memory");
+        self.map_physical(start, end, MairNormal::attr_value());
+    }
+    /// Map the device memory [start, end) of physical addresses
+    /// to virtual space [phys_to_virt(start), phys_to_virt(end))
+    pub fn map_physical_mmio(&mut self, start: usize, end: usize) {
+        info!("mapping physical mmio");
+        self.map_physical(start, end, MairDevice::attr_value());
+    }
+    fn map_physical(&mut self, start: usize, end: usize, attr: PageTableAttribute) {
         let aligned_start = align_down(start as u64, ALIGN_2MIB);
         let aligned_end = align_up(end as u64, ALIGN_2MIB);
         let flags = EF::default_block() | EF::UXN | EF::PXN;
-        let attr = MairNormal::attr_value();
         for frame in Frame::<Size2MiB>::range_of(aligned_start, aligned_end) {
             let paddr = frame.start_address();
             let vaddr = phys_to_virt(paddr.as_u64() as usize);
@@ -118,10 +118,22 @@ impl MachineContext {
         tf.general.x30 = self.x30;
         tf.sp = self.sp;
         tf.elr = self.pc;
-        tf.spsr = self.pstate;
+        // only the condition flags, so that it stays in EL0
+        tf.spsr = (tf.spsr & !PSTATE_NZCV) | (self.pstate & PSTATE_NZCV);
     }
 }
 
+/// Condition flags in PSTATE
+const PSTATE_NZCV: usize = 0xf000_0000;
+
+/// Let the syscall `num` with `args`, which returned EINTR, run again on return to user
+pub fn restart_syscall(tf: &mut UserContext, num: usize, args: &[usize; 6]) {
+    // elr is after svc, and x0 holds the return value
+    tf.elr -= 4;
+    tf.general.x8 = num;
+    tf.general.x0 = args[0];
+}
+
 // TODO
 pub const RET_CODE: [u8; 7] = [0; 7];
 
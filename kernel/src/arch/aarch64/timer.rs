@@ -1,6 +1,38 @@
+use crate::consts::USEC_PER_TICK;
 use core::time::Duration;
 
+/// Frequency of the system counter in Hz, set by the firmware, or 0 if it is not
+pub fn counter_frequency() -> u64 {
+    let freq: u64;
+    unsafe { llvm_asm!("mrs $0, cntfrq_el0" : "=r"(freq) ::: "volatile") };
+    freq
+}
+
+/// Value of the system counter, in `counter_frequency`
+pub fn counter() -> u64 {
+    let count: u64;
+    unsafe { llvm_asm!("isb; mrs $0, cntpct_el0" : "=r"(count) ::: "volatile") };
+    count
+}
+
+/// Time since boot, by the system counter,
+/// or counted by timer interrupts if its frequency is unknown
 pub fn timer_now() -> Duration {
-    // TODO
-    Duration::from_nanos(0)
+    let freq = counter_frequency();
+    if freq == 0 {
+        let tick = unsafe { crate::trap::wall_tick() };
+        return Duration::from_micros((tick * USEC_PER_TICK) as u64);
+    }
+    Duration::from_nanos((counter() as u128 * 1_000_000_000 / freq as u128) as u64)
 }
+
+/// Resolution of `timer_now`
+pub fn timer_resolution() -> Duration {
+    match counter_frequency() {
+        0 => Duration::from_micros(USEC_PER_TICK as u64),
+        freq => Duration::from_nanos((1_000_000_000 / freq).max(1)),
+    }
+}
+
+/// The timer is periodic, so the events are checked at every tick
+pub fn set_next_event(_deadline: Option<Duration>) {}
@@ -21,6 +21,9 @@ pub unsafe fn start_others(hart_mask: usize) {
     }
 }
 
+/// Idle CPUs are woken up by the periodic tick, without IPIs
+pub fn send_ipi(_cpu_id: usize) {}
+
 pub fn halt() {
     unsafe {
         instructions::wait();
@@ -1,4 +1,8 @@
+use crate::signal::{
+    Signal, BUS_ADRALN, BUS_ADRERR, FPE_FLTINV, FPE_INTOVF, ILL_COPROC, ILL_ILLOPC,
+};
 use mips::registers::cp0;
+use trapframe::UserContext;
 
 pub fn is_page_fault(trap: usize) -> bool {
     use cp0::cause::Exception as E;
@@ -18,6 +22,16 @@ pub fn is_syscall(trap: usize) -> bool {
     }
 }
 
+pub fn is_breakpoint(trap: usize) -> bool {
+    // ExcCode of the cause register, 9 for Bp
+    (trap >> 2) & 0x1f == 9
+}
+
+/// The FPU state is not switched lazily
+pub fn is_fp_unavailable(_trap: usize) -> bool {
+    false
+}
+
 pub fn is_intr(trap: usize) -> bool {
     use cp0::cause::Exception as E;
     let cause = cp0::cause::Cause { bits: trap as u32 };
@@ -36,6 +50,22 @@ pub fn is_timer_intr(trap: usize) -> bool {
     }
 }
 
+/// The signal for a fault of user code other than a page fault,
+/// with its si_code and si_addr
+pub fn fault_signal(context: &UserContext, trap: usize) -> Option<(Signal, i32, usize)> {
+    let addr = crate::arch::memory::get_page_fault_addr();
+    // ExcCode of the cause register
+    Some(match (trap >> 2) & 0x1f {
+        4 | 5 => (Signal::SIGBUS, BUS_ADRALN, addr),
+        6 | 7 => (Signal::SIGBUS, BUS_ADRERR, addr),
+        10 => (Signal::SIGILL, ILL_ILLOPC, context.epc),
+        11 => (Signal::SIGILL, ILL_COPROC, context.epc),
+        12 => (Signal::SIGFPE, FPE_INTOVF, context.epc),
+        15 => (Signal::SIGFPE, FPE_FLTINV, context.epc),
+        _ => return None,
+    })
+}
+
 pub fn is_reserved_inst(trap: usize) -> bool {
     use cp0::cause::Exception as E;
     let cause = cp0::cause::Cause { bits: trap as u32 };
@@ -306,7 +306,11 @@ pub fn ack(_irq: usize) {
     // TODO
 }
 
+/// Sleep until an interrupt comes and handle it.
+/// Only interrupts which can be taken wake up `wait`, so they are enabled before it,
+/// while the periodic tick bounds the time an interrupt right before it is missed.
 pub fn wait_for_interrupt() {
     cp0::status::enable_interrupt();
+    mips::instructions::wait();
     cp0::status::disable_interrupt();
 }
@@ -23,7 +23,6 @@ pub fn init_other() {
 }
 
 fn init_frame_allocator() {
-    use bitmap_allocator::BitAlloc;
     use core::ops::Range;
 
     let mut ba = FRAME_ALLOCATOR.lock();
@@ -77,6 +76,9 @@ pub fn set_page_table(vmtoken: usize) {
     }
 }
 
+/// There are no other CPUs
+pub fn flush_tlb_others(_mask: usize, _range: core::ops::Range<usize>) {}
+
 pub fn get_page_fault_addr() -> usize {
     cp0::bad_vaddr::read_u32() as usize
 }
@@ -60,6 +60,10 @@ impl PageTable for PageTableImpl {
     }
 
     fn flush_cache_copy_user(&mut self, _start: usize, _end: usize, _execute: bool) {}
+
+    fn flush_tlb_others(&self, start: usize, end: usize) {
+        crate::memory::shootdown_tlb(self.token(), start..end);
+    }
 }
 
 extern "C" {
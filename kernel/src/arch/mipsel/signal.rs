@@ -15,6 +15,15 @@ impl MachineContext {
     pub fn fill_tf(&self, tf: &mut UserContext) {}
 }
 
+/// Let the syscall `num` with `args`, which returned EINTR, run again on return to user
+pub fn restart_syscall(tf: &mut UserContext, num: usize, args: &[usize; 6]) {
+    // epc is after syscall already, v0 holds the return value and a3 the error flag
+    tf.epc -= 4;
+    tf.general.v0 = num;
+    tf.general.a0 = args[0];
+    tf.general.a3 = args[3];
+}
+
 // TODO
 pub const RET_CODE: [u8; 7] = [0; 7];
 
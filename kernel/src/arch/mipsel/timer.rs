@@ -1,3 +1,4 @@
+use crate::consts::USEC_PER_TICK;
 use core::time::Duration;
 use log::*;
 use mips::registers::cp0;
@@ -19,7 +20,16 @@ pub fn set_next() {
     cp0::compare::write_u32(timebase);
 }
 
+/// Time since boot, counted by timer interrupts for now
 pub fn timer_now() -> Duration {
-    // TODO
-    Duration::from_nanos(0)
+    let tick = unsafe { crate::trap::wall_tick() };
+    Duration::from_micros((tick * USEC_PER_TICK) as u64)
 }
+
+/// Resolution of `timer_now`
+pub fn timer_resolution() -> Duration {
+    Duration::from_micros(USEC_PER_TICK as u64)
+}
+
+/// The timer is periodic, so the events are checked at every tick
+pub fn set_next_event(_deadline: Option<Duration>) {}
@@ -9,4 +9,6 @@ impl FpState {
     pub fn save(&mut self) {}
 
     pub fn restore(&self) {}
+
+    pub fn load(&mut self) {}
 }
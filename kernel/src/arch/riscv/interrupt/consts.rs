@@ -1,3 +1,14 @@
+use crate::signal::{Signal, BUS_ADRALN, ILL_ILLOPC, SEGV_ACCERR};
+use trapframe::UserContext;
+
+pub const InstructionMisaligned: usize = 0;
+pub const InstructionFault: usize = 1;
+pub const IllegalInstruction: usize = 2;
+pub const Breakpoint: usize = 3;
+pub const LoadMisaligned: usize = 4;
+pub const LoadFault: usize = 5;
+pub const StoreMisaligned: usize = 6;
+pub const StoreFault: usize = 7;
 pub const Syscall: usize = 8;
 pub const InstructionPageFault: usize = 12;
 pub const LoadPageFault: usize = 13;
@@ -27,6 +38,15 @@ pub fn is_syscall(trap: usize) -> bool {
     trap == Syscall
 }
 
+pub fn is_breakpoint(trap: usize) -> bool {
+    trap == Breakpoint
+}
+
+/// The FPU state is not switched lazily
+pub fn is_fp_unavailable(_trap: usize) -> bool {
+    false
+}
+
 pub fn is_intr(trap: usize) -> bool {
     IrqMin <= trap && trap <= IrqMax
 }
@@ -38,3 +58,17 @@ pub fn is_timer_intr(trap: usize) -> bool {
 pub fn is_reserved_inst(trap: usize) -> bool {
     false
 }
+
+/// The signal for a fault of user code other than a page fault,
+/// with its si_code and si_addr
+pub fn fault_signal(context: &UserContext, trap: usize) -> Option<(Signal, i32, usize)> {
+    let addr = crate::arch::memory::get_page_fault_addr();
+    Some(match trap {
+        IllegalInstruction => (Signal::SIGILL, ILL_ILLOPC, context.sepc),
+        InstructionMisaligned | LoadMisaligned | StoreMisaligned => {
+            (Signal::SIGBUS, BUS_ADRALN, addr)
+        }
+        InstructionFault | LoadFault | StoreFault => (Signal::SIGSEGV, SEGV_ACCERR, addr),
+        _ => return None,
+    })
+}
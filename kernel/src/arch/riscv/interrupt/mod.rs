@@ -80,7 +80,6 @@ fn ipi() {
 }
 
 pub fn timer() {
-    super::timer::set_next();
     crate::trap::timer();
 }
 
@@ -118,10 +117,11 @@ pub fn get_trap_num(_context: &UserContext) -> usize {
 
 pub fn wait_for_interrupt() {
     unsafe {
-        // enable interrupt and disable
+        // wfi wakes up on a pending interrupt even if it is disabled,
+        // so one coming before it is not missed, then it is handled
         let sie = riscv::register::sstatus::read().sie();
-        riscv::register::sstatus::set_sie();
         riscv::asm::wfi();
+        riscv::register::sstatus::set_sie();
         if !sie {
             riscv::register::sstatus::clear_sie();
         }
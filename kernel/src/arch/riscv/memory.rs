@@ -1,6 +1,7 @@
 use crate::consts::{KERNEL_OFFSET, MEMORY_END, MEMORY_OFFSET};
 use crate::memory::{init_heap, MemorySet, FRAME_ALLOCATOR};
 use core::mem;
+use core::ops::Range;
 use log::*;
 use rcore_memory::PAGE_SIZE;
 use riscv::asm::sfence_vma_all;
@@ -27,7 +28,6 @@ pub fn init_other() {
 }
 
 fn init_frame_allocator() {
-    use bitmap_allocator::BitAlloc;
     use core::ops::Range;
 
     let mut ba = FRAME_ALLOCATOR.lock();
@@ -99,3 +99,8 @@ pub fn set_page_table(vmtoken: usize) {
     satp::write(vmtoken);
     unsafe { sfence_vma_all() }
 }
+
+/// Invalidate the TLB entries of `range` on the harts in `mask`, by the SBI
+pub fn flush_tlb_others(mask: usize, range: Range<usize>) {
+    super::sbi::remote_sfence_vma(mask, range.start, range.end - range.start);
+}
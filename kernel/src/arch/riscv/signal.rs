@@ -54,7 +54,7 @@ impl MachineContext {
             t2: tf.general.t2,
             s0: tf.general.s0,
             s1: tf.general.s1,
-            a0: tf.general.a1,
+            a0: tf.general.a0,
             a1: tf.general.a1,
             a2: tf.general.a2,
             a3: tf.general.a3,
@@ -82,6 +82,14 @@ impl MachineContext {
     pub fn fill_tf(&self, ctx: &mut UserContext) {}
 }
 
+/// Let the syscall `num` with `args`, which returned EINTR, run again on return to user
+pub fn restart_syscall(tf: &mut UserContext, num: usize, args: &[usize; 6]) {
+    // sepc is after ecall already, and a0 holds the return value
+    tf.sepc -= 4;
+    tf.general.a7 = num;
+    tf.general.a0 = args[0];
+}
+
 // TODO
 pub const RET_CODE: [u8; 7] = [0; 7];
 
@@ -26,18 +26,26 @@ pub fn init() {
     unsafe {
         sie::set_stimer();
     }
-    set_next();
+    // the first tick, then the next events are set as needed
+    set_next_event(Some(timer_now() + Duration::from_millis(10)));
     info!("timer: init end");
 }
 
-/// Set the next timer interrupt
-pub fn set_next() {
-    // 100Hz @ QEMU
-    let timebase = 100000;
-    sbi::sbi_set_timer(get_cycle() + timebase);
+/// Program the timer interrupt at `deadline` of `timer_now`, or never if None
+pub fn set_next_event(deadline: Option<Duration>) {
+    let cycle = match deadline {
+        Some(deadline) => (deadline.as_nanos() / 100).min(u64::max_value() as u128) as u64,
+        None => u64::max_value(),
+    };
+    sbi::sbi_set_timer(cycle);
 }
 
 pub fn timer_now() -> Duration {
     let time = get_cycle();
     Duration::from_nanos(time * 100)
 }
+
+/// Resolution of `timer_now`
+pub fn timer_resolution() -> Duration {
+    Duration::from_nanos(100)
+}
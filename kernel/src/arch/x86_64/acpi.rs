@@ -1,6 +1,44 @@
+//! ACPI tables: processors and I/O APICs in the MADT, the HPET,
+//! and the registers to power off and reset in the FADT.
+//!
+//! The `acpi` crate parses the MADT and the HPET table. The FADT is read here, as it is
+//! not exposed by the crate, and so is the sleep type of `\_S5` in the DSDT, found by
+//! its bytes instead of interpreting AML.
+
 use crate::memory::phys_to_virt;
-use acpi::{parse_rsdp, AcpiHandler, PhysicalMapping};
+use acpi::{parse_rsdp, AcpiHandler, InterruptModel, PhysicalMapping, ProcessorState};
+use alloc::vec::Vec;
 use core::ptr::NonNull;
+use spin::RwLock;
+use x86_64::instructions::port::Port;
+
+#[derive(Default)]
+struct Tables {
+    /// Local APIC ids of the usable processors in the MADT, the boot one first
+    cpus: Vec<usize>,
+    /// (physical address, first global system interrupt) of the I/O APICs
+    io_apics: Vec<(usize, usize)>,
+    /// Physical address of the HPET registers
+    hpet: Option<usize>,
+    fadt: Option<Fadt>,
+}
+
+/// Registers to power off and reset, from the FADT
+#[derive(Debug, Clone, Copy)]
+struct Fadt {
+    smi_cmd: u16,
+    acpi_enable: u8,
+    pm1a_cnt: u16,
+    pm1b_cnt: u16,
+    /// SLP_TYPa and SLP_TYPb of the sleep state S5
+    s5: Option<(u16, u16)>,
+    /// (address space, address, value) to write to reset
+    reset: Option<(u8, u64, u8)>,
+}
+
+lazy_static! {
+    static ref TABLES: RwLock<Tables> = RwLock::new(Tables::default());
+}
 
 struct Handler;
 
@@ -23,9 +61,215 @@ impl AcpiHandler for Handler {
     }
 }
 
+/// Parse the tables from the RSDP at `rsdp_addr`, or found in the BIOS area if it is 0
 pub fn init(rsdp_addr: usize) {
-    let res = parse_rsdp(&mut Handler, rsdp_addr);
-    if let Ok(acpi) = res {
-        debug!("ACPI {:#x?}", acpi);
+    let rsdp_addr = match rsdp_addr {
+        0 => match search_rsdp() {
+            Some(addr) => addr,
+            None => {
+                warn!("acpi: RSDP not found");
+                return;
+            }
+        },
+        addr => addr,
+    };
+    let mut tables = Tables::default();
+    match parse_rsdp(&mut Handler, rsdp_addr) {
+        Ok(acpi) => {
+            debug!("ACPI {:#x?}", acpi);
+            let processors = acpi
+                .boot_processor
+                .iter()
+                .chain(&acpi.application_processors);
+            for processor in processors {
+                if processor.state != ProcessorState::Disabled {
+                    tables.cpus.push(processor.local_apic_id as usize);
+                }
+            }
+            if let Some(InterruptModel::Apic(apic)) = &acpi.interrupt_model {
+                for io_apic in apic.io_apics.iter() {
+                    tables.io_apics.push((
+                        io_apic.address as usize,
+                        io_apic.global_system_interrupt_base as usize,
+                    ));
+                }
+            }
+            tables.hpet = acpi.hpet.as_ref().map(|hpet| hpet.base_address);
+            info!(
+                "acpi: {} processors and {} I/O APICs in MADT, HPET at {:x?}",
+                tables.cpus.len(),
+                tables.io_apics.len(),
+                tables.hpet
+            );
+        }
+        Err(err) => warn!("acpi: failed to parse: {:?}", err),
+    }
+    tables.fadt = find_table(rsdp_addr, b"FACP").map(parse_fadt);
+    info!("acpi: FADT {:x?}", tables.fadt);
+    *TABLES.write() = tables;
+}
+
+/// Local APIC ids of the usable processors, the boot one first, empty without MADT
+pub fn cpus() -> Vec<usize> {
+    TABLES.read().cpus.clone()
+}
+
+/// (physical address, first global system interrupt) of the I/O APIC
+/// through which global system interrupt `gsi` comes
+pub fn io_apic_of(gsi: usize) -> Option<(usize, usize)> {
+    TABLES
+        .read()
+        .io_apics
+        .iter()
+        .filter(|&&(_, base)| base <= gsi)
+        .max_by_key(|&&(_, base)| base)
+        .cloned()
+}
+
+/// Physical address of the HPET registers
+pub fn hpet() -> Option<usize> {
+    TABLES.read().hpet
+}
+
+const SCI_EN: u16 = 1 << 0;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_TYP_MASK: u16 = 0b111 << SLP_TYP_SHIFT;
+const SLP_EN: u16 = 1 << 13;
+
+const SYSTEM_MEMORY: u8 = 0;
+const SYSTEM_IO: u8 = 1;
+
+/// Enter the sleep state S5, returning if it is not described
+pub fn poweroff() {
+    let fadt = match TABLES.read().fadt {
+        Some(fadt) => fadt,
+        None => return,
+    };
+    let (typa, typb) = match fadt.s5 {
+        Some(s5) if fadt.pm1a_cnt != 0 => s5,
+        _ => return,
+    };
+    unsafe {
+        let mut pm1a = Port::<u16>::new(fadt.pm1a_cnt);
+        // switch to ACPI mode if the firmware has not
+        if pm1a.read() & SCI_EN == 0 && fadt.smi_cmd != 0 {
+            Port::<u8>::new(fadt.smi_cmd).write(fadt.acpi_enable);
+            for _ in 0..1_000_000 {
+                if pm1a.read() & SCI_EN != 0 {
+                    break;
+                }
+            }
+        }
+        let value = pm1a.read() & !SLP_TYP_MASK;
+        pm1a.write(value | typa << SLP_TYP_SHIFT | SLP_EN);
+        if fadt.pm1b_cnt != 0 {
+            let mut pm1b = Port::<u16>::new(fadt.pm1b_cnt);
+            let value = pm1b.read() & !SLP_TYP_MASK;
+            pm1b.write(value | typb << SLP_TYP_SHIFT | SLP_EN);
+        }
+    }
+}
+
+/// Reset by the reset register, returning if there is none
+pub fn reset() {
+    let reset = TABLES.read().fadt.and_then(|fadt| fadt.reset);
+    match reset {
+        Some((SYSTEM_IO, addr, value)) => unsafe { Port::<u8>::new(addr as u16).write(value) },
+        Some((SYSTEM_MEMORY, addr, value)) => unsafe {
+            (phys_to_virt(addr as usize) as *mut u8).write_volatile(value)
+        },
+        _ => {}
     }
 }
+
+fn read<T: Copy>(paddr: usize) -> T {
+    unsafe { (phys_to_virt(paddr) as *const T).read_unaligned() }
+}
+
+fn checksum(paddr: usize, len: usize) -> bool {
+    (0..len).fold(0u8, |sum, i| sum.wrapping_add(read::<u8>(paddr + i))) == 0
+}
+
+/// Look for the RSDP in the first KiB of the EBDA, then in the BIOS read-only memory
+fn search_rsdp() -> Option<usize> {
+    let ebda = (read::<u16>(0x40e) as usize) << 4;
+    let areas = [(ebda, ebda + 0x400), (0xe0000, 0x100000)];
+    areas
+        .iter()
+        .flat_map(|&(start, end)| (start..end).step_by(16))
+        .find(|&addr| read::<[u8; 8]>(addr) == *b"RSD PTR " && checksum(addr, 20))
+}
+
+/// Physical address of the table with `signature` in the XSDT, or in the RSDT before ACPI 2.0
+fn find_table(rsdp_addr: usize, signature: &[u8; 4]) -> Option<usize> {
+    let revision = read::<u8>(rsdp_addr + 15);
+    let xsdt = read::<u64>(rsdp_addr + 24) as usize;
+    let (sdt, entry_size) = if revision >= 2 && xsdt != 0 {
+        (xsdt, 8)
+    } else {
+        (read::<u32>(rsdp_addr + 16) as usize, 4)
+    };
+    let len = read::<u32>(sdt + 4) as usize;
+    (sdt + 36..sdt + len)
+        .step_by(entry_size)
+        .map(|entry| match entry_size {
+            8 => read::<u64>(entry) as usize,
+            _ => read::<u32>(entry) as usize,
+        })
+        .find(|&table| read::<[u8; 4]>(table) == *signature)
+}
+
+fn parse_fadt(fadt: usize) -> Fadt {
+    let len = read::<u32>(fadt + 4) as usize;
+    // X_DSDT of ACPI 2.0 first
+    let dsdt = if len >= 148 && read::<u64>(fadt + 140) != 0 {
+        read::<u64>(fadt + 140) as usize
+    } else {
+        read::<u32>(fadt + 40) as usize
+    };
+    let reset_supported = read::<u32>(fadt + 112) & (1 << 10) != 0;
+    Fadt {
+        smi_cmd: read::<u32>(fadt + 48) as u16,
+        acpi_enable: read::<u8>(fadt + 52),
+        pm1a_cnt: read::<u32>(fadt + 64) as u16,
+        pm1b_cnt: read::<u32>(fadt + 68) as u16,
+        s5: if dsdt != 0 { s5_sleep_type(dsdt) } else { None },
+        reset: if len > 128 && reset_supported {
+            Some((
+                read::<u8>(fadt + 116),
+                read::<u64>(fadt + 120),
+                read::<u8>(fadt + 128),
+            ))
+        } else {
+            None
+        },
+    }
+}
+
+/// SLP_TYPa and SLP_TYPb in the package named `\_S5_` of the DSDT
+fn s5_sleep_type(dsdt: usize) -> Option<(u16, u16)> {
+    const NAME_OP: u8 = 0x08;
+    const PACKAGE_OP: u8 = 0x12;
+    const BYTE_PREFIX: u8 = 0x0a;
+    let len = read::<u32>(dsdt + 4) as usize;
+    let aml = unsafe { core::slice::from_raw_parts(phys_to_virt(dsdt) as *const u8, len) };
+    let pos = (36..len.checked_sub(5)?).find(|&i| {
+        &aml[i..i + 4] == b"_S5_"
+            && aml[i + 4] == PACKAGE_OP
+            && (aml[i - 1] == NAME_OP || (aml[i - 2] == NAME_OP && aml[i - 1] == b'\\'))
+    })?;
+    // skip PkgLength, whose bytes follow the first, and NumElements
+    let mut p = pos + 5;
+    p += (*aml.get(p)? >> 6) as usize + 2;
+    let mut element = || {
+        if *aml.get(p)? == BYTE_PREFIX {
+            p += 1;
+        }
+        let value = *aml.get(p)? as u16;
+        p += 1;
+        Some(value)
+    };
+    let typa = element()?;
+    let typb = element()?;
+    Some((typa, typb))
+}
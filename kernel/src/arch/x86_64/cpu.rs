@@ -2,14 +2,27 @@ use crate::memory::phys_to_virt;
 use apic::{LocalApic, XApic};
 use raw_cpuid::CpuId;
 use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+
+/// Holding the id of this CPU, read by `rdtscp`
+const IA32_TSC_AUX: u32 = 0xc000_0103;
+
+lazy_static! {
+    /// Whether the id is read from TSC_AUX, as `cpuid` exits to the hypervisor in a VM
+    static ref HAS_RDTSCP: bool = CpuId::new()
+        .get_extended_function_info()
+        .map_or(false, |info| info.has_rdtscp());
+}
 
 /// Exit qemu
 /// See: https://wiki.osdev.org/Shutdown
 /// Must run qemu with `-device isa-debug-exit`
 /// The error code is `value written to 0x501` *2 +1, so it should be odd when non-zero
+/// Powering off by ACPI is tried first for the code 0, which also works outside qemu
 pub unsafe fn exit_in_qemu(error_code: u8) -> ! {
     use x86_64::instructions::port::Port;
     if error_code == 0 {
+        super::acpi::poweroff();
         Port::new(0xB004).write(0x2000 as u16);
     } else {
         assert_eq!(error_code & 1, 1, "error code should be odd");
@@ -18,22 +31,49 @@ pub unsafe fn exit_in_qemu(error_code: u8) -> ! {
     unreachable!()
 }
 
+/// Reset by the ACPI reset register, or else the keyboard controller
 pub unsafe fn reboot() -> ! {
     use x86_64::instructions::port::Port;
+    super::acpi::reset();
     Port::new(0x64).write(0xfeu8);
     unreachable!()
 }
 
 pub fn id() -> usize {
+    if *HAS_RDTSCP {
+        let aux: u32;
+        unsafe { llvm_asm!("rdtscp" : "={ecx}"(aux) :: "eax", "edx" : "volatile") };
+        aux as usize
+    } else {
+        apic_id()
+    }
+}
+
+/// Whether `rdtscp` gives the id of this CPU, in user as well
+pub fn has_rdtscp() -> bool {
+    *HAS_RDTSCP
+}
+
+fn apic_id() -> usize {
     CpuId::new()
         .get_feature_info()
         .unwrap()
         .initial_local_apic_id() as usize
 }
 
+/// Keep the id of this CPU in TSC_AUX, before anything asks for it
+pub fn init_id() {
+    if *HAS_RDTSCP {
+        unsafe { Msr::new(IA32_TSC_AUX).write(apic_id() as u64) };
+    }
+}
+
+/// Interrupt CPU `cpu_id`, e.g. to wake it up from `hlt`
 pub fn send_ipi(cpu_id: usize) {
+    use super::interrupt::consts::IPIFuncCall;
     let mut lapic = unsafe { XApic::new(phys_to_virt(0xfee00000)) };
-    lapic.send_ipi(cpu_id as u8, 0x30); // TODO: Find a IPI trap num
+    // with nothing to call, it only wakes the CPU up
+    lapic.send_ipi(cpu_id as u8, IPIFuncCall as u8);
 }
 
 pub fn init() {
@@ -52,6 +92,8 @@ pub fn init() {
             // enable fpu
             cr0.remove(Cr0Flags::EMULATE_COPROCESSOR);
             cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);
+            // kernel writes to user pages must fault on copy-on-write pages
+            cr0.insert(Cr0Flags::WRITE_PROTECT);
         });
     }
 }
@@ -1,9 +1,20 @@
+//! The FPU, SSE and AVX state of user threads, switched lazily
+//!
+//! The registers of each CPU are left with the state of the last thread that used them.
+//! A thread going to user finds whether they still hold its state. If they do not,
+//! CR0.TS is set, and its first FPU instruction traps at #NM (`DeviceNotAvailable`),
+//! where its state is loaded. Threads not using the FPU never have it saved or restored.
+//! The kernel is built with soft float, so it never touches the registers.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+
 // state saved by fxsave64
 // 512 bytes
 // https://www.felixcloutier.com/x86/fxsave#tbl-3-47
 #[repr(C, align(16))]
 #[derive(Debug, Copy, Clone, Default)]
-pub struct FpState {
+struct FxArea {
     // 0
     fcw: u16,
     fsw: u16,
@@ -22,28 +33,67 @@ pub struct FpState {
     rest: [u64; 12],
 }
 
+#[derive(Debug)]
+pub struct FpState {
+    area: FxArea,
+    /// Never reused, to tell which state the registers hold
+    id: usize,
+    /// The CPU it was last loaded on, whose registers may have changed since
+    cpu: Option<usize>,
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+percpu! {
+    /// The id of the state loaded in the registers of each CPU, 0 if none
+    static ref OWNER: AtomicUsize = AtomicUsize::new(0);
+}
+
 impl FpState {
     pub fn new() -> Self {
-        assert!(core::mem::size_of::<Self>() == 512);
+        assert!(core::mem::size_of::<FxArea>() == 512);
         Self {
-            // default values
-            // intel manual 11.6.4 Initialization of SSE/SSE2 Extensions
-            mxcsr: 0x1f80,
-            // intel manual 8.1.5 x87 FPU Control Word
-            fcw: 0x037f,
-            ..Self::default()
+            area: FxArea {
+                // default values
+                // intel manual 11.6.4 Initialization of SSE/SSE2 Extensions
+                mxcsr: 0x1f80,
+                // intel manual 8.1.5 x87 FPU Control Word
+                fcw: 0x037f,
+                ..FxArea::default()
+            },
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            cpu: None,
         }
     }
 
+    /// Save the registers back from user, if they hold this state
     pub fn save(&mut self) {
+        // TS is cleared only for the state loaded
+        if Cr0::read().contains(Cr0Flags::TASK_SWITCHED) {
+            return;
+        }
         unsafe {
-            core::arch::x86_64::_fxsave64(self as *mut FpState as *mut u8);
+            core::arch::x86_64::_fxsave64(&mut self.area as *mut FxArea as *mut u8);
         }
     }
 
+    /// Going to user, let the registers be used if they still hold this state,
+    /// or else trap the first use of them
     pub fn restore(&self) {
+        let cpu = super::cpu::id();
+        let loaded = self.cpu == Some(cpu) && OWNER.get().load(Ordering::Relaxed) == self.id;
+        unsafe {
+            Cr0::update(|cr0| cr0.set(Cr0Flags::TASK_SWITCHED, !loaded));
+        }
+    }
+
+    /// Load the state at the first use of the registers, trapped since `restore`
+    pub fn load(&mut self) {
         unsafe {
-            core::arch::x86_64::_fxrstor64(self as *const FpState as *const u8);
+            Cr0::update(|cr0| cr0.remove(Cr0Flags::TASK_SWITCHED));
+            core::arch::x86_64::_fxrstor64(&self.area as *const FxArea as *const u8);
         }
+        OWNER.get().store(self.id, Ordering::Relaxed);
+        self.cpu = Some(super::cpu::id());
     }
 }
@@ -1,6 +1,9 @@
 #![allow(non_upper_case_globals)]
 // Reference: https://wiki.osdev.org/Exceptions
 
+use crate::signal::{Signal, BUS_ADRALN, FPE_FLTINV, FPE_INTDIV, ILL_ILLOPN, SI_KERNEL};
+use trapframe::UserContext;
+
 pub const DivideError: usize = 0;
 pub const Debug: usize = 1;
 pub const NonMaskableInterrupt: usize = 2;
@@ -54,8 +57,17 @@ pub fn is_syscall(trap: usize) -> bool {
     trap == Syscall
 }
 
+pub fn is_breakpoint(trap: usize) -> bool {
+    trap == Breakpoint || trap == Debug
+}
+
+/// The first use of the FPU since `FpState::restore`
+pub fn is_fp_unavailable(trap: usize) -> bool {
+    trap == DeviceNotAvailable
+}
+
 pub fn is_intr(trap: usize) -> bool {
-    IrqMin <= trap && trap <= IrqMax
+    IrqMin <= trap && trap <= IrqMax || trap == IPIFuncCall
 }
 
 pub fn is_timer_intr(trap: usize) -> bool {
@@ -65,3 +77,22 @@ pub fn is_timer_intr(trap: usize) -> bool {
 pub fn is_reserved_inst(trap: usize) -> bool {
     false
 }
+
+/// The signal for a fault of user code other than a page fault,
+/// with its si_code and si_addr
+pub fn fault_signal(context: &UserContext, trap: usize) -> Option<(Signal, i32, usize)> {
+    let pc = context.general.rip;
+    Some(match trap {
+        DivideError => (Signal::SIGFPE, FPE_INTDIV, pc),
+        InvalidOpcode => (Signal::SIGILL, ILL_ILLOPN, pc),
+        FloatingPointException | SIMDFloatingPointException => (Signal::SIGFPE, FPE_FLTINV, pc),
+        AlignmentCheck => (Signal::SIGBUS, BUS_ADRALN, 0),
+        // no address is known for protection faults
+        Overflow
+        | BoundRangeExceeded
+        | SegmentNotPresent
+        | StackSegmentFault
+        | GeneralProtectionFault => (Signal::SIGSEGV, SI_KERNEL, 0),
+        _ => return None,
+    })
+}
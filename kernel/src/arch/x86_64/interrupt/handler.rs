@@ -103,7 +103,7 @@ pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
         IPIFuncCall => {
             let irq = tf.trap_num - IrqMin;
             super::ack(irq); // must ack before switching
-            super::super::gdt::Cpu::current().handle_ipi();
+            super::super::ipi::handle_ipi();
         }
         _ => panic!("Unhandled interrupt {:x}", tf.trap_num),
     }
@@ -127,8 +127,13 @@ fn page_fault(tf: &mut TrapFrame) {
         }
     }
     let code = PageError::from_bits(tf.error_code as u8).unwrap();
+    let access = crate::memory::AccessType {
+        write: code.contains(PageError::WRITE),
+        execute: code.contains(PageError::INST),
+        user: code.contains(PageError::USER),
+    };
 
-    if crate::memory::handle_page_fault(addr) {
+    if crate::memory::handle_page_fault_ext(addr, access) {
         return;
     }
 
@@ -2,7 +2,7 @@ pub mod consts;
 mod handler;
 
 pub use self::handler::*;
-use crate::memory::phys_to_virt;
+use crate::memory::{phys_to_virt, AccessType};
 use crate::process::thread::Thread;
 use alloc::sync::Arc;
 use apic::*;
@@ -39,9 +39,11 @@ pub fn no_interrupt(f: impl FnOnce()) {
 
 #[inline(always)]
 pub fn enable_irq(irq: usize) {
-    let mut ioapic = unsafe { IoApic::new(phys_to_virt(IOAPIC_ADDR as usize)) };
-    ioapic.set_irq_vector(irq as u8, (consts::IrqMin + irq) as u8);
-    ioapic.enable(irq as u8, 0);
+    // the I/O APIC in the MADT for the interrupt, whose first one is `base`
+    let (addr, base) = super::acpi::io_apic_of(irq).unwrap_or((IOAPIC_ADDR as usize, 0));
+    let mut ioapic = unsafe { IoApic::new(phys_to_virt(addr)) };
+    ioapic.set_irq_vector((irq - base) as u8, (consts::IrqMin + irq) as u8);
+    ioapic.enable((irq - base) as u8, 0);
 }
 
 pub fn timer() {
@@ -67,6 +69,19 @@ pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize) -> bool {
     thread.vm.lock().handle_page_fault(addr)
 }
 
+pub fn handle_user_page_fault_ext(thread: &Arc<Thread>, addr: usize, access: AccessType) -> bool {
+    thread.vm.lock().handle_page_fault_ext(addr, access)
+}
+
+/// Decode the access type from the error code of a page fault
+pub fn get_page_fault_access(context: &UserContext) -> AccessType {
+    AccessType {
+        write: context.error_code & (1 << 1) != 0,
+        execute: context.error_code & (1 << 4) != 0,
+        user: true,
+    }
+}
+
 pub fn handle_reserved_inst(tf: &mut UserContext) -> bool {
     false
 }
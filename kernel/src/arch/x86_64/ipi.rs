@@ -1,40 +1,81 @@
 //! Interface for inter-processor interrupt.
 //! This module wraps inter-processor interrupt into a broadcast-calling style.
 
+use super::cpu;
+use crate::consts::MAX_CPU_NUM;
 use crate::memory::phys_to_virt;
+use crate::process::online_cpus;
+use crate::sync::SpinNoIrqLock as Mutex;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use apic::{LocalApic, XApic, LAPIC_ADDR};
-use core::sync::atomic::{spin_loop_hint, AtomicU8, Ordering};
+use core::mem::size_of;
+use core::sync::atomic::{spin_loop_hint, AtomicUsize, Ordering};
 
-pub type IPIEventItem = Box<dyn Fn()>;
+pub type IPIEventItem = Box<dyn Fn() + Send + Sync>;
+
+percpu! {
+    /// Functions each CPU is asked to call at the next IPI
+    static ref IPI_QUEUES: Mutex<Vec<IPIEventItem>> = Mutex::new(Vec::new());
+}
+
+/// CPUs with functions queued, one bit for each, checked without taking the queues
+static PENDING: AtomicUsize = AtomicUsize::new(0);
 
 unsafe fn get_apic() -> XApic {
     let lapic = XApic::new(phys_to_virt(LAPIC_ADDR));
     lapic
 }
 
-pub fn invoke_on_allcpu(f: impl Fn() + 'static, wait: bool) {
+pub fn invoke_on_allcpu(f: impl Fn() + Send + Sync + 'static, wait: bool) {
+    invoke_on_cpus(online_cpus(), f, wait);
+}
+
+/// Call `f` on the CPUs in `mask`, one bit for each
+pub fn invoke_on_cpus(mask: usize, f: impl Fn() + Send + Sync + 'static, wait: bool) {
     // Step 1: initialize
     use super::interrupt::consts::IPIFuncCall;
     let mut apic = unsafe { get_apic() };
     let func = Arc::new(f);
-    let cpu_count = super::gdt::Cpu::iter().count();
-    let rest_count = Arc::new(AtomicU8::new(cpu_count as u8));
+    let max = MAX_CPU_NUM.min(size_of::<usize>() * 8);
+    let cpus: Vec<usize> = (0..max).filter(|&id| mask & (1 << id) != 0).collect();
+    let rest_count = Arc::new(AtomicUsize::new(cpus.len()));
     // Step 2: invoke
-    for cpu in super::gdt::Cpu::iter() {
+    for &cpu_id in cpus.iter() {
         let func_clone = func.clone();
         let rest_clone = rest_count.clone();
-        cpu.notify_event(Box::new(move || {
+        IPI_QUEUES.of(cpu_id).lock().push(Box::new(move || {
             func_clone();
             rest_clone.fetch_sub(1, Ordering::Relaxed);
         }));
-        apic.send_ipi(cpu.id() as u8, IPIFuncCall as u8);
+        PENDING.fetch_or(1 << cpu_id, Ordering::Release);
+        apic.send_ipi(cpu_id as u8, IPIFuncCall as u8);
     }
     if wait {
-        // spin if remote invocation do not complete
+        // spin if remote invocation do not complete,
+        // answering those waiting for this CPU meanwhile
         while rest_count.load(Ordering::Relaxed) != 0 {
+            poll();
             spin_loop_hint();
         }
     }
 }
+
+/// Call the functions queued for this CPU, at the IPI
+pub fn handle_ipi() {
+    PENDING.fetch_and(!(1 << cpu::id()), Ordering::Acquire);
+    let handlers = core::mem::replace(&mut *IPI_QUEUES.get().lock(), Vec::new());
+    for handler in handlers {
+        handler();
+    }
+}
+
+/// Call the functions queued for this CPU if any, without waiting for the IPI.
+/// Called when spinning with interrupts disabled, as the CPU spun for
+/// may be waiting for this one to call them, e.g. to flush its TLB.
+pub fn poll() {
+    if PENDING.load(Ordering::Acquire) & (1 << cpu::id()) != 0 {
+        handle_ipi();
+    }
+}
@@ -0,0 +1,135 @@
+//! Kernel address space layout randomization
+//!
+//! The kernel is linked as a position independent executable at `KERNEL_OFFSET`,
+//! where rboot loads it. First at boot, the boot processor maps the same frames again
+//! at a random multiple of 1GiB above, in the same PML4 entry, so that the page tables
+//! copying the entry get it too. It applies the relative relocations of `.rela.dyn`
+//! for there, and each processor jumps over. The mapping where it is linked is removed
+//! once all of them have moved. Only the virtual address is randomized,
+//! as the physical one is chosen by rboot.
+
+use super::consts::KERNEL_PM4;
+use super::ipi;
+use crate::consts::KERNEL_OFFSET;
+use crate::lkm::const_reloc::x86_64::R_X86_64_RELATIVE;
+use crate::memory::phys_to_virt;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rcore_memory::PAGE_SIZE;
+use x86_64::instructions::tlb;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
+use x86_64::structures::paging::PageTable;
+use x86_64::VirtAddr;
+
+const GIGA: usize = 1 << 30;
+/// The PDPT entry where the kernel is linked
+const KERNEL_PDPT: usize = (KERNEL_OFFSET >> 30) & 0o777;
+
+/// How far the kernel is moved from where it is linked
+static OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" {
+    fn srela();
+    fn erela();
+    fn ebss();
+}
+
+#[repr(C)]
+struct Rela {
+    offset: usize,
+    info: usize,
+    addend: usize,
+}
+
+/// How far the kernel is moved from where it is linked, as by `nm` or `addr2line`
+pub fn offset() -> usize {
+    OFFSET.load(Ordering::Acquire)
+}
+
+/// The PDPT of the kernel, in the page table of the bootloader
+unsafe fn kernel_pdpt() -> &'static mut PageTable {
+    let pml4 =
+        &*(phys_to_virt(Cr3::read().0.start_address().as_u64() as usize) as *const PageTable);
+    &mut *(phys_to_virt(pml4[KERNEL_PM4].addr().as_u64() as usize) as *mut PageTable)
+}
+
+/// Map the kernel at a random address and relocate it for there.
+/// Called by the boot processor first at boot, before anything keeps a pointer.
+/// It stays where it is linked if it is not relocatable.
+pub fn init() {
+    if cfg!(feature = "nokaslr") {
+        return;
+    }
+    let start = srela as usize;
+    let relas = unsafe {
+        core::slice::from_raw_parts(
+            start as *const Rela,
+            (erela as usize - start) / size_of::<Rela>(),
+        )
+    };
+    // linked without -pie, or with symbols to look up
+    if relas.is_empty()
+        || relas
+            .iter()
+            .any(|rela| rela.info as u32 as usize != R_X86_64_RELATIVE)
+    {
+        return;
+    }
+    // where it is linked, as nothing is relocated yet
+    if ebss as usize - KERNEL_OFFSET > GIGA {
+        return;
+    }
+    let pdpt = unsafe { kernel_pdpt() };
+    let slots = 511 - KERNEL_PDPT;
+    let first = super::rand::rand() as usize % slots;
+    let slot = match (0..slots)
+        .map(|i| KERNEL_PDPT + 1 + (first + i) % slots)
+        .find(|&slot| pdpt[slot].is_unused())
+    {
+        Some(slot) => slot,
+        None => return,
+    };
+    pdpt[slot] = pdpt[KERNEL_PDPT].clone();
+    let offset = (slot - KERNEL_PDPT) * GIGA;
+    unsafe {
+        // the relocated pointers may be in pages read-only
+        Cr0::update(|cr0| cr0.remove(Cr0Flags::WRITE_PROTECT));
+        for rela in relas {
+            *((rela.offset + offset) as *mut usize) = rela.addend + offset;
+        }
+        Cr0::update(|cr0| cr0.insert(Cr0Flags::WRITE_PROTECT));
+    }
+    OFFSET.store(offset, Ordering::Release);
+}
+
+/// Go on at `entry` moved with the kernel, from where it is linked
+pub unsafe fn jump(entry: usize, arg: usize) -> ! {
+    let offset = offset();
+    // taken by `lea` where it is linked, unless loaded from a relocated pointer
+    let entry = if entry < KERNEL_OFFSET + offset {
+        entry + offset
+    } else {
+        entry
+    };
+    let entry: extern "C" fn(usize) -> ! = core::mem::transmute(entry);
+    entry(arg)
+}
+
+/// Remove the mapping where the kernel is linked, once all processors have moved
+pub fn unmap_linked() {
+    let offset = offset();
+    if offset == 0 {
+        info!("kaslr: kernel stays where it is linked");
+        return;
+    }
+    unsafe { kernel_pdpt()[KERNEL_PDPT].set_unused() };
+    let end = ebss as usize - offset;
+    let flush = move || {
+        for addr in (KERNEL_OFFSET..end).step_by(PAGE_SIZE) {
+            tlb::flush(VirtAddr::new(addr as u64));
+        }
+    };
+    flush();
+    ipi::invoke_on_allcpu(flush, false);
+    info!("kaslr: kernel moved to {:#x}", KERNEL_OFFSET + offset);
+}
@@ -1,30 +1,166 @@
+use super::consts::*;
+use super::ipi;
 use super::paging::PageTableImpl;
-use crate::memory::FRAME_ALLOCATOR;
-use bitmap_allocator::BitAlloc;
+use crate::memory::{alloc_frame, phys_to_virt, FRAME_ALLOCATOR};
+use core::ops::Range;
 use rboot::{BootInfo, MemoryType};
 use rcore_memory::paging::*;
+use rcore_memory::HUGE_PAGE_SIZE;
 use rcore_memory::PAGE_SIZE;
 use x86_64::{
+    instructions::tlb,
     registers::control::{Cr2, Cr3, Cr3Flags},
-    structures::paging::PhysFrame,
+    structures::paging::{
+        PageTable as x86PageTable, PageTableEntry, PageTableFlags as EF, PhysFrame,
+    },
     PhysAddr, VirtAddr,
 };
 
 pub fn init(boot_info: &BootInfo) {
     init_frame_allocator(boot_info);
+    remap_physical_memory(boot_info);
+    protect_kernel();
     info!("memory: init end");
 }
 
+/// The memory below it is kept for the trampoline of the application processors
+const LOW_MEMORY_END: usize = 0x100000;
+
 /// Init FrameAllocator and insert all 'Usable' regions from BootInfo.
+/// The memory below 1MiB is kept for the trampoline of the application processors.
 fn init_frame_allocator(boot_info: &BootInfo) {
     let mut ba = FRAME_ALLOCATOR.lock();
     for region in boot_info.memory_map.clone().iter {
         if region.ty == MemoryType::CONVENTIONAL {
-            let start_frame = region.phys_start as usize / PAGE_SIZE;
-            let end_frame = start_frame + region.page_count as usize;
-            ba.insert(start_frame..end_frame);
+            let start_frame = (region.phys_start as usize).max(LOW_MEMORY_END) / PAGE_SIZE;
+            let end_frame = region.phys_start as usize / PAGE_SIZE + region.page_count as usize;
+            if start_frame < end_frame {
+                ba.insert(start_frame..end_frame);
+            }
+        }
+    }
+}
+
+/// Map the physical memory at `PHYSICAL_MEMORY_OFFSET` with 2MiB pages,
+/// in place of the mapping left by the bootloader.
+/// The first 4GiB are always mapped, for MMIO regions below it.
+fn remap_physical_memory(boot_info: &BootInfo) {
+    const GIGA: usize = 1 << 30;
+    let ram_end = boot_info
+        .memory_map
+        .clone()
+        .iter
+        .map(|region| region.phys_start as usize + region.page_count as usize * PAGE_SIZE)
+        .max()
+        .unwrap_or(0);
+    let end = ram_end.max(4 * GIGA);
+    // a PML4 entry covers 512 PDPT entries of 1GiB
+    let pdpt_entries = ((end + GIGA - 1) / GIGA).min(512);
+
+    let table = unsafe {
+        &mut *(phys_to_virt(Cr3::read().0.start_address().as_u64() as usize) as *mut x86PageTable)
+    };
+    let old = table[PHYSICAL_MEMORY_PM4].clone();
+    let new_pdpt_frame = alloc_frame().expect("failed to allocate frame");
+    let new_pdpt = unsafe { &mut *(phys_to_virt(new_pdpt_frame) as *mut x86PageTable) };
+    // keep anything mapped above the physical memory
+    let old_pdpt = unsafe { &*(phys_to_virt(old.addr().as_u64() as usize) as *const x86PageTable) };
+    for (new, old) in new_pdpt.iter_mut().zip(old_pdpt.iter()) {
+        *new = old.clone();
+    }
+
+    let flags = EF::PRESENT | EF::WRITABLE | EF::GLOBAL | EF::NO_EXECUTE;
+    for i in 0..pdpt_entries {
+        let pd_frame = alloc_frame().expect("failed to allocate frame");
+        let pd = unsafe { &mut *(phys_to_virt(pd_frame) as *mut x86PageTable) };
+        for (j, entry) in pd.iter_mut().enumerate() {
+            let paddr = i * GIGA + j * HUGE_PAGE_SIZE;
+            entry.set_addr(PhysAddr::new(paddr as u64), flags | EF::HUGE_PAGE);
+        }
+        new_pdpt[i].set_addr(PhysAddr::new(pd_frame as u64), flags);
+    }
+    table[PHYSICAL_MEMORY_PM4].set_addr(PhysAddr::new(new_pdpt_frame as u64), old.flags());
+    tlb::flush_all();
+
+    // the old tables of the remapped range are not used any more,
+    // they are allocated by the bootloader outside the free regions
+    let mut ba = FRAME_ALLOCATOR.lock();
+    let mut free = |paddr: PhysAddr| {
+        let frame = paddr.as_u64() as usize;
+        if frame >= LOW_MEMORY_END {
+            ba.insert(frame / PAGE_SIZE..frame / PAGE_SIZE + 1);
+        }
+    };
+    for entry in old_pdpt.iter().take(pdpt_entries) {
+        if entry.is_unused() || entry.flags().contains(EF::HUGE_PAGE) {
+            continue;
         }
+        let pd = unsafe { &*(phys_to_virt(entry.addr().as_u64() as usize) as *const x86PageTable) };
+        for pd_entry in pd.iter() {
+            if !pd_entry.is_unused() && !pd_entry.flags().contains(EF::HUGE_PAGE) {
+                free(pd_entry.addr());
+            }
+        }
+        free(entry.addr());
     }
+    free(old.addr());
+    drop(ba);
+    info!(
+        "memory: physical memory mapped with huge pages up to {:#x}",
+        pdpt_entries * GIGA
+    );
+}
+
+/// Map `.text` read-only, `.rodata` read-only and not executable,
+/// and the rest not executable, in the mapping of the kernel left by the bootloader.
+/// The mapping where it is linked shares the page tables, if the kernel is moved.
+fn protect_kernel() {
+    extern "C" {
+        fn stext();
+        fn etext();
+        fn ebss();
+    }
+    let begin = KERNEL_OFFSET + super::kaslr::offset();
+    let data = (etext as usize + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let areas = [
+        (begin..stext as usize, EF::NO_EXECUTE),
+        (stext as usize..data, EF::empty()),
+        (data..ebss as usize, EF::WRITABLE | EF::NO_EXECUTE),
+    ];
+    for (range, flags) in areas.iter() {
+        for addr in range.clone().step_by(PAGE_SIZE) {
+            let entry = match unsafe { kernel_entry(addr) } {
+                Some(entry) => entry,
+                None => {
+                    warn!("memory: kernel not mapped by 4KiB pages at {:#x}", addr);
+                    return;
+                }
+            };
+            let old = entry.flags() - (EF::WRITABLE | EF::NO_EXECUTE);
+            entry.set_flags(old | *flags);
+            tlb::flush(VirtAddr::new(addr as u64));
+        }
+    }
+    info!("memory: kernel text read-only, and the rest not executable");
+}
+
+/// The entry of the 4KiB page at `vaddr` in the current page table
+unsafe fn kernel_entry(vaddr: usize) -> Option<&'static mut PageTableEntry> {
+    let mut table =
+        &mut *(phys_to_virt(Cr3::read().0.start_address().as_u64() as usize) as *mut x86PageTable);
+    // PML4, PDPT and PD
+    for level in (1..4).rev() {
+        let entry = &table[(vaddr >> (12 + 9 * level)) & 0o777];
+        if entry.is_unused() || entry.flags().contains(EF::HUGE_PAGE) {
+            return None;
+        }
+        table = &mut *(phys_to_virt(entry.addr().as_u64() as usize) as *mut x86PageTable);
+    }
+    let entry = &mut table[(vaddr >> 12) & 0o777];
+    if entry.is_unused() {
+        return None;
+    }
+    Some(entry)
 }
 
 /// The method for initializing kernel virtual memory space, a memory space of 512 GiB.
@@ -50,6 +186,25 @@ pub fn set_page_table(vmtoken: usize) {
     }
 }
 
+/// Invalidate the TLB entries of `range` on the CPUs in `mask`, waiting for them.
+/// The whole TLB is flushed by reloading CR3 if the range is large.
+pub fn flush_tlb_others(mask: usize, range: Range<usize>) {
+    const MAX_PAGES: usize = 32;
+    ipi::invoke_on_cpus(
+        mask,
+        move || {
+            if (range.end - range.start) / PAGE_SIZE > MAX_PAGES {
+                tlb::flush_all();
+            } else {
+                for addr in range.clone().step_by(PAGE_SIZE) {
+                    tlb::flush(VirtAddr::new(addr as u64));
+                }
+            }
+        },
+        true,
+    );
+}
+
 pub fn get_page_fault_addr() -> usize {
     Cr2::read().as_u64() as usize
 }
@@ -11,27 +11,40 @@ pub mod gdt;
 pub mod interrupt;
 pub mod io;
 pub mod ipi;
+pub mod kaslr;
 pub mod memory;
 pub mod paging;
 pub mod rand;
 pub mod signal;
+pub mod smp;
 pub mod syscall;
 pub mod timer;
+pub mod vdso;
 
 static AP_CAN_INIT: AtomicBool = AtomicBool::new(false);
 
 /// The entry point of kernel
 #[no_mangle] // don't mangle the name of this function
 pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    cpu::init_id();
     let cpu_id = cpu::id();
 
     if cpu_id != 0 {
-        while !AP_CAN_INIT.load(Ordering::Relaxed) {
+        while !AP_CAN_INIT.load(Ordering::Acquire) {
             spin_loop_hint();
         }
-        other_start();
+        unsafe { kaslr::jump(other_start as usize, 0) }
     }
 
+    // move to a random address, before anything keeps a pointer to the kernel
+    kaslr::init();
+    unsafe { kaslr::jump(primary_start as usize, boot_info as *const _ as usize) }
+}
+
+/// The boot processor, moved with the kernel
+extern "C" fn primary_start(boot_info: &'static BootInfo) -> ! {
+    let cpu_id = cpu::id();
+
     // init log and heap
     crate::logging::init();
     crate::memory::init_heap();
@@ -60,30 +73,37 @@ pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
     memory::init_kernel_kseg2_map();
     // init local apic
     cpu::init();
+    // calibrate TSC and turn to tickless
+    timer::init();
+    // the clock read by user from the vDSO
+    vdso::init();
     // now we can start LKM.
     crate::lkm::manager::ModuleManager::init();
+    // load acpi, for the I/O APICs of the devices
+    acpi::init(boot_info.acpi2_rsdp_addr as usize);
     // init board
     board::init(boot_info);
     // init cpu scheduler and process manager, and add user shell app in process manager
     crate::process::init();
-    // load acpi
-    acpi::init(boot_info.acpi2_rsdp_addr as usize);
 
-    // wake up other CPUs
-    AP_CAN_INIT.store(true, Ordering::Relaxed);
+    // wake up other CPUs, and start those not started by the bootloader
+    AP_CAN_INIT.store(true, Ordering::Release);
+    smp::start_aps();
+    kaslr::unmap_linked();
 
     // call the first main function in kernel.
     crate::kmain();
 }
 
 /// The entry point for other processors
-fn other_start() -> ! {
+extern "C" fn other_start() -> ! {
     // init trap handling
     unsafe {
         trapframe::init();
     }
     // init local apic
     cpu::init();
+    timer::init_local();
     // call the first main function in kernel.
     crate::kmain();
 }
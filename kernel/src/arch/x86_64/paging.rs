@@ -7,8 +7,8 @@ use x86_64::instructions::tlb;
 use x86_64::registers::control::{Cr3, Cr3Flags};
 use x86_64::structures::paging::{
     frame::PhysFrame as Frame,
-    mapper::{MappedPageTable, Mapper},
-    page::{Page, PageRange, Size4KiB},
+    mapper::{MappedPageTable, Mapper, MapperAllSizes},
+    page::{Page, PageRange, Size2MiB, Size4KiB},
     page_table::{PageTable as x86PageTable, PageTableEntry, PageTableFlags as EF},
     FrameAllocator, FrameDeallocator,
 };
@@ -60,13 +60,29 @@ impl PageTable for PageTableImpl {
                 .unwrap()
                 .flush();
         }
-        flush_tlb_all(addr);
         self.get_entry(addr).unwrap()
     }
 
     fn unmap(&mut self, addr: usize) {
         self.0.unmap(Page::of_addr(addr)).unwrap().1.flush();
-        flush_tlb_all(addr);
+    }
+
+    fn map_huge(&mut self, addr: usize, target: usize) -> Option<&mut dyn Entry> {
+        let page = Page::<Size2MiB>::containing_address(VirtAddr::new(addr as u64));
+        let frame = Frame::<Size2MiB>::containing_address(PhysAddr::new(target as u64));
+        let flags = EF::PRESENT | EF::WRITABLE | EF::NO_EXECUTE | EF::HUGE_PAGE;
+        unsafe {
+            self.0
+                .map_to(page, frame, flags, &mut FrameAllocatorForX86)
+                .ok()?
+                .flush();
+        }
+        self.get_entry(addr)
+    }
+
+    fn unmap_huge(&mut self, addr: usize) {
+        let page = Page::<Size2MiB>::containing_address(VirtAddr::new(addr as u64));
+        self.0.unmap(page).unwrap().1.flush();
     }
 
     fn get_entry(&mut self, addr: usize) -> Option<&mut dyn Entry> {
@@ -74,7 +90,8 @@ impl PageTable for PageTableImpl {
         for level in 0..4 {
             let index = (addr >> (12 + (3 - level) * 9)) & 0o777;
             let entry = unsafe { &mut (&mut *page_table)[index] };
-            if level == 3 {
+            // a huge page is mapped by the entry of a higher level
+            if level == 3 || entry.flags().contains(EF::PRESENT | EF::HUGE_PAGE) {
                 let page = Page::of_addr(addr);
                 self.1 = Some(PageEntry(entry, page, self.2));
                 return Some(self.1.as_mut().unwrap());
@@ -88,12 +105,17 @@ impl PageTable for PageTableImpl {
     }
 
     fn get_page_slice_mut<'a>(&mut self, addr: usize) -> &'a mut [u8] {
-        let frame = self.0.translate_page(Page::of_addr(addr)).unwrap();
-        let vaddr = phys_to_virt(frame.start_address().as_u64() as usize);
+        let page = Page::of_addr(addr).start_address();
+        let paddr = self.0.translate_addr(page).unwrap();
+        let vaddr = phys_to_virt(paddr.as_u64() as usize);
         unsafe { core::slice::from_raw_parts_mut(vaddr as *mut u8, 0x1000) }
     }
 
     fn flush_cache_copy_user(&mut self, _start: usize, _end: usize, _execute: bool) {}
+
+    fn flush_tlb_others(&self, start: usize, end: usize) {
+        crate::memory::shootdown_tlb(self.token(), start..end);
+    }
 }
 
 fn frame_to_page_table(frame: Frame) -> *mut x86PageTable {
@@ -106,7 +128,6 @@ impl Entry for PageEntry {
         use x86_64::instructions::tlb::flush;
         let addr = self.1.start_address();
         flush(addr);
-        flush_tlb_all(addr.as_u64() as usize);
     }
     fn accessed(&self) -> bool {
         self.0.flags().contains(EF::ACCESSED)
@@ -171,7 +192,7 @@ impl Entry for PageEntry {
                     (self.1.start_address().as_u64() as usize >> (12 + (3 - level) * 9)) & 0o777;
                 let entry = unsafe { &mut (&mut *page_table)[index] };
                 entry.set_flags(entry.flags() | EF::USER_ACCESSIBLE);
-                if level == 3 {
+                if level == 3 || entry.flags().contains(EF::HUGE_PAGE) {
                     return;
                 }
                 page_table = frame_to_page_table(entry.frame().unwrap());
@@ -188,6 +209,9 @@ impl Entry for PageEntry {
         0
     }
     fn set_mmio(&mut self, _value: u8) {}
+    fn huge(&self) -> bool {
+        self.0.flags().contains(EF::HUGE_PAGE)
+    }
 }
 
 impl PageEntry {
@@ -281,13 +305,3 @@ impl FrameDeallocator<Size4KiB> for FrameAllocatorForX86 {
         dealloc_frame(frame.start_address().as_u64() as usize);
     }
 }
-
-/// Flush TLB for `vaddr` on all CPU
-fn flush_tlb_all(_vaddr: usize) {
-    // TODO: too slow, disable now.
-    return;
-    // if !super::AP_CAN_INIT.load(Ordering::Relaxed) {
-    //     return;
-    // }
-    // super::ipi::invoke_on_allcpu(move || tlb::flush(VirtAddr::new(vaddr as u64)), false);
-}
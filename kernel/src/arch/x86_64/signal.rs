@@ -91,12 +91,24 @@ impl MachineContext {
         ctx.general.r14 = self.r14;
         ctx.general.r15 = self.r15;
         ctx.general.rip = self.rip;
-        ctx.general.rflags = self.eflags;
+        // keep IOPL and the other system flags
+        ctx.general.rflags = (ctx.general.rflags & !USER_RFLAGS) | (self.eflags & USER_RFLAGS);
         ctx.trap_num = self.trapno;
         ctx.error_code = self.err;
     }
 }
 
+/// Flags in RFLAGS which user mode can change:
+/// CF PF AF ZF SF TF DF OF and RF, AC
+pub const USER_RFLAGS: usize = 0x1_0dd5 | 1 << 18;
+
+/// Let the syscall `num` with `args`, which returned EINTR, run again on return to user
+pub fn restart_syscall(tf: &mut UserContext, num: usize, _args: &[usize; 6]) {
+    // the syscall instruction is 2 bytes long, and only rax is changed by the return value
+    tf.general.rip -= 2;
+    tf.general.rax = num;
+}
+
 pub const RET_CODE: [u8; 7] = [
     // mov SYS_RT_SIGRETURN, %eax
     0xb8, // SYS_RT_SIGRETURN
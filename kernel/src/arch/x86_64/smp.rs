@@ -0,0 +1,193 @@
+//! Bringup of the application processors
+//!
+//! rboot starts the processors the firmware knows and they wait for `AP_CAN_INIT`.
+//! Those in the MADT which are not online soon after it are started here by INIT-SIPI-SIPI,
+//! through the trampoline copied below 1MiB, each with its own kernel stack.
+//! Each CPU then sets up its own GDT and TSS in `trapframe::init` and runs its own idle loop.
+
+use super::acpi;
+use super::timer::{lapic_read, lapic_write, timer_now};
+use crate::consts::MAX_CPU_NUM;
+use crate::memory::{alloc_frame_contiguous, phys_to_virt};
+use crate::process::online_cpus;
+use alloc::vec::Vec;
+use core::sync::atomic::{spin_loop_hint, AtomicUsize, Ordering};
+use core::time::Duration;
+use rcore_memory::PAGE_SIZE;
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::structures::paging::{PageTable, PageTableFlags as EF, PhysFrame};
+use x86_64::PhysAddr;
+
+/// Where the trampoline is copied, also in `trampoline.S`
+const AP_TRAMPOLINE: usize = 0x8000;
+/// Page table of the trampoline, mapping the first 2MiB to itself and the kernel
+const AP_PML4: usize = 0x9000;
+const AP_PDPT: usize = 0xa000;
+const AP_PD: usize = 0xb000;
+
+const AP_STACK_SIZE: usize = 0x80000;
+
+/// Time for the processors started by the bootloader to be online
+const BOOT_TIMEOUT: Duration = Duration::from_millis(100);
+/// Time for a processor to be online after SIPI
+const SIPI_TIMEOUT: Duration = Duration::from_millis(200);
+
+const ICR_LOW: usize = 0x300;
+const ICR_HIGH: usize = 0x310;
+const ICR_INIT: u32 = 0x500;
+const ICR_STARTUP: u32 = 0x600;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+const ICR_LEVEL: u32 = 1 << 15;
+
+global_asm!(include_str!("trampoline.S"));
+
+extern "C" {
+    fn ap_trampoline();
+    fn ap_trampoline_cr3();
+    fn ap_trampoline_stack();
+    fn ap_trampoline_entry();
+    fn ap_trampoline_end();
+}
+
+/// The page table the processors switch to from the trampoline
+static KERNEL_CR3: AtomicUsize = AtomicUsize::new(0);
+
+/// Start the processors in the MADT which are not online yet.
+/// Called by the boot processor after `AP_CAN_INIT`.
+pub fn start_aps() {
+    let bsp = super::cpu::id();
+    let cpus: Vec<usize> = acpi::cpus()
+        .into_iter()
+        .filter(|&id| {
+            if id >= MAX_CPU_NUM || id >= core::mem::size_of::<usize>() * 8 {
+                warn!("smp: CPU with local APIC id {} is not supported", id);
+                return false;
+            }
+            id != bsp
+        })
+        .collect();
+    if wait_online(&cpus, BOOT_TIMEOUT) {
+        return;
+    }
+    setup_trampoline();
+    for &id in cpus.iter() {
+        if is_online(id) {
+            continue;
+        }
+        info!("smp: start CPU {} by SIPI", id);
+        let stack = match alloc_frame_contiguous(AP_STACK_SIZE / PAGE_SIZE, 0) {
+            Some(stack) => phys_to_virt(stack),
+            None => {
+                warn!("smp: no memory for the stack of CPU {}", id);
+                break;
+            }
+        };
+        let stack_top = stack + AP_STACK_SIZE;
+        unsafe {
+            write_param(ap_trampoline_stack as usize, stack_top as u64);
+        }
+        send_init_sipi(id);
+        // one at a time, as the stack is passed in the trampoline
+        if !wait_online(&[id], SIPI_TIMEOUT) {
+            warn!("smp: CPU {} does not respond", id);
+        }
+    }
+}
+
+fn is_online(id: usize) -> bool {
+    online_cpus() & (1 << id) != 0
+}
+
+/// Wait until all of `cpus` are online, or `timeout`
+fn wait_online(cpus: &[usize], timeout: Duration) -> bool {
+    let deadline = timer_now() + timeout;
+    loop {
+        if cpus.iter().all(|&id| is_online(id)) {
+            return true;
+        }
+        if timer_now() >= deadline {
+            return false;
+        }
+        spin_loop_hint();
+    }
+}
+
+fn delay(duration: Duration) {
+    let deadline = timer_now() + duration;
+    while timer_now() < deadline {
+        spin_loop_hint();
+    }
+}
+
+/// Copy the trampoline and build its page table
+fn setup_trampoline() {
+    let code = ap_trampoline as usize;
+    let len = ap_trampoline_end as usize - code;
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            code as *const u8,
+            phys_to_virt(AP_TRAMPOLINE) as *mut u8,
+            len,
+        );
+    }
+    let (frame, _) = Cr3::read();
+    let kernel_cr3 = frame.start_address().as_u64() as usize;
+    KERNEL_CR3.store(kernel_cr3, Ordering::SeqCst);
+
+    let flags = EF::PRESENT | EF::WRITABLE;
+    unsafe {
+        let kernel = &*(phys_to_virt(kernel_cr3) as *const PageTable);
+        let pml4 = &mut *(phys_to_virt(AP_PML4) as *mut PageTable);
+        let pdpt = &mut *(phys_to_virt(AP_PDPT) as *mut PageTable);
+        let pd = &mut *(phys_to_virt(AP_PD) as *mut PageTable);
+        for (entry, kernel) in pml4.iter_mut().zip(kernel.iter()) {
+            *entry = kernel.clone();
+        }
+        pdpt.zero();
+        pd.zero();
+        pd[0].set_addr(PhysAddr::new(0), flags | EF::HUGE_PAGE);
+        pdpt[0].set_addr(PhysAddr::new(AP_PD as u64), flags);
+        pml4[0].set_addr(PhysAddr::new(AP_PDPT as u64), flags);
+        write_param(ap_trampoline_cr3 as usize, AP_PML4 as u64);
+        write_param(ap_trampoline_entry as usize, ap_entry as usize as u64);
+    }
+}
+
+/// Write the parameter at `symbol` of the trampoline to its copy
+unsafe fn write_param(symbol: usize, value: u64) {
+    let offset = symbol - ap_trampoline as usize;
+    *(phys_to_virt(AP_TRAMPOLINE + offset) as *mut u64) = value;
+}
+
+/// The universal startup algorithm of the Intel MultiProcessor Specification
+fn send_init_sipi(id: usize) {
+    send_ipi_raw(id, ICR_INIT | ICR_LEVEL | ICR_ASSERT);
+    send_ipi_raw(id, ICR_INIT | ICR_LEVEL);
+    delay(Duration::from_millis(10));
+    for _ in 0..2 {
+        send_ipi_raw(id, ICR_STARTUP | (AP_TRAMPOLINE >> 12) as u32);
+        delay(Duration::from_micros(200));
+    }
+}
+
+fn send_ipi_raw(id: usize, command: u32) {
+    lapic_write(ICR_HIGH, (id as u32) << 24);
+    lapic_write(ICR_LOW, command);
+    while lapic_read(ICR_LOW) & ICR_PENDING != 0 {
+        spin_loop_hint();
+    }
+}
+
+/// The entry of the processors from the trampoline, on their own stack
+extern "C" fn ap_entry() -> ! {
+    super::cpu::init_id();
+    let cr3 = KERNEL_CR3.load(Ordering::SeqCst);
+    unsafe {
+        Cr3::write(
+            PhysFrame::containing_address(PhysAddr::new(cr3 as u64)),
+            Cr3Flags::empty(),
+        );
+    }
+    super::other_start();
+}
@@ -1,8 +1,178 @@
+use crate::memory::phys_to_virt;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{spin_loop_hint, AtomicU64, Ordering};
 use core::time::Duration;
+use log::*;
+use raw_cpuid::CpuId;
+use x86_64::instructions::port::Port;
 
+/// TSC frequency in kHz, calibrated at boot
+static TSC_KHZ: AtomicU64 = AtomicU64::new(2_600_000);
+/// Local APIC timer frequency in kHz, 0 if not calibrated so that the timer stays periodic
+static LAPIC_KHZ: AtomicU64 = AtomicU64::new(0);
+
+const LAPIC_ADDR: usize = 0xfee00000;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_INITIAL_COUNT: usize = 0x380;
+const LAPIC_CURRENT_COUNT: usize = 0x390;
+const LAPIC_DIVIDE_CONFIG: usize = 0x3e0;
+/// Divide the bus clock by 16
+const LAPIC_DIVIDE_16: u32 = 0b0011;
+/// Timer mode bits of LVT timer, 0 for one-shot
+const LAPIC_TIMER_MODE: u32 = 0b11 << 17;
+const LAPIC_MASKED: u32 = 1 << 16;
+
+pub(super) fn lapic_read(reg: usize) -> u32 {
+    unsafe { read_volatile((phys_to_virt(LAPIC_ADDR) + reg) as *const u32) }
+}
+
+pub(super) fn lapic_write(reg: usize, value: u32) {
+    unsafe { write_volatile((phys_to_virt(LAPIC_ADDR) + reg) as *mut u32, value) }
+}
+
+/// Frequency of PIT input clock in Hz
+const PIT_FREQUENCY: u64 = 1_193_182;
+/// Time to calibrate TSC and local APIC timer in ms
+const CALIBRATE_MS: u64 = 10;
+
+/// Find the frequency of TSC, calibrate the local APIC timer against it,
+/// then turn the local APIC timer to one-shot and stop the PIT.
+/// Should be called with interrupts disabled.
+pub fn init() {
+    // the periodic tick, restored if the local APIC timer is not calibrated
+    let divide = lapic_read(LAPIC_DIVIDE_CONFIG);
+    let count = lapic_read(LAPIC_INITIAL_COUNT);
+    match tsc_khz_by_cpuid().or_else(calibrate_tsc_by_pit) {
+        Some(khz) => {
+            TSC_KHZ.store(khz, Ordering::Relaxed);
+            info!("timer: TSC frequency {}.{:03} MHz", khz / 1000, khz % 1000);
+        }
+        None => warn!("timer: failed to calibrate TSC"),
+    }
+    let lapic_ticks = {
+        let tsc_ticks = TSC_KHZ.load(Ordering::Relaxed) * CALIBRATE_MS;
+        lapic_write(LAPIC_DIVIDE_CONFIG, LAPIC_DIVIDE_16);
+        lapic_write(LAPIC_INITIAL_COUNT, u32::max_value());
+        let start = unsafe { core::arch::x86_64::_rdtsc() };
+        while unsafe { core::arch::x86_64::_rdtsc() } - start < tsc_ticks {
+            spin_loop_hint();
+        }
+        u32::max_value() - lapic_read(LAPIC_CURRENT_COUNT)
+    };
+    let lapic_khz = lapic_ticks as u64 / CALIBRATE_MS;
+    LAPIC_KHZ.store(lapic_khz, Ordering::Relaxed);
+    if lapic_khz == 0 {
+        lapic_write(LAPIC_DIVIDE_CONFIG, divide);
+        lapic_write(LAPIC_INITIAL_COUNT, count);
+    }
+    info!("timer: local APIC timer frequency {} kHz", lapic_khz);
+    stop_pit();
+    init_local();
+}
+
+/// TSC frequency in kHz told by CPUID, as the core crystal clock times a ratio,
+/// or else as the base frequency of the processor
+fn tsc_khz_by_cpuid() -> Option<u64> {
+    let cpuid = CpuId::new();
+    if let Some(info) = cpuid.get_tsc_info() {
+        let crystal_hz = info.nominal_frequency() as u64;
+        if crystal_hz != 0 && info.denominator() != 0 {
+            return Some(crystal_hz * info.numerator() as u64 / info.denominator() as u64 / 1000)
+                .filter(|&khz| khz != 0);
+        }
+    }
+    cpuid
+        .get_processor_frequency_info()
+        .map(|info| info.processor_base_frequency() as u64 * 1000)
+        .filter(|&khz| khz != 0)
+}
+
+/// TSC frequency in kHz, counted against channel 2 of PIT
+fn calibrate_tsc_by_pit() -> Option<u64> {
+    let latch = PIT_FREQUENCY * CALIBRATE_MS / 1000;
+    let mut control = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel2 = Port::<u8>::new(0x42);
+    let (start, end) = unsafe {
+        // enable the gate of channel 2, but not the speaker
+        let value = control.read();
+        control.write((value & !0x02) | 0x01);
+        // channel 2, lobyte/hibyte, mode 0: interrupt on terminal count
+        command.write(0xb0);
+        channel2.write(latch as u8);
+        channel2.write((latch >> 8) as u8);
+        let start = core::arch::x86_64::_rdtsc();
+        // output of channel 2 goes high on terminal count
+        while control.read() & 0x20 == 0 {}
+        let end = core::arch::x86_64::_rdtsc();
+        control.write(value & !0x03);
+        (start, end)
+    };
+    Some((end - start) / CALIBRATE_MS).filter(|&khz| khz != 0)
+}
+
+/// Stop the periodic interrupt of channel 0 of PIT the firmware may have left,
+/// as each CPU ticks by its local APIC timer
+fn stop_pit() {
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel0 = Port::<u8>::new(0x40);
+    unsafe {
+        // channel 0, lobyte/hibyte, mode 0, which fires once and stops
+        command.write(0x30);
+        channel0.write(0);
+        channel0.write(0);
+    }
+}
+
+/// Turn the local APIC timer of this CPU to one-shot, if it is calibrated.
+/// It is disarmed until the next event is set.
+pub fn init_local() {
+    if LAPIC_KHZ.load(Ordering::Relaxed) == 0 {
+        warn!("timer: local APIC timer not calibrated, keep the periodic tick");
+        return;
+    }
+    lapic_write(LAPIC_DIVIDE_CONFIG, LAPIC_DIVIDE_16);
+    let lvt = lapic_read(LAPIC_LVT_TIMER);
+    lapic_write(LAPIC_LVT_TIMER, lvt & !(LAPIC_TIMER_MODE | LAPIC_MASKED));
+    lapic_write(LAPIC_INITIAL_COUNT, 0);
+}
+
+/// Program the timer interrupt of this CPU at `deadline` of `timer_now`, or never if None
+pub fn set_next_event(deadline: Option<Duration>) {
+    let khz = LAPIC_KHZ.load(Ordering::Relaxed);
+    if khz == 0 {
+        return;
+    }
+    // writing the initial count arms the timer, and 0 disarms it
+    let count = match deadline {
+        Some(deadline) => {
+            let nanos = deadline
+                .checked_sub(timer_now())
+                .unwrap_or_default()
+                .as_nanos();
+            // a farther deadline is reprogrammed when the timer fires early
+            (nanos * khz as u128 / 1_000_000)
+                .max(1)
+                .min(u32::max_value() as u128) as u32
+        }
+        None => 0,
+    };
+    lapic_write(LAPIC_INITIAL_COUNT, count);
+}
+
+/// TSC frequency in kHz, by which `timer_now` counts
+pub fn tsc_khz() -> u64 {
+    TSC_KHZ.load(Ordering::Relaxed)
+}
+
+/// Time since boot, from TSC
 pub fn timer_now() -> Duration {
-    // TODO: get actual tsc
-    const TSC_FREQUENCY: u16 = 2600;
     let tsc = unsafe { core::arch::x86_64::_rdtsc() };
-    Duration::from_nanos(tsc * 1000 / TSC_FREQUENCY as u64)
+    let khz = TSC_KHZ.load(Ordering::Relaxed);
+    Duration::from_nanos((tsc as u128 * 1_000_000 / khz as u128) as u64)
+}
+
+/// Resolution of `timer_now`
+pub fn timer_resolution() -> Duration {
+    Duration::from_nanos(1)
 }
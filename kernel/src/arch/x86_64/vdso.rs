@@ -0,0 +1,120 @@
+//! The vDSO, a shared object mapped into every process,
+//! whose `__vdso_clock_gettime` and `__vdso_getcpu` are answered in user without a system call.
+//!
+//! The image in `vdso.S` reads the data page mapped right before it,
+//! which the kernel updates under a sequence count.
+
+use crate::memory::{alloc_frame_contiguous, phys_to_virt, Linear, MemoryAttr, MemorySet};
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+use rcore_memory::PAGE_SIZE;
+
+global_asm!(include_str!("vdso.S"));
+
+extern "C" {
+    fn vdso_start();
+    fn vdso_end();
+}
+
+/// The data page, as read by `vdso.S`
+#[repr(C)]
+struct VdsoData {
+    /// Odd while written
+    seq: AtomicU32,
+    flags: AtomicU32,
+    tsc_khz: AtomicU64,
+    /// The real time in ns when TSC is zero
+    realtime_offset: AtomicU64,
+}
+
+/// TSC is the clock
+const FLAG_TSC: u32 = 1;
+/// `rdtscp` gives the CPU id in TSC_AUX
+const FLAG_RDTSCP: u32 = 2;
+
+/// Physical address of the data page, followed by the image, 0 if there is none
+static PADDR: AtomicUsize = AtomicUsize::new(0);
+
+fn image_size() -> usize {
+    vdso_end as usize - vdso_start as usize
+}
+
+fn pages() -> usize {
+    1 + (image_size() + PAGE_SIZE - 1) / PAGE_SIZE
+}
+
+fn data() -> Option<&'static VdsoData> {
+    match PADDR.load(Ordering::Acquire) {
+        0 => None,
+        paddr => Some(unsafe { &*(phys_to_virt(paddr) as *const VdsoData) }),
+    }
+}
+
+/// Copy the image to the frames mapped into processes, after the timer is calibrated
+pub fn init() {
+    let paddr = match alloc_frame_contiguous(pages(), 0) {
+        Some(paddr) => paddr,
+        None => {
+            warn!("vdso: no memory");
+            return;
+        }
+    };
+    unsafe {
+        let vaddr = phys_to_virt(paddr) as *mut u8;
+        core::ptr::write_bytes(vaddr, 0, pages() * PAGE_SIZE);
+        core::ptr::copy_nonoverlapping(vdso_start as *const u8, vaddr.add(PAGE_SIZE), image_size());
+        let data = &*(vaddr as *const VdsoData);
+        let tsc_khz = super::timer::tsc_khz();
+        data.tsc_khz.store(tsc_khz, Ordering::Relaxed);
+        let mut flags = 0;
+        if tsc_khz != 0 {
+            flags |= FLAG_TSC;
+        }
+        if super::cpu::has_rdtscp() {
+            flags |= FLAG_RDTSCP;
+        }
+        data.flags.store(flags, Ordering::Relaxed);
+    }
+    PADDR.store(paddr, Ordering::Release);
+    info!("vdso: init end");
+}
+
+/// Publish the real time when `timer_now` is zero.
+/// Callers are serialized by the lock of the real time.
+pub fn set_realtime_offset(offset: Duration) {
+    if let Some(data) = data() {
+        data.seq.fetch_add(1, Ordering::SeqCst);
+        data.realtime_offset
+            .store(offset.as_nanos() as u64, Ordering::SeqCst);
+        data.seq.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Map the data page and the image at `addr` of `vm`, returning the address of the image
+pub fn map(vm: &mut MemorySet, addr: usize) -> Option<usize> {
+    let paddr = PADDR.load(Ordering::Acquire);
+    if paddr == 0 {
+        return None;
+    }
+    let offset = paddr as isize - addr as isize;
+    vm.push(
+        addr,
+        addr + PAGE_SIZE,
+        MemoryAttr::default().user().readonly(),
+        Linear::new(offset),
+        "vvar",
+    );
+    vm.push(
+        addr + PAGE_SIZE,
+        addr + pages() * PAGE_SIZE,
+        MemoryAttr::default().user().readonly().execute(),
+        Linear::new(offset),
+        "vdso",
+    );
+    Some(addr + PAGE_SIZE)
+}
+
+/// Size of the mapping
+pub fn size() -> usize {
+    pages() * PAGE_SIZE
+}
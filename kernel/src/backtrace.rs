@@ -71,6 +71,12 @@ pub fn backtrace() {
             current_fp = ((current_fp as isize) - sp_offset) as usize;
         }
 
+        // PCs are printed where they are linked, for addr2line
+        #[cfg(target_arch = "x86_64")]
+        let moved = crate::arch::kaslr::offset();
+        #[cfg(not(target_arch = "x86_64"))]
+        let moved = 0;
+
         println!("=== BEGIN rCore stack trace ===");
 
         while current_pc >= stext as usize
@@ -83,7 +89,7 @@ pub fn backtrace() {
                     println!(
                         "#{:02} PC: {:#010X} FP: {:#010X}",
                         stack_num,
-                        current_pc - size_of::<usize>(),
+                        current_pc - size_of::<usize>() - moved,
                         current_fp
                     );
                 }
@@ -91,7 +97,7 @@ pub fn backtrace() {
                     println!(
                         "#{:02} PC: {:#018X} FP: {:#018X}",
                         stack_num,
-                        current_pc - size_of::<usize>(),
+                        current_pc - size_of::<usize>() - moved,
                         current_fp
                     );
                 }
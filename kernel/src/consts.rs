@@ -8,6 +8,9 @@ pub const USEC_PER_TICK: usize = 10000;
 
 pub const INFORM_PER_MSEC: usize = 50;
 
+/// Address space after the program break kept for the heap to grow into
+pub const USER_HEAP_SIZE: usize = 0x400_0000; // 64 MB
+
 lazy_static! {
     pub static ref SMP_CORES: usize = {
         if let Some(smp_str) = option_env!("SMP") {
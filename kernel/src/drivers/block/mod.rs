@@ -12,4 +12,9 @@ pub trait BlockDriver: Driver {
     fn write_block(&self, _block_id: usize, _buf: &[u8]) -> bool {
         unimplemented!("not a block driver")
     }
+
+    /// Flush the volatile write cache of the device, if any
+    fn flush(&self) -> bool {
+        true
+    }
 }
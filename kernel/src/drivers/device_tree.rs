@@ -23,7 +23,9 @@ fn walk_dt_node(dt: &Node, intc_only: bool) {
     if let Ok(compatible) = dt.prop_str("compatible") {
         if dt.has_prop("interrupt-controller") == intc_only {
             let registry = DEVICE_TREE_REGISTRY.read();
-            if let Some(f) = registry.get(compatible) {
+            // the most specific one first
+            let found = compatible.split('\0').find_map(|name| registry.get(name));
+            if let Some(f) = found {
                 f(dt);
             }
         }
@@ -0,0 +1,171 @@
+//! ARM Generic Interrupt Controller v2
+//!
+//! Interrupts 0~15 are SGIs sent between CPUs, 16~31 PPIs private to each CPU,
+//! and the rest SPIs of devices, which are routed to the first CPU.
+
+use super::super::DRIVERS;
+use super::{super::IRQ_MANAGER, IntcDriver, IrqManager};
+use crate::drivers::{
+    device_tree::DEVICE_TREE_INTC, device_tree::DEVICE_TREE_REGISTRY, DeviceType, Driver,
+};
+use crate::memory::phys_to_virt;
+use crate::{sync::SpinNoIrqLock as Mutex, util::read, util::write};
+use alloc::string::String;
+use alloc::sync::Arc;
+use device_tree::util::SliceRead;
+use device_tree::Node;
+use spin::RwLock;
+
+// distributor
+const GICD_CTLR: usize = 0x000;
+const GICD_ISENABLER: usize = 0x100;
+const GICD_IPRIORITYR: usize = 0x400;
+const GICD_ITARGETSR: usize = 0x800;
+const GICD_SGIR: usize = 0xf00;
+
+// CPU interface
+const GICC_CTLR: usize = 0x000;
+const GICC_PMR: usize = 0x004;
+const GICC_IAR: usize = 0x00c;
+const GICC_EOIR: usize = 0x010;
+
+const SPURIOUS: usize = 1023;
+const SGI_END: usize = 16;
+const PRIVATE_END: usize = 32;
+const DEFAULT_PRIORITY: u8 = 0xa0;
+
+pub struct Gic {
+    dist: usize,
+    cpu: usize,
+    manager: Mutex<IrqManager>,
+}
+
+lazy_static! {
+    static ref GIC: RwLock<Option<Arc<Gic>>> = RwLock::new(None);
+}
+
+impl Driver for Gic {
+    fn try_handle_interrupt(&self, _irq: Option<usize>) -> bool {
+        let iar: u32 = read(self.cpu + GICC_IAR);
+        let id = (iar & 0x3ff) as usize;
+        if id == SPURIOUS {
+            return false;
+        }
+        let res = if id < SGI_END {
+            // IPIs only wake the CPU up
+            true
+        } else {
+            self.manager.lock().try_handle_interrupt(Some(id))
+        };
+        // complete
+        write(self.cpu + GICC_EOIR, iar);
+        res
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Intc
+    }
+
+    fn get_id(&self) -> String {
+        format!("gic_{}", self.dist)
+    }
+}
+
+impl IntcDriver for Gic {
+    /// Register interrupt controller local irq
+    fn register_local_irq(&self, irq: usize, driver: Arc<dyn Driver>) {
+        if irq >= PRIVATE_END {
+            // to the first CPU
+            write::<u8>(self.dist + GICD_ITARGETSR + irq, 1);
+        }
+        write::<u8>(self.dist + GICD_IPRIORITYR + irq, DEFAULT_PRIORITY);
+        write::<u32>(self.dist + GICD_ISENABLER + irq / 32 * 4, 1 << (irq % 32));
+        let mut manager = self.manager.lock();
+        manager.register_irq(irq, driver);
+    }
+}
+
+impl Gic {
+    /// Enable the CPU interface and the SGIs and PPIs of this CPU, which are banked
+    fn init_cpu(&self) {
+        for irq in 0..PRIVATE_END {
+            write::<u8>(self.dist + GICD_IPRIORITYR + irq, DEFAULT_PRIORITY);
+        }
+        write::<u32>(self.dist + GICD_ISENABLER, u32::max_value());
+        // accept all priorities
+        write::<u32>(self.cpu + GICC_PMR, 0xff);
+        write::<u32>(self.cpu + GICC_CTLR, 1);
+    }
+}
+
+/// The interrupt number in the GIC of a device, from its `interrupts` of 3 cells
+pub fn irq_of(dt: &Node) -> Option<usize> {
+    let cells = dt.prop_raw("interrupts")?.as_slice();
+    let kind = cells.read_be_u32(0).ok()?;
+    let num = cells.read_be_u32(4).ok()? as usize;
+    // SPI or PPI
+    Some(if kind == 0 {
+        num + PRIVATE_END
+    } else {
+        num + SGI_END
+    })
+}
+
+/// Register `driver` for the `irq` of the GIC, or for all irqs if there is none
+pub fn register_irq(irq: Option<usize>, driver: Arc<dyn Driver>) {
+    match (irq, GIC.read().as_ref()) {
+        (Some(irq), Some(gic)) => gic.register_local_irq(irq, driver),
+        _ => IRQ_MANAGER.write().register_all(driver),
+    }
+}
+
+/// Set up the GIC for this CPU, called by each CPU once it is found
+pub fn init_cpu() {
+    if let Some(gic) = GIC.read().as_ref() {
+        gic.init_cpu();
+    }
+}
+
+/// Send the software generated interrupt `sgi` to CPU `cpu_id`
+pub fn send_sgi(cpu_id: usize, sgi: usize) {
+    if let Some(gic) = GIC.read().as_ref() {
+        // make the memory written visible to the target first
+        unsafe { llvm_asm!("dsb sy" ::: "memory" : "volatile") };
+        write::<u32>(gic.dist + GICD_SGIR, (1 << (16 + cpu_id) | sgi) as u32);
+    }
+}
+
+fn init_dt(dt: &Node) {
+    let reg = match dt.prop_raw("reg") {
+        Some(reg) => reg.as_slice(),
+        None => return,
+    };
+    let dist = reg.read_be_u64(0).unwrap() as usize;
+    let cpu = reg.read_be_u64(16).unwrap() as usize;
+    info!("Found GICv2 at {:#x} and {:#x}, {:?}", dist, cpu, dt);
+    let gic = Arc::new(Gic {
+        dist: phys_to_virt(dist),
+        cpu: phys_to_virt(cpu),
+        manager: Mutex::new(IrqManager::new(false)),
+    });
+    write::<u32>(gic.dist + GICD_CTLR, 1);
+    gic.init_cpu();
+
+    DRIVERS.write().push(gic.clone());
+    // register under root irq manager
+    // 0x10002: from lower el, irq
+    IRQ_MANAGER.write().register_irq(0x10002, gic.clone());
+    // 0x10001: from current el, irq
+    IRQ_MANAGER.write().register_irq(0x10001, gic.clone());
+    // register interrupt controller
+    if let Ok(phandle) = dt.prop_u32("phandle") {
+        DEVICE_TREE_INTC.write().insert(phandle, gic.clone());
+    }
+    *GIC.write() = Some(gic);
+}
+
+pub fn driver_init() {
+    DEVICE_TREE_REGISTRY
+        .write()
+        .insert("arm,cortex-a15-gic", init_dt);
+}
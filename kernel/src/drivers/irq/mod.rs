@@ -7,6 +7,8 @@ use alloc::vec::Vec;
 
 #[cfg(feature = "board_raspi3")]
 pub mod bcm2837;
+#[cfg(target_arch = "aarch64")]
+pub mod gic;
 pub mod plic;
 
 // Irq manager
@@ -105,7 +105,10 @@ impl BlockDevice for BlockDriverWrapper {
     }
 
     fn sync(&self) -> dev::Result<()> {
-        Ok(())
+        match self.0.flush() {
+            true => Ok(()),
+            false => Err(DevError),
+        }
     }
 }
 
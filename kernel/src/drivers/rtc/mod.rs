@@ -9,6 +9,8 @@ pub mod rtc_goldfish;
 pub trait RtcDriver: Driver {
     // read seconds since epoch
     fn read_epoch(&self) -> u64;
+    // set seconds since epoch
+    fn write_epoch(&self, epoch: u64);
 }
 
 /// Try to read epoch from one rtc driver
@@ -20,3 +22,11 @@ pub fn read_epoch() -> u64 {
         0
     }
 }
+
+/// Set the time of one rtc driver, so that it is kept after reboot
+pub fn write_epoch(epoch: u64) {
+    let drivers = RTC_DRIVERS.read();
+    if let Some(driver) = drivers.first() {
+        driver.write_epoch(epoch);
+    }
+}
@@ -28,10 +28,37 @@ unsafe fn read_rtc(reg: u8) -> u8 {
     return data.read();
 }
 
+unsafe fn write_rtc(reg: u8, value: u8) {
+    let mut addr = Port::<u8>::new(CMOS_ADDR);
+    let mut data = Port::<u8>::new(CMOS_DATA);
+
+    addr.write(reg);
+    data.write(value);
+}
+
 fn bcd2bin(num: u64) -> u64 {
     (num & 0x0f) + (num >> 4) * 10
 }
 
+fn bin2bcd(num: u64) -> u64 {
+    (num / 10) << 4 | (num % 10)
+}
+
+/// Year, month, day of days since 1970-01-01
+// Ref: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
 pub struct RtcCmos;
 
 impl Driver for RtcCmos {
@@ -100,6 +127,39 @@ impl RtcDriver for RtcCmos {
             result
         }
     }
+
+    fn write_epoch(&self, epoch: u64) {
+        let (year, month, day) = civil_from_days(epoch / 86400);
+        let time = epoch % 86400;
+        // the century is not kept, as in `read_epoch`
+        let mut values = [
+            (0x00, time % 60),
+            (0x02, time / 60 % 60),
+            (0x04, time / 3600),
+            (0x07, day),
+            (0x08, month),
+            (0x09, year % 100),
+        ];
+        unsafe {
+            let flags = interrupt::disable_and_store();
+
+            let control = read_rtc(0x0B);
+            if (control & 0x04) == 0 {
+                // BCD
+                for (_, value) in values.iter_mut() {
+                    *value = bin2bcd(*value);
+                }
+            }
+            // stop updates while setting the time
+            write_rtc(0x0B, control | 0x80);
+            for &(reg, value) in values.iter() {
+                write_rtc(reg, value as u8);
+            }
+            write_rtc(0x0B, control & !0x80);
+
+            interrupt::restore(flags);
+        }
+    }
 }
 
 pub fn init() {
@@ -2,7 +2,7 @@
 use super::super::RTC_DRIVERS;
 use super::RtcDriver;
 use crate::drivers::device_tree::DEVICE_TREE_REGISTRY;
-use crate::util::read;
+use crate::util::{read, write};
 use crate::{
     arch::interrupt,
     drivers::{DeviceType, Driver},
@@ -40,6 +40,13 @@ impl RtcDriver for RtcGoldfish {
         let ns = ((high as u64) << 32) | (low as u64);
         ns / 1_000_000_000u64
     }
+
+    fn write_epoch(&self, epoch: u64) {
+        let ns = epoch * 1_000_000_000u64;
+        // the time is set when the low half is written
+        write(self.base + TIMER_TIME_HIGH, (ns >> 32) as u32);
+        write(self.base + TIMER_TIME_LOW, ns as u32);
+    }
 }
 
 fn init_dt(dt: &Node) {
@@ -9,6 +9,8 @@ pub mod bcm2837;
 pub mod com;
 #[cfg(target_arch = "x86_64")]
 pub mod keyboard;
+#[cfg(target_arch = "aarch64")]
+pub mod pl011;
 pub mod uart16550;
 
 pub mod virtio_console;
@@ -0,0 +1,112 @@
+//! ARM PrimeCell UART (PL011) driver for aarch64 QEMU virt
+
+use super::SerialDriver;
+use crate::drivers::device_tree::DEVICE_TREE_REGISTRY;
+use crate::drivers::irq::gic;
+use crate::drivers::SERIAL_DRIVERS;
+use crate::drivers::{DeviceType, Driver, DRIVERS};
+use crate::{
+    memory::phys_to_virt,
+    util::{read, write},
+};
+use alloc::{string::String, sync::Arc};
+use device_tree::Node;
+
+pub struct Pl011 {
+    base: usize,
+}
+
+impl Driver for Pl011 {
+    fn try_handle_interrupt(&self, irq: Option<usize>) -> bool {
+        let mut handled = false;
+        while let Some(c) = self.getchar_option() {
+            crate::trap::serial(c);
+            handled = true;
+        }
+        if handled {
+            write::<u32>(self.base + UART_ICR, UART_INT_RX | UART_INT_RT);
+            super::SERIAL_ACTIVITY.notify_all();
+        }
+        handled
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Serial
+    }
+
+    fn get_id(&self) -> String {
+        format!("pl011_{}", self.base)
+    }
+}
+
+impl Pl011 {
+    fn new(base: usize) -> Pl011 {
+        // disable while setting up
+        write::<u32>(base + UART_CR, 0);
+        // 8 data bits, 1 stop bit, parity off, FIFO off to interrupt at each byte
+        write::<u32>(base + UART_LCRH, UART_LCRH_WLEN8);
+        // Enable rcv interrupts
+        write::<u32>(base + UART_IMSC, UART_INT_RX | UART_INT_RT);
+        write::<u32>(base + UART_CR, UART_CR_UARTEN | UART_CR_TXE | UART_CR_RXE);
+        Pl011 { base }
+    }
+
+    pub fn putchar(&self, c: u8) {
+        while read::<u32>(self.base + UART_FR) & UART_FR_TXFF != 0 {}
+        write::<u32>(self.base + UART_DR, c as u32);
+    }
+
+    /// non-blocking version of getchar()
+    pub fn getchar_option(&self) -> Option<u8> {
+        if read::<u32>(self.base + UART_FR) & UART_FR_RXFE != 0 {
+            None
+        } else {
+            Some(read::<u32>(self.base + UART_DR) as u8)
+        }
+    }
+}
+
+impl SerialDriver for Pl011 {
+    fn read(&self) -> u8 {
+        self.getchar_option().unwrap_or(0)
+    }
+
+    fn write(&self, data: &[u8]) {
+        for byte in data {
+            self.putchar(*byte);
+        }
+    }
+
+    fn try_read(&self) -> Option<u8> {
+        self.getchar_option()
+    }
+}
+
+const UART_DR: usize = 0x00; // Data Register
+const UART_FR: usize = 0x18; // Flag Register
+const UART_FR_RXFE: u32 = 1 << 4; // Receive FIFO empty
+const UART_FR_TXFF: u32 = 1 << 5; // Transmit FIFO full
+const UART_LCRH: usize = 0x2c; // Line Control Register
+const UART_LCRH_WLEN8: u32 = 0b11 << 5; // Wordlength: 8 bits
+const UART_CR: usize = 0x30; // Control Register
+const UART_CR_UARTEN: u32 = 1 << 0; // UART enable
+const UART_CR_TXE: u32 = 1 << 8; // Transmit enable
+const UART_CR_RXE: u32 = 1 << 9; // Receive enable
+const UART_IMSC: usize = 0x38; // Interrupt Mask Set/Clear Register
+const UART_ICR: usize = 0x44; // Interrupt Clear Register
+const UART_INT_RX: u32 = 1 << 4; // Receive interrupt
+const UART_INT_RT: u32 = 1 << 6; // Receive timeout interrupt
+
+pub fn init_dt(dt: &Node) {
+    let addr = dt.prop_usize("reg").unwrap();
+    let base = phys_to_virt(addr);
+    info!("Init pl011 at {:#x}, {:?}", base, dt);
+    let uart = Arc::new(Pl011::new(base));
+    DRIVERS.write().push(uart.clone());
+    SERIAL_DRIVERS.write().push(uart.clone());
+    gic::register_irq(gic::irq_of(dt), uart);
+}
+
+pub fn driver_init() {
+    DEVICE_TREE_REGISTRY.write().insert("arm,pl011", init_dt);
+}
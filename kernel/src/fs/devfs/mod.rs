@@ -1,5 +1,10 @@
 //! Device file system mounted at /dev
 
+use alloc::{collections::BTreeMap, sync::Arc};
+
+use rcore_fs::vfs::{FileType, INode, Metadata};
+use spin::RwLock;
+
 mod fbdev;
 mod random;
 mod serial;
@@ -11,3 +16,27 @@ pub use random::*;
 pub use serial::*;
 pub use shm::*;
 pub use tty::*;
+
+lazy_static! {
+    /// Device drivers indexed by device number, used to back device nodes
+    /// created with mknod on any file system
+    static ref DEVICES: RwLock<BTreeMap<usize, Arc<dyn INode>>> = RwLock::new(BTreeMap::new());
+}
+
+/// Register `inode` as the driver for device number `rdev`
+pub fn register_device(rdev: usize, inode: Arc<dyn INode>) {
+    DEVICES.write().insert(rdev, inode);
+}
+
+/// Find the driver registered for device number `rdev`
+pub fn find_device(rdev: usize) -> Option<Arc<dyn INode>> {
+    DEVICES.read().get(&rdev).cloned()
+}
+
+/// If `metadata` describes a device node, return the driver it refers to
+pub fn device_of(metadata: &Metadata) -> Option<Arc<dyn INode>> {
+    match metadata.type_ {
+        FileType::CharDevice | FileType::BlockDevice => find_device(metadata.rdev),
+        _ => None,
+    }
+}
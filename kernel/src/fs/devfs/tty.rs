@@ -35,34 +35,58 @@ pub fn foreground_pgid() -> Pgid {
     *TTY.foreground_pgid.read()
 }
 
+/// Send `signal` to the foreground process group.
+/// Characters come in interrupts, when the process table may be locked,
+/// so the signal is sent later in a task.
+fn signal_foreground(signal: Signal) {
+    let pgid = foreground_pgid();
+    crate::sched::spawn(async move {
+        info!(
+            "tty: send {:?} to foreground process group {}",
+            signal, pgid
+        );
+        let info = Siginfo {
+            signo: signal as i32,
+            errno: 0,
+            code: SI_KERNEL,
+            field: Default::default(),
+        };
+        for proc in process_group(pgid) {
+            send_signal(proc, -1, info);
+        }
+    });
+}
+
 impl TtyINode {
+    /// Take a character from the console.
+    /// With ISIG, the interrupt, quit and suspend characters are not input, but send
+    /// SIGINT, SIGQUIT and SIGTSTP to the foreground process group, see termios(3).
     pub fn push(&self, c: u8) {
-        let lflag = LocalModes::from_bits_truncate(self.termios.read().lflag);
-        if lflag.contains(LocalModes::ISIG) && [0o3, 0o34, 0o32, 0o31].contains(&(c as i32)) {
-            use Signal::*;
-            let foregroud_processes = process_group(foreground_pgid());
-            match c as i32 {
-                // INTR
-                0o3 => {
-                    for proc in foregroud_processes {
-                        send_signal(
-                            proc,
-                            -1,
-                            Siginfo {
-                                signo: SIGINT as i32,
-                                errno: 0,
-                                code: SI_KERNEL,
-                                field: Default::default(),
-                            },
-                        );
-                    }
+        let termios = *self.termios.read();
+        let lflag = LocalModes::from_bits_truncate(termios.lflag);
+        if lflag.contains(LocalModes::ISIG) {
+            // 0 disables a special character
+            let special = |idx: usize| termios.cc[idx] != 0 && termios.cc[idx] == c;
+            let signal = if special(VINTR) {
+                Some(Signal::SIGINT)
+            } else if special(VQUIT) {
+                Some(Signal::SIGQUIT)
+            } else if special(VSUSP) {
+                Some(Signal::SIGTSTP)
+            } else {
+                None
+            };
+            if let Some(signal) = signal {
+                if !lflag.contains(LocalModes::NOFLSH) {
+                    self.buf.lock().clear();
+                    self.eventbus.lock().clear(Event::READABLE);
                 }
-                _ => warn!("special char {} is unimplented", c),
+                signal_foreground(signal);
+                return;
             }
-        } else {
-            self.buf.lock().push_back(c);
-            self.eventbus.lock().set(Event::READABLE);
         }
+        self.buf.lock().push_back(c);
+        self.eventbus.lock().set(Event::READABLE);
     }
 
     pub fn pop(&self) -> u8 {
@@ -159,6 +183,18 @@ impl INode for TtyINode {
                 }
                 Ok(0)
             }
+            TIOCSWINSZ => {
+                let winsize = data as *const Winsize;
+                unsafe {
+                    *self.winsize.write() = *winsize;
+                }
+                Ok(0)
+            }
+            FIONREAD => {
+                let argp = data as *mut i32;
+                unsafe { *argp = self.buf.lock().len() as i32 };
+                Ok(0)
+            }
             TCGETS => {
                 let termois = data as *mut Termios;
                 unsafe {
@@ -1,3 +1,4 @@
+use crate::fs::fcntl::O_CLOEXEC;
 use crate::fs::FileLike;
 use crate::process::Process;
 use crate::sync::SpinNoIrqLock;
@@ -5,64 +6,73 @@ use crate::syscall::{SysError, SysResult};
 use alloc::{collections::BTreeMap, collections::BTreeSet};
 
 pub struct EpollInstance {
+    /// interest list
     pub events: BTreeMap<usize, EpollEvent>,
+    /// edge-triggered fds which have been reported ready and not yet seen idle
     pub ready_list: SpinNoIrqLock<BTreeSet<usize>>,
-    pub new_ctl_list: SpinNoIrqLock<BTreeSet<usize>>,
+    /// close on exec
+    pub fd_cloexec: bool,
 }
 
 impl Clone for EpollInstance {
     fn clone(&self) -> Self {
-        EpollInstance::new(0)
+        EpollInstance {
+            fd_cloexec: self.fd_cloexec,
+            ..EpollInstance::new(0)
+        }
     }
 }
 
 impl EpollInstance {
-    pub fn new(_flags: usize) -> Self {
+    pub fn new(flags: usize) -> Self {
         return EpollInstance {
             events: BTreeMap::new(),
             ready_list: Default::default(),
-            new_ctl_list: Default::default(),
+            fd_cloexec: (flags & O_CLOEXEC) != 0,
         };
     }
 
     pub fn control(&mut self, op: usize, fd: usize, event: &EpollEvent) -> SysResult {
         match op as i32 {
             EPollCtlOp::ADD => {
+                if self.events.contains_key(&fd) {
+                    return Err(SysError::EEXIST);
+                }
                 self.events.insert(fd, event.clone());
-                self.new_ctl_list.lock().insert(fd);
             }
 
             EPollCtlOp::MOD => {
-                if self.events.get(&fd).is_some() {
-                    self.events.remove(&fd);
+                if self.events.contains_key(&fd) {
                     self.events.insert(fd, event.clone());
-                    self.new_ctl_list.lock().insert(fd);
                 } else {
-                    return Err(SysError::EPERM);
+                    return Err(SysError::ENOENT);
                 }
             }
 
             EPollCtlOp::DEL => {
-                if self.events.get(&fd).is_some() {
-                    self.events.remove(&fd);
-                } else {
-                    return Err(SysError::EPERM);
+                if self.events.remove(&fd).is_none() {
+                    return Err(SysError::ENOENT);
                 }
             }
             _ => {
-                return Err(SysError::EPERM);
+                return Err(SysError::EINVAL);
             }
         }
+        // (re)arm the edge trigger
+        self.ready_list.lock().remove(&fd);
         Ok(0)
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct EpollData {
     _ptr: u64,
 }
 
-#[derive(Clone)]
+// struct epoll_event is packed on x86_64
+#[cfg_attr(target_arch = "x86_64", repr(packed))]
+#[cfg_attr(not(target_arch = "x86_64"), repr(C))]
+#[derive(Clone, Copy, Default)]
 pub struct EpollEvent {
     pub events: u32,     /* Epoll events */
     pub data: EpollData, /* User data variable */
@@ -86,6 +96,10 @@ impl EpollEvent {
     pub const EPOLLONESHOT: u32 = 1 << 30;
     pub const EPOLLET: u32 = 1 << 31;
 
+    /// Events which can be reported, excluding input flags
+    pub const EPOLL_INPUT_FLAGS: u32 =
+        Self::EPOLLET | Self::EPOLLONESHOT | Self::EPOLLWAKEUP | Self::EPOLLEXCLUSIVE;
+
     pub fn contains(&self, events: u32) -> bool {
         if (self.events & events) == 0 {
             return false;
@@ -97,16 +111,16 @@ impl EpollEvent {
 
 pub struct EPollCtlOp;
 impl EPollCtlOp {
-    const ADD: i32 = 1; /* Add a file descriptor to the interface.  */
-    const DEL: i32 = 2; /* Remove a file descriptor from the interface.  */
-    const MOD: i32 = 3; /* Change file descriptor epoll_event structure.  */
+    pub const ADD: i32 = 1; /* Add a file descriptor to the interface.  */
+    pub const DEL: i32 = 2; /* Remove a file descriptor from the interface.  */
+    pub const MOD: i32 = 3; /* Change file descriptor epoll_event structure.  */
 }
 
 impl Process {
     pub fn get_epoll_instance_mut(&mut self, fd: usize) -> Result<&mut EpollInstance, SysError> {
         match self.get_file_like(fd)? {
             FileLike::EpollInstance(instance) => Ok(instance),
-            _ => Err(SysError::EPERM),
+            _ => Err(SysError::EINVAL),
         }
     }
 
@@ -114,10 +128,10 @@ impl Process {
         match self.files.get(&fd) {
             Some(file_like) => match file_like {
                 FileLike::EpollInstance(instance) => Ok(&instance),
-                _ => Err(SysError::EPERM),
+                _ => Err(SysError::EINVAL),
             },
             None => {
-                return Err(SysError::EPERM);
+                return Err(SysError::EBADF);
             }
         }
     }
@@ -10,11 +10,18 @@ pub const F_GETLK: usize = 5; /* Get record locking info.  */
 pub const F_SETLK: usize = 6; /* Set record locking info (non-blocking).  */
 pub const F_SETLKW: usize = 7; /* Set record locking info (blocking).  */
 
+pub const F_RDLCK: usize = 0; /* Read lock.  */
+pub const F_WRLCK: usize = 1; /* Write lock.  */
+pub const F_UNLCK: usize = 2; /* Remove lock.  */
+
 const F_LINUX_SPECIFIC_BASE: usize = 1024;
 
 pub const FD_CLOEXEC: usize = 1;
 pub const F_DUPFD_CLOEXEC: usize = F_LINUX_SPECIFIC_BASE + 6;
 
+pub const O_RDONLY: usize = 0o0;
+pub const O_WRONLY: usize = 0o1;
+pub const O_RDWR: usize = 0o2;
 pub const O_NONBLOCK: usize = 0o4000;
 pub const O_APPEND: usize = 0o2000;
 pub const O_CLOEXEC: usize = 0o2000000; /* set close_on_exec */
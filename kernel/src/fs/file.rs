@@ -2,20 +2,23 @@
 
 use crate::memory::GlobalFrameAlloc;
 use crate::process::{current_thread, INodeForMap};
-use crate::syscall::{MmapProt, SysResult, TimeSpec};
+use crate::syscall::{MmapFlags, MmapProt, SysResult, TimeSpec};
 use alloc::{string::String, sync::Arc};
 use core::fmt;
 
 use rcore_fs::vfs::FsError::{Interrupted, NotSupported};
 use rcore_fs::vfs::{FileType, FsError, INode, MMapArea, Metadata, PollStatus, Result};
-use rcore_memory::memory_set::handler::File;
+use rcore_memory::memory_set::handler::{CowFrames, File};
 
-use crate::fs::fcntl::{O_APPEND, O_NONBLOCK};
+use crate::fs::fcntl::{O_APPEND, O_NONBLOCK, O_RDONLY, O_RDWR, O_WRONLY};
+use crate::fs::ioctl::FIONREAD;
+use crate::fs::{lookup_follow_at, page_cache, release_flock, try_flock, INodeExt, LockKind};
 use crate::sync::SpinLock as Mutex;
 use crate::syscall::SysError::{EAGAIN, ESPIPE};
 use bitflags::_core::cell::Cell;
 use spin::RwLock;
 
+#[derive(PartialEq, Eq)]
 enum Flock {
     None = 0,
     Shared = 1,
@@ -91,17 +94,29 @@ impl FileHandle {
         }
     }
 
+    /// Set the file status flags, only O_APPEND and O_NONBLOCK can be changed
     pub fn set_options(&self, arg: usize) {
         let options = &mut self.description.write().options;
         options.nonblock = (arg & O_NONBLOCK) != 0;
-        // TODO: handle append
-        // options.append = (arg & O_APPEND) != 0;
+        options.append = (arg & O_APPEND) != 0;
     }
 
-    // pub fn get_options(&self) -> usize {
-    // let options = self.description.read().options;
-    // let mut ret = 0 as usize;
-    // }
+    /// Get the access mode and file status flags
+    pub fn get_options(&self) -> usize {
+        let options = self.description.read().options;
+        let mut ret = match (options.read, options.write) {
+            (true, true) => O_RDWR,
+            (false, true) => O_WRONLY,
+            _ => O_RDONLY,
+        };
+        if options.append {
+            ret |= O_APPEND;
+        }
+        if options.nonblock {
+            ret |= O_NONBLOCK;
+        }
+        ret
+    }
 
     pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         let offset = self.description.read().offset as usize;
@@ -115,12 +130,12 @@ impl FileHandle {
         if !self.description.read().options.read {
             return Err(FsError::InvalidParam); // TODO: => EBADF
         }
-        if !self.description.read().options.nonblock {
+        let len = if !self.description.read().options.nonblock {
             // block
             loop {
-                match self.inode.read_at(offset, buf) {
+                match page_cache::read_at(&self.inode, offset, buf) {
                     Ok(read_len) => {
-                        return Ok(read_len);
+                        break read_len;
                     }
                     Err(FsError::Again) => {
                         self.async_poll().await?;
@@ -131,29 +146,65 @@ impl FileHandle {
                 }
             }
         } else {
-            let len = self.inode.read_at(offset, buf)?;
-            Ok(len)
+            page_cache::read_at(&self.inode, offset, buf)?
+        };
+        if len > 0 && !self.pipe {
+            TimeSpec::update_atime(&self.inode);
         }
+        Ok(len)
     }
 
-    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let description = self.description.read();
-        let offset = match description.options.append {
-            true => self.inode.metadata()?.size as u64,
-            false => description.offset,
-        } as usize;
-        drop(description);
-        let len = self.write_at(offset, buf)?;
-        self.description.write().offset += len as u64;
-        Ok(len)
+    /// Write `buf`, waiting for room unless nonblocking.
+    /// A blocking write to a pipe goes on until all is written, or the pipe is broken.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut written = 0;
+        loop {
+            // find the offset and write with the description locked, so that
+            // appends through the same open file description never overlap
+            let mut description = self.description.write();
+            if !description.options.write {
+                return Err(FsError::InvalidParam); // TODO: => EBADF
+            }
+            let offset = match description.options.append {
+                true => self.inode.metadata()?.size as u64,
+                false => description.offset,
+            } as usize;
+            match page_cache::write_at(&self.inode, offset, &buf[written..]) {
+                Ok(len) => {
+                    description.offset = (offset + len) as u64;
+                    written += len;
+                    let more = self.pipe
+                        && len > 0
+                        && written < buf.len()
+                        && !description.options.nonblock;
+                    drop(description);
+                    if !more {
+                        TimeSpec::update_mtime(&self.inode);
+                        return Ok(written);
+                    }
+                }
+                Err(FsError::Again) if !description.options.nonblock => {
+                    // block
+                    drop(description);
+                    self.async_poll().await?;
+                }
+                // return what has been written so far
+                Err(_) if written > 0 => {
+                    drop(description);
+                    TimeSpec::update_mtime(&self.inode);
+                    return Ok(written);
+                }
+                Err(err) => return Err(err),
+            }
+        }
     }
 
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
         if !self.description.read().options.write {
             return Err(FsError::InvalidParam); // TODO: => EBADF
         }
-        let len = self.inode.write_at(offset, buf)?;
-        TimeSpec::update(&self.inode);
+        let len = page_cache::write_at(&self.inode, offset, buf)?;
+        TimeSpec::update_mtime(&self.inode);
         Ok(len)
     }
 
@@ -168,27 +219,41 @@ impl FileHandle {
     }
 
     pub fn set_len(&mut self, len: u64) -> Result<()> {
-        if !self.description.read().options.write {
+        if !self.description.read().options.write || self.pipe {
             return Err(FsError::InvalidParam); // TODO: => EBADF
         }
-        self.inode.resize(len as usize)?;
+        self.inode.truncate(len as usize)?;
+        TimeSpec::update_mtime(&self.inode);
         Ok(())
     }
 
+    /// Write back the data and metadata of the file,
+    /// then flush dirty blocks of the file system to the device.
     pub fn sync_all(&mut self) -> Result<()> {
-        self.inode.sync_all()
+        if self.pipe {
+            return Err(FsError::InvalidParam);
+        }
+        page_cache::sync(&self.inode)?;
+        self.inode.sync_all()?;
+        self.inode.fs().sync()
     }
 
+    /// Like `sync_all`, but metadata are only written if needed to read the data.
     pub fn sync_data(&mut self) -> Result<()> {
-        self.inode.sync_data()
+        if self.pipe {
+            return Err(FsError::InvalidParam);
+        }
+        page_cache::sync(&self.inode)?;
+        self.inode.sync_data()?;
+        self.inode.fs().sync()
     }
 
     pub fn metadata(&self) -> Result<Metadata> {
         self.inode.metadata()
     }
 
-    pub fn lookup_follow(&self, path: &str, max_follow: usize) -> Result<Arc<dyn INode>> {
-        self.inode.lookup_follow(path, max_follow)
+    pub fn lookup_follow(&self, path: &str, follow: bool) -> Result<Arc<dyn INode>> {
+        lookup_follow_at(&self.inode, path, follow)
     }
 
     pub fn read_entry(&mut self) -> Result<String> {
@@ -221,8 +286,19 @@ impl FileHandle {
         self.inode.async_poll().await
     }
 
+    /// Dispatch the ioctl `cmd` to the device behind the file.
+    /// Regular files answer FIONREAD themselves.
     pub fn io_control(&self, cmd: u32, arg: usize) -> Result<usize> {
-        self.inode.io_control(cmd, arg)
+        let metadata = self.inode.metadata()?;
+        match cmd as usize {
+            FIONREAD if metadata.type_ == FileType::File => {
+                let offset = self.description.read().offset as usize;
+                let len = metadata.size.saturating_sub(offset);
+                unsafe { *(arg as *mut i32) = len as i32 };
+                Ok(0)
+            }
+            _ => self.inode.io_control(cmd, arg),
+        }
     }
 
     pub fn mmap(&mut self, area: MMapArea) -> Result<()> {
@@ -230,6 +306,7 @@ impl FileHandle {
         match self.inode.metadata()?.type_ {
             FileType::File => {
                 let prot = MmapProt::from_bits_truncate(area.prot);
+                let shared = MmapFlags::from_bits_truncate(area.flags).contains(MmapFlags::SHARED);
                 let thread = current_thread().unwrap();
                 thread.vm.lock().push(
                     area.start_vaddr,
@@ -240,7 +317,9 @@ impl FileHandle {
                         mem_start: area.start_vaddr,
                         file_start: area.offset,
                         file_end: area.offset + area.end_vaddr - area.start_vaddr,
+                        shared,
                         allocator: GlobalFrameAlloc,
+                        frames: CowFrames::new(),
                     },
                     "mmap_file",
                 );
@@ -254,6 +333,48 @@ impl FileHandle {
     pub fn inode(&self) -> Arc<dyn INode> {
         self.inode.clone()
     }
+
+    /// Id of the open file description, which owns flock locks
+    fn description_id(&self) -> usize {
+        &*self.description as *const RwLock<OpenFileDescription> as usize
+    }
+
+    /// Try to place a flock lock on the file, converting the one already
+    /// held through this open file description.
+    /// Return false if it conflicts with a lock held by others.
+    pub fn try_flock(&self, kind: LockKind) -> Result<bool> {
+        if !try_flock(&self.inode, self.description_id(), kind)? {
+            return Ok(false);
+        }
+        self.description.write().flock = match kind {
+            LockKind::Read => Flock::Shared,
+            LockKind::Write => Flock::Exclusive,
+        };
+        Ok(true)
+    }
+
+    /// Release the flock lock held through this open file description
+    pub fn unlock_flock(&self) -> Result<()> {
+        self.description.write().flock = Flock::None;
+        release_flock(&self.inode, self.description_id())
+    }
+}
+
+impl Drop for FileHandle {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.description) != 1 {
+            return;
+        }
+        let description = self.description.read();
+        // flock locks live as long as the open file description
+        if description.flock != Flock::None {
+            release_flock(&self.inode, self.description_id()).ok();
+        }
+        // written data reach the file system at the last close
+        if description.options.write && !self.pipe {
+            page_cache::sync(&self.inode).ok();
+        }
+    }
 }
 
 impl fmt::Debug for FileHandle {
@@ -2,11 +2,12 @@ use core::fmt;
 
 use super::ioctl::*;
 use super::FileHandle;
+use super::Pipe;
 use crate::fs::epoll::EpollInstance;
 use crate::net::Socket;
 use crate::syscall::{SysError, SysResult};
 use alloc::boxed::Box;
-use rcore_fs::vfs::{MMapArea, PollStatus};
+use rcore_fs::vfs::{FsError, MMapArea, PollStatus};
 
 // TODO: merge FileLike to FileHandle ?
 #[derive(Clone)]
@@ -22,7 +23,10 @@ impl FileLike {
         match self {
             File(file) => File(file.dup(fd_cloexec)),
             Socket(s) => Socket(s.clone()),
-            EpollInstance(e) => EpollInstance(e.clone()),
+            EpollInstance(e) => EpollInstance(crate::fs::epoll::EpollInstance {
+                fd_cloexec,
+                ..e.clone()
+            }),
         }
     }
 
@@ -36,9 +40,20 @@ impl FileLike {
         };
         Ok(len)
     }
-    pub fn write(&mut self, buf: &[u8]) -> SysResult {
+    pub async fn write(&mut self, buf: &[u8]) -> SysResult {
         let len = match self {
-            FileLike::File(file) => file.write(buf)?,
+            FileLike::File(file) => {
+                let len = file.write(buf).await?;
+                // the read end is closed, before the write or while it blocks
+                if len == 0 && !buf.is_empty() && file.pipe {
+                    if let Some(pipe) = file.inode().as_any_ref().downcast_ref::<Pipe>() {
+                        if pipe.is_broken() {
+                            return Err(SysError::EPIPE);
+                        }
+                    }
+                }
+                len
+            }
             FileLike::Socket(socket) => socket.write(buf, None)?,
             FileLike::EpollInstance(_) => {
                 return Err(SysError::ENOSYS);
@@ -48,11 +63,13 @@ impl FileLike {
     }
     pub fn ioctl(&mut self, request: usize, arg1: usize, arg2: usize, arg3: usize) -> SysResult {
         match self {
-            FileLike::File(file) => file.io_control(request as u32, arg1).map_err(Into::into),
+            FileLike::File(file) => match file.io_control(request as u32, arg1) {
+                // the device does not know this request
+                Err(FsError::NotSupported) => Err(SysError::ENOTTY),
+                other => other.map_err(Into::into),
+            },
             FileLike::Socket(socket) => socket.ioctl(request, arg1, arg2, arg3),
-            FileLike::EpollInstance(_) => {
-                return Err(SysError::ENOSYS);
-            }
+            FileLike::EpollInstance(_) => Err(SysError::ENOTTY),
         }
     }
     pub fn mmap(&mut self, area: MMapArea) -> SysResult {
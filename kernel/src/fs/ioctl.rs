@@ -33,6 +33,18 @@ pub const TIOCGWINSZ: usize = 0x5413;
 #[cfg(target_arch = "mips")]
 pub const TIOCGWINSZ: usize = 0x4_008_74_68;
 
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCSWINSZ: usize = 0x5414;
+// _IOW('t', 103, struct winsize)
+#[cfg(target_arch = "mips")]
+pub const TIOCSWINSZ: usize = 0x8_008_74_67;
+
+// number of bytes available for reading
+#[cfg(not(target_arch = "mips"))]
+pub const FIONREAD: usize = 0x541B;
+#[cfg(target_arch = "mips")]
+pub const FIONREAD: usize = 0x467F;
+
 #[cfg(not(target_arch = "mips"))]
 pub const FIONCLEX: usize = 0x5450;
 #[cfg(target_arch = "mips")]
@@ -73,6 +85,11 @@ bitflags! {
     }
 }
 
+/// Indexes of special characters in `Termios::cc`
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VSUSP: usize = 10;
+
 // Ref: https://www.man7.org/linux/man-pages/man3/termios.3.html
 #[repr(C)]
 #[derive(Clone, Copy)]
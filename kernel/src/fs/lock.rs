@@ -0,0 +1,169 @@
+//! Advisory file locks
+//!
+//! Two independent kinds of locks are kept per inode:
+//! - flock locks cover the whole file and are owned by an open file description
+//! - POSIX record locks cover a byte range and are owned by a process
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+
+use rcore_fs::vfs::{FileSystem, INode, Result};
+
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    /// Shared lock, compatible with other read locks
+    Read,
+    /// Exclusive lock
+    Write,
+}
+
+/// A POSIX record lock on the bytes in `[start, end)`
+#[derive(Debug, Clone, Copy)]
+pub struct RangeLock {
+    pub kind: LockKind,
+    pub start: u64,
+    pub end: u64,
+    pub pid: usize,
+}
+
+impl RangeLock {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start < end && start < self.end
+    }
+
+    fn conflicts(&self, other: &RangeLock) -> bool {
+        self.pid != other.pid
+            && self.overlaps(other.start, other.end)
+            && (self.kind == LockKind::Write || other.kind == LockKind::Write)
+    }
+}
+
+#[derive(Default)]
+struct InodeLocks {
+    /// flock locks with the id of their open file description
+    flocks: Vec<(usize, LockKind)>,
+    /// POSIX record locks
+    ranges: Vec<RangeLock>,
+}
+
+impl InodeLocks {
+    fn is_empty(&self) -> bool {
+        self.flocks.is_empty() && self.ranges.is_empty()
+    }
+
+    /// Remove the part of the locks of `pid` in `[start, end)`.
+    /// Return whether any lock was affected.
+    fn remove_ranges(&mut self, pid: usize, start: u64, end: u64) -> bool {
+        let mut ranges = Vec::with_capacity(self.ranges.len());
+        let mut removed = false;
+        for lock in self.ranges.drain(..) {
+            if lock.pid != pid || !lock.overlaps(start, end) {
+                ranges.push(lock);
+                continue;
+            }
+            removed = true;
+            if lock.start < start {
+                ranges.push(RangeLock { end: start, ..lock });
+            }
+            if end < lock.end {
+                ranges.push(RangeLock { start: end, ..lock });
+            }
+        }
+        self.ranges = ranges;
+        removed
+    }
+}
+
+/// Identify an inode by its file system and inode number,
+/// since the same file may be reached through different `INode` objects
+type InodeKey = (usize, usize);
+
+lazy_static! {
+    static ref LOCKS: Mutex<BTreeMap<InodeKey, InodeLocks>> = Mutex::new(BTreeMap::new());
+    /// Notified whenever a lock is released, to wake up blocked lockers
+    pub static ref LOCK_RELEASED: Condvar = Condvar::new();
+}
+
+fn key_of(inode: &Arc<dyn INode>) -> Result<InodeKey> {
+    let fs = inode.fs();
+    let fs_id = &*fs as *const dyn FileSystem as *const u8 as usize;
+    Ok((fs_id, inode.metadata()?.inode))
+}
+
+/// Run `f` on the locks of `inode`, then drop the entry if it becomes empty
+fn with_locks<T>(inode: &Arc<dyn INode>, f: impl FnOnce(&mut InodeLocks) -> T) -> Result<T> {
+    let key = key_of(inode)?;
+    let mut table = LOCKS.lock();
+    let locks = table.entry(key).or_default();
+    let ret = f(locks);
+    if locks.is_empty() {
+        table.remove(&key);
+    }
+    Ok(ret)
+}
+
+/// Try to place a flock lock of `kind` for the open file description `owner`,
+/// replacing the lock it already holds.
+/// Return false if another open file description holds a conflicting lock.
+pub fn try_flock(inode: &Arc<dyn INode>, owner: usize, kind: LockKind) -> Result<bool> {
+    with_locks(inode, |locks| {
+        let conflict = locks.flocks.iter().any(|&(other, other_kind)| {
+            other != owner && (kind == LockKind::Write || other_kind == LockKind::Write)
+        });
+        if conflict {
+            return false;
+        }
+        locks.flocks.retain(|&(other, _)| other != owner);
+        locks.flocks.push((owner, kind));
+        true
+    })
+}
+
+/// Release the flock lock held by the open file description `owner`
+pub fn release_flock(inode: &Arc<dyn INode>, owner: usize) -> Result<()> {
+    with_locks(inode, |locks| {
+        locks.flocks.retain(|&(other, _)| other != owner)
+    })?;
+    LOCK_RELEASED.notify_all();
+    Ok(())
+}
+
+/// Return a lock held by another process which conflicts with `lock`
+pub fn test_range_lock(inode: &Arc<dyn INode>, lock: &RangeLock) -> Result<Option<RangeLock>> {
+    with_locks(inode, |locks| {
+        locks
+            .ranges
+            .iter()
+            .find(|other| other.conflicts(lock))
+            .cloned()
+    })
+}
+
+/// Try to place a POSIX record lock, replacing the part of the locks of
+/// the same process in its range.
+/// Return false if another process holds a conflicting lock.
+pub fn try_range_lock(inode: &Arc<dyn INode>, lock: RangeLock) -> Result<bool> {
+    with_locks(inode, |locks| {
+        if locks.ranges.iter().any(|other| other.conflicts(&lock)) {
+            return false;
+        }
+        locks.remove_ranges(lock.pid, lock.start, lock.end);
+        locks.ranges.push(lock);
+        true
+    })
+}
+
+/// Release the part of the POSIX record locks of `pid` in `[start, end)`
+pub fn release_range_lock(inode: &Arc<dyn INode>, pid: usize, start: u64, end: u64) -> Result<()> {
+    if with_locks(inode, |locks| locks.remove_ranges(pid, start, end))? {
+        LOCK_RELEASED.notify_all();
+    }
+    Ok(())
+}
+
+/// Release all POSIX record locks of `pid` on `inode`,
+/// which happens when the process closes any fd referring to it
+pub fn release_range_locks(inode: &Arc<dyn INode>, pid: usize) -> Result<()> {
+    release_range_lock(inode, pid, 0, u64::max_value())
+}
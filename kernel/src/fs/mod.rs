@@ -1,4 +1,4 @@
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{collections::VecDeque, string::String, sync::Arc, vec::Vec};
 
 use rcore_fs::{dev::block_cache::BlockCache, vfs::*};
 use rcore_fs_devfs::{
@@ -11,11 +11,14 @@ use rcore_fs_sfs::{INodeImpl, SimpleFileSystem};
 
 use self::devfs::{Fbdev, RandomINode};
 
-pub use self::devfs::{Serial, ShmINode, TTY};
+pub use self::devfs::{device_of, find_device, register_device, Serial, ShmINode, TTY};
 pub use self::file::*;
 pub use self::file_like::*;
+pub use self::lock::*;
 pub use self::pipe::Pipe;
 pub use self::pseudo::*;
+pub use self::signalfd::SignalFd;
+pub use self::timerfd::TimerFd;
 use crate::drivers::{BlockDriver, BlockDriverWrapper};
 
 mod devfs;
@@ -25,8 +28,12 @@ pub mod fcntl;
 mod file;
 mod file_like;
 pub mod ioctl;
+mod lock;
+pub mod page_cache;
 mod pipe;
 mod pseudo;
+mod signalfd;
+mod timerfd;
 
 // Hard link user programs
 #[cfg(feature = "link_user")]
@@ -75,17 +82,21 @@ lazy_static! {
 
         // create DevFS
         let devfs = DevFS::new();
-        devfs.add("null", Arc::new(NullINode::default())).expect("failed to mknod /dev/null");
-        devfs.add("zero", Arc::new(ZeroINode::default())).expect("failed to mknod /dev/zero");
-        devfs.add("random", Arc::new(RandomINode::new(false))).expect("failed to mknod /dev/random");
-        devfs.add("urandom", Arc::new(RandomINode::new(true))).expect("failed to mknod /dev/urandom");
-        devfs.add("tty", TTY.clone()).expect("failed to mknod /dev/tty");
-        devfs.add("fb0", Arc::new(Fbdev::default())).expect("failed to mknod /dev/fb0");
-        devfs.add("shm", Arc::new(ShmINode::default())).expect("failed to mkdir shm");
-        for (i, serial) in Serial::wrap_all_serial_devices().into_iter().enumerate(){
-            devfs.add(&format!("ttyS{}", i), Arc::new(serial)).expect("failed to add a serial");
+        let add_device = |name: &str, rdev: usize, inode: Arc<dyn INode>| {
+            devfs.add(name, inode.clone()).expect("failed to mknod device");
+            register_device(rdev, inode);
+        };
+        add_device("null", make_rdev(1, 3), Arc::new(NullINode::default()));
+        add_device("zero", make_rdev(1, 5), Arc::new(ZeroINode::default()));
+        add_device("random", make_rdev(1, 8), Arc::new(RandomINode::new(false)));
+        add_device("urandom", make_rdev(1, 9), Arc::new(RandomINode::new(true)));
+        add_device("tty", make_rdev(5, 0), TTY.clone());
+        add_device("fb0", make_rdev(29, 0), Arc::new(Fbdev::default()));
+        for (i, serial) in Serial::wrap_all_serial_devices().into_iter().enumerate() {
+            let rdev = serial.metadata().expect("failed to stat a serial").rdev;
+            add_device(&format!("ttyS{}", i), rdev, Arc::new(serial));
         }
-
+        devfs.add("shm", Arc::new(ShmINode::default())).expect("failed to mkdir shm");
 
         #[cfg(feature = "hypervisor")]
         devfs.add("rvm", Arc::new(crate::rvm::RvmINode::new())).expect("failed to mknod /dev/rvm");
@@ -112,20 +123,94 @@ lazy_static! {
     };
 }
 
-pub const FOLLOW_MAX_DEPTH: usize = 3;
+/// Max number of symbolic links to follow in one path resolution, same as Linux
+pub const FOLLOW_MAX_DEPTH: usize = 40;
+
+/// Resolve `path` starting from `dir`.
+///
+/// Symbolic links are followed in every intermediate component,
+/// and in the last component only if `follow` is set (or the path ends with '/').
+/// Returns `FsError::SymLoop` when more than `FOLLOW_MAX_DEPTH` links are met.
+pub fn lookup_follow_at(dir: &Arc<dyn INode>, path: &str, follow: bool) -> Result<Arc<dyn INode>> {
+    let must_dir = path.ends_with('/');
+    let mut current = match path.starts_with('/') {
+        true => ROOT_INODE.clone(),
+        false => dir.clone(),
+    };
+    let mut rest: VecDeque<String> = path
+        .split('/')
+        .filter(|&x| x != "")
+        .map(String::from)
+        .collect();
+    let mut follow_times = 0;
+    while let Some(name) = rest.pop_front() {
+        if current.metadata()?.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        if name == "." {
+            continue;
+        }
+        let inode = current.find(&name)?;
+        let is_last = rest.is_empty();
+        if inode.metadata()?.type_ == FileType::SymLink && (!is_last || follow || must_dir) {
+            follow_times += 1;
+            if follow_times > FOLLOW_MAX_DEPTH {
+                return Err(FsError::SymLoop);
+            }
+            let target =
+                String::from_utf8(inode.read_as_vec()?).map_err(|_| FsError::InvalidParam)?;
+            if target.starts_with('/') {
+                current = ROOT_INODE.clone();
+            }
+            // a relative target is resolved against the directory containing the link
+            for seg in target.split('/').filter(|&x| x != "").rev() {
+                rest.push_front(String::from(seg));
+            }
+        } else {
+            current = inode;
+        }
+    }
+    if must_dir && current.metadata()?.type_ != FileType::Dir {
+        return Err(FsError::NotDir);
+    }
+    Ok(current)
+}
 
 pub trait INodeExt {
     fn read_as_vec(&self) -> Result<Vec<u8>>;
+    /// Resize the file to `len` bytes, filling the extended part with zeros
+    fn truncate(&self, len: usize) -> Result<()>;
 }
 
-impl INodeExt for dyn INode {
+impl INodeExt for Arc<dyn INode> {
     fn read_as_vec(&self) -> Result<Vec<u8>> {
         let size = self.metadata()?.size;
         let mut buf = Vec::with_capacity(size);
         unsafe {
             buf.set_len(size);
         }
-        self.read_at(0, buf.as_mut_slice())?;
+        // the latest data may be in the page cache only
+        let len = page_cache::read_at(self, 0, buf.as_mut_slice())?;
+        buf.truncate(len);
         Ok(buf)
     }
+
+    fn truncate(&self, len: usize) -> Result<()> {
+        let metadata = self.metadata()?;
+        if metadata.type_ == FileType::Dir {
+            return Err(FsError::IsDir);
+        }
+        let old_len = metadata.size;
+        self.resize(len)?;
+        page_cache::truncate(&**self, old_len.min(len));
+        // the file system is not required to clear newly allocated blocks
+        let zeros = [0u8; 0x1000];
+        let mut offset = old_len;
+        while offset < len {
+            let chunk = (len - offset).min(zeros.len());
+            self.write_at(offset, &zeros[..chunk])?;
+            offset += chunk;
+        }
+        Ok(())
+    }
 }
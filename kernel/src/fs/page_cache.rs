@@ -0,0 +1,499 @@
+//! Page cache of regular files
+//!
+//! File data are cached in frames one page at a time, and the same pages
+//! are used by read/write and mapped by shared file mappings.
+//! Pages are kept per file, however many `INode` objects it is reached through.
+//! Written pages stay dirty until they are written back on fsync/sync,
+//! at the last close of an open file for writing, or when a file has too many of them.
+//! Clean pages are dropped in least recently used order when frames run out.
+
+use crate::memory::{alloc_frame, count_page_in, dealloc_frame, phys_to_virt};
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::slice;
+use rcore_fs::vfs::{FileSystem, FileType, INode, Result};
+use rcore_memory::PAGE_SIZE;
+
+/// Write back a file as soon as it has more dirty pages than this
+const MAX_DIRTY_PAGES: usize = 1024;
+
+struct CachedPage {
+    frame: usize,
+    dirty: bool,
+    /// Number of shared mappings and writebacks using the frame,
+    /// which keep the page from being dropped
+    users: usize,
+    /// Time of the last access, the key of the page in the LRU list
+    tick: usize,
+}
+
+impl CachedPage {
+    fn data(&self) -> &'static mut [u8] {
+        frame_data(self.frame)
+    }
+}
+
+fn frame_data(frame: usize) -> &'static mut [u8] {
+    unsafe { slice::from_raw_parts_mut(phys_to_virt(frame) as *mut u8, PAGE_SIZE) }
+}
+
+struct InodeCache {
+    /// One of the objects of the file, to write back through
+    inode: Arc<dyn INode>,
+    pages: BTreeMap<usize, CachedPage>,
+    /// Frames of truncated pages which are still in use, with their user counts
+    detached: BTreeMap<usize, usize>,
+    /// Number of dirty pages
+    dirty: usize,
+}
+
+impl InodeCache {
+    fn is_unused(&self) -> bool {
+        self.pages.is_empty() && self.detached.is_empty()
+    }
+}
+
+struct PageCache {
+    inodes: BTreeMap<InodeKey, InodeCache>,
+    /// (inode key, page index) of all cached pages, least recently used first
+    lru: BTreeMap<usize, (InodeKey, usize)>,
+    tick: usize,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<PageCache> = Mutex::new(PageCache {
+        inodes: BTreeMap::new(),
+        lru: BTreeMap::new(),
+        tick: 0,
+    });
+}
+
+/// Identify a file by its file system and inode number, as the file locks do,
+/// since the same file may be reached through different `INode` objects
+type InodeKey = (usize, usize);
+
+impl PageCache {
+    fn contains(&self, key: InodeKey, index: usize) -> bool {
+        match self.inodes.get(&key) {
+            Some(entry) => entry.pages.contains_key(&index),
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, inode: &Arc<dyn INode>, key: InodeKey, index: usize, frame: usize) {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.inodes.entry(key).or_insert_with(|| InodeCache {
+            inode: inode.clone(),
+            pages: BTreeMap::new(),
+            detached: BTreeMap::new(),
+            dirty: 0,
+        });
+        let page = CachedPage {
+            frame,
+            dirty: false,
+            users: 0,
+            tick,
+        };
+        entry.pages.insert(index, page);
+        self.lru.insert(tick, (key, index));
+    }
+
+    /// Run `f` on a cached page, and move it to the end of the LRU list
+    fn access<T>(
+        &mut self,
+        key: InodeKey,
+        index: usize,
+        f: impl FnOnce(&mut CachedPage) -> T,
+    ) -> T {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.inodes.get_mut(&key).unwrap();
+        let page = entry.pages.get_mut(&index).unwrap();
+        self.lru.remove(&page.tick);
+        self.lru.insert(tick, (key, index));
+        page.tick = tick;
+        let dirty = page.dirty;
+        let ret = f(page);
+        match (dirty, page.dirty) {
+            (false, true) => entry.dirty += 1,
+            (true, false) => entry.dirty -= 1,
+            _ => {}
+        }
+        ret
+    }
+
+    fn remove_page(&mut self, key: InodeKey, index: usize) -> Option<CachedPage> {
+        let entry = self.inodes.get_mut(&key)?;
+        let page = entry.pages.remove(&index)?;
+        if page.dirty {
+            entry.dirty -= 1;
+        }
+        self.lru.remove(&page.tick);
+        Some(page)
+    }
+
+    /// Drop a user of `frame`, which is the page `index` or a truncated one.
+    /// Return false if the frame is not in the cache.
+    fn put(&mut self, key: InodeKey, index: usize, frame: usize) -> bool {
+        let entry = match self.inodes.get_mut(&key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        match entry.pages.get_mut(&index) {
+            Some(page) if page.frame == frame && page.users > 0 => {
+                page.users -= 1;
+                return true;
+            }
+            _ => {}
+        }
+        match entry.detached.get_mut(&frame) {
+            Some(users) => {
+                *users -= 1;
+                if *users == 0 {
+                    entry.detached.remove(&frame);
+                    dealloc_frame(frame);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the entry of an inode without cached pages.
+    /// It is dropped by the caller after unlocking, since that may drop the inode.
+    fn take_if_unused(&mut self, key: InodeKey) -> Option<InodeCache> {
+        match self.inodes.get(&key) {
+            Some(entry) if entry.is_unused() => self.inodes.remove(&key),
+            _ => None,
+        }
+    }
+}
+
+/// The key and the size of `inode`, if its data go through the page cache.
+/// Pipes and other special inodes may have no metadata,
+/// and pseudo files have no inode number.
+fn is_cached(inode: &dyn INode) -> Option<(InodeKey, usize)> {
+    match inode.metadata() {
+        Ok(metadata) if metadata.type_ == FileType::File && metadata.inode != 0 => {
+            let fs = inode.fs();
+            let fs_id = &*fs as *const dyn FileSystem as *const u8 as usize;
+            Some(((fs_id, metadata.inode), metadata.size))
+        }
+        _ => None,
+    }
+}
+
+/// Run `f` on the page `index` of `inode` with the cache locked,
+/// reading the first `fill` bytes of the page from the file if it is not cached.
+/// Return None if there is no frame to cache the page.
+fn with_page<T>(
+    inode: &Arc<dyn INode>,
+    key: InodeKey,
+    index: usize,
+    fill: usize,
+    f: impl FnOnce(&mut CachedPage) -> T,
+) -> Result<Option<T>> {
+    let mut cache = CACHE.lock();
+    if !cache.contains(key, index) {
+        // allocating a frame may shrink the cache, and reading may take a while
+        drop(cache);
+        let frame = match alloc_frame() {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let data = frame_data(frame);
+        data.iter_mut().for_each(|x| *x = 0);
+        if let Err(err) = inode.read_at(index * PAGE_SIZE, &mut data[..fill]) {
+            dealloc_frame(frame);
+            return Err(err);
+        }
+        count_page_in();
+        cache = CACHE.lock();
+        if cache.contains(key, index) {
+            // read by someone else in the meantime
+            dealloc_frame(frame);
+        } else {
+            cache.insert(inode, key, index, frame);
+        }
+    }
+    Ok(Some(cache.access(key, index, f)))
+}
+
+/// Read from `inode` at `offset` through the page cache
+pub fn read_at(inode: &Arc<dyn INode>, offset: usize, buf: &mut [u8]) -> Result<usize> {
+    let (key, size) = match is_cached(&**inode) {
+        Some(cached) => cached,
+        None => return inode.read_at(offset, buf),
+    };
+    if offset >= size {
+        return Ok(0);
+    }
+    let end = size.min(offset + buf.len());
+    let mut pos = offset;
+    while pos < end {
+        let (index, start) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+        let len = (PAGE_SIZE - start).min(end - pos);
+        let dst = &mut buf[pos - offset..pos - offset + len];
+        let fill = (size - index * PAGE_SIZE).min(PAGE_SIZE);
+        let cached = with_page(inode, key, index, fill, |page| {
+            dst.copy_from_slice(&page.data()[start..start + len])
+        })?;
+        if cached.is_none() {
+            inode.read_at(pos, dst)?;
+        }
+        pos += len;
+    }
+    Ok(end - offset)
+}
+
+/// Write to `inode` at `offset` through the page cache.
+/// The data are written back later, but the file is resized at once.
+pub fn write_at(inode: &Arc<dyn INode>, offset: usize, buf: &[u8]) -> Result<usize> {
+    let (key, size) = match is_cached(&**inode) {
+        Some(cached) => cached,
+        None => return inode.write_at(offset, buf),
+    };
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    let end = offset + buf.len();
+    if end > size {
+        // allocate the space now, so that writing back does not run out of it
+        inode.resize(end)?;
+        // the file system is not required to clear newly allocated blocks
+        let zeros = [0u8; PAGE_SIZE];
+        let mut pos = size;
+        while pos < offset {
+            let len = (offset - pos).min(PAGE_SIZE - pos % PAGE_SIZE);
+            write_pages(inode, key, pos, &zeros[..len], size)?;
+            pos += len;
+        }
+    }
+    write_pages(inode, key, offset, buf, size)?;
+
+    let dirty = match CACHE.lock().inodes.get(&key) {
+        Some(entry) => entry.dirty,
+        None => 0,
+    };
+    if dirty > MAX_DIRTY_PAGES {
+        sync(inode)?;
+    }
+    Ok(buf.len())
+}
+
+/// Copy `buf` to the cached pages of `inode` at `offset`,
+/// where `size` is the size of the file before it is resized
+fn write_pages(
+    inode: &Arc<dyn INode>,
+    key: InodeKey,
+    offset: usize,
+    buf: &[u8],
+    size: usize,
+) -> Result<()> {
+    let end = offset + buf.len();
+    let mut pos = offset;
+    while pos < end {
+        let (index, start) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+        let len = (PAGE_SIZE - start).min(end - pos);
+        let src = &buf[pos - offset..pos - offset + len];
+        // the old data are not needed if the whole page is overwritten
+        let fill = match len {
+            PAGE_SIZE => 0,
+            _ => size.saturating_sub(index * PAGE_SIZE).min(PAGE_SIZE),
+        };
+        let cached = with_page(inode, key, index, fill, |page| {
+            page.data()[start..start + len].copy_from_slice(src);
+            page.dirty = true;
+        })?;
+        if cached.is_none() {
+            inode.write_at(pos, src)?;
+        }
+        pos += len;
+    }
+    Ok(())
+}
+
+/// Write back the dirty pages of `inode`
+pub fn sync(inode: &Arc<dyn INode>) -> Result<()> {
+    let (key, size) = match is_cached(&**inode) {
+        Some(cached) => cached,
+        None => return Ok(()),
+    };
+    let mut pages = Vec::new();
+    {
+        let mut cache = CACHE.lock();
+        let entry = match cache.inodes.get_mut(&key) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        for (&index, page) in entry.pages.iter_mut().filter(|(_, page)| page.dirty) {
+            // the frame is kept while it is being written
+            page.dirty = false;
+            page.users += 1;
+            pages.push((index, page.frame));
+        }
+        entry.dirty = 0;
+    }
+
+    let mut ret = Ok(());
+    let mut written = 0;
+    for &(index, frame) in pages.iter() {
+        let start = index * PAGE_SIZE;
+        if start < size {
+            let len = (size - start).min(PAGE_SIZE);
+            if let Err(err) = inode.write_at(start, &frame_data(frame)[..len]) {
+                ret = Err(err);
+                break;
+            }
+        }
+        written += 1;
+    }
+
+    let mut cache = CACHE.lock();
+    for (i, &(index, frame)) in pages.iter().enumerate() {
+        if i >= written && cache.contains(key, index) {
+            // try again next time
+            cache.access(key, index, |page| page.dirty |= page.frame == frame);
+        }
+        cache.put(key, index, frame);
+    }
+    let unused = cache.take_if_unused(key);
+    drop(cache);
+    drop(unused);
+    ret
+}
+
+/// Write back the dirty pages of all files,
+/// and forget files without cached pages
+pub fn sync_all() -> Result<()> {
+    let inodes: Vec<Arc<dyn INode>> = CACHE
+        .lock()
+        .inodes
+        .values()
+        .map(|entry| entry.inode.clone())
+        .collect();
+    for inode in inodes.iter() {
+        sync(inode)?;
+    }
+    Ok(())
+}
+
+/// Drop the cached data of `inode` beyond `len`, after the file is resized
+pub fn truncate(inode: &dyn INode, len: usize) {
+    let key = match is_cached(inode) {
+        Some((key, _)) => key,
+        None => return,
+    };
+    let mut cache = CACHE.lock();
+    let first = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+    let indexes: Vec<usize> = match cache.inodes.get(&key) {
+        Some(entry) => entry
+            .pages
+            .range(first..)
+            .map(|(&index, _)| index)
+            .collect(),
+        None => return,
+    };
+    for index in indexes {
+        let page = cache.remove_page(key, index).unwrap();
+        if page.users > 0 {
+            // still mapped, freed by its last user
+            let entry = cache.inodes.get_mut(&key).unwrap();
+            *entry.detached.entry(page.frame).or_insert(0) += page.users;
+        } else {
+            dealloc_frame(page.frame);
+        }
+    }
+    if len % PAGE_SIZE != 0 && cache.contains(key, len / PAGE_SIZE) {
+        cache.access(key, len / PAGE_SIZE, |page| {
+            page.data()[len % PAGE_SIZE..]
+                .iter_mut()
+                .for_each(|x| *x = 0)
+        });
+    }
+    let unused = cache.take_if_unused(key);
+    drop(cache);
+    drop(unused);
+}
+
+/// Get the frame caching the page of `inode` at `offset` to map it,
+/// which stays in the cache until `put_frame`.
+/// Return None if the page is beyond the end of the file or can not be cached.
+pub fn get_frame(inode: &Arc<dyn INode>, offset: usize) -> Option<usize> {
+    let (key, size) = is_cached(&**inode)?;
+    if offset >= size {
+        return None;
+    }
+    let index = offset / PAGE_SIZE;
+    let fill = (size - index * PAGE_SIZE).min(PAGE_SIZE);
+    with_page(inode, key, index, fill, |page| {
+        page.users += 1;
+        page.frame
+    })
+    .ok()?
+}
+
+/// Stop using `frame` got from `get_frame`.
+/// Return false if it is not a frame of the cache.
+pub fn put_frame(inode: &Arc<dyn INode>, offset: usize, frame: usize) -> bool {
+    match is_cached(&**inode) {
+        Some((key, _)) => CACHE.lock().put(key, offset / PAGE_SIZE, frame),
+        None => false,
+    }
+}
+
+/// Mark the page using `frame` got from `get_frame` dirty, after it is written
+/// through a mapping. Return false if it is not a frame of the cache.
+pub fn set_dirty(inode: &Arc<dyn INode>, offset: usize, frame: usize) -> bool {
+    let key = match is_cached(&**inode) {
+        Some((key, _)) => key,
+        None => return false,
+    };
+    let index = offset / PAGE_SIZE;
+    let mut cache = CACHE.lock();
+    let (cached, detached) = match cache.inodes.get(&key) {
+        Some(entry) => (
+            entry.pages.get(&index).map(|page| page.frame) == Some(frame),
+            entry.detached.contains_key(&frame),
+        ),
+        None => return false,
+    };
+    if cached {
+        cache.access(key, index, |page| page.dirty = true);
+    }
+    // truncated pages are not written back
+    cached || detached
+}
+
+/// Number of cached pages, and of dirty ones among them
+pub fn stats() -> (usize, usize) {
+    let cache = CACHE.lock();
+    let dirty = cache.inodes.values().map(|inode| inode.dirty).sum();
+    (cache.lru.len(), dirty)
+}
+
+/// Drop the least recently used page which is clean and not in use,
+/// to free a frame. Return false if there is none.
+pub fn shrink() -> bool {
+    // frames may be allocated with the cache locked
+    let mut cache = match CACHE.try_lock() {
+        Some(cache) => cache,
+        None => return false,
+    };
+    let inodes = &cache.inodes;
+    let victim = cache.lru.values().cloned().find(|(key, index)| {
+        let page = &inodes[key].pages[index];
+        !page.dirty && page.users == 0
+    });
+    let (key, index) = match victim {
+        Some(victim) => victim,
+        None => return false,
+    };
+    // the entry is left to be removed later, since dropping the inode here
+    // may need locks held by the one allocating frames
+    let page = cache.remove_page(key, index).unwrap();
+    drop(cache);
+    dealloc_frame(page.frame);
+    true
+}
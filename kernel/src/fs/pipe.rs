@@ -1,5 +1,6 @@
 //! Implement INode for Pipe
 
+use crate::fs::ioctl::FIONREAD;
 use crate::sync::{Event, EventBus, SpinNoIrqLock as Mutex};
 use crate::syscall::SysError::EAGAIN;
 use alloc::boxed::Box;
@@ -16,6 +17,11 @@ use core::{
 use rcore_fs::vfs::FsError::Again;
 use rcore_fs::vfs::*;
 
+/// Capacity of the pipe buffer, same as the default one on Linux
+pub const PIPE_BUF_SIZE: usize = 0x10000;
+/// Writes of at most this are atomic, same as PIPE_BUF on Linux
+pub const PIPE_BUF: usize = 4096;
+
 #[derive(Clone, PartialEq)]
 pub enum PipeEnd {
     Read,
@@ -48,7 +54,7 @@ impl Pipe {
     /// Create a pair of INode: (read, write)
     pub fn create_pair() -> (Pipe, Pipe) {
         let inner = PipeData {
-            buf: VecDeque::new(),
+            buf: VecDeque::with_capacity(PIPE_BUF_SIZE),
             eventbus: EventBus::default(),
             end_cnt: 2, // one read, one write
         };
@@ -77,11 +83,17 @@ impl Pipe {
 
     fn can_write(&self) -> bool {
         if let PipeEnd::Write = self.direction {
-            self.data.lock().end_cnt == 2
+            let data = self.data.lock();
+            PIPE_BUF_SIZE - data.buf.len() >= PIPE_BUF || data.end_cnt < 2 // other end closed
         } else {
             false
         }
     }
+
+    /// Whether this is a write end whose read end has been closed
+    pub fn is_broken(&self) -> bool {
+        self.direction == PipeEnd::Write && self.data.lock().end_cnt < 2
+    }
 }
 
 impl INode for Pipe {
@@ -101,6 +113,10 @@ impl INode for Pipe {
                 if data.buf.len() == 0 {
                     data.eventbus.clear(Event::READABLE);
                 }
+                if PIPE_BUF_SIZE - data.buf.len() >= PIPE_BUF {
+                    // wake up writers waiting for free space
+                    data.eventbus.set(Event::WRITABLE);
+                }
                 Ok(len)
             }
         } else {
@@ -109,18 +125,46 @@ impl INode for Pipe {
     }
 
     fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        if buf.len() == 0 {
+            return Ok(0);
+        }
         if let PipeEnd::Write = self.direction {
             let mut data = self.data.lock();
-            for c in buf {
-                data.buf.push_back(*c);
+            if data.end_cnt < 2 {
+                // nobody will ever read it, let the caller see the broken pipe
+                return Ok(0);
+            }
+            let free = PIPE_BUF_SIZE - data.buf.len();
+            // up to PIPE_BUF bytes go in at once, never interleaved with other writes
+            if free == 0 || (buf.len() <= PIPE_BUF && free < buf.len()) {
+                return Err(Again);
+            }
+            let len = min(buf.len(), free);
+            data.buf.extend(&buf[..len]);
+            if PIPE_BUF_SIZE - data.buf.len() < PIPE_BUF {
+                data.eventbus.clear(Event::WRITABLE);
             }
             data.eventbus.set(Event::READABLE);
-            Ok(buf.len())
+            Ok(len)
         } else {
             Ok(0)
         }
     }
 
+    fn io_control(&self, cmd: u32, data: usize) -> Result<usize> {
+        match cmd as usize {
+            FIONREAD => {
+                let len = match self.direction {
+                    PipeEnd::Read => self.data.lock().buf.len(),
+                    PipeEnd::Write => 0,
+                };
+                unsafe { *(data as *mut i32) = len as i32 };
+                Ok(0)
+            }
+            _ => Err(FsError::NotSupported),
+        }
+    }
+
     fn poll(&self) -> Result<PollStatus> {
         Ok(PollStatus {
             read: self.can_read(),
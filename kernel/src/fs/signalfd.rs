@@ -0,0 +1,198 @@
+//! Implement INode for signalfd
+//!
+//! Reading takes the signals in the mask pending for the reading thread,
+//! each as a struct signalfd_siginfo, instead of handling them.
+
+use crate::process::current_thread;
+use crate::signal::{
+    has_signal_in, take_signal, Siginfo, Signal, Sigset, SI_KERNEL, SI_TIMER, SI_USER,
+};
+use crate::sync::{Event, SpinNoIrqLock as Mutex};
+use alloc::boxed::Box;
+use core::any::Any;
+use core::mem::size_of;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use num::FromPrimitive;
+use rcore_fs::vfs::*;
+
+pub struct SignalFd {
+    mask: Mutex<Sigset>,
+}
+
+impl SignalFd {
+    pub fn new(mask: Sigset) -> Self {
+        SignalFd {
+            mask: Mutex::new(mask),
+        }
+    }
+
+    pub fn set_mask(&self, mask: Sigset) {
+        *self.mask.lock() = mask;
+    }
+
+    fn can_read(&self) -> bool {
+        let mask = *self.mask.lock();
+        current_thread().map_or(false, |thread| has_signal_in(&thread, &mask))
+    }
+}
+
+impl INode for SignalFd {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        const SIZE: usize = size_of::<SignalfdSiginfo>();
+        if buf.len() < SIZE {
+            return Err(FsError::InvalidParam);
+        }
+        let thread = current_thread().ok_or(FsError::Again)?;
+        let mask = *self.mask.lock();
+        let mut len = 0;
+        while buf.len() - len >= SIZE {
+            let info = match take_signal(&thread, &mask) {
+                Some(info) => SignalfdSiginfo::from(&info),
+                None => break,
+            };
+            let bytes = unsafe {
+                core::slice::from_raw_parts(&info as *const SignalfdSiginfo as *const u8, SIZE)
+            };
+            buf[len..len + SIZE].copy_from_slice(bytes);
+            len += SIZE;
+        }
+        if len == 0 {
+            return Err(FsError::Again);
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::InvalidParam)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: self.can_read(),
+            write: false,
+            error: false,
+        })
+    }
+
+    fn async_poll<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<PollStatus>> + Send + Sync + 'a>> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct SignalFdFuture<'a> {
+            signalfd: &'a SignalFd,
+        };
+
+        impl<'a> Future for SignalFdFuture<'a> {
+            type Output = Result<PollStatus>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                if self.signalfd.can_read() {
+                    return Poll::Ready(self.signalfd.poll());
+                }
+                let thread = match current_thread() {
+                    Some(thread) => thread,
+                    None => return Poll::Ready(self.signalfd.poll()),
+                };
+                let eventbus = thread.proc.lock().eventbus.clone();
+                let waker = cx.waker().clone();
+                eventbus.lock().subscribe(Box::new(move |event| {
+                    if !event.contains(Event::RECEIVE_SIGNAL) {
+                        return false;
+                    }
+                    waker.wake_by_ref();
+                    true
+                }));
+                // a signal may come before subscribing
+                if self.signalfd.can_read() {
+                    return Poll::Ready(self.signalfd.poll());
+                }
+                Poll::Pending
+            }
+        }
+
+        Box::pin(SignalFdFuture { signalfd: self })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Linux struct signalfd_siginfo, only read by user
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct SignalfdSiginfo {
+    signo: u32,
+    errno: i32,
+    code: i32,
+    pid: u32,
+    uid: u32,
+    fd: i32,
+    tid: u32,
+    band: u32,
+    overrun: u32,
+    trapno: u32,
+    status: i32,
+    int: i32,
+    ptr: u64,
+    utime: u64,
+    stime: u64,
+    addr: u64,
+    addr_lsb: u16,
+    _pad2: u16,
+    syscall: i32,
+    call_addr: u64,
+    arch: u32,
+    _pad: [u8; 28],
+}
+
+impl From<&Siginfo> for SignalfdSiginfo {
+    /// Take the fields of the union used by the signal and its si_code
+    fn from(info: &Siginfo) -> Self {
+        use Signal::*;
+        let field = &info.field;
+        let mut ssi = SignalfdSiginfo {
+            signo: info.signo as u32,
+            errno: info.errno,
+            code: info.code,
+            ..Default::default()
+        };
+        let signal = <Signal as FromPrimitive>::from_i32(info.signo);
+        let by_kernel = info.code > SI_USER && info.code < SI_KERNEL;
+        match signal {
+            Some(SIGILL) | Some(SIGFPE) | Some(SIGSEGV) | Some(SIGBUS) | Some(SIGTRAP)
+                if by_kernel =>
+            {
+                ssi.addr = field.u64_at(0);
+            }
+            Some(SIGCHLD) if by_kernel => {
+                ssi.pid = field.u32_at(0);
+                ssi.uid = field.u32_at(4);
+                ssi.status = field.u32_at(8) as i32;
+                ssi.utime = field.u64_at(16);
+                ssi.stime = field.u64_at(24);
+            }
+            _ if info.code == SI_TIMER => {
+                ssi.tid = field.u32_at(0);
+                ssi.overrun = field.u32_at(4);
+                ssi.ptr = field.u64_at(8);
+                ssi.int = field.u32_at(8) as i32;
+            }
+            _ => {
+                ssi.pid = field.u32_at(0);
+                ssi.uid = field.u32_at(4);
+                // sigqueue and the like carry a value
+                if info.code < 0 {
+                    ssi.ptr = field.u64_at(8);
+                    ssi.int = field.u32_at(8) as i32;
+                }
+            }
+        }
+        ssi
+    }
+}
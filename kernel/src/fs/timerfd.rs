@@ -0,0 +1,189 @@
+//! Implement INode for timerfd
+//!
+//! Reading gets the number of expirations since the last read, as a u64.
+//! Expirations are counted when the timer is read or polled, so nothing is done
+//! in the timer interrupt but waking up the waiting threads.
+
+use crate::arch::timer::timer_now;
+use crate::process::itimer::IntervalTimer;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::mem::size_of;
+use core::time::Duration;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use rcore_fs::vfs::*;
+
+const ZERO: Duration = Duration::from_secs(0);
+
+pub struct TimerFd {
+    /// Whether it is set by CLOCK_REALTIME, otherwise CLOCK_MONOTONIC
+    pub realtime: bool,
+    state: Mutex<TimerState>,
+}
+
+#[derive(Default)]
+struct TimerState {
+    /// The next expiration in `timer_now`, zero if it is disarmed
+    deadline: Duration,
+    interval: Duration,
+    /// Expirations not read yet
+    expirations: u64,
+    /// Threads waiting for the timer, woken up when it is set
+    wakers: Vec<Waker>,
+}
+
+impl TimerState {
+    /// Count the expirations up to now
+    fn update(&mut self) {
+        let now = timer_now();
+        if self.deadline == ZERO || now < self.deadline {
+            return;
+        }
+        if self.interval == ZERO {
+            self.expirations += 1;
+            self.deadline = ZERO;
+            return;
+        }
+        let interval = self.interval.as_nanos();
+        let count = (now - self.deadline).as_nanos() / interval + 1;
+        self.expirations += count as u64;
+        self.deadline += Duration::from_nanos((interval * count) as u64);
+    }
+}
+
+impl TimerFd {
+    pub fn new(realtime: bool) -> Self {
+        TimerFd {
+            realtime,
+            state: Mutex::new(TimerState::default()),
+        }
+    }
+
+    /// The time left before the next expiration, and the interval
+    pub fn get(&self) -> IntervalTimer {
+        let mut state = self.state.lock();
+        state.update();
+        let value = if state.deadline == ZERO {
+            ZERO
+        } else {
+            // about to expire, but not yet
+            state
+                .deadline
+                .checked_sub(timer_now())
+                .unwrap_or(Duration::from_nanos(1))
+        };
+        IntervalTimer {
+            value,
+            interval: state.interval,
+        }
+    }
+
+    /// Arm the timer to expire at `deadline` of `timer_now`, then every `interval`,
+    /// or disarm it if `deadline` is None. Return the old setting.
+    pub fn set(&self, deadline: Option<Duration>, interval: Duration) -> IntervalTimer {
+        let old = self.get();
+        let mut state = self.state.lock();
+        state.expirations = 0;
+        match deadline {
+            // an expired deadline fires at once
+            Some(deadline) => {
+                state.deadline = deadline.max(Duration::from_nanos(1));
+                state.interval = interval;
+            }
+            None => {
+                state.deadline = ZERO;
+                state.interval = ZERO;
+            }
+        }
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+        old
+    }
+
+    fn can_read(&self) -> bool {
+        let mut state = self.state.lock();
+        state.update();
+        state.expirations > 0
+    }
+}
+
+impl INode for TimerFd {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        const SIZE: usize = size_of::<u64>();
+        if buf.len() < SIZE {
+            return Err(FsError::InvalidParam);
+        }
+        let mut state = self.state.lock();
+        state.update();
+        if state.expirations == 0 {
+            return Err(FsError::Again);
+        }
+        buf[..SIZE].copy_from_slice(&state.expirations.to_ne_bytes());
+        state.expirations = 0;
+        Ok(SIZE)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::InvalidParam)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: self.can_read(),
+            write: false,
+            error: false,
+        })
+    }
+
+    fn async_poll<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<PollStatus>> + Send + Sync + 'a>> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct TimerFdFuture<'a> {
+            timerfd: &'a TimerFd,
+            /// The deadline the timer interrupt is set to wake up at
+            timer_added: Duration,
+        };
+
+        impl<'a> Future for TimerFdFuture<'a> {
+            type Output = Result<PollStatus>;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                let deadline = {
+                    let mut state = self.timerfd.state.lock();
+                    state.update();
+                    if state.expirations > 0 {
+                        drop(state);
+                        return Poll::Ready(self.timerfd.poll());
+                    }
+                    if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                        state.wakers.push(cx.waker().clone());
+                    }
+                    state.deadline
+                };
+                if deadline != ZERO && deadline != self.timer_added {
+                    self.timer_added = deadline;
+                    let waker = cx.waker().clone();
+                    crate::timer::add(deadline, Box::new(move |_| waker.wake()));
+                }
+                Poll::Pending
+            }
+        }
+
+        Box::pin(TimerFdFuture {
+            timerfd: self,
+            timer_added: ZERO,
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
@@ -41,6 +41,8 @@ pub use buddy_system_allocator::LockedHeapWithRescue;
 pub mod logging;
 #[macro_use]
 pub mod util;
+#[macro_use]
+pub mod percpu;
 
 pub mod backtrace;
 pub mod consts;
@@ -54,10 +56,12 @@ pub mod net;
 pub mod process;
 #[cfg(feature = "hypervisor")]
 pub mod rvm;
+pub mod sched;
 pub mod shell;
 pub mod signal;
 pub mod sync;
 pub mod syscall;
+pub mod timer;
 pub mod trap;
 
 #[allow(dead_code)]
@@ -78,10 +82,8 @@ pub mod arch;
 pub mod arch;
 
 pub fn kmain() -> ! {
-    loop {
-        executor::run_until_idle();
-        arch::interrupt::wait_for_interrupt();
-    }
+    process::set_cpu_online();
+    sched::idle()
 }
 
 /// Global heap allocator
@@ -90,5 +92,5 @@ pub fn kmain() -> ! {
 ///
 /// It should be defined in memory mod, but in Rust `global_allocator` must be in root mod.
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeapWithRescue =
-    LockedHeapWithRescue::new(crate::memory::enlarge_heap);
+static HEAP_ALLOCATOR: memory::slab::SlabHeap =
+    memory::slab::SlabHeap::new(crate::memory::enlarge_heap);
@@ -105,12 +105,19 @@ impl ModuleManager {
             let address = words.next().unwrap();
             let _stype = words.next().unwrap();
             let name = words.next().unwrap();
+            #[allow(unused_mut)]
+            let mut loc = usize::from_str_radix(address, 16).unwrap();
+            // the kernel is moved from where it is linked
+            #[cfg(target_arch = "x86_64")]
+            {
+                loc += crate::arch::kaslr::offset();
+            }
             // Simply add the symbol into stub.
             self.stub_symbols.insert(
                 String::from(name),
                 ModuleSymbol {
                     name: String::from(name),
-                    loc: usize::from_str_radix(address, 16).unwrap(),
+                    loc,
                 },
             );
         }
@@ -1,35 +1,89 @@
 //! Define the FrameAllocator for physical memory
 
 use super::HEAP_ALLOCATOR;
-use crate::consts::{KERNEL_OFFSET, MEMORY_OFFSET, PHYSICAL_MEMORY_OFFSET};
-use crate::process::current_thread;
+use crate::arch::cpu;
+use crate::arch::memory::{flush_tlb_others, set_page_table};
+use crate::consts::{KERNEL_OFFSET, MAX_CPU_NUM, MEMORY_OFFSET, PHYSICAL_MEMORY_OFFSET};
+use crate::fs::page_cache;
+use crate::process::{current_thread, online_cpus};
 use crate::sync::SpinNoIrqLock;
-use bitmap_allocator::BitAlloc;
 use buddy_system_allocator::Heap;
+use core::fmt;
 use core::mem;
 use core::mem::size_of;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use log::*;
+use rcore_memory::buddy::{BuddyFrameAlloc, FrameMemory};
 use rcore_memory::*;
 
+pub use self::swap::GlobalSwapper;
 pub use crate::arch::paging::*;
 pub use rcore_memory::memory_set::{handler::*, MemoryArea, MemoryAttr};
+
+mod heap_debug;
+pub mod oom;
+pub mod slab;
+pub mod swap;
 pub type MemorySet = rcore_memory::memory_set::MemorySet<PageTableImpl>;
 
-// x86_64 support up to 1T memory
-#[cfg(target_arch = "x86_64")]
-pub type FrameAlloc = bitmap_allocator::BitAlloc256M;
+/// Free frames are reached through the linear mapping of physical memory
+pub struct PhysFrames;
 
-// RISCV, ARM, MIPS has 1G memory
-#[cfg(any(
-    target_arch = "riscv32",
-    target_arch = "riscv64",
-    target_arch = "aarch64",
-    target_arch = "mips"
-))]
-pub type FrameAlloc = bitmap_allocator::BitAlloc1M;
+impl FrameMemory for PhysFrames {
+    fn frame_addr(frame: usize) -> usize {
+        phys_to_virt(frame * PAGE_SIZE + MEMORY_OFFSET)
+    }
+}
+
+pub type FrameAlloc = BuddyFrameAlloc<PhysFrames>;
 
 pub static FRAME_ALLOCATOR: SpinNoIrqLock<FrameAlloc> = SpinNoIrqLock::new(FrameAlloc::DEFAULT);
 
+/// Number of pages read from storage on each CPU,
+/// by which page faults needing I/O are told apart
+percpu! {
+    static ref PAGE_INS: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Count a page read from storage
+pub fn count_page_in() {
+    PAGE_INS.get().fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of pages read from storage on the current CPU
+pub fn page_ins() -> usize {
+    PAGE_INS.get().load(Ordering::Relaxed)
+}
+
+percpu! {
+    /// The user page table each CPU is in, whose entries its TLB may hold
+    static ref ACTIVE_TOKEN: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Switch this CPU to the user page table `token`
+pub fn activate_page_table(token: usize) {
+    ACTIVE_TOKEN.get().store(token, Ordering::SeqCst);
+    set_page_table(token);
+}
+
+/// Invalidate the TLB entries of `range` on the other CPUs in the page table `token`,
+/// after its entries are changed, which invalidates those of this CPU.
+///
+/// It waits for the CPUs, which answer even when spinning for a lock with interrupts
+/// disabled, so it can be called with the memory set locked.
+pub fn shootdown_tlb(token: usize, range: Range<usize>) {
+    let me = cpu::id();
+    let online = online_cpus();
+    let mask = (0..MAX_CPU_NUM.min(size_of::<usize>() * 8))
+        .filter(|&id| id != me && online & (1 << id) != 0)
+        .filter(|&id| ACTIVE_TOKEN.of(id).load(Ordering::SeqCst) == token)
+        .fold(0, |mask, id| mask | 1 << id);
+    if mask != 0 {
+        flush_tlb_others(mask, range);
+    }
+}
+
 /// Convert physical address to virtual address
 #[inline]
 #[cfg(not(mipsel))]
@@ -65,14 +119,19 @@ pub struct GlobalFrameAlloc;
 
 impl FrameAllocator for GlobalFrameAlloc {
     fn alloc(&self) -> Option<usize> {
-        // get the real address of the alloc frame
-        let ret = FRAME_ALLOCATOR
-            .lock()
-            .alloc()
-            .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
-        trace!("Allocate frame: {:x?}", ret);
-        ret
-        // TODO: try to swap out when alloc failed
+        loop {
+            // get the real address of the alloc frame
+            let ret = FRAME_ALLOCATOR
+                .lock()
+                .alloc()
+                .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+            trace!("Allocate frame: {:x?}", ret);
+            // try to drop cached file pages, swap out,
+            // or kill a process at last when alloc failed
+            if ret.is_some() || !(page_cache::shrink() || swap::reclaim() || oom::kill()) {
+                return ret;
+            }
+        }
     }
     fn alloc_contiguous(&self, size: usize, align_log2: usize) -> Option<PhysAddr> {
         // get the real address of the alloc frame
@@ -102,6 +161,70 @@ pub fn alloc_frame_contiguous(size: usize, align_log2: usize) -> Option<usize> {
     GlobalFrameAlloc.alloc_contiguous(size, align_log2)
 }
 
+/// A snapshot of memory usage in bytes, formatted like `/proc/meminfo`
+#[derive(Debug, Default)]
+pub struct MemInfo {
+    pub total: usize,
+    pub free: usize,
+    /// Pages in the page cache
+    pub cached: usize,
+    pub dirty: usize,
+    pub swap_total: usize,
+    pub swap_free: usize,
+    /// Pages taken by the slab caches
+    pub slab: usize,
+    pub heap_total: usize,
+    pub heap_used: usize,
+}
+
+impl MemInfo {
+    pub fn get() -> Self {
+        let (total, free) = {
+            let allocator = FRAME_ALLOCATOR.lock();
+            (allocator.total(), allocator.available())
+        };
+        let (cached, dirty) = page_cache::stats();
+        let (swap_total, swap_used) = swap::stats();
+        let (heap_total, heap_used) = {
+            let heap = HEAP_ALLOCATOR.heap().lock();
+            (heap.stats_total_bytes(), heap.stats_alloc_actual())
+        };
+        MemInfo {
+            total: total * PAGE_SIZE,
+            free: free * PAGE_SIZE,
+            cached: cached * PAGE_SIZE,
+            dirty: dirty * PAGE_SIZE,
+            swap_total: swap_total * PAGE_SIZE,
+            swap_free: (swap_total - swap_used) * PAGE_SIZE,
+            slab: HEAP_ALLOCATOR.slab_bytes(),
+            heap_total,
+            heap_used,
+        }
+    }
+}
+
+impl fmt::Display for MemInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fields = [
+            ("MemTotal", self.total),
+            ("MemFree", self.free),
+            // clean cached pages can be dropped at any time
+            ("MemAvailable", self.free + self.cached - self.dirty),
+            ("Cached", self.cached),
+            ("SwapTotal", self.swap_total),
+            ("SwapFree", self.swap_free),
+            ("Dirty", self.dirty),
+            ("Slab", self.slab),
+            ("HeapTotal", self.heap_total),
+            ("HeapUsed", self.heap_used),
+        ];
+        for (name, bytes) in fields.iter() {
+            writeln!(f, "{:<16}{:>8} kB", format!("{}:", name), bytes / 1024)?;
+        }
+        Ok(())
+    }
+}
+
 pub struct KernelStack(usize);
 const KSTACK_SIZE: usize = 0x4000; //16KB
 
@@ -129,11 +252,22 @@ impl Drop for KernelStack {
     }
 }
 
+/// Make sure a frame can be allocated, swapping out pages if necessary.
+/// Called before locking a memory set to handle its page fault,
+/// since pages of a locked memory set can not be swapped out.
+pub fn reserve_frame() {
+    let frame = GlobalFrameAlloc.alloc();
+    if let Some(frame) = frame {
+        GlobalFrameAlloc.dealloc(frame);
+    }
+}
+
 /// Handle page fault at `addr`.
 /// Return true to continue, false to halt.
 pub fn handle_page_fault(addr: usize) -> bool {
     debug!("page fault from kernel @ {:#x}", addr);
 
+    reserve_frame();
     let thread = current_thread().unwrap();
     let mut lock = thread.vm.lock();
     lock.handle_page_fault(addr)
@@ -147,6 +281,7 @@ pub fn handle_page_fault_ext(addr: usize, access: crate::memory::AccessType) ->
         addr, access
     );
 
+    reserve_frame();
     let thread = current_thread().unwrap();
     let mut lock = thread.vm.lock();
     lock.handle_page_fault_ext(addr, access)
@@ -159,6 +294,7 @@ pub fn init_heap() {
     static mut HEAP: [usize; HEAP_BLOCK] = [0; HEAP_BLOCK];
     unsafe {
         HEAP_ALLOCATOR
+            .heap()
             .lock()
             .init(HEAP.as_ptr() as usize, HEAP_BLOCK * MACHINE_ALIGN);
     }
@@ -0,0 +1,121 @@
+//! Debugging checks of the kernel heap, enabled by the `heap_debug` feature
+//!
+//! Each allocation is surrounded by redzones filled with a known byte,
+//! and a header in front of it records its state and size.
+//! Newly allocated memory is filled with `POISON_ALLOC`, and freed memory with
+//! `POISON_FREE`, so that using it uninitialized or after free is easy to spot.
+//! Freeing memory twice, freeing with a wrong size, or writing out of bounds
+//! into a redzone panics with a report of the allocation.
+
+use core::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::slice;
+
+const POISON_ALLOC: u8 = 0xa5;
+const POISON_FREE: u8 = 0x6b;
+const REDZONE: u8 = 0xfd;
+/// Size of the redzone after an allocation
+const BACK_REDZONE_SIZE: usize = 16;
+
+const MAGIC_ALLOCATED: usize = 0x6865_6170_616c_6c63;
+const MAGIC_FREED: usize = 0x6865_6170_6672_6565;
+
+/// Put at the start of the front redzone
+#[repr(C)]
+struct Header {
+    /// Left alone, since allocators keep their free lists at the start of free blocks,
+    /// so that a freed header can be recognized until it is reused
+    _link: usize,
+    magic: usize,
+    size: usize,
+}
+
+/// Alignment of the memory allocated for `layout`, enough for the header
+fn outer_align(layout: &Layout) -> usize {
+    layout.align().max(align_of::<Header>())
+}
+
+/// Size of the header and the redzone before an allocation,
+/// keeping the allocation aligned
+fn front_size(layout: &Layout) -> usize {
+    let align = outer_align(layout);
+    (size_of::<Header>() + BACK_REDZONE_SIZE + align - 1) & !(align - 1)
+}
+
+/// The layout allocated for `layout`, including the header and redzones
+pub fn outer_layout(layout: &Layout) -> Layout {
+    let size = front_size(layout) + layout.size() + BACK_REDZONE_SIZE;
+    Layout::from_size_align(size, outer_align(layout)).unwrap()
+}
+
+/// Fill the redzones and the header of memory allocated at `ptr`,
+/// and return the memory for `layout` in it
+pub unsafe fn on_alloc(ptr: *mut u8, layout: &Layout) -> *mut u8 {
+    if ptr.is_null() {
+        return ptr;
+    }
+    let front = front_size(layout);
+    let outer = slice::from_raw_parts_mut(ptr, outer_layout(layout).size());
+    outer[size_of::<Header>()..front]
+        .iter_mut()
+        .for_each(|x| *x = REDZONE);
+    outer[front..front + layout.size()]
+        .iter_mut()
+        .for_each(|x| *x = POISON_ALLOC);
+    outer[front + layout.size()..]
+        .iter_mut()
+        .for_each(|x| *x = REDZONE);
+    let header = &mut *(ptr as *mut Header);
+    header.magic = MAGIC_ALLOCATED;
+    header.size = layout.size();
+    ptr.add(front)
+}
+
+/// Check the allocation at `ptr` being freed and poison it.
+/// Return the memory to free, including the header and redzones.
+pub unsafe fn on_dealloc(ptr: *mut u8, layout: &Layout) -> *mut u8 {
+    let front = front_size(layout);
+    let outer_ptr = ptr.sub(front);
+    let header = &mut *(outer_ptr as *mut Header);
+    match header.magic {
+        MAGIC_ALLOCATED => {}
+        MAGIC_FREED => panic!(
+            "heap: double free of {:p} with size {:#x}",
+            ptr,
+            layout.size()
+        ),
+        magic => panic!(
+            "heap: free of {:p} not allocated, or with its header overwritten (magic {:#x})",
+            ptr, magic
+        ),
+    }
+    if header.size != layout.size() {
+        panic!(
+            "heap: free of {:p} with size {:#x}, allocated with size {:#x}",
+            ptr,
+            layout.size(),
+            header.size
+        );
+    }
+    let outer = slice::from_raw_parts_mut(outer_ptr, outer_layout(layout).size());
+    let front_zone = size_of::<Header>()..front;
+    let back_zone = front + layout.size()..outer.len();
+    for (name, zone) in [("front", front_zone), ("back", back_zone)].iter() {
+        if let Some(offset) = outer[zone.clone()].iter().position(|&x| x != REDZONE) {
+            let addr = outer_ptr as usize + zone.start + offset;
+            panic!(
+                "heap: {} redzone of {:p} with size {:#x} overwritten at {:#x}: {:x?}",
+                name,
+                ptr,
+                layout.size(),
+                addr,
+                &outer[zone.start + offset..zone.end]
+            );
+        }
+    }
+    outer[front..front + layout.size()]
+        .iter_mut()
+        .for_each(|x| *x = POISON_FREE);
+    header.magic = MAGIC_FREED;
+    outer_ptr
+}
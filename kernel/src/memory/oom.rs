@@ -0,0 +1,64 @@
+//! Out of memory killer
+//!
+//! When no frame can be allocated even after dropping cached file pages and swapping,
+//! the process with the most resident pages is killed by SIGKILL. Its pages which can
+//! be filled again on fault are freed at once, since it may not run and exit
+//! before the allocation has to succeed.
+
+use crate::process::PROCESSES;
+use crate::signal::{send_signal, Siginfo, Signal, SI_KERNEL};
+
+/// Kill the process using the most memory and free as much of its memory as possible.
+/// The init process is never chosen, and memory sets in use are skipped.
+/// Return false if no memory was freed.
+pub fn kill() -> bool {
+    let processes = match PROCESSES.try_read() {
+        Some(processes) => processes,
+        None => return false,
+    };
+    let mut victim = None;
+    let mut max_pages = 0;
+    for (&pid, proc) in processes.iter() {
+        if pid == 1 {
+            continue;
+        }
+        let vm = match proc.try_lock() {
+            Some(proc) => proc.vm.clone(),
+            None => continue,
+        };
+        let pages = match vm.try_lock() {
+            Some(mut vm) => vm.resident_pages(),
+            None => continue,
+        };
+        if pages > max_pages {
+            max_pages = pages;
+            victim = Some((pid, proc.clone(), vm));
+        }
+    }
+    drop(processes);
+
+    let (pid, proc, vm) = match victim {
+        Some(victim) => victim,
+        None => return false,
+    };
+    let mut vm = match vm.try_lock() {
+        Some(vm) => vm,
+        None => return false,
+    };
+    warn!(
+        "out of memory: kill process {} with {} resident pages",
+        pid, max_pages
+    );
+    // free the frames first, as sending the signal may allocate
+    vm.discard(0, usize::MAX);
+    let freed = max_pages - vm.resident_pages();
+    drop(vm);
+    let info = Siginfo {
+        signo: Signal::SIGKILL as i32,
+        errno: 0,
+        code: SI_KERNEL,
+        field: Default::default(),
+    };
+    send_signal(proc, -1, info);
+    freed > 0
+}
@@ -0,0 +1,280 @@
+//! Slab allocator of small kernel objects
+//!
+//! Allocations up to `MAX_SLAB_SIZE` are served from caches of power-of-two size classes.
+//! Each cache carves pages taken from the frame allocator into slots of its size,
+//! so that hot kernel objects, such as threads, files and cached inodes allocated
+//! through `Box` and `Arc`, do not fragment the general heap. Bigger allocations
+//! go to the heap.
+//!
+//! A page of a cache starts with a header, followed by slots aligned to their size.
+//! Pages having free slots are linked into a list of the cache, and a page left with
+//! no object in use is given back, except for one kept as a spare.
+
+use super::{heap_debug, phys_to_virt, virt_to_phys, FRAME_ALLOCATOR};
+use crate::consts::MEMORY_OFFSET;
+use crate::sync::SpinNoIrqLock as Mutex;
+use buddy_system_allocator::{Heap, LockedHeapWithRescue};
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::size_of;
+use core::ptr::null_mut;
+use rcore_memory::PAGE_SIZE;
+
+/// Slots of the smallest size class
+const MIN_SLAB_SIZE: usize = 16;
+/// Slots of the biggest size class
+const MAX_SLAB_SIZE: usize = 1024;
+/// Number of size classes from `MIN_SLAB_SIZE` to `MAX_SLAB_SIZE`
+const CLASSES: usize = 7;
+
+/// Header at the start of a page of a cache
+#[repr(C)]
+struct SlabPage {
+    /// Neighbours in the list of pages with free slots, 0 for none
+    prev: usize,
+    next: usize,
+    /// The first free slot, each of which holds the address of the next one, 0 for none
+    free: usize,
+    /// Number of slots in use
+    used: usize,
+    /// Whether the page was taken from the heap, because no frame was available
+    from_heap: bool,
+}
+
+/// A cache of objects of one size class
+struct SlabCache {
+    size: usize,
+    /// The first page with free slots, 0 for none
+    partial: usize,
+    /// An empty page kept to avoid freeing and allocating pages repeatedly, 0 for none
+    spare: usize,
+    /// Number of pages taken, including the spare one
+    pages: usize,
+}
+
+/// The global allocator, made of slab caches in front of the heap
+pub struct SlabHeap {
+    caches: [Mutex<SlabCache>; CLASSES],
+    heap: LockedHeapWithRescue,
+}
+
+impl SlabHeap {
+    pub const fn new(rescue: fn(&mut Heap)) -> Self {
+        SlabHeap {
+            caches: [
+                Mutex::new(SlabCache::new(16)),
+                Mutex::new(SlabCache::new(32)),
+                Mutex::new(SlabCache::new(64)),
+                Mutex::new(SlabCache::new(128)),
+                Mutex::new(SlabCache::new(256)),
+                Mutex::new(SlabCache::new(512)),
+                Mutex::new(SlabCache::new(1024)),
+            ],
+            heap: LockedHeapWithRescue::new(rescue),
+        }
+    }
+
+    /// The heap serving allocations bigger than slab objects
+    pub fn heap(&self) -> &LockedHeapWithRescue {
+        &self.heap
+    }
+
+    /// Bytes of the pages taken by the caches
+    pub fn slab_bytes(&self) -> usize {
+        let pages: usize = self.caches.iter().map(|cache| cache.lock().pages).sum();
+        pages * PAGE_SIZE
+    }
+
+    /// Index of the cache for `layout`, or None if it is too big
+    fn class(layout: &Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align()).max(MIN_SLAB_SIZE);
+        if size > MAX_SLAB_SIZE {
+            return None;
+        }
+        let class = size.next_power_of_two().trailing_zeros() - MIN_SLAB_SIZE.trailing_zeros();
+        Some(class as usize)
+    }
+
+    fn page_layout() -> Layout {
+        Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()
+    }
+
+    /// Take a page for a cache, from the frame allocator if possible.
+    /// Frames are not reclaimed from the page cache or swap here,
+    /// since doing so allocates memory itself.
+    unsafe fn alloc_page(&self) -> Option<(usize, bool)> {
+        if let Some(frame) = FRAME_ALLOCATOR.lock().alloc() {
+            return Some((phys_to_virt(frame * PAGE_SIZE + MEMORY_OFFSET), false));
+        }
+        let page = self.heap.alloc(Self::page_layout());
+        if page.is_null() {
+            return None;
+        }
+        Some((page as usize, true))
+    }
+
+    unsafe fn dealloc_page(&self, page: usize) {
+        if (*(page as *const SlabPage)).from_heap {
+            self.heap.dealloc(page as *mut u8, Self::page_layout());
+        } else {
+            let frame = (virt_to_phys(page) - MEMORY_OFFSET) / PAGE_SIZE;
+            FRAME_ALLOCATOR.lock().dealloc(frame);
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for SlabHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if cfg!(feature = "heap_debug") {
+            let ptr = self.alloc_inner(heap_debug::outer_layout(&layout));
+            heap_debug::on_alloc(ptr, &layout)
+        } else {
+            self.alloc_inner(layout)
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if cfg!(feature = "heap_debug") {
+            let ptr = heap_debug::on_dealloc(ptr, &layout);
+            self.dealloc_inner(ptr, heap_debug::outer_layout(&layout));
+        } else {
+            self.dealloc_inner(ptr, layout)
+        }
+    }
+}
+
+impl SlabHeap {
+    unsafe fn alloc_inner(&self, layout: Layout) -> *mut u8 {
+        let class = match Self::class(&layout) {
+            Some(class) => class,
+            None => return self.heap.alloc(layout),
+        };
+        if let Some(ptr) = self.caches[class].lock().alloc() {
+            return ptr as *mut u8;
+        }
+        // the lock is released while allocating a page
+        let (page, from_heap) = match self.alloc_page() {
+            Some(page) => page,
+            None => return null_mut(),
+        };
+        let mut cache = self.caches[class].lock();
+        cache.add_page(page, from_heap);
+        cache.alloc().unwrap() as *mut u8
+    }
+
+    unsafe fn dealloc_inner(&self, ptr: *mut u8, layout: Layout) {
+        let class = match Self::class(&layout) {
+            Some(class) => class,
+            None => return self.heap.dealloc(ptr, layout),
+        };
+        let page = self.caches[class].lock().dealloc(ptr as usize);
+        if let Some(page) = page {
+            self.dealloc_page(page);
+        }
+    }
+}
+
+impl SlabCache {
+    const fn new(size: usize) -> Self {
+        SlabCache {
+            size,
+            partial: 0,
+            spare: 0,
+            pages: 0,
+        }
+    }
+
+    unsafe fn page(page: usize) -> &'static mut SlabPage {
+        &mut *(page as *mut SlabPage)
+    }
+
+    /// Carve a new page into free slots
+    unsafe fn add_page(&mut self, page: usize, from_heap: bool) {
+        let first = (size_of::<SlabPage>() + self.size - 1) / self.size;
+        let mut free = 0;
+        for i in (first..PAGE_SIZE / self.size).rev() {
+            let slot = page + i * self.size;
+            *(slot as *mut usize) = free;
+            free = slot;
+        }
+        *Self::page(page) = SlabPage {
+            prev: 0,
+            next: 0,
+            free,
+            used: 0,
+            from_heap,
+        };
+        self.push(page);
+        self.pages += 1;
+    }
+
+    /// Allocate a slot, or return None if no page has free slots
+    unsafe fn alloc(&mut self) -> Option<usize> {
+        if self.partial == 0 && self.spare != 0 {
+            let spare = self.spare;
+            self.spare = 0;
+            self.push(spare);
+        }
+        if self.partial == 0 {
+            return None;
+        }
+        let page = Self::page(self.partial);
+        let slot = page.free;
+        page.free = *(slot as *const usize);
+        page.used += 1;
+        if page.free == 0 {
+            // full pages are not in the list
+            self.remove(self.partial);
+        }
+        Some(slot)
+    }
+
+    /// Free a slot.
+    /// Return the page of it if the page is empty and should be freed.
+    unsafe fn dealloc(&mut self, slot: usize) -> Option<usize> {
+        let addr = slot & !(PAGE_SIZE - 1);
+        let page = Self::page(addr);
+        let was_full = page.free == 0;
+        *(slot as *mut usize) = page.free;
+        page.free = slot;
+        page.used -= 1;
+        if was_full {
+            self.push(addr);
+        }
+        if page.used > 0 {
+            return None;
+        }
+        self.remove(addr);
+        if self.spare == 0 {
+            self.spare = addr;
+            return None;
+        }
+        self.pages -= 1;
+        Some(addr)
+    }
+
+    /// Link `page` at the head of the list of pages with free slots
+    unsafe fn push(&mut self, page: usize) {
+        let next = self.partial;
+        let header = Self::page(page);
+        header.prev = 0;
+        header.next = next;
+        if next != 0 {
+            Self::page(next).prev = page;
+        }
+        self.partial = page;
+    }
+
+    /// Unlink `page` from the list of pages with free slots
+    unsafe fn remove(&mut self, page: usize) {
+        let header = Self::page(page);
+        let (prev, next) = (header.prev, header.next);
+        if prev != 0 {
+            Self::page(prev).next = next;
+        } else {
+            self.partial = next;
+        }
+        if next != 0 {
+            Self::page(next).prev = prev;
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Swap space for anonymous pages
+//!
+//! The swap area is a swap file or partition prepared by mkswap.
+//! Page 0 holds the swap header, and each following page is a slot
+//! for one swapped out page, whose number is kept in the page table entry.
+
+use super::{Swapper, PAGE_SIZE};
+use crate::fs::page_cache;
+use crate::process::{all_processes, PROCESSES};
+use crate::sync::SpinLock as Mutex;
+use crate::syscall::SysError;
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rcore_fs::vfs::{FileType, INode};
+
+/// Signature at the end of the header page
+const SWAP_MAGIC: &[u8] = b"SWAPSPACE2";
+/// Offset of the index of the last usable page in the header
+const LAST_PAGE_OFFSET: usize = 1028;
+
+struct SwapArea {
+    inode: Arc<dyn INode>,
+    /// Whether each slot is in use, slot 0 being the header
+    used: Vec<bool>,
+    /// Being turned off, so no more pages are swapped out to it
+    closing: bool,
+}
+
+lazy_static! {
+    static ref SWAP: Mutex<Option<SwapArea>> = Mutex::new(None);
+}
+
+/// Pid of the process swapped out from last time,
+/// so that processes take turns to give up their pages
+static LAST_VICTIM: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct GlobalSwapper;
+
+impl Swapper for GlobalSwapper {
+    fn swap_out(&self, data: &[u8]) -> Option<usize> {
+        // swapping out may happen while doing swap I/O, when allocating frames
+        let mut swap = SWAP.try_lock()?;
+        let area = swap.as_mut().filter(|area| !area.closing)?;
+        let slot = area.used.iter().position(|&used| !used)?;
+        area.inode.write_at(slot * PAGE_SIZE, data).ok()?;
+        area.used[slot] = true;
+        Some(slot)
+    }
+
+    fn swap_in(&self, slot: usize, data: &mut [u8]) {
+        let swap = SWAP.lock();
+        let area = swap.as_ref().expect("no swap area");
+        area.inode
+            .read_at(slot * PAGE_SIZE, data)
+            .expect("failed to read swap area");
+        super::count_page_in();
+    }
+
+    fn swap_free(&self, slot: usize) {
+        if let Some(area) = SWAP.lock().as_mut() {
+            area.used[slot] = false;
+        }
+    }
+}
+
+/// Start swapping to `inode`, which must have a valid swap header
+pub fn swap_on(inode: Arc<dyn INode>) -> Result<(), SysError> {
+    let metadata = inode.metadata()?;
+    if metadata.type_ != FileType::File && metadata.type_ != FileType::BlockDevice {
+        return Err(SysError::EINVAL);
+    }
+    // slots are accessed without the page cache
+    page_cache::sync(&inode)?;
+    page_cache::truncate(&*inode, 0);
+    let mut header = [0u8; PAGE_SIZE];
+    if inode.read_at(0, &mut header)? != PAGE_SIZE
+        || &header[PAGE_SIZE - SWAP_MAGIC.len()..] != SWAP_MAGIC
+    {
+        return Err(SysError::EINVAL);
+    }
+    let mut last_page = [0u8; 4];
+    last_page.copy_from_slice(&header[LAST_PAGE_OFFSET..LAST_PAGE_OFFSET + 4]);
+    let mut pages = u32::from_le_bytes(last_page) as usize + 1;
+    if metadata.type_ == FileType::File {
+        pages = pages.min(metadata.size / PAGE_SIZE);
+    }
+    if pages < 2 {
+        return Err(SysError::EINVAL);
+    }
+
+    let mut swap = SWAP.lock();
+    if swap.is_some() {
+        return Err(SysError::EBUSY);
+    }
+    let mut used = vec![false; pages];
+    used[0] = true;
+    *swap = Some(SwapArea {
+        inode,
+        used,
+        closing: false,
+    });
+    info!("swap on: {} pages", pages - 1);
+    Ok(())
+}
+
+/// Stop swapping to `inode`, after reading the pages in it back into memory
+pub fn swap_off(inode: &Arc<dyn INode>) -> Result<(), SysError> {
+    {
+        let mut swap = SWAP.lock();
+        let area = swap.as_mut().ok_or(SysError::EINVAL)?;
+        let (a, b) = (area.inode.metadata()?, inode.metadata()?);
+        if (a.dev, a.inode) != (b.dev, b.inode) {
+            return Err(SysError::EINVAL);
+        }
+        // being turned off by someone else
+        if area.closing {
+            return Err(SysError::EBUSY);
+        }
+        area.closing = true;
+    }
+    // the area is unlocked, as reading the pages back locks it
+    for proc in all_processes() {
+        let vm = proc.lock().vm.clone();
+        let done = vm.lock().swap_in_all();
+        if !done {
+            SWAP.lock().as_mut().expect("no swap area").closing = false;
+            return Err(SysError::ENOMEM);
+        }
+    }
+    *SWAP.lock() = None;
+    info!("swap off");
+    Ok(())
+}
+
+/// Number of slots in the swap area, and of used ones among them
+pub fn stats() -> (usize, usize) {
+    match SWAP.lock().as_ref() {
+        Some(area) => {
+            let used = area.used.iter().skip(1).filter(|&&used| used).count();
+            (area.used.len() - 1, used)
+        }
+        None => (0, 0),
+    }
+}
+
+/// Swap out a page of some process to free a frame.
+/// Memory sets in use are skipped, instead of waiting for them.
+/// Return false if no page can be swapped out.
+pub fn reclaim() -> bool {
+    match SWAP.try_lock() {
+        Some(swap) if swap.as_ref().map_or(false, |area| !area.closing) => {}
+        _ => return false,
+    }
+    let processes = match PROCESSES.try_read() {
+        Some(processes) => processes,
+        None => return false,
+    };
+    let last = LAST_VICTIM.load(Ordering::Relaxed);
+    let candidates = processes.range(last + 1..).chain(processes.range(..=last));
+    for (&pid, proc) in candidates {
+        let vm = match proc.try_lock() {
+            Some(proc) => proc.vm.clone(),
+            None => continue,
+        };
+        let mut vm = match vm.try_lock() {
+            Some(vm) => vm,
+            None => continue,
+        };
+        if vm.swap_out() {
+            LAST_VICTIM.store(pid, Ordering::Relaxed);
+            return true;
+        }
+    }
+    false
+}
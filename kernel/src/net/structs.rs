@@ -80,7 +80,7 @@ pub trait Socket: Send + Sync + Debug {
     }
     fn ioctl(&mut self, _request: usize, _arg1: usize, _arg2: usize, _arg3: usize) -> SysResult {
         warn!("ioctl is unimplemented for this socket");
-        Ok(0)
+        Err(SysError::ENOTTY)
     }
     fn box_clone(&self) -> Box<dyn Socket>;
 }
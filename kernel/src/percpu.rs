@@ -0,0 +1,84 @@
+//! Per-CPU variables
+//!
+//! Each CPU has its own copy of a per-CPU variable, found by the id of the CPU kept in a
+//! register of its own: `tp` on RISC-V, `TSC_AUX` read by `rdtscp` on x86_64 where there is
+//! one, as `GS` is switched by `trapframe` on the way to and from user, `MPIDR_EL1` on
+//! AArch64 and `EBase` on MIPS. The copies are in cache lines of their own, so that the
+//! CPUs do not contend for them. What is only touched by its own CPU needs no lock,
+//! but the interrupts on the CPU may touch it meanwhile, so it is accessed with them
+//! disabled in `Local`.
+
+use crate::arch::{cpu, interrupt};
+use crate::consts::MAX_CPU_NUM;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+
+/// Declare per-CPU variables lazily initialized, each CPU with a copy of the value.
+///
+/// `static ref NAME: T = value;` declares `NAME` of `PerCpu<T>`.
+macro_rules! percpu {
+    ($(#[$attr:meta])* static ref $name:ident: $ty:ty = $init:expr; $($rest:tt)*) => {
+        lazy_static! {
+            $(#[$attr])*
+            static ref $name: $crate::percpu::PerCpu<$ty> = $crate::percpu::PerCpu::new(|| $init);
+        }
+        percpu! { $($rest)* }
+    };
+    ($(#[$attr:meta])* pub static ref $name:ident: $ty:ty = $init:expr; $($rest:tt)*) => {
+        lazy_static! {
+            $(#[$attr])*
+            pub static ref $name: $crate::percpu::PerCpu<$ty> = $crate::percpu::PerCpu::new(|| $init);
+        }
+        percpu! { $($rest)* }
+    };
+    () => {};
+}
+
+#[repr(align(64))]
+struct CacheLine<T>(T);
+
+pub struct PerCpu<T> {
+    copies: Vec<CacheLine<T>>,
+}
+
+impl<T> PerCpu<T> {
+    pub fn new(init: impl Fn() -> T) -> Self {
+        PerCpu {
+            copies: (0..MAX_CPU_NUM).map(|_| CacheLine(init())).collect(),
+        }
+    }
+
+    /// The copy of this CPU
+    pub fn get(&self) -> &T {
+        &self.copies[cpu::id()].0
+    }
+
+    /// The copy of CPU `cpu_id`
+    pub fn of(&self, cpu_id: usize) -> &T {
+        &self.copies[cpu_id].0
+    }
+}
+
+/// A copy only accessed by its own CPU, without a lock
+pub struct Local<T>(UnsafeCell<T>);
+
+unsafe impl<T: Send> Sync for Local<T> {}
+
+impl<T> Local<T> {
+    pub fn new(value: T) -> Self {
+        Local(UnsafeCell::new(value))
+    }
+}
+
+impl<T> PerCpu<Local<T>> {
+    /// Access the copy of this CPU, with interrupts disabled.
+    /// `f` must not access it again.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        unsafe {
+            let flags = interrupt::disable_and_store();
+            let res = f(&mut *self.get().0.get());
+            interrupt::restore(flags);
+            res
+        }
+    }
+}
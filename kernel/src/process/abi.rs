@@ -1,20 +1,29 @@
 use alloc::collections::btree_map::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
+use core::mem::size_of;
 use core::ptr::null;
 
 pub struct ProcInitInfo {
     pub args: Vec<String>,
     pub envs: Vec<String>,
     pub auxv: BTreeMap<u8, usize>,
+    /// Bytes pointed to by AT_RANDOM, used by libc to seed stack canaries
+    pub random: [u8; 16],
 }
 
 impl ProcInitInfo {
+    /// Write argc, argv, envp and the auxiliary vector, with the strings they
+    /// point to, below `stack_top`. Return the stack pointer, at argc,
+    /// which is aligned to 16 bytes as the ABIs require.
     pub unsafe fn push_at(&self, stack_top: usize) -> usize {
         let mut writer = StackWriter { sp: stack_top };
         // from stack_top:
         // program name
         writer.push_str(&self.args[0]);
+        // random bytes
+        writer.push_slice(&self.random);
+        let random = writer.sp;
         // environment strings
         let envs: Vec<_> = self
             .envs
@@ -33,9 +42,15 @@ impl ProcInitInfo {
                 writer.sp
             })
             .collect();
+        let mut auxv = self.auxv.clone();
+        auxv.insert(AT_RANDOM, random);
+        // pad so that argc ends up aligned
+        let words = (auxv.len() + 1) * 2 + envs.len() + 1 + argv.len() + 1 + 1;
+        writer.sp -= writer.sp % 16;
+        writer.sp -= (16 - words * size_of::<usize>() % 16) % 16;
         // auxiliary vector entries
         writer.push_slice(&[null::<u8>(), null::<u8>()]);
-        for (&type_, &value) in self.auxv.iter() {
+        for (&type_, &value) in auxv.iter() {
             writer.push_slice(&[type_ as usize, value]);
         }
         // envionment pointers
@@ -56,10 +71,7 @@ struct StackWriter {
 
 impl StackWriter {
     fn push_slice<T: Copy>(&mut self, vs: &[T]) {
-        use core::{
-            mem::{align_of, size_of},
-            slice,
-        };
+        use core::{mem::align_of, slice};
         self.sp -= vs.len() * size_of::<T>();
         self.sp -= self.sp % align_of::<T>();
         unsafe { slice::from_raw_parts_mut(self.sp as *mut T, vs.len()) }.copy_from_slice(vs);
@@ -76,3 +88,5 @@ pub const AT_PHNUM: u8 = 5;
 pub const AT_PAGESZ: u8 = 6;
 pub const AT_BASE: u8 = 7;
 pub const AT_ENTRY: u8 = 9;
+pub const AT_RANDOM: u8 = 25;
+pub const AT_SYSINFO_EHDR: u8 = 33;
@@ -0,0 +1,56 @@
+//! Address space layout randomization
+//!
+//! The user stack, the base of mmap, the vDSO and the load address of PIE executables
+//! are moved by random page-aligned offsets at exec time.
+//! It is disabled by `norandmaps` in the kernel command line, like Linux.
+
+use crate::arch::{rand, timer::timer_now};
+use crate::consts::USER_HEAP_SIZE;
+use crate::drivers::CMDLINE;
+use crate::sync::SpinNoIrqLock as Mutex;
+use rcore_memory::PAGE_SIZE;
+
+/// The stack top is moved down by at most this
+pub const STACK_RANDOM_RANGE: usize = 0x100_0000;
+/// The base of mmap is moved up by at most this
+pub const MMAP_RANDOM_RANGE: usize = 0x100_0000;
+/// The vDSO is mapped below the lowest the stack may grow to, moved down by at most this
+pub const VDSO_RANDOM_RANGE: usize = 0x100_0000;
+/// PIE executables are loaded at this address, moved up by at most `PIE_RANDOM_RANGE`
+pub const PIE_BASE: usize = 0x1000_0000;
+pub const PIE_RANDOM_RANGE: usize = 0x100_0000;
+
+lazy_static! {
+    static ref ENABLED: bool = !CMDLINE
+        .read()
+        .split_whitespace()
+        .any(|arg| arg == "norandmaps");
+    /// State of the xorshift generator, mixed with the entropy of
+    /// the architecture and the timer on every use
+    static ref STATE: Mutex<u64> = Mutex::new(0x2545_f491_4f6c_dd1d);
+}
+
+/// A random number, even if randomization of the layout is disabled
+pub fn random() -> u64 {
+    let mut state = STATE.lock();
+    let mut x = *state ^ rand::rand() ^ timer_now().as_nanos() as u64;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
+
+/// Where mmap starts for a program whose heap starts at `brk_start`
+pub fn mmap_base(brk_start: usize) -> usize {
+    brk_start + USER_HEAP_SIZE + random_offset(MMAP_RANDOM_RANGE)
+}
+
+/// A random page-aligned offset less than `range`, or 0 if disabled
+pub fn random_offset(range: usize) -> usize {
+    let pages = range / PAGE_SIZE;
+    if !*ENABLED || pages == 0 {
+        return 0;
+    }
+    (random() % pages as u64) as usize * PAGE_SIZE
+}
@@ -0,0 +1,275 @@
+//! ELF core dumps of processes killed by signals
+//!
+//! The core file `core` in the working directory holds a PT_NOTE segment with the
+//! process status, including the registers of the faulting thread, and a PT_LOAD
+//! segment for each user memory area. Pages never touched are left as holes.
+//! It is limited by RLIMIT_CORE, which is 0 by default, as in Linux.
+//! Only x86_64 is supported for now.
+
+use super::{Process, Thread};
+use crate::fs::{page_cache, INodeExt};
+use crate::signal::Signal;
+use crate::syscall::SysError;
+use alloc::{sync::Arc, vec::Vec};
+use core::time::Duration;
+use rcore_fs::vfs::{FileType, FsError, INode};
+use rcore_memory::PAGE_SIZE;
+use trapframe::UserContext;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+
+/// Write a core file of `proc`, as `thread` is killed by `signal` at `context`.
+/// Return whether the core file is written.
+pub fn core_dump(proc: &Process, thread: &Thread, context: &UserContext, signal: Signal) -> bool {
+    if !cfg!(target_arch = "x86_64") || !proc.dumpable || proc.core_limit.cur == 0 {
+        return false;
+    }
+    match write_core(proc, thread, context, signal) {
+        Ok(()) => true,
+        Err(err) => {
+            warn!("core dump of process {} failed: {:?}", proc.pid, err);
+            false
+        }
+    }
+}
+
+fn write_core(
+    proc: &Process,
+    thread: &Thread,
+    context: &UserContext,
+    signal: Signal,
+) -> Result<(), SysError> {
+    let limit = proc.core_limit.cur.min(usize::MAX as u64) as usize;
+    let areas: Vec<_> = thread
+        .vm
+        .lock()
+        .iter()
+        .filter(|area| area.attr().is_user())
+        .map(|area| (area.start_addr(), area.end_addr(), area.attr()))
+        .collect();
+
+    let notes = notes(proc, thread, context, signal);
+    let headers_size = ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE * (areas.len() + 1);
+    let mut headers = Vec::with_capacity(headers_size);
+    elf_header(&mut headers, areas.len() + 1);
+    program_header(
+        &mut headers,
+        PT_NOTE,
+        0,
+        headers_size,
+        0,
+        notes.len(),
+        notes.len(),
+        4,
+    );
+    // segments start at page boundaries
+    let mut offset = round_up(headers_size + notes.len());
+    let mut segments = Vec::new();
+    for &(start, end, attr) in areas.iter() {
+        let flags =
+            4 | if attr.is_readonly() { 0 } else { 2 } | if attr.is_execute() { 1 } else { 0 };
+        let size = end - start;
+        program_header(
+            &mut headers,
+            PT_LOAD,
+            flags,
+            offset,
+            start,
+            size,
+            size,
+            PAGE_SIZE,
+        );
+        segments.push((offset, start, end));
+        offset += size;
+    }
+
+    let inode = create_core_file(proc)?;
+    let mut data = headers;
+    data.extend_from_slice(&notes);
+    data.truncate(limit);
+    page_cache::write_at(&inode, 0, &data)?;
+    let mut page = [0u8; PAGE_SIZE];
+    let mut vm = thread.vm.lock();
+    for (offset, start, end) in segments {
+        for addr in (start..end).step_by(PAGE_SIZE) {
+            let page_offset = offset + (addr - start);
+            if page_offset + PAGE_SIZE > limit {
+                break;
+            }
+            if vm.read_page(addr, &mut page) {
+                page_cache::write_at(&inode, page_offset, &page)?;
+            }
+        }
+    }
+    drop(vm);
+    // the pages never touched at the end
+    let size = offset.min(limit);
+    if inode.metadata()?.size < size {
+        inode.truncate(size)?;
+    }
+    info!("core dumped for process {}: {} bytes", proc.pid, size);
+    Ok(())
+}
+
+/// Create `core` in the working directory, or truncate it if it exists
+fn create_core_file(proc: &Process) -> Result<Arc<dyn INode>, SysError> {
+    let dir = proc.lookup_inode(".")?;
+    match dir.find("core") {
+        Ok(inode) => {
+            if inode.metadata()?.type_ != FileType::File {
+                return Err(SysError::EEXIST);
+            }
+            inode.truncate(0)?;
+            Ok(inode)
+        }
+        Err(FsError::EntryNotFound) => {
+            let inode = dir.create("core", FileType::File, 0o600)?;
+            proc.set_owner(&inode)?;
+            Ok(inode)
+        }
+        Err(err) => Err(SysError::from(err)),
+    }
+}
+
+fn round_up(size: usize) -> usize {
+    (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+fn put16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn elf_header(buf: &mut Vec<u8>, phnum: usize) {
+    // ELFCLASS64, ELFDATA2LSB, EV_CURRENT, ELFOSABI_NONE
+    buf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    buf.extend_from_slice(&[0; 8]);
+    put16(buf, 4); // ET_CORE
+    put16(buf, 62); // EM_X86_64
+    put32(buf, 1);
+    put64(buf, 0); // entry
+    put64(buf, ELF_HEADER_SIZE as u64); // phoff
+    put64(buf, 0); // shoff
+    put32(buf, 0); // flags
+    put16(buf, ELF_HEADER_SIZE as u16);
+    put16(buf, PROGRAM_HEADER_SIZE as u16);
+    put16(buf, phnum as u16);
+    put16(buf, 0); // shentsize
+    put16(buf, 0); // shnum
+    put16(buf, 0); // shstrndx
+}
+
+#[allow(clippy::too_many_arguments)]
+fn program_header(
+    buf: &mut Vec<u8>,
+    type_: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    file_size: usize,
+    mem_size: usize,
+    align: usize,
+) {
+    put32(buf, type_);
+    put32(buf, flags);
+    put64(buf, offset as u64);
+    put64(buf, vaddr as u64);
+    put64(buf, 0); // paddr
+    put64(buf, file_size as u64);
+    put64(buf, mem_size as u64);
+    put64(buf, align as u64);
+}
+
+/// A note named "CORE", padded to 4 bytes
+fn note(buf: &mut Vec<u8>, type_: u32, desc: &[u8]) {
+    put32(buf, 5);
+    put32(buf, desc.len() as u32);
+    put32(buf, type_);
+    buf.extend_from_slice(b"CORE\0\0\0\0");
+    buf.extend_from_slice(desc);
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+fn put_timeval(buf: &mut Vec<u8>, time: Duration) {
+    put64(buf, time.as_secs());
+    put64(buf, time.subsec_micros() as u64);
+}
+
+/// NT_PRSTATUS of the thread and NT_PRPSINFO of the process
+fn notes(proc: &Process, thread: &Thread, context: &UserContext, signal: Signal) -> Vec<u8> {
+    let (usage, sig_mask, mut pending) = {
+        let inner = thread.inner.lock();
+        (inner.usage, inner.sig_mask, inner.pending_sigset)
+    };
+    pending.add_set(&proc.pending_sigset);
+    let mut notes = Vec::new();
+
+    // struct elf_prstatus
+    let mut status = Vec::new();
+    put32(&mut status, signal as u32); // si_signo
+    put32(&mut status, 0); // si_code
+    put32(&mut status, 0); // si_errno
+    put16(&mut status, signal as u16); // pr_cursig
+    put16(&mut status, 0);
+    put64(&mut status, pending.bits());
+    put64(&mut status, sig_mask.bits());
+    put32(&mut status, thread.tid as u32);
+    put32(&mut status, proc.parent.0.get() as u32);
+    put32(&mut status, proc.pgid as u32);
+    put32(&mut status, proc.sid as u32);
+    put_timeval(&mut status, usage.utime);
+    put_timeval(&mut status, usage.stime);
+    put_timeval(&mut status, proc.children_usage.utime);
+    put_timeval(&mut status, proc.children_usage.stime);
+    registers(&mut status, context);
+    put32(&mut status, 0); // pr_fpvalid
+    put32(&mut status, 0);
+    note(&mut notes, NT_PRSTATUS, &status);
+
+    // struct elf_prpsinfo
+    let mut info = Vec::new();
+    info.extend_from_slice(&[b'R', b'R', 0, proc.nice as u8]);
+    put32(&mut info, 0);
+    put64(&mut info, 0); // pr_flag
+    put32(&mut info, proc.ruid as u32);
+    put32(&mut info, proc.rgid as u32);
+    put32(&mut info, proc.pid.get() as u32);
+    put32(&mut info, proc.parent.0.get() as u32);
+    put32(&mut info, proc.pgid as u32);
+    put32(&mut info, proc.sid as u32);
+    let mut fname = [0u8; 16];
+    let name = thread.inner.lock().name.clone();
+    fname[..name.len()].copy_from_slice(name.as_bytes());
+    info.extend_from_slice(&fname);
+    let mut psargs = [0u8; 80];
+    let len = proc.exec_path.len().min(psargs.len() - 1);
+    psargs[..len].copy_from_slice(&proc.exec_path.as_bytes()[..len]);
+    info.extend_from_slice(&psargs);
+    note(&mut notes, NT_PRPSINFO, &info);
+    notes
+}
+
+/// struct user_regs_struct
+#[cfg(target_arch = "x86_64")]
+fn registers(buf: &mut Vec<u8>, context: &UserContext) {
+    for &value in super::ptrace::user_regs(context).iter() {
+        put64(buf, value as u64);
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn registers(_buf: &mut Vec<u8>, _context: &UserContext) {}
@@ -1,18 +1,27 @@
-use crate::trap::NAIVE_TIMER;
+//! Futexes, i.e. wait queues for user addresses
+//!
+//! Queues are kept in a global table keyed by the address space and the user address,
+//! and removed when no one uses them. A waiter is queued while the futex word is checked,
+//! so a wake after changing the word is never lost.
+
 use crate::{
     arch::timer::timer_now,
+    memory::MemorySet,
     sync::SpinNoIrqLock as Mutex,
     syscall::{SysError, SysResult},
+    timer::TimerId,
 };
 use alloc::boxed::Box;
-use alloc::{collections::VecDeque, sync::Arc};
+use alloc::{collections::BTreeMap, collections::VecDeque, sync::Arc, vec::Vec};
 use core::pin::Pin;
+use core::sync::atomic::{AtomicI32, Ordering};
 use core::task::{Context, Poll};
 use core::{future::Future, task::Waker, time::Duration};
 
 pub struct Waiter {
     waker: Option<Waker>,
     woken: bool,
+    /// The futex waited for, which is changed by requeue
     futex: Arc<Futex>,
 }
 
@@ -22,83 +31,211 @@ pub struct FutexInner {
 
 pub struct Futex {
     pub inner: Mutex<FutexInner>,
+    key: FutexKey,
+}
+
+/// The address space, identified by the address of its lock, and the user address
+pub type FutexKey = (usize, usize);
+
+lazy_static! {
+    static ref FUTEXES: Mutex<BTreeMap<FutexKey, Arc<Futex>>> = Mutex::new(BTreeMap::new());
+}
+
+/// Key of the futex at `uaddr` in address space `vm`
+pub fn futex_key(vm: &Arc<Mutex<MemorySet>>, uaddr: usize) -> FutexKey {
+    (&**vm as *const Mutex<MemorySet> as usize, uaddr)
+}
+
+/// Get the futex of `key`, which is created if it does not exist.
+/// `put_futex` should be called after using it.
+pub fn get_futex(key: FutexKey) -> Arc<Futex> {
+    FUTEXES
+        .lock()
+        .entry(key)
+        .or_insert_with(|| Arc::new(Futex::new(key)))
+        .clone()
+}
+
+/// Remove the futex of `key` from the table if no one uses it
+pub fn put_futex(key: FutexKey) {
+    let mut futexes = FUTEXES.lock();
+    if let Some(futex) = futexes.get(&key) {
+        if Arc::strong_count(futex) == 1 {
+            futexes.remove(&key);
+        }
+    }
 }
 
 impl Futex {
-    pub fn new() -> Self {
+    pub fn new(key: FutexKey) -> Self {
         Futex {
             inner: Mutex::new(FutexInner {
                 waiters: VecDeque::new(),
             }),
+            key,
         }
     }
 
+    /// Wake up at most `wake_count` waiters, return the number woken up
     pub fn wake(&self, wake_count: usize) -> usize {
         let mut inner = self.inner.lock();
-        for i in 0..wake_count {
-            if let Some(waiter) = inner.waiters.pop_front() {
-                let mut waiter = waiter.lock();
-                waiter.woken = true;
-                if let Some(waker) = waiter.waker.take() {
-                    waker.wake();
+        let mut woken = 0;
+        while woken < wake_count {
+            let waiter = match inner.waiters.pop_front() {
+                Some(waiter) => waiter,
+                None => break,
+            };
+            let mut waiter = waiter.lock();
+            // it may have timed out meanwhile
+            if waiter.woken {
+                continue;
+            }
+            waiter.woken = true;
+            if let Some(waker) = waiter.waker.take() {
+                waker.wake();
+            }
+            woken += 1;
+        }
+        woken
+    }
+
+    /// Wake up at most `wake_count` waiters, and move at most `requeue_count`
+    /// of the rest to wait for `target` instead.
+    /// Return the numbers woken up and moved.
+    pub fn requeue(
+        &self,
+        wake_count: usize,
+        target: &Arc<Futex>,
+        requeue_count: usize,
+    ) -> (usize, usize) {
+        let woken = self.wake(wake_count);
+        if core::ptr::eq(self, &**target) {
+            // the rest are already waiting for the target
+            let rest = self.inner.lock().waiters.len();
+            return (woken, rest.min(requeue_count));
+        }
+        // the queues are not locked at the same time, to avoid a dead lock
+        // with a requeue the other way round
+        let mut moved = Vec::new();
+        {
+            let mut inner = self.inner.lock();
+            while moved.len() < requeue_count {
+                match inner.waiters.pop_front() {
+                    Some(waiter) => {
+                        waiter.lock().futex = target.clone();
+                        moved.push(waiter);
+                    }
+                    None => break,
                 }
-            } else {
-                return i;
             }
         }
-        wake_count
+        let mut target_inner = target.inner.lock();
+        let mut requeued = 0;
+        for waiter in moved {
+            if !waiter.lock().woken {
+                target_inner.waiters.push_back(waiter);
+                requeued += 1;
+            }
+        }
+        (woken, requeued)
     }
 
-    pub fn wait(self: &Arc<Self>, timeout: Option<Duration>) -> impl Future<Output = SysResult> {
+    /// Wait on the futex if `value` is `expected`, until woken up or `timeout` passes.
+    /// Return EAGAIN at once if `value` is different.
+    pub fn wait(
+        self: &Arc<Self>,
+        value: &AtomicI32,
+        expected: i32,
+        timeout: Option<Duration>,
+    ) -> Result<impl Future<Output = SysResult>, SysError> {
         #[must_use = "future does nothing unless polled/`await`-ed"]
         struct FutexFuture {
             waiter: Arc<Mutex<Waiter>>,
+            /// The futex waited for at first, which the caller puts
+            origin: Arc<Futex>,
             deadline: Option<Duration>,
+            timer: Option<TimerId>,
+        }
+
+        impl FutexFuture {
+            /// Leave the queue unless woken up, return whether it was woken up
+            fn cancel(&self) -> bool {
+                let mut waiter = self.waiter.lock();
+                if waiter.woken {
+                    return true;
+                }
+                waiter.woken = true;
+                let futex = waiter.futex.clone();
+                drop(waiter);
+                let mut inner = futex.inner.lock();
+                inner
+                    .waiters
+                    .retain(|waiter| !Arc::ptr_eq(waiter, &self.waiter));
+                false
+            }
         }
 
         impl Future for FutexFuture {
             type Output = SysResult;
 
-            fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-                let mut inner = self.waiter.lock();
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
                 // check wakeup
-                if inner.woken {
+                if self.waiter.lock().woken {
                     return Poll::Ready(Ok(0));
                 }
                 if let Some(deadline) = self.deadline {
                     if timer_now() >= deadline {
-                        inner.woken = true;
-                        return Poll::Ready(Err(SysError::ETIMEDOUT));
+                        return Poll::Ready(if self.cancel() {
+                            Ok(0)
+                        } else {
+                            Err(SysError::ETIMEDOUT)
+                        });
                     }
-                }
-
-                // first time?
-                if inner.waker.is_none() {
-                    // futex
-                    let mut futex = inner.futex.inner.lock();
-                    futex.waiters.push_back(self.waiter.clone());
-                    drop(futex);
-                    inner.waker.replace(cx.waker().clone());
-
-                    // timer
-                    if let Some(deadline) = self.deadline {
+                    if self.timer.is_none() {
                         let waker = cx.waker().clone();
-                        NAIVE_TIMER
-                            .lock()
-                            .add(deadline, Box::new(move |_| waker.wake()));
+                        self.timer =
+                            Some(crate::timer::add(deadline, Box::new(move |_| waker.wake())));
                     }
                 }
+                let mut waiter = self.waiter.lock();
+                if waiter.woken {
+                    return Poll::Ready(Ok(0));
+                }
+                waiter.waker = Some(cx.waker().clone());
                 Poll::Pending
             }
         }
 
-        FutexFuture {
-            waiter: Arc::new(Mutex::new(Waiter {
-                waker: None,
-                woken: false,
-                futex: self.clone(),
-            })),
-            deadline: timeout.map(|t| timer_now() + t),
+        impl Drop for FutexFuture {
+            fn drop(&mut self) {
+                if let Some(timer) = self.timer.take() {
+                    crate::timer::cancel(timer);
+                }
+                // e.g. the thread is killed
+                self.cancel();
+                // put the futex it has been requeued to, if any
+                let futex = core::mem::replace(&mut self.waiter.lock().futex, self.origin.clone());
+                let key = futex.key;
+                drop(futex);
+                put_futex(key);
+            }
         }
+
+        let mut inner = self.inner.lock();
+        if value.load(Ordering::Acquire) != expected {
+            return Err(SysError::EAGAIN);
+        }
+        let waiter = Arc::new(Mutex::new(Waiter {
+            waker: None,
+            woken: false,
+            futex: self.clone(),
+        }));
+        inner.waiters.push_back(waiter.clone());
+        Ok(FutexFuture {
+            waiter,
+            origin: self.clone(),
+            deadline: timeout.map(|t| timer_now() + t),
+            timer: None,
+        })
     }
 }
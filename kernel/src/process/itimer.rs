@@ -0,0 +1,171 @@
+//! Interval timers of processes, set by setitimer and alarm
+//!
+//! ITIMER_REAL counts down in wall time and sends SIGALRM. It is run by a kernel task
+//! sleeping on the timer, so that the signal is not sent in interrupt context.
+//! ITIMER_VIRTUAL counts down in user time and sends SIGVTALRM, and ITIMER_PROF
+//! in user and system time and sends SIGPROF. They are charged as the threads run.
+//! As in Linux, the timers are kept across exec but not inherited by fork.
+
+use super::Process;
+use crate::arch::timer::timer_now;
+use crate::signal::{send_signal, Siginfo, Signal, SI_KERNEL};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError;
+use crate::timer::sleep_until;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+pub const ITIMER_REAL: usize = 0;
+pub const ITIMER_VIRTUAL: usize = 1;
+pub const ITIMER_PROF: usize = 2;
+
+const ZERO: Duration = Duration::from_secs(0);
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IntervalTimer {
+    /// Time left before it expires, zero if it is disarmed
+    pub value: Duration,
+    /// Period to rearm it with when it expires, zero if it expires once
+    pub interval: Duration,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct IntervalTimers {
+    /// The value of ITIMER_REAL is its deadline in `timer_now` instead
+    timers: [IntervalTimer; 3],
+    /// Bumped whenever ITIMER_REAL is set, to stop the task of the old setting
+    real_generation: usize,
+}
+
+impl IntervalTimers {
+    /// The timer `which`, with the time left before it expires
+    pub fn get(&self, which: usize) -> Result<IntervalTimer, SysError> {
+        let mut timer = *self.timers.get(which).ok_or(SysError::EINVAL)?;
+        if which == ITIMER_REAL && timer.value != ZERO {
+            // about to expire, but not yet
+            timer.value = timer
+                .value
+                .checked_sub(timer_now())
+                .unwrap_or(Duration::from_micros(1));
+        }
+        Ok(timer)
+    }
+
+    /// Count down ITIMER_VIRTUAL and ITIMER_PROF by the time spent,
+    /// return the signals of the expired ones
+    fn charge(&mut self, user: Duration, system: Duration) -> Vec<Signal> {
+        let mut expired = Vec::new();
+        let charges = [
+            (ITIMER_VIRTUAL, user, Signal::SIGVTALRM),
+            (ITIMER_PROF, user + system, Signal::SIGPROF),
+        ];
+        for &(which, time, signal) in charges.iter() {
+            let timer = &mut self.timers[which];
+            if timer.value == ZERO {
+                continue;
+            }
+            match timer.value.checked_sub(time) {
+                Some(left) if left != ZERO => timer.value = left,
+                _ => {
+                    timer.value = timer.interval;
+                    expired.push(signal);
+                }
+            }
+        }
+        expired
+    }
+}
+
+/// Set the timer `which` of `process` to expire after `value`, then every `interval`.
+/// A zero `value` disarms it. Return the old setting.
+pub fn set_timer(
+    process: &Arc<Mutex<Process>>,
+    which: usize,
+    value: Duration,
+    interval: Duration,
+) -> Result<IntervalTimer, SysError> {
+    let mut proc = process.lock();
+    let old = proc.itimers.get(which)?;
+    let timer = &mut proc.itimers.timers[which];
+    timer.interval = interval;
+    if which != ITIMER_REAL {
+        timer.value = value;
+        return Ok(old);
+    }
+    timer.value = if value == ZERO {
+        ZERO
+    } else {
+        timer_now() + value
+    };
+    proc.itimers.real_generation += 1;
+    if value != ZERO {
+        let generation = proc.itimers.real_generation;
+        crate::sched::spawn(real_timer(Arc::downgrade(process), generation));
+    }
+    Ok(old)
+}
+
+/// Charge the time spent by a thread of `process` to its timers,
+/// and send signals for those expired
+pub fn charge_timers(process: &Arc<Mutex<Process>>, user: Duration, system: Duration) {
+    let expired = {
+        let mut proc = process.lock();
+        if proc.zombie {
+            return;
+        }
+        proc.itimers.charge(user, system)
+    };
+    for signal in expired {
+        send_signal(process.clone(), -1, timer_info(signal));
+    }
+}
+
+fn timer_info(signal: Signal) -> Siginfo {
+    Siginfo {
+        signo: signal as i32,
+        errno: 0,
+        code: SI_KERNEL,
+        field: Default::default(),
+    }
+}
+
+/// Run ITIMER_REAL of the process, until it is disarmed or set again
+async fn real_timer(process: Weak<Mutex<Process>>, generation: usize) {
+    loop {
+        let deadline = match real_deadline(&process, generation) {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        if timer_now() < deadline {
+            sleep_until(deadline).await;
+            continue;
+        }
+        let process = match process.upgrade() {
+            Some(process) => process,
+            None => return,
+        };
+        {
+            let mut proc = process.lock();
+            let now = timer_now();
+            let timer = &mut proc.itimers.timers[ITIMER_REAL];
+            timer.value = if timer.interval == ZERO {
+                ZERO
+            } else {
+                now + timer.interval
+            };
+        }
+        send_signal(process, -1, timer_info(Signal::SIGALRM));
+    }
+}
+
+/// The deadline of ITIMER_REAL, if it is still armed by the setting of `generation`
+fn real_deadline(process: &Weak<Mutex<Process>>, generation: usize) -> Option<Duration> {
+    let process = process.upgrade()?;
+    let proc = process.lock();
+    let deadline = proc.itimers.timers[ITIMER_REAL].value;
+    if proc.zombie || proc.itimers.real_generation != generation || deadline == ZERO {
+        return None;
+    }
+    Some(deadline)
+}
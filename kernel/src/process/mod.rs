@@ -10,15 +10,22 @@ use log::*;
 use trapframe::UserContext;
 
 mod abi;
+pub mod aslr;
+pub mod coredump;
 pub mod futex;
+pub mod itimer;
+pub mod posix_timer;
 pub mod proc;
+pub mod ptrace;
 pub mod structs;
 pub mod thread;
 
+use crate::percpu::Local;
 use crate::sync::SpinNoIrqLock as Mutex;
 use core::{
     future::Future,
     pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
     task::{Context, Poll},
 };
 pub use futex::*;
@@ -27,13 +34,32 @@ pub use structs::*;
 pub use thread::*;
 
 pub fn init() {
+    // the drivers are ready now
+    crate::syscall::init_realtime();
+
     // create init process
     crate::shell::add_user_shell();
 
     info!("process: init end");
 }
 
-static mut PROCESSORS: [Option<Arc<Thread>>; MAX_CPU_NUM] = [None; MAX_CPU_NUM];
+percpu! {
+    /// The thread polled by each CPU
+    pub static ref PROCESSORS: Local<Option<Arc<Thread>>> = Local::new(None);
+}
+
+/// CPUs running threads, one bit for each
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(0);
+
+/// Mark the current CPU as ready to run threads
+pub fn set_cpu_online() {
+    ONLINE_CPUS.fetch_or(1 << cpu::id(), Ordering::SeqCst);
+}
+
+/// CPUs running threads, one bit for each
+pub fn online_cpus() -> usize {
+    ONLINE_CPUS.load(Ordering::SeqCst)
+}
 
 /// Get current thread
 ///
@@ -42,6 +68,5 @@ static mut PROCESSORS: [Option<Arc<Thread>>; MAX_CPU_NUM] = [None; MAX_CPU_NUM];
 ///
 /// Don't use it unless necessary.
 pub fn current_thread() -> Option<Arc<Thread>> {
-    let cpu_id = cpu::id();
-    unsafe { PROCESSORS[cpu_id].clone() }
+    PROCESSORS.with(|thread| thread.clone())
 }
@@ -0,0 +1,213 @@
+//! POSIX per-process timers, created by timer_create
+//!
+//! Each armed timer is run by a kernel task sleeping until its deadline, as ITIMER_REAL,
+//! which sends the signal chosen at creation to the process or one of its threads.
+//! Expirations missed while the task is late are counted as the overrun.
+//! As in Linux, the timers are neither inherited by fork nor kept across exec.
+
+use super::itimer::IntervalTimer;
+use super::Process;
+use crate::arch::timer::timer_now;
+use crate::signal::{send_signal, Siginfo, SiginfoFields, Signal, SI_TIMER};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::{realtime, SysError};
+use crate::timer::sleep_until;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use core::time::Duration;
+
+/// Timers a process may create at most
+const MAX_TIMERS: usize = 256;
+
+const ZERO: Duration = Duration::from_secs(0);
+
+/// What to do when a timer expires
+#[derive(Debug, Clone, Copy)]
+pub enum TimerNotify {
+    /// Nothing, the timer is only read by timer_gettime
+    None,
+    /// Send `signo` with `value` to the process, or the thread `tid` if any
+    Signal {
+        signo: i32,
+        value: usize,
+        tid: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct PosixTimer {
+    /// Whether it is set by CLOCK_REALTIME, otherwise CLOCK_MONOTONIC
+    realtime: bool,
+    notify: TimerNotify,
+    /// The next expiration in `timer_now`, zero if it is disarmed
+    deadline: Duration,
+    interval: Duration,
+    /// Expirations missed before the last signal
+    overrun: usize,
+    /// The setting the running task is for
+    generation: usize,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PosixTimers {
+    timers: BTreeMap<usize, PosixTimer>,
+    /// Bumped whenever a timer is set, never reused, to stop the task of the old setting
+    generation: usize,
+}
+
+impl PosixTimers {
+    /// Create a disarmed timer, return its id.
+    /// By default, it sends SIGALRM with the timer id as the value.
+    pub fn create(
+        &mut self,
+        realtime: bool,
+        notify: Option<TimerNotify>,
+    ) -> Result<usize, SysError> {
+        if self.timers.len() >= MAX_TIMERS {
+            return Err(SysError::EAGAIN);
+        }
+        let id = (0..).find(|id| !self.timers.contains_key(id)).unwrap();
+        let notify = notify.unwrap_or(TimerNotify::Signal {
+            signo: Signal::SIGALRM as i32,
+            value: id,
+            tid: None,
+        });
+        self.timers.insert(
+            id,
+            PosixTimer {
+                realtime,
+                notify,
+                deadline: ZERO,
+                interval: ZERO,
+                overrun: 0,
+                generation: 0,
+            },
+        );
+        Ok(id)
+    }
+
+    pub fn delete(&mut self, id: usize) -> Result<(), SysError> {
+        self.timers.remove(&id).ok_or(SysError::EINVAL)?;
+        Ok(())
+    }
+
+    /// Delete all timers, e.g. at exec
+    pub fn clear(&mut self) {
+        self.timers.clear();
+    }
+
+    /// The timer `id`, with the time left before it expires
+    pub fn get(&self, id: usize) -> Result<IntervalTimer, SysError> {
+        let timer = self.timers.get(&id).ok_or(SysError::EINVAL)?;
+        let value = if timer.deadline == ZERO {
+            ZERO
+        } else {
+            // about to expire, but not yet
+            timer
+                .deadline
+                .checked_sub(timer_now())
+                .unwrap_or(Duration::from_nanos(1))
+        };
+        Ok(IntervalTimer {
+            value,
+            interval: timer.interval,
+        })
+    }
+
+    /// The overrun of the last signal of timer `id`
+    pub fn overrun(&self, id: usize) -> Result<usize, SysError> {
+        let timer = self.timers.get(&id).ok_or(SysError::EINVAL)?;
+        Ok(timer.overrun)
+    }
+}
+
+/// Set the timer `id` of `process` to expire after `value`, or at `value` of its clock
+/// if `absolute`, then every `interval`. A zero `value` disarms it. Return the old setting.
+pub fn set_timer(
+    process: &Arc<Mutex<Process>>,
+    id: usize,
+    value: Duration,
+    interval: Duration,
+    absolute: bool,
+) -> Result<IntervalTimer, SysError> {
+    let mut proc = process.lock();
+    let old = proc.posix_timers.get(id)?;
+    proc.posix_timers.generation += 1;
+    let generation = proc.posix_timers.generation;
+    let timer = proc.posix_timers.timers.get_mut(&id).unwrap();
+    let now = timer_now();
+    timer.deadline = if value == ZERO {
+        ZERO
+    } else if !absolute {
+        now + value
+    } else if timer.realtime {
+        now + value.checked_sub(realtime()).unwrap_or_default()
+    } else {
+        value
+    };
+    timer.interval = if value == ZERO { ZERO } else { interval };
+    timer.overrun = 0;
+    timer.generation = generation;
+    if timer.deadline != ZERO {
+        crate::sched::spawn(run_timer(Arc::downgrade(process), id, generation));
+    }
+    Ok(old)
+}
+
+/// Run the timer `id` of the process, until it is disarmed, set again or deleted
+async fn run_timer(process: Weak<Mutex<Process>>, id: usize, generation: usize) {
+    loop {
+        let deadline = match deadline(&process, id, generation) {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        if timer_now() < deadline {
+            sleep_until(deadline).await;
+            continue;
+        }
+        let process = match process.upgrade() {
+            Some(process) => process,
+            None => return,
+        };
+        let notify = {
+            let mut proc = process.lock();
+            let now = timer_now();
+            let timer = match proc.posix_timers.timers.get_mut(&id) {
+                Some(timer) if timer.generation == generation => timer,
+                // deleted or set again meanwhile
+                _ => return,
+            };
+            if timer.interval == ZERO {
+                timer.deadline = ZERO;
+                timer.overrun = 0;
+            } else {
+                let interval = timer.interval.as_nanos();
+                let missed = (now - timer.deadline).as_nanos() / interval;
+                timer.deadline += Duration::from_nanos((interval * (missed + 1)) as u64);
+                timer.overrun = missed as usize;
+            }
+            (timer.notify, timer.overrun)
+        };
+        if let (TimerNotify::Signal { signo, value, tid }, overrun) = notify {
+            let info = Siginfo {
+                signo,
+                errno: 0,
+                code: SI_TIMER,
+                field: SiginfoFields::timer(id, overrun, value),
+            };
+            let tid = tid.map_or(-1, |tid| tid as isize);
+            send_signal(process, tid, info);
+        }
+    }
+}
+
+/// The deadline of timer `id`, if it is still armed by the setting of `generation`
+fn deadline(process: &Weak<Mutex<Process>>, id: usize, generation: usize) -> Option<Duration> {
+    let process = process.upgrade()?;
+    let proc = process.lock();
+    let timer = proc.posix_timers.timers.get(&id)?;
+    if proc.zombie || timer.generation != generation || timer.deadline == ZERO {
+        return None;
+    }
+    Some(timer.deadline)
+}
@@ -1,18 +1,23 @@
 use super::{
     abi::{self, ProcInitInfo},
-    Futex, Tid,
+    Tid,
 };
 use crate::arch::paging::*;
-use crate::fs::{FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
+use crate::fs::{release_range_locks, FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
 use crate::ipc::{SemProc, ShmProc};
 use crate::memory::{
     phys_to_virt, ByFrame, Delay, File, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet, Read,
 };
-use crate::process::thread::THREADS;
+use crate::process::itimer::IntervalTimers;
+use crate::process::posix_timer::PosixTimers;
+use crate::process::thread::{ResourceUsage, THREADS};
 use crate::sync::{Event, EventBus, SpinLock, SpinNoIrqLock as Mutex};
 use crate::{
-    signal::{Siginfo, Signal, SignalAction, SignalStack, Sigset},
-    syscall::handle_syscall,
+    signal::{
+        send_signal, Siginfo, SiginfoFields, Signal, SignalAction, SignalActionFlags, SignalStack,
+        Sigset, CLD_CONTINUED, CLD_DUMPED, CLD_EXITED, CLD_KILLED, CLD_STOPPED, SIG_IGN,
+    },
+    syscall::{handle_syscall, RLimit},
 };
 use alloc::{
     boxed::Box, collections::BTreeMap, collections::VecDeque, string::String, sync::Arc,
@@ -24,6 +29,7 @@ use core::str;
 use core::{
     future::Future,
     mem::MaybeUninit,
+    ops::Range,
     pin::Pin,
     task::{Context, Poll},
 };
@@ -83,9 +89,6 @@ pub struct Process {
     /// Executable path
     pub exec_path: String,
 
-    /// Futex
-    pub futexes: BTreeMap<usize, Arc<Futex>>,
-
     /// Semaphore
     pub semaphores: SemProc,
 
@@ -95,6 +98,45 @@ pub struct Process {
     //// Process group id
     pub pgid: Pgid,
 
+    /// Session id, i.e. pid of the session leader
+    pub sid: Pgid,
+
+    /// Effective user id and group id, used for file permission checks
+    pub uid: usize,
+    pub gid: usize,
+    /// Real and saved user id and group id, which the effective ones can be switched to
+    pub ruid: usize,
+    pub suid: usize,
+    pub rgid: usize,
+    pub sgid: usize,
+
+    /// File mode creation mask
+    pub umask: usize,
+
+    /// Nice value, from -20 for the highest priority to 19 for the lowest
+    pub nice: i32,
+
+    /// Whether a core dump may be produced, and other processes may attach to it
+    pub dumpable: bool,
+
+    /// Limit of memory locked by mlock (RLIMIT_MEMLOCK)
+    pub memlock_limit: RLimit,
+
+    /// Limit of the core file size (RLIMIT_CORE)
+    pub core_limit: RLimit,
+
+    /// Start of the heap, right after the loaded program
+    pub brk_start: usize,
+    /// Current program break, i.e. end of the heap
+    pub brk: usize,
+    /// Where mmap starts to look for free space, above the room of the heap
+    pub mmap_base: usize,
+
+    /// Range of the main stack, which grows down on faults below it
+    pub stack: Range<usize>,
+    /// Limit of the stack size (RLIMIT_STACK)
+    pub stack_limit: RLimit,
+
     /// Parent process
     /// Avoid deadlock, put pid out
     pub parent: (Pid, Weak<Mutex<Process>>),
@@ -102,6 +144,11 @@ pub struct Process {
     /// Children process
     pub children: Vec<(Pid, Weak<Mutex<Process>>)>,
 
+    /// The process tracing this one by ptrace
+    pub tracer: Option<Pid>,
+    /// Processes traced by this one
+    pub tracees: Vec<Pid>,
+
     /// Threads
     /// threads in the same process
     pub threads: Vec<Tid>,
@@ -109,12 +156,19 @@ pub struct Process {
     /// Events like exiting
     pub eventbus: Arc<Mutex<EventBus>>,
 
-    /// Exit code
+    /// Status reported by wait after exit, in the encoding of Linux
     pub exit_code: usize,
-
-    // delivered signals, tid specified thread, -1 stands for any thread
+    /// Whether the process has exited, and waits for its parent to reap it.
+    /// Only its exit status and resource usage are kept meanwhile.
+    pub zombie: bool,
+    /// Whether the process is stopped by a signal, until SIGCONT
+    pub stopped: bool,
+    /// Status of the last stop or continue, until reported by wait
+    pub stop_report: Option<i32>,
+
+    // signals delivered to any thread, those to a specified thread are kept in the thread
     // TODO: implement with doubly linked list, but how to do it in rust safely? [doggy]
-    pub sig_queue: VecDeque<(Siginfo, isize)>,
+    pub sig_queue: VecDeque<Siginfo>,
     pub pending_sigset: Sigset,
 
     /// signal actions
@@ -122,8 +176,24 @@ pub struct Process {
 
     /// shared memory
     pub shm_identifiers: ShmProc,
+
+    /// Address space of the parent taken by vfork, which is given back on exec or exit
+    pub vfork_vm: Option<Arc<Mutex<MemorySet>>>,
+
+    /// Resource usage of exited threads
+    pub usage: ResourceUsage,
+    /// Resource usage of children waited for, and their own waited children
+    pub children_usage: ResourceUsage,
+
+    /// Interval timers set by setitimer
+    pub itimers: IntervalTimers,
+    /// Timers created by timer_create
+    pub posix_timers: PosixTimers,
 }
 
+/// RLIMIT_NOFILE, which can not be changed: fds are below it
+pub const MAX_FILES: usize = 1024;
+
 lazy_static! {
     /// Records the mapping between pid and Process struct.
     pub static ref PROCESSES: RwLock<BTreeMap<usize, Arc<Mutex<Process>>>> =
@@ -132,11 +202,7 @@ lazy_static! {
 
 /// Return the process which thread tid is in
 pub fn process_of(tid: usize) -> Option<Arc<Mutex<Process>>> {
-    PROCESSES
-        .read()
-        .iter()
-        .map(|(_, proc)| proc.clone())
-        .find(|proc| proc.lock().threads.contains(&tid))
+    THREADS.read().get(&tid).map(|thread| thread.proc.clone())
 }
 
 /// Get process by pid
@@ -144,31 +210,34 @@ pub fn process(pid: usize) -> Option<Arc<Mutex<Process>>> {
     PROCESSES.read().get(&pid).cloned()
 }
 
+/// Get all processes. They are locked after the table, which is locked
+/// while holding a process lock when reaping.
+pub fn all_processes() -> Vec<Arc<Mutex<Process>>> {
+    PROCESSES.read().values().cloned().collect()
+}
+
 /// Get process group by pgid
 pub fn process_group(pgid: Pgid) -> Vec<Arc<Mutex<Process>>> {
-    PROCESSES
-        .read()
-        .iter()
-        .map(|(_, proc)| proc.clone())
+    all_processes()
+        .into_iter()
         .filter(|proc| proc.lock().pgid == pgid)
         .collect::<Vec<_>>()
 }
 
 /// Set pid and put itself to global process table.
 pub fn add_to_process_table(proc: Arc<Mutex<Process>>, pid: Pid) {
-    let mut process_table = PROCESSES.write();
-
     // set pid
     proc.lock().pid = pid;
 
     // put to process table
-    process_table.insert(pid.get(), proc.clone());
+    PROCESSES.write().insert(pid.get(), proc.clone());
 }
 
 impl Process {
     /// Get lowest free fd
-    fn get_free_fd(&self) -> usize {
-        (0..).find(|i| !self.files.contains_key(i)).unwrap()
+    /// get the lowest available fd
+    pub fn get_free_fd(&self) -> usize {
+        self.get_free_fd_from(0)
     }
 
     /// get the lowest available fd great than or equal to arg
@@ -183,46 +252,222 @@ impl Process {
         fd
     }
 
-    /// Get futex by addr
-    pub fn get_futex(&mut self, uaddr: usize) -> Arc<Futex> {
-        if !self.futexes.contains_key(&uaddr) {
-            self.futexes.insert(uaddr, Arc::new(Futex::new()));
-        }
-        self.futexes.get(&uaddr).unwrap().clone()
-    }
-
     /// Exit the process.
     /// Kill all threads and notify parent with the exit code.
     pub fn exit(&mut self, exit_code: usize) {
+        self.exit_with_status((exit_code & 0xff) << 8);
+    }
+
+    /// Exit the process as it is terminated by `signal`,
+    /// telling the parent whether the core is dumped.
+    pub fn exit_by_signal(&mut self, signal: Signal, core_dumped: bool) {
+        let core_flag = if core_dumped { 0x80 } else { 0 };
+        self.exit_with_status(signal as usize | core_flag);
+    }
+
+    fn exit_with_status(&mut self, status: usize) {
+        // e.g. a thread calling exit_group while another thread is terminated by a signal
+        if self.zombie {
+            return;
+        }
         // avoid some strange dead lock
         // self.files.clear(); this does not work sometime, for unknown reason
         // manually drop
         let fds = self.files.iter().map(|(fd, _)| *fd).collect::<Vec<_>>();
         for fd in fds.iter() {
             let file = self.files.remove(fd).unwrap();
+            if let FileLike::File(file) = &file {
+                release_range_locks(&file.inode(), self.pid.get()).ok();
+            }
             drop(file);
         }
 
-        // notify parent and fill exit code
+        self.exit_code = status;
+        if self.vfork_vm.is_some() {
+            let vm = core::mem::replace(&mut *self.vm.lock(), MemorySet::new());
+            self.end_vfork(vm);
+        }
+
+        // quit all threads
+        // this must be after setting the value of subprocess, or the threads will be treated exit before actually exits
+        self.kill_threads(None);
+
+        self.reparent_children();
+        let tracees = core::mem::replace(&mut self.tracees, Vec::new());
+        super::ptrace::detach_tracees(self.pid, &tracees);
+
+        // free the memory now, rather than when the parent reaps the process
+        self.vm.lock().clear();
+        self.semaphores = SemProc::default();
+        self.shm_identifiers = ShmProc::default();
+        self.sig_queue.clear();
+        self.pending_sigset = Sigset::empty();
+        self.stopped = false;
+        self.stop_report = None;
+
+        self.zombie = true;
         self.eventbus.lock().set(Event::PROCESS_QUIT);
+        self.notify_parent(status);
+        info!("process {} exit with status {:#x}", self.pid.get(), status);
+    }
+
+    /// Wake up the parent waiting for children, and send SIGCHLD to it.
+    /// The process is reaped at once if the parent ignores SIGCHLD or sets SA_NOCLDWAIT.
+    fn notify_parent(&self, status: usize) {
+        let parent = match self.parent.1.upgrade() {
+            Some(parent) => parent,
+            None => return,
+        };
+        let reaped = {
+            let mut parent = parent.lock();
+            let action = parent.dispositions[Signal::SIGCHLD as usize];
+            let flags = SignalActionFlags::from_bits_truncate(action.flags);
+            let reaped = action.handler == SIG_IGN || flags.contains(SignalActionFlags::NOCLDWAIT);
+            if reaped {
+                parent.children.retain(|(pid, _)| *pid != self.pid);
+            }
+            parent.eventbus.lock().set(Event::CHILD_PROCESS_QUIT);
+            reaped
+        };
+        if reaped {
+            info!("process {} reaped as its parent ignores SIGCHLD", self.pid);
+            PROCESSES.write().remove(&self.pid.get());
+        }
+        let (code, value) = match status & 0x7f {
+            0 => (CLD_EXITED, (status >> 8) & 0xff),
+            signal if status & 0x80 != 0 => (CLD_DUMPED, signal),
+            signal => (CLD_KILLED, signal),
+        };
+        self.signal_parent(code, value as i32);
+    }
+
+    /// Mark the process stopped by `signal`, or continued if None,
+    /// and let the parent know by wait and SIGCHLD
+    pub fn report_stop(&mut self, signal: Option<Signal>) {
+        let (code, status) = match signal {
+            Some(signal) => {
+                self.stop_report = Some(((signal as i32) << 8) | 0x7f);
+                (CLD_STOPPED, signal as i32)
+            }
+            None => {
+                self.stop_report = Some(0xffff);
+                (CLD_CONTINUED, Signal::SIGCONT as i32)
+            }
+        };
         if let Some(parent) = self.parent.1.upgrade() {
             parent.lock().eventbus.lock().set(Event::CHILD_PROCESS_QUIT);
         }
-        self.exit_code = exit_code;
+        self.signal_parent(code, status);
+    }
 
-        // quit all threads
-        // this must be after setting the value of subprocess, or the threads will be treated exit before actually exits
-        // remove from thread table
+    /// Send SIGCHLD to the parent, with `code` and `status` of the child.
+    /// It is not sent for stops and continues if the parent sets SA_NOCLDSTOP.
+    pub fn signal_parent(&self, code: i32, status: i32) {
+        let parent = match self.parent.1.upgrade() {
+            Some(parent) => parent,
+            None => return,
+        };
+        if code == CLD_STOPPED || code == CLD_CONTINUED {
+            let flags = parent.lock().dispositions[Signal::SIGCHLD as usize].flags;
+            if SignalActionFlags::from_bits_truncate(flags).contains(SignalActionFlags::NOCLDSTOP) {
+                return;
+            }
+        }
+        // discarded if ignored
+        let info = Siginfo {
+            signo: Signal::SIGCHLD as i32,
+            errno: 0,
+            code,
+            field: SiginfoFields::child(self.pid.get(), self.ruid, status),
+        };
+        send_signal(parent, -1, info);
+    }
+
+    /// Remove the threads except `except` from the process and the thread table.
+    /// They quit as soon as they are polled or trap into the kernel next,
+    /// so the ones waiting are woken up.
+    pub fn kill_threads(&mut self, except: Option<Tid>) {
+        let current = super::current_thread().map(|thread| thread.tid);
+        let mut killed = Vec::new();
         let mut thread_table = THREADS.write();
+        for &tid in self.threads.iter().filter(|&&tid| Some(tid) != except) {
+            if let Some(thread) = thread_table.remove(&tid) {
+                if Some(tid) != current {
+                    killed.push(thread);
+                }
+            }
+        }
+        drop(thread_table);
+        self.threads.retain(|&tid| Some(tid) == except);
+        for thread in killed {
+            thread.wake();
+        }
+    }
+
+    /// Give the address space `vm` taken by vfork back to the parent,
+    /// and let the parent go on
+    pub fn end_vfork(&mut self, vm: MemorySet) {
+        let parent_vm = match self.vfork_vm.take() {
+            Some(parent_vm) => parent_vm,
+            None => return,
+        };
+        *parent_vm.lock() = vm;
+        if let Some(parent) = self.parent.1.upgrade() {
+            parent.lock().eventbus.lock().set(Event::VFORK_DONE);
+        }
+    }
+
+    /// Give the children to init, which reaps them when they exit
+    fn reparent_children(&mut self) {
+        if self.pid.is_init() || self.children.is_empty() {
+            return;
+        }
+        let init = match process(Pid::INIT) {
+            Some(init) => init,
+            None => return,
+        };
+        let children = core::mem::replace(&mut self.children, Vec::new());
+        let mut any_exited = false;
+        for (_, child) in children.iter() {
+            if let Some(child) = child.upgrade() {
+                let mut child = child.lock();
+                child.parent = (Pid(Pid::INIT), Arc::downgrade(&init));
+                any_exited |= child.exited();
+            }
+        }
+        let mut init = init.lock();
+        init.children.extend(children);
+        if any_exited {
+            init.eventbus.lock().set(Event::CHILD_PROCESS_QUIT);
+        }
+    }
+
+    /// Resource usage of all threads of the process so far
+    pub fn thread_usage(&self) -> ResourceUsage {
+        let mut usage = self.usage;
+        let threads = THREADS.read();
         for tid in self.threads.iter() {
-            thread_table.remove(tid);
+            if let Some(thread) = threads.get(tid) {
+                usage += thread.inner.lock().usage;
+            }
         }
-        self.threads.clear();
+        usage
+    }
 
-        info!("process {} exit with {}", self.pid.get(), exit_code);
+    /// Set the nice value of the process and its threads
+    pub fn set_nice(&mut self, nice: i32) {
+        self.nice = nice;
+        let threads = THREADS.read();
+        for tid in self.threads.iter() {
+            if let Some(thread) = threads.get(tid) {
+                if let Some(task) = thread.inner.lock().sched_task {
+                    crate::sched::set_nice(task, nice);
+                }
+            }
+        }
     }
 
     pub fn exited(&self) -> bool {
-        self.threads.is_empty()
+        self.zombie
     }
 }
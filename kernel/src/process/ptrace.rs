@@ -0,0 +1,258 @@
+//! Process tracing for debuggers
+//!
+//! A traced process stops at each signal it receives, except SIGKILL, before the signal
+//! is delivered. Breakpoints, single steps and exec stop it with SIGTRAP as well.
+//! The tracer learns about a stop by wait4, then it may look at and change the memory
+//! and registers of the stopped thread, and resume it with a signal to deliver or none.
+
+use super::{process, Pid, Thread};
+use crate::signal::{send_signal, Siginfo, Signal};
+use crate::sync::Event;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use trapframe::UserContext;
+
+/// si_code of SIGTRAP by a breakpoint or a single step
+const TRAP_BRKPT: i32 = 1;
+const TRAP_TRACE: i32 = 2;
+
+/// A thread stopped for its tracer
+pub struct PtraceStop {
+    /// The signal stopping the thread
+    pub info: Siginfo,
+    /// Whether the stop is reported to the tracer by wait4
+    pub reported: bool,
+    /// Whether the tracer resumed the thread
+    pub resumed: bool,
+    /// The signal to deliver when resumed, if any
+    pub deliver: Option<Signal>,
+}
+
+impl PtraceStop {
+    pub fn new(info: Siginfo) -> Self {
+        PtraceStop {
+            info,
+            reported: false,
+            resumed: false,
+            deliver: None,
+        }
+    }
+
+    /// The status reported by wait4 for the stop
+    pub fn status(&self) -> i32 {
+        (self.info.signo << 8) | 0x7f
+    }
+
+    /// The signal to deliver, with the original information if it is the one stopping the thread
+    fn deliver_info(&self) -> Option<Siginfo> {
+        let signal = self.deliver?;
+        if signal as i32 == self.info.signo {
+            return Some(self.info);
+        }
+        Some(Siginfo {
+            signo: signal as i32,
+            errno: 0,
+            code: crate::signal::SI_USER,
+            field: Default::default(),
+        })
+    }
+}
+
+/// Wait until the tracer resumes the stopped `thread`,
+/// or SIGKILL is sent to it. Return the signal to deliver.
+pub fn wait_resume(thread: &Arc<Thread>) -> impl Future<Output = Option<Siginfo>> {
+    #[must_use = "future does nothing unless polled/`await`-ed"]
+    struct ResumeFuture {
+        thread: Arc<Thread>,
+        notified: bool,
+    }
+
+    impl Future for ResumeFuture {
+        type Output = Option<Siginfo>;
+
+        fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let (tracer, killed) = {
+                let proc = self.thread.proc.lock();
+                (proc.tracer, proc.pending_sigset.contains(Signal::SIGKILL))
+            };
+            if !self.notified {
+                // the process is not locked, as the tracer locks its tracees
+                self.notified = true;
+                if let Some(tracer) = tracer.and_then(|pid| process(pid.get())) {
+                    tracer.lock().eventbus.lock().set(Event::CHILD_PROCESS_QUIT);
+                }
+            }
+            let mut inner = self.thread.inner.lock();
+            let resumed = match &inner.ptrace_stop {
+                Some(stop) => stop.resumed,
+                None => true,
+            };
+            // detached, or the tracer is gone
+            if resumed || killed || tracer.is_none() {
+                let stop = inner.ptrace_stop.take();
+                return Poll::Ready(stop.and_then(|stop| stop.deliver_info()));
+            }
+            // woken up by `resume` through the waker of the thread
+            Poll::Pending
+        }
+    }
+
+    ResumeFuture {
+        thread: thread.clone(),
+        notified: false,
+    }
+}
+
+/// Resume the stopped `thread`, delivering `deliver` if any
+pub fn resume(thread: &Arc<Thread>, deliver: Option<Signal>) {
+    if let Some(stop) = thread.inner.lock().ptrace_stop.as_mut() {
+        stop.resumed = true;
+        stop.deliver = deliver;
+    }
+    thread.wake();
+}
+
+/// Handle a breakpoint or a single step of `thread` by SIGTRAP
+pub fn trap(thread: &Arc<Thread>, context: &mut UserContext) {
+    let stepping = single_step(context);
+    set_single_step(context, false);
+    let info = Siginfo {
+        signo: Signal::SIGTRAP as i32,
+        errno: 0,
+        code: if stepping { TRAP_TRACE } else { TRAP_BRKPT },
+        field: Default::default(),
+    };
+    send_signal(thread.proc.clone(), thread.tid as isize, info);
+}
+
+/// Stop tracing the processes traced by `tracer`, letting them go on
+pub fn detach_tracees(tracer: Pid, tracees: &[Pid]) {
+    for &pid in tracees {
+        let proc = match process(pid.get()) {
+            Some(proc) => proc,
+            None => continue,
+        };
+        let threads = {
+            let mut proc = proc.lock();
+            if proc.tracer != Some(tracer) {
+                continue;
+            }
+            proc.tracer = None;
+            proc.threads.clone()
+        };
+        for tid in threads {
+            let thread = super::THREADS.read().get(&tid).cloned();
+            if let Some(thread) = thread {
+                resume(&thread, None);
+            }
+        }
+    }
+}
+
+/// Whether the thread traps after the next instruction
+#[cfg(target_arch = "x86_64")]
+fn single_step(context: &UserContext) -> bool {
+    context.general.rflags & RFLAGS_TF != 0
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn single_step(_context: &UserContext) -> bool {
+    false
+}
+
+/// Trap flag in RFLAGS
+#[cfg(target_arch = "x86_64")]
+const RFLAGS_TF: usize = 1 << 8;
+
+/// Let the thread trap after the next instruction or not.
+/// Return false if it is not supported.
+#[cfg(target_arch = "x86_64")]
+pub fn set_single_step(context: &mut UserContext, step: bool) -> bool {
+    if step {
+        context.general.rflags |= RFLAGS_TF;
+    } else {
+        context.general.rflags &= !RFLAGS_TF;
+    }
+    true
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn set_single_step(_context: &mut UserContext, step: bool) -> bool {
+    !step
+}
+
+/// Number of registers in struct user_regs_struct
+#[cfg(target_arch = "x86_64")]
+pub const USER_REGS_LEN: usize = 27;
+
+/// Registers in the layout of struct user_regs_struct,
+/// as read by PTRACE_GETREGS and in core dumps
+#[cfg(target_arch = "x86_64")]
+pub fn user_regs(context: &UserContext) -> [usize; USER_REGS_LEN] {
+    let regs = &context.general;
+    [
+        regs.r15,
+        regs.r14,
+        regs.r13,
+        regs.r12,
+        regs.rbp,
+        regs.rbx,
+        regs.r11,
+        regs.r10,
+        regs.r9,
+        regs.r8,
+        regs.rax,
+        regs.rcx,
+        regs.rdx,
+        regs.rsi,
+        regs.rdi,
+        regs.rax, // orig_rax
+        regs.rip,
+        0x23, // cs
+        regs.rflags,
+        regs.rsp,
+        0x1b, // ss
+        regs.fsbase,
+        regs.gsbase,
+        0, // ds
+        0, // es
+        0, // fs
+        0, // gs
+    ]
+}
+
+/// Set the registers from struct user_regs_struct, except for the segment selectors.
+/// Only the flags which user mode can change are taken.
+/// Return false if an address is not in user space, which would fault on return.
+#[cfg(target_arch = "x86_64")]
+pub fn set_user_regs(context: &mut UserContext, values: &[usize; USER_REGS_LEN]) -> bool {
+    use crate::arch::signal::USER_RFLAGS;
+    const USER_END: usize = 1 << 47;
+    if values[16] >= USER_END || values[21] >= USER_END || values[22] >= USER_END {
+        return false;
+    }
+    let regs = &mut context.general;
+    regs.r15 = values[0];
+    regs.r14 = values[1];
+    regs.r13 = values[2];
+    regs.r12 = values[3];
+    regs.rbp = values[4];
+    regs.rbx = values[5];
+    regs.r11 = values[6];
+    regs.r10 = values[7];
+    regs.r9 = values[8];
+    regs.r8 = values[9];
+    regs.rax = values[10];
+    regs.rcx = values[11];
+    regs.rdx = values[12];
+    regs.rsi = values[13];
+    regs.rdi = values[14];
+    regs.rip = values[16];
+    regs.rflags = (regs.rflags & !USER_RFLAGS) | (values[18] & USER_RFLAGS);
+    regs.rsp = values[19];
+    regs.fsbase = values[21];
+    regs.gsbase = values[22];
+    true
+}
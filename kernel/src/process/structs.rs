@@ -1,9 +1,10 @@
 use super::abi::{self, ProcInitInfo};
 use crate::arch::paging::*;
-use crate::fs::{FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
+use crate::fs::{page_cache, FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
 use crate::ipc::SemProc;
 use crate::memory::{
-    phys_to_virt, ByFrame, Delay, File, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet, Read,
+    phys_to_virt, ByFrame, CowFrames, Delay, File, GlobalFrameAlloc, KernelStack, MemoryAttr,
+    MemorySet, Read, Write,
 };
 use crate::sync::{SpinLock, SpinNoIrqLock as Mutex};
 use crate::{
@@ -55,56 +56,76 @@ impl ToMemoryAttr for Flags {
 
 /// Helper functions to process ELF file
 pub trait ElfExt {
-    /// Setup MemorySet according to the ELF file.
-    fn make_memory_set(&self, ms: &mut MemorySet, inode: &Arc<dyn INode>) -> usize;
+    /// Setup MemorySet according to the ELF file, loaded `bias` bytes above its addresses.
+    /// Return the end address of the program.
+    fn make_memory_set(&self, ms: &mut MemorySet, inode: &Arc<dyn INode>, bias: usize) -> usize;
 
     /// Get interpreter string if it has.
     fn get_interpreter(&self) -> Result<&str, &str>;
 
     /// Append current ELF file as interpreter into given memory set.
     /// This will insert the interpreter it a place which is "good enough" (since ld.so should be PIC).
+    /// Return the end address of the interpreter.
     fn append_as_interpreter(
         &self,
         inode: &Arc<dyn INode>,
         memory_set: &mut MemorySet,
         bias: usize,
-    );
+    ) -> usize;
 
     /// Get virtual address of PHDR section if it has.
     fn get_phdr_vaddr(&self) -> Option<u64>;
+
+    /// Map the static TLS block of the main thread at `addr`, initialized from the PT_TLS segment.
+    /// Return the thread pointer and the end address of the block, or None if there is no TLS.
+    fn make_tls(
+        &self,
+        ms: &mut MemorySet,
+        inode: &Arc<dyn INode>,
+        addr: usize,
+    ) -> Option<(usize, usize)>;
 }
 
 impl ElfExt for ElfFile<'_> {
-    fn make_memory_set(&self, ms: &mut MemorySet, inode: &Arc<dyn INode>) -> usize {
+    fn make_memory_set(&self, ms: &mut MemorySet, inode: &Arc<dyn INode>, bias: usize) -> usize {
         debug!("creating MemorySet from ELF");
         let mut farthest_memory: usize = 0;
         for ph in self.program_iter() {
             if ph.get_type() != Ok(Type::Load) {
                 continue;
             }
+            let virtual_addr = ph.virtual_addr() as usize + bias;
             ms.push(
-                ph.virtual_addr() as usize,
-                ph.virtual_addr() as usize + ph.mem_size() as usize,
+                virtual_addr,
+                virtual_addr + ph.mem_size() as usize,
                 ph.flags().to_attr(),
                 File {
                     file: INodeForMap(inode.clone()),
-                    mem_start: ph.virtual_addr() as usize,
+                    mem_start: virtual_addr,
                     file_start: ph.offset() as usize,
                     file_end: ph.offset() as usize + ph.file_size() as usize,
+                    shared: false,
                     allocator: GlobalFrameAlloc,
+                    frames: CowFrames::new(),
                 },
                 "elf",
             );
-            if ph.virtual_addr() as usize + ph.mem_size() as usize > farthest_memory {
-                farthest_memory = ph.virtual_addr() as usize + ph.mem_size() as usize;
+            if virtual_addr + ph.mem_size() as usize > farthest_memory {
+                farthest_memory = virtual_addr + ph.mem_size() as usize;
             }
         }
 
         Page::of_addr(farthest_memory + PAGE_SIZE).start_address()
     }
-    fn append_as_interpreter(&self, inode: &Arc<dyn INode>, ms: &mut MemorySet, bias: usize) {
+    fn append_as_interpreter(
+        &self,
+        inode: &Arc<dyn INode>,
+        ms: &mut MemorySet,
+        bias: usize,
+    ) -> usize {
         debug!("inserting interpreter from ELF");
 
+        let mut farthest_memory: usize = bias;
         for ph in self.program_iter() {
             if ph.get_type() != Ok(Type::Load) {
                 continue;
@@ -118,11 +139,19 @@ impl ElfExt for ElfFile<'_> {
                     mem_start: ph.virtual_addr() as usize + bias,
                     file_start: ph.offset() as usize,
                     file_end: ph.offset() as usize + ph.file_size() as usize,
+                    shared: false,
                     allocator: GlobalFrameAlloc,
+                    frames: CowFrames::new(),
                 },
                 "elf-interp",
-            )
+            );
+            let end = ph.virtual_addr() as usize + ph.mem_size() as usize + bias;
+            if end > farthest_memory {
+                farthest_memory = end;
+            }
         }
+
+        Page::of_addr(farthest_memory + PAGE_SIZE - 1).start_address()
     }
     fn get_interpreter(&self) -> Result<&str, &str> {
         let header = self
@@ -160,6 +189,65 @@ impl ElfExt for ElfFile<'_> {
             None
         }
     }
+
+    fn make_tls(
+        &self,
+        ms: &mut MemorySet,
+        inode: &Arc<dyn INode>,
+        addr: usize,
+    ) -> Option<(usize, usize)> {
+        let ph = self
+            .program_iter()
+            .find(|ph| ph.get_type() == Ok(Type::Tls))?;
+        let file_size = ph.file_size() as usize;
+        let mem_size = ph.mem_size() as usize;
+        let align = (ph.align() as usize).max(1);
+        let mut image = vec![0u8; file_size];
+        page_cache::read_at(inode, ph.offset() as usize, &mut image).ok()?;
+
+        // where the TLS data and the thread pointer are, as in musl
+        // x86_64: the data is right below the thread pointer, which points to itself
+        #[cfg(target_arch = "x86_64")]
+        let (data, tp, size) = {
+            let offset = (mem_size + align - 1) & !(align - 1);
+            (addr, addr + offset, offset + core::mem::size_of::<usize>())
+        };
+        // aarch64: a TCB of 2 words at the thread pointer, then the data
+        #[cfg(target_arch = "aarch64")]
+        let (data, tp, size) = {
+            let offset = (16 + align - 1) & !(align - 1);
+            (addr + offset, addr, offset + mem_size)
+        };
+        // riscv: the data is right at the thread pointer
+        #[cfg(riscv)]
+        let (data, tp, size) = (addr, addr, mem_size);
+        // mips: the thread pointer is 0x7000 above the data
+        #[cfg(target_arch = "mips")]
+        let (data, tp, size) = (addr, addr + 0x7000, mem_size);
+
+        let end = Page::of_addr(addr + size + PAGE_SIZE - 1).start_address();
+        ms.push(
+            addr,
+            end,
+            MemoryAttr::default().user(),
+            ByFrame::new(GlobalFrameAlloc),
+            "tls",
+        );
+        unsafe {
+            ms.with(|| {
+                let block = core::slice::from_raw_parts_mut(data as *mut u8, mem_size);
+                block[..file_size].copy_from_slice(&image);
+                for byte in block[file_size..].iter_mut() {
+                    *byte = 0;
+                }
+                #[cfg(target_arch = "x86_64")]
+                {
+                    *(tp as *mut usize) = tp;
+                }
+            });
+        }
+        Some((tp, end))
+    }
 }
 
 #[derive(Clone)]
@@ -167,6 +255,34 @@ pub struct INodeForMap(pub Arc<dyn INode>);
 
 impl Read for INodeForMap {
     fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        self.0.read_at(offset, buf).unwrap()
+        page_cache::read_at(&self.0, offset, buf).unwrap()
+    }
+
+    fn get_frame(&self, offset: usize) -> Option<usize> {
+        page_cache::get_frame(&self.0, offset)
+    }
+
+    fn put_frame(&self, offset: usize, frame: usize) -> bool {
+        page_cache::put_frame(&self.0, offset, frame)
+    }
+
+    fn set_dirty(&self, offset: usize, frame: usize) -> bool {
+        page_cache::set_dirty(&self.0, offset, frame)
+    }
+}
+
+impl Write for INodeForMap {
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        // a shared mapping never extends the file
+        let size = self.0.metadata().unwrap().size;
+        if offset >= size {
+            return 0;
+        }
+        let len = buf.len().min(size - offset);
+        page_cache::write_at(&self.0, offset, &buf[..len]).unwrap()
+    }
+
+    fn sync(&self) -> bool {
+        page_cache::sync(&self.0).is_ok() && self.0.sync_data().is_ok()
     }
 }
@@ -1,28 +1,34 @@
 use super::{
     abi::{self, ProcInitInfo},
-    add_to_process_table, Pid, Process, PROCESSORS,
+    add_to_process_table, aslr,
+    itimer::{charge_timers, IntervalTimers},
+    posix_timer::PosixTimers,
+    ptrace::{self, PtraceStop},
+    Pid, Process, PROCESSORS,
 };
 use crate::arch::interrupt::consts::{
-    is_intr, is_page_fault, is_reserved_inst, is_syscall, is_timer_intr,
+    fault_signal, is_breakpoint, is_fp_unavailable, is_intr, is_page_fault, is_reserved_inst,
+    is_syscall, is_timer_intr,
 };
 use crate::arch::interrupt::{get_trap_num, handle_reserved_inst};
-use crate::arch::{
-    cpu,
-    fp::FpState,
-    memory::{get_page_fault_addr, set_page_table},
-    paging::*,
-};
+use crate::arch::{cpu, fp::FpState, memory::get_page_fault_addr, paging::*, timer::timer_now};
 use crate::drivers::IRQ_MANAGER;
-use crate::fs::{FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
+use crate::fs::{page_cache, FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
 use crate::ipc::{SemProc, ShmProc};
 use crate::memory::{
-    phys_to_virt, ByFrame, Delay, File, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet, Read,
+    activate_page_table, phys_to_virt, ByFrame, Delay, File, GlobalFrameAlloc, GlobalSwapper,
+    KernelStack, MemoryAttr, MemorySet, Read,
 };
 use crate::process::structs::ElfExt;
+use crate::sched::stat::{account, CpuTime};
+use crate::sched::{SchedPolicy, TaskId};
 use crate::sync::{EventBus, SpinLock, SpinNoIrqLock as Mutex};
 use crate::{
-    signal::{handle_signal, Siginfo, Signal, SignalAction, SignalStack, Sigset},
-    syscall::handle_syscall,
+    signal::{
+        deliver_signal, force_signal, handle_signal, is_blocked, wait_continue, Siginfo,
+        SiginfoFields, Signal, SignalAction, SignalStack, Sigset, SEGV_ACCERR, SEGV_MAPERR,
+    },
+    syscall::{handle_syscall, RLimit, DEFAULT_MEMLOCK_LIMIT, RLIM_INFINITY},
 };
 use alloc::{
     boxed::Box, collections::BTreeMap, collections::VecDeque, string::String, sync::Arc,
@@ -34,8 +40,10 @@ use core::str;
 use core::{
     future::Future,
     mem::MaybeUninit,
+    ops::{AddAssign, Range},
     pin::Pin,
-    task::{Context, Poll},
+    task::{Context, Poll, Waker},
+    time::Duration,
 };
 use log::*;
 use num::FromPrimitive;
@@ -56,7 +64,7 @@ pub type Tid = usize;
 
 pub struct ThreadContext {
     user: Box<UserContext>,
-    /// TODO: lazy fp
+    /// Loaded at the first use after going to user
     fp: Box<FpState>,
 }
 
@@ -69,10 +77,73 @@ pub struct ThreadInner {
     /// Kernel performs futex wake when thread exits.
     /// Ref: [http://man7.org/linux/man-pages/man2/set_tid_address.2.html]
     pub clear_child_tid: usize,
+    /// Kernel stores the tid here before the thread first returns to user.
+    /// Ref: [http://man7.org/linux/man-pages/man2/clone.2.html] (CLONE_CHILD_SETTID)
+    pub set_child_tid: usize,
     /// Signal mask
     pub sig_mask: Sigset,
+    /// Signal mask replaced by sigsuspend, to restore when a signal is handled
+    pub saved_sig_mask: Option<Sigset>,
+    /// Signals sent to the thread, rather than to any thread of the process
+    pub sig_queue: VecDeque<Siginfo>,
+    pub pending_sigset: Sigset,
     /// signal alternate stack
     pub signal_alternate_stack: SignalStack,
+    /// CPU time and page faults so far
+    pub usage: ResourceUsage,
+    /// CPUs the thread may run on, one bit for each
+    pub affinity: usize,
+    /// Name of the thread (comm), the file name of the program by default
+    pub name: String,
+    /// The syscall interrupted by a signal and its arguments, to run again after the signal
+    pub restart_syscall: Option<(usize, [usize; 6])>,
+    /// Set when the thread stops for its tracer
+    pub ptrace_stop: Option<PtraceStop>,
+    /// Policy of scheduling, inherited by the threads created
+    pub sched_policy: SchedPolicy,
+    /// The task of the thread in the scheduler, once it is spawned
+    pub sched_task: Option<TaskId>,
+    /// Waker of the future of the thread, used to let it quit when it is killed
+    waker: Option<Waker>,
+}
+
+/// Names of threads are at most this long, excluding the terminating null
+pub const THREAD_NAME_LEN: usize = 15;
+
+/// Make a thread name from `name`, which is truncated if too long
+pub fn thread_name(name: &str) -> String {
+    let mut len = name.len().min(THREAD_NAME_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    String::from(&name[..len])
+}
+
+/// The default thread name of programs at `path`
+pub fn program_name(path: &str) -> String {
+    thread_name(path.rsplit('/').next().unwrap())
+}
+
+/// CPU time and page faults of threads
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ResourceUsage {
+    /// Time running in user mode
+    pub utime: Duration,
+    /// Time running in kernel mode
+    pub stime: Duration,
+    /// Page faults served without I/O
+    pub minflt: usize,
+    /// Page faults which read the page from storage
+    pub majflt: usize,
+}
+
+impl AddAssign for ResourceUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.minflt += other.minflt;
+        self.majflt += other.majflt;
+    }
 }
 
 #[allow(dead_code)]
@@ -93,6 +164,9 @@ lazy_static! {
         RwLock::new(BTreeMap::new());
 }
 
+/// The stack never grows closer than this to the area below it, as in Linux
+const STACK_GUARD_GAP: usize = 256 * PAGE_SIZE;
+
 impl Thread {
     /// Assign a tid and put itself to global thread table.
     pub fn add_to_table(mut self) -> Arc<Self> {
@@ -112,19 +186,19 @@ impl Thread {
     }
 
     /// Construct virtual memory of a new user process from ELF at `inode`.
-    /// Return `(MemorySet, entry_point, ustack_top)`
+    /// Return `(entry_point, ustack_top, brk_start, stack, tls)`,
+    /// where `stack` is the range of the stack mapped initially,
+    /// and `tls` is the thread pointer to the static TLS block, 0 if there is none
     pub fn new_user_vm(
         inode: &Arc<dyn INode>,
         args: Vec<String>,
         envs: Vec<String>,
         vm: &mut MemorySet,
-    ) -> Result<(usize, usize), &'static str> {
+    ) -> Result<(usize, usize, usize, Range<usize>, usize), &'static str> {
         // Read ELF header
         // 0x3c0: magic number from ld-musl.so
         let mut data = [0u8; 0x3c0];
-        inode
-            .read_at(0, &mut data)
-            .map_err(|_| "failed to read from INode")?;
+        page_cache::read_at(inode, 0, &mut data).map_err(|_| "failed to read from INode")?;
 
         // Parse ELF
         let elf = ElfFile::new(&data)?;
@@ -149,11 +223,19 @@ impl Thread {
             _ => return Err("invalid ELF arch"),
         }
 
+        // PIE executables are loaded at a random address
+        let load_bias = match elf.header.pt2.type_().as_type() {
+            header::Type::SharedObject => {
+                aslr::PIE_BASE + aslr::random_offset(aslr::PIE_RANDOM_RANGE)
+            }
+            _ => 0,
+        };
+
         // auxiliary vector
         let mut auxv = {
             let mut map = BTreeMap::new();
             if let Some(phdr_vaddr) = elf.get_phdr_vaddr() {
-                map.insert(abi::AT_PHDR, phdr_vaddr as usize);
+                map.insert(abi::AT_PHDR, phdr_vaddr as usize + load_bias);
             }
             map.insert(abi::AT_PHENT, elf.header.pt2.ph_entry_size() as usize);
             map.insert(abi::AT_PHNUM, elf.header.pt2.ph_count() as usize);
@@ -162,10 +244,15 @@ impl Thread {
         };
 
         // entry point
-        let mut entry_addr = elf.header.pt2.entry_point() as usize;
+        let mut entry_addr = elf.header.pt2.entry_point() as usize + load_bias;
+        auxv.insert(abi::AT_ENTRY, entry_addr);
         // Make page table
         vm.clear();
-        let bias = elf.make_memory_set(vm, inode);
+        let bias = elf.make_memory_set(vm, inode, load_bias);
+        // static TLS of the main thread, for programs which do not set it up themselves
+        let (tls, tls_end) = elf.make_tls(vm, inode, bias).unwrap_or((0, bias));
+        // the heap starts right after the loaded program
+        let mut brk_start = tls_end;
 
         // Check interpreter (for dynamic link)
         // When interpreter is used, map both dynamic linker and executable
@@ -177,33 +264,33 @@ impl Thread {
                 .map_err(|_| "interpreter not found")?;
             // load loader by bias and set aux vector.
             let mut interp_data: [u8; 0x3c0] = unsafe { MaybeUninit::zeroed().assume_init() };
-            interp_inode
-                .read_at(0, &mut interp_data)
+            page_cache::read_at(&interp_inode, 0, &mut interp_data)
                 .map_err(|_| "failed to read from INode")?;
             let elf_interp = ElfFile::new(&interp_data)?;
-            elf_interp.append_as_interpreter(&interp_inode, vm, bias);
+            brk_start = elf_interp.append_as_interpreter(&interp_inode, vm, bias);
 
             // update auxiliary vector
-            auxv.insert(abi::AT_ENTRY, elf.header.pt2.entry_point() as usize);
             auxv.insert(abi::AT_BASE, bias);
 
             // use interpreter as actual entry point
-            debug!("entry point: {:x}", elf.header.pt2.entry_point() as usize);
+            debug!("entry point: {:x}", entry_addr);
             entry_addr = elf_interp.header.pt2.entry_point() as usize + bias;
         }
 
         // User stack
         use crate::consts::{USER_STACK_OFFSET, USER_STACK_SIZE};
-        let mut ustack_top = {
-            let ustack_buttom = USER_STACK_OFFSET;
-            let ustack_top = USER_STACK_OFFSET + USER_STACK_SIZE;
+        let stack = {
+            let ustack_top =
+                USER_STACK_OFFSET + USER_STACK_SIZE - aslr::random_offset(aslr::STACK_RANDOM_RANGE);
+            // mapped lazily, and grows on demand below it up to RLIMIT_STACK
+            let ustack_buttom = ustack_top - USER_STACK_SIZE;
 
             // user stack except top 4 pages
             vm.push(
                 ustack_buttom,
                 ustack_top - PAGE_SIZE * 4,
-                MemoryAttr::default().user().execute(),
-                Delay::new(GlobalFrameAlloc),
+                MemoryAttr::default().user(),
+                Delay::with_swapper(GlobalFrameAlloc, GlobalSwapper),
                 "user_stack_delay",
             );
 
@@ -211,20 +298,41 @@ impl Thread {
             vm.push(
                 ustack_top - PAGE_SIZE * 4,
                 ustack_top,
-                MemoryAttr::default().user().execute(), // feature
+                MemoryAttr::default().user(),
                 ByFrame::new(GlobalFrameAlloc),
                 "user_stack",
             );
-            ustack_top
+            ustack_buttom..ustack_top
         };
+        let mut ustack_top = stack.end;
+
+        // the vDSO, below the stack however far it grows
+        #[cfg(target_arch = "x86_64")]
+        {
+            let vdso_addr = USER_STACK_OFFSET
+                - aslr::STACK_RANDOM_RANGE
+                - crate::arch::vdso::size()
+                - aslr::random_offset(aslr::VDSO_RANDOM_RANGE);
+            if let Some(ehdr) = crate::arch::vdso::map(vm, vdso_addr) {
+                auxv.insert(abi::AT_SYSINFO_EHDR, ehdr);
+            }
+        }
 
         // Make init info
-        let init_info = ProcInitInfo { args, envs, auxv };
+        let mut random = [0u8; 16];
+        random[..8].copy_from_slice(&aslr::random().to_ne_bytes());
+        random[8..].copy_from_slice(&aslr::random().to_ne_bytes());
+        let init_info = ProcInitInfo {
+            args,
+            envs,
+            auxv,
+            random,
+        };
         unsafe {
             vm.with(|| ustack_top = init_info.push_at(ustack_top));
         }
 
-        Ok((entry_addr, ustack_top))
+        Ok((entry_addr, ustack_top, brk_start, stack, tls))
     }
 
     /// Make a new user process from ELF `data`
@@ -236,7 +344,8 @@ impl Thread {
     ) -> Arc<Thread> {
         // get virtual memory info
         let mut vm = MemorySet::new();
-        let (entry_addr, ustack_top) = Self::new_user_vm(inode, args, envs, &mut vm).unwrap();
+        let (entry_addr, ustack_top, brk_start, stack, tls) =
+            Self::new_user_vm(inode, args, envs, &mut vm).unwrap();
 
         let vm_token = vm.token();
         let vm = Arc::new(Mutex::new(vm));
@@ -293,6 +402,7 @@ impl Thread {
         let mut context = UserContext::default();
         context.set_ip(entry_addr);
         context.set_sp(ustack_top);
+        context.set_tls(tls);
 
         // arch specific
         #[cfg(target_arch = "x86_64")]
@@ -327,8 +437,20 @@ impl Thread {
                     fp: Box::new(FpState::new()),
                 }),
                 clear_child_tid: 0,
+                set_child_tid: 0,
                 sig_mask: Sigset::default(),
                 signal_alternate_stack: SignalStack::default(),
+                usage: ResourceUsage::default(),
+                affinity: usize::MAX,
+                name: program_name(exec_path),
+                pending_sigset: Sigset::empty(),
+                sig_queue: VecDeque::new(),
+                restart_syscall: None,
+                saved_sig_mask: None,
+                ptrace_stop: None,
+                sched_policy: SchedPolicy::Normal,
+                sched_task: None,
+                waker: None,
             }),
             vm: vm.clone(),
             proc: Arc::new(Mutex::new(Process {
@@ -336,19 +458,54 @@ impl Thread {
                 files,
                 cwd: String::from("/"),
                 exec_path: String::from(exec_path),
-                futexes: BTreeMap::default(),
                 semaphores: SemProc::default(),
                 pid: Pid::new(), // allocated later
-                pgid: 0,
+                pgid: 0,         // allocated later
+                sid: 0,          // allocated later
+                uid: 0,
+                gid: 0,
+                ruid: 0,
+                suid: 0,
+                rgid: 0,
+                sgid: 0,
+                umask: 0o022,
+                nice: 0,
+                dumpable: true,
+                memlock_limit: RLimit {
+                    cur: DEFAULT_MEMLOCK_LIMIT,
+                    max: DEFAULT_MEMLOCK_LIMIT,
+                },
+                core_limit: RLimit {
+                    cur: 0,
+                    max: RLIM_INFINITY,
+                },
+                brk_start,
+                brk: brk_start,
+                mmap_base: aslr::mmap_base(brk_start),
+                stack,
+                stack_limit: RLimit {
+                    cur: crate::consts::USER_STACK_SIZE as u64,
+                    max: RLIM_INFINITY,
+                },
                 parent: (Pid::new(), Weak::new()),
                 children: Vec::new(),
+                tracer: None,
+                tracees: Vec::new(),
                 threads: Vec::new(),
                 exit_code: 0,
+                zombie: false,
+                stopped: false,
+                stop_report: None,
                 pending_sigset: Sigset::empty(),
                 sig_queue: VecDeque::new(),
                 dispositions: [SignalAction::default(); Signal::RTMAX + 1],
                 eventbus: EventBus::new(),
                 shm_identifiers: ShmProc::default(),
+                vfork_vm: None,
+                usage: ResourceUsage::default(),
+                children_usage: ResourceUsage::default(),
+                itimers: IntervalTimers::default(),
+                posix_timers: PosixTimers::default(),
             })),
         };
 
@@ -357,6 +514,14 @@ impl Thread {
         // set pid to tid
         add_to_process_table(res.proc.clone(), Pid(res.tid));
 
+        // a process started by the kernel leads a new session
+        {
+            let mut proc = res.proc.lock();
+            proc.threads.push(res.tid);
+            proc.pgid = res.tid as Pgid;
+            proc.sid = res.tid as Pgid;
+        }
+
         res
     }
 
@@ -365,7 +530,20 @@ impl Thread {
     pub fn fork(&self, tf: &UserContext) -> Arc<Thread> {
         // clone virtual memory
         let vm = self.vm.lock().clone();
-        let vm_token = vm.token();
+        self.fork_with_vm(tf, vm)
+    }
+
+    /// Fork a new process which takes the address space of the current one
+    /// instead of a copy, until it execs or exits (vfork).
+    /// The current process is left with an empty one in the meantime.
+    pub fn vfork(&self, tf: &UserContext) -> Arc<Thread> {
+        let vm = core::mem::replace(&mut *self.vm.lock(), MemorySet::new());
+        let new_thread = self.fork_with_vm(tf, vm);
+        new_thread.proc.lock().vfork_vm = Some(self.vm.clone());
+        new_thread
+    }
+
+    fn fork_with_vm(&self, tf: &UserContext, vm: MemorySet) -> Arc<Thread> {
         let vm = Arc::new(Mutex::new(vm));
 
         // context of new thread
@@ -379,19 +557,46 @@ impl Thread {
             files: proc.files.clone(), // share open file descriptions
             cwd: proc.cwd.clone(),
             exec_path: proc.exec_path.clone(),
-            futexes: BTreeMap::default(),
             semaphores: proc.semaphores.clone(),
             pid: Pid::new(), // assigned later
             pgid: proc.pgid,
+            sid: proc.sid,
+            uid: proc.uid,
+            gid: proc.gid,
+            ruid: proc.ruid,
+            suid: proc.suid,
+            rgid: proc.rgid,
+            sgid: proc.sgid,
+            umask: proc.umask,
+            nice: proc.nice,
+            dumpable: proc.dumpable,
+            memlock_limit: proc.memlock_limit,
+            core_limit: proc.core_limit,
+            brk_start: proc.brk_start,
+            brk: proc.brk,
+            mmap_base: proc.mmap_base,
+            stack: proc.stack.clone(),
+            stack_limit: proc.stack_limit,
             parent: (proc.pid.clone(), Arc::downgrade(&self.proc)),
             children: Vec::new(),
+            tracer: None,
+            tracees: Vec::new(),
             threads: Vec::new(),
             exit_code: 0,
+            zombie: false,
+            stopped: false,
+            stop_report: None,
             pending_sigset: Sigset::empty(),
             sig_queue: VecDeque::new(),
             dispositions: proc.dispositions.clone(),
             eventbus: EventBus::new(),
             shm_identifiers: proc.shm_identifiers.clone(),
+            vfork_vm: None,
+            usage: ResourceUsage::default(),
+            children_usage: ResourceUsage::default(),
+            // not inherited, as in Linux
+            itimers: IntervalTimers::default(),
+            posix_timers: PosixTimers::default(),
         }));
 
         // new thread
@@ -401,6 +606,9 @@ impl Thread {
         // mask; the signal mask is preserved across execve(2).
         let sig_mask = self.inner.lock().sig_mask;
         let sigaltstack = self.inner.lock().signal_alternate_stack;
+        let affinity = self.inner.lock().affinity;
+        let sched_policy = self.inner.lock().sched_policy;
+        let name = self.inner.lock().name.clone();
         let new_thread = Thread {
             tid: 0, // allocated below
             inner: Mutex::new(ThreadInner {
@@ -409,8 +617,20 @@ impl Thread {
                     fp: Box::new(FpState::new()),
                 }),
                 clear_child_tid: 0,
+                set_child_tid: 0,
                 sig_mask,
                 signal_alternate_stack: sigaltstack,
+                usage: ResourceUsage::default(),
+                affinity,
+                name,
+                pending_sigset: Sigset::empty(),
+                sig_queue: VecDeque::new(),
+                restart_syscall: None,
+                saved_sig_mask: None,
+                ptrace_stop: None,
+                sched_policy,
+                sched_task: None,
+                waker: None,
             }),
             vm,
             proc: new_proc,
@@ -429,33 +649,39 @@ impl Thread {
         new_thread
     }
 
-    /// Create a new thread in the same process.
-    pub fn new_clone(
-        &self,
-        context: &UserContext,
-        stack_top: usize,
-        tls: usize,
-        clear_child_tid: usize,
-    ) -> Arc<Thread> {
-        let vm_token = self.vm.lock().token();
+    /// Create a new thread in the same process, starting with `context`.
+    pub fn new_clone(&self, context: &UserContext) -> Arc<Thread> {
         let mut new_context = context.clone();
         new_context.set_syscall_ret(0);
-        new_context.set_sp(stack_top);
-        new_context.set_tls(tls);
         let thread_context = ThreadContext {
             user: Box::new(new_context),
             fp: Box::new(FpState::new()),
         };
 
         let sig_mask = self.inner.lock().sig_mask;
-        let sigaltstack = self.inner.lock().signal_alternate_stack;
+        let affinity = self.inner.lock().affinity;
+        let sched_policy = self.inner.lock().sched_policy;
+        let name = self.inner.lock().name.clone();
         let thread = Thread {
             tid: 0,
             inner: Mutex::new(ThreadInner {
-                clear_child_tid,
+                clear_child_tid: 0,
+                set_child_tid: 0,
                 context: Some(thread_context),
                 sig_mask,
-                signal_alternate_stack: sigaltstack,
+                // a new thread does not share the stack, as in Linux
+                signal_alternate_stack: SignalStack::default(),
+                usage: ResourceUsage::default(),
+                affinity,
+                name,
+                pending_sigset: Sigset::empty(),
+                sig_queue: VecDeque::new(),
+                restart_syscall: None,
+                saved_sig_mask: None,
+                ptrace_stop: None,
+                sched_policy,
+                sched_task: None,
+                waker: None,
             }),
             vm: self.vm.clone(),
             proc: self.proc.clone(),
@@ -473,48 +699,87 @@ impl Thread {
         self.inner.lock().context = Some(cx);
     }
 
+    /// Run `f` with the user context of the thread, unless it is running,
+    /// e.g. for a tracer while the thread is stopped
+    pub fn with_user_context<T>(&self, f: impl FnOnce(&mut UserContext) -> T) -> Option<T> {
+        self.inner
+            .lock()
+            .context
+            .as_mut()
+            .map(|context| f(&mut context.user))
+    }
+
+    /// Whether the thread is killed, by the exit of the process or exec in another thread
+    pub fn is_killed(&self) -> bool {
+        !self.proc.lock().threads.contains(&self.tid)
+    }
+
+    /// Wake the future of the thread if it is waiting
+    pub fn wake(&self) {
+        if let Some(waker) = self.inner.lock().waker.take() {
+            waker.wake();
+        }
+    }
+
     /// this thread has signal to handle
     pub fn has_signal_to_handle(&self) -> bool {
-        self.proc
-            .lock()
+        let proc = self.proc.lock();
+        let inner = self.inner.lock();
+        // sent to me or to the process, and not masked
+        inner
             .sig_queue
             .iter()
-            .find(|(info, tid)| {
-                let tid = *tid;
-                // targets me and not masked
-                (tid == -1 || tid as usize == self.tid)
-                    && !self
-                        .inner
-                        .lock()
-                        .sig_mask
-                        .contains(FromPrimitive::from_i32(info.signo).unwrap())
+            .chain(proc.sig_queue.iter())
+            .any(|info| {
+                !is_blocked(
+                    &inner.sig_mask,
+                    FromPrimitive::from_i32(info.signo).unwrap(),
+                )
             })
-            .is_some()
     }
 }
 
 pub fn spawn(thread: Arc<Thread>) {
-    let vmtoken = thread.vm.lock().token();
     let temp = thread.clone();
     let future = async move {
+        let set_child_tid = core::mem::replace(&mut thread.inner.lock().set_child_tid, 0);
+        if set_child_tid != 0 {
+            // in the address space of the new thread, so after fork as well
+            let ptr = unsafe { thread.vm.lock().check_write_ptr(set_child_tid as *mut u32) };
+            if let Ok(ptr) = ptr {
+                *ptr = thread.tid as u32;
+            }
+        }
         loop {
             let mut thread_context = thread.begin_running();
             let cx = &mut thread_context.user;
 
             trace!("go to user: {:#x?}", cx);
             thread_context.fp.restore();
+            crate::timer::arm_time_slice();
+            let user_start = timer_now();
+            account(CpuTime::System);
             cx.run();
+            account(CpuTime::User);
+            let user_time = timer_now() - user_start;
+            thread.inner.lock().usage.utime += user_time;
+            if thread.is_killed() {
+                info!("thread {} killed", thread.tid);
+                break;
+            }
             thread_context.fp.save();
             let trap_num = get_trap_num(&cx);
             trace!("back from user: {:#x?} trap_num {:#x}", cx, trap_num);
             let mut exit = false;
-            let mut do_yield = false;
             match trap_num {
                 // must be first
                 _ if is_page_fault(trap_num) => {
                     // page fault
                     let addr = get_page_fault_addr();
                     info!("page fault from user @ {:#x}", addr);
+                    let page_ins = crate::memory::page_ins();
+                    crate::memory::reserve_frame();
+                    grow_user_stack(&thread, addr);
                     #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
                     {
                         use crate::arch::interrupt::consts::{
@@ -534,43 +799,63 @@ pub fn spawn(thread: Arc<Thread>) {
                             _ => unreachable!(),
                         };
                         if !handle_user_page_fault_ext(&thread, addr, access_type) {
-                            // TODO: SIGSEGV
-                            panic!("page fault handle failed");
+                            segmentation_fault(&thread, addr);
+                        }
+                    }
+                    #[cfg(target_arch = "x86_64")]
+                    {
+                        use crate::arch::interrupt::{
+                            get_page_fault_access, handle_user_page_fault_ext,
+                        };
+                        let access_type = get_page_fault_access(&cx);
+                        if !handle_user_page_fault_ext(&thread, addr, access_type) {
+                            segmentation_fault(&thread, addr);
                         }
                     }
-                    #[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
+                    #[cfg(not(any(
+                        target_arch = "riscv32",
+                        target_arch = "riscv64",
+                        target_arch = "x86_64"
+                    )))]
                     {
                         use crate::arch::interrupt::handle_user_page_fault;
                         if !handle_user_page_fault(&thread, addr) {
-                            // TODO: SIGSEGV
-                            panic!("page fault handle failed");
+                            segmentation_fault(&thread, addr);
                         }
                     }
+                    let mut inner = thread.inner.lock();
+                    if crate::memory::page_ins() != page_ins {
+                        inner.usage.majflt += 1;
+                    } else {
+                        inner.usage.minflt += 1;
+                    }
                 }
+                _ if is_fp_unavailable(trap_num) => thread_context.fp.load(),
+                // before syscalls, which may be told apart only by the cause
+                _ if is_breakpoint(trap_num) => ptrace::trap(&thread, cx),
                 _ if is_syscall(trap_num) => exit = handle_syscall(&thread, cx).await,
                 _ if is_intr(trap_num) => {
                     crate::arch::interrupt::ack(trap_num);
                     trace!("handle irq {:#x}", trap_num);
                     if is_timer_intr(trap_num) {
-                        do_yield = true;
                         crate::arch::interrupt::timer();
                     }
+                    // another CPU asks this one to call something, or wakes it up
+                    #[cfg(target_arch = "x86_64")]
+                    {
+                        use crate::arch::interrupt::consts::IPIFuncCall;
+                        if trap_num == IPIFuncCall {
+                            crate::arch::ipi::handle_ipi();
+                        }
+                    }
                     IRQ_MANAGER.read().try_handle_interrupt(Some(trap_num));
                 }
                 _ if is_reserved_inst(trap_num) => {
                     if !handle_reserved_inst(cx) {
-                        panic!(
-                            "unhandled reserved intr in thread {} trap {:#x} {:x?}",
-                            thread.tid, trap_num, cx
-                        );
+                        fault(&thread, cx, trap_num);
                     }
                 }
-                _ => {
-                    panic!(
-                        "unhandled trap in thread {} trap {:#x} {:x?}",
-                        thread.tid, trap_num, cx
-                    );
-                }
+                _ => fault(&thread, cx, trap_num),
             }
 
             // check signals
@@ -579,51 +864,188 @@ pub fn spawn(thread: Arc<Thread>) {
             }
 
             thread.end_running(thread_context);
+
+            // a traced thread stopped by a signal waits for its tracer,
+            // then goes on with the signal given by the tracer if any
+            while !exit && thread.inner.lock().ptrace_stop.is_some() {
+                let info = ptrace::wait_resume(&thread).await;
+                let mut thread_context = thread.begin_running();
+                if let Some(info) = info {
+                    exit = deliver_signal(&thread, &mut thread_context.user, info);
+                }
+                if !exit {
+                    exit = handle_signal(&thread, &mut thread_context.user);
+                }
+                thread.end_running(thread_context);
+            }
+            // stopped by a signal, until SIGCONT
+            while !exit && thread.proc.lock().stopped {
+                wait_continue(&thread).await;
+                let mut thread_context = thread.begin_running();
+                exit = handle_signal(&thread, &mut thread_context.user);
+                thread.end_running(thread_context);
+            }
             if exit {
                 info!("thread {} stopped", thread.tid);
                 break;
-            } else if do_yield {
+            } else if crate::sched::need_resched() {
+                // preempted in user, or in the kernel but not yet at a preemption point
                 yield_now().await;
             }
         }
     };
 
-    spawn_thread(Box::pin(future), vmtoken, temp);
+    spawn_thread(Box::pin(future), temp);
 }
 
-fn spawn_thread(
-    future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
-    vmtoken: usize,
-    thread: Arc<Thread>,
-) {
-    executor::spawn(PageTableSwitchWrapper {
-        inner: Mutex::new(future),
-        vmtoken,
+/// Send SIGSEGV to `thread` for the page fault at `addr` which cannot be handled
+fn segmentation_fault(thread: &Arc<Thread>, addr: usize) {
+    let mapped = thread.vm.lock().iter().any(|area| area.contains(addr));
+    info!("segmentation fault of thread {} at {:#x}", thread.tid, addr);
+    force_signal(
         thread,
-    });
+        Siginfo {
+            signo: Signal::SIGSEGV as i32,
+            errno: 0,
+            code: if mapped { SEGV_ACCERR } else { SEGV_MAPERR },
+            field: SiginfoFields::fault(addr),
+        },
+    );
+}
+
+/// Send the signal for the fault of `thread` at `context`
+fn fault(thread: &Arc<Thread>, context: &UserContext, trap_num: usize) {
+    let (signal, code, addr) = match fault_signal(context, trap_num) {
+        Some(fault) => fault,
+        None => panic!(
+            "unhandled trap in thread {} trap {:#x} {:x?}",
+            thread.tid, trap_num, context
+        ),
+    };
+    info!(
+        "fault of thread {} trap {:#x}: {:?} at {:#x}",
+        thread.tid, trap_num, signal, addr
+    );
+    force_signal(
+        thread,
+        Siginfo {
+            signo: signal as i32,
+            errno: 0,
+            code,
+            field: SiginfoFields::fault(addr),
+        },
+    );
+}
+
+/// Grow the main stack down to the page of `addr` if the fault is below it,
+/// as long as its size is within RLIMIT_STACK and the guard gap is kept.
+/// Return false if the stack is not grown.
+fn grow_user_stack(thread: &Arc<Thread>, addr: usize) -> bool {
+    let (stack, limit) = {
+        let proc = thread.proc.lock();
+        (proc.stack.clone(), proc.stack_limit.cur)
+    };
+    let limit = limit.min(usize::MAX as u64) as usize;
+    if addr >= stack.start || stack.end - addr > limit {
+        return false;
+    }
+    let new_start = addr & !(PAGE_SIZE - 1);
+    let mut vm = thread.vm.lock();
+    if !vm.test_free_area(new_start.saturating_sub(STACK_GUARD_GAP), stack.start) {
+        return false;
+    }
+    if !vm.grow_down(stack.start, new_start) {
+        return false;
+    }
+    drop(vm);
+    debug!("grow user stack to {:#x}", new_start);
+    thread.proc.lock().stack.start = new_start;
+    true
+}
+
+fn spawn_thread(future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>, thread: Arc<Thread>) {
+    let nice = thread.proc.lock().nice;
+    let (policy, affinity) = {
+        let inner = thread.inner.lock();
+        (inner.sched_policy, inner.affinity)
+    };
+    let task = crate::sched::spawn_with(
+        PageTableSwitchWrapper {
+            inner: Mutex::new(future),
+            thread: thread.clone(),
+        },
+        nice,
+    );
+    crate::sched::set_policy(task, policy);
+    crate::sched::set_affinity(task, affinity);
+    thread.inner.lock().sched_task = Some(task);
 }
 
 #[must_use = "future does nothing unless polled/`await`-ed"]
 struct PageTableSwitchWrapper {
     inner: Mutex<Pin<Box<dyn Future<Output = ()> + Send>>>,
-    vmtoken: usize,
     thread: Arc<Thread>,
 }
 
 impl Future for PageTableSwitchWrapper {
     type Output = ();
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        // set cpu local thread
-        // TODO: task local?
+        {
+            // SIGKILL ends the process at once, whatever the thread is waiting for
+            let mut proc = self.thread.proc.lock();
+            if proc.pending_sigset.contains(Signal::SIGKILL) {
+                proc.exit_by_signal(Signal::SIGKILL, false);
+            }
+        }
+        if self.thread.is_killed() {
+            // drop the future, in whatever syscall it is waiting
+            info!("thread {} killed", self.thread.tid);
+            let mut inner = self.thread.inner.lock();
+            inner.waker = None;
+            let usage = inner.usage;
+            drop(inner);
+            self.thread.proc.lock().usage += usage;
+            return Poll::Ready(());
+        }
         let cpu_id = cpu::id();
-        unsafe {
-            PROCESSORS[cpu_id] = Some(self.thread.clone());
+        let mut inner = self.thread.inner.lock();
+        inner.waker = Some(cx.waker().clone());
+        if inner.affinity & (1 << cpu_id) == 0 {
+            // leave it to the CPUs it may run on
+            let allowed = inner.affinity & super::online_cpus();
+            drop(inner);
+            if allowed != 0 {
+                crate::sched::migrate_current(allowed.trailing_zeros() as usize);
+            }
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
         }
-        // vmtoken won't change
-        set_page_table(self.vmtoken);
+        drop(inner);
+        // set cpu local thread
+        // TODO: task local?
+        PROCESSORS.with(|thread| *thread = Some(self.thread.clone()));
+        // the address space is replaced on exec after vfork, so the token may change
+        let vmtoken = self.thread.vm.lock().token();
+        activate_page_table(vmtoken);
+        let start = timer_now();
+        let utime = self.thread.inner.lock().usage.utime;
         let res = self.inner.lock().as_mut().poll(cx);
-        unsafe {
-            PROCESSORS[cpu_id] = None;
+        PROCESSORS.with(|thread| *thread = None);
+        // the time of the poll not spent in user is spent in kernel
+        let mut inner = self.thread.inner.lock();
+        let elapsed = timer_now() - start;
+        let user_time = inner.usage.utime - utime;
+        let system_time = elapsed.checked_sub(user_time).unwrap_or_default();
+        inner.usage.stime += system_time;
+        let usage = inner.usage;
+        if res.is_ready() {
+            inner.waker = None;
+        }
+        drop(inner);
+        charge_timers(&self.thread.proc, user_time, system_time);
+        if res.is_ready() {
+            // keep the usage of exited threads in the process
+            self.thread.proc.lock().usage += usage;
         }
         res
     }
@@ -648,6 +1070,7 @@ impl Future for YieldFuture {
             Poll::Ready(())
         } else {
             self.flag = true;
+            crate::sched::yield_current();
             cx.waker().clone().wake();
             Poll::Pending
         }
@@ -0,0 +1,146 @@
+//! Completely fair scheduling, as in Linux
+//!
+//! Each task has a virtual runtime, which grows by its runtime scaled down by
+//! its weight, and the task of the least virtual runtime is picked. So the CPU is
+//! shared in proportion to the weights of the nice values. Tasks waking up after a
+//! sleep get a bit of credit, so interactive tasks run soon after they are woken up,
+//! but do not take over the CPU for the time they have been sleeping.
+
+use super::{nice_to_weight, Scheduler, TaskId, NICE_0_WEIGHT};
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::time::Duration;
+
+/// All runnable tasks run once within this, unless there are too many of them
+const SCHED_LATENCY: Duration = Duration::from_millis(6);
+/// Time slices are never shorter than this
+const MIN_GRANULARITY: Duration = Duration::from_micros(750);
+
+#[derive(Default)]
+pub struct Cfs {
+    tasks: BTreeMap<TaskId, CfsTask>,
+    /// Runnable tasks by virtual runtime in ns
+    queue: BTreeSet<(u64, TaskId)>,
+    /// Weight of the tasks in the queue
+    queue_weight: u64,
+    /// Never decreasing, about the least virtual runtime of the runnable tasks
+    min_vruntime: u64,
+}
+
+struct CfsTask {
+    vruntime: u64,
+    weight: u64,
+    queued: bool,
+}
+
+impl Cfs {
+    fn update_min_vruntime(&mut self, vruntime: u64) {
+        let least = match self.queue.iter().next() {
+            Some(&(first, _)) => first.min(vruntime),
+            None => vruntime,
+        };
+        self.min_vruntime = self.min_vruntime.max(least);
+    }
+}
+
+impl Scheduler for Cfs {
+    fn name(&self) -> &'static str {
+        "cfs"
+    }
+
+    fn add(&mut self, task: TaskId, nice: i32) {
+        let t = CfsTask {
+            // a new task waits for those runnable to run their time slices
+            vruntime: self.min_vruntime + SCHED_LATENCY.as_nanos() as u64 / 2,
+            weight: nice_to_weight(nice),
+            queued: false,
+        };
+        self.tasks.insert(task, t);
+    }
+
+    fn remove(&mut self, task: TaskId) -> bool {
+        match self.tasks.remove(&task) {
+            Some(t) if t.queued => {
+                self.queue.remove(&(t.vruntime, task));
+                self.queue_weight -= t.weight;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn enqueue(&mut self, task: TaskId) {
+        let min_vruntime = self.min_vruntime;
+        let t = match self.tasks.get_mut(&task) {
+            Some(t) if !t.queued => t,
+            _ => return,
+        };
+        // credit of a sleeper, half a latency at most
+        let credit = SCHED_LATENCY.as_nanos() as u64 / 2;
+        t.vruntime = t.vruntime.max(min_vruntime.saturating_sub(credit));
+        t.queued = true;
+        self.queue.insert((t.vruntime, task));
+        self.queue_weight += t.weight;
+    }
+
+    fn pick_next(&mut self) -> Option<TaskId> {
+        let &(vruntime, task) = self.queue.iter().next()?;
+        self.queue.remove(&(vruntime, task));
+        let t = self.tasks.get_mut(&task).unwrap();
+        t.queued = false;
+        self.queue_weight -= t.weight;
+        self.update_min_vruntime(vruntime);
+        Some(task)
+    }
+
+    /// The share of the latency by weight among the runnable tasks
+    fn time_slice(&self, task: TaskId) -> Duration {
+        let weight = match self.tasks.get(&task) {
+            Some(t) => t.weight,
+            None => return MIN_GRANULARITY,
+        };
+        let total = self.queue_weight + weight;
+        let nr_running = self.queue.len() as u32 + 1;
+        let period = SCHED_LATENCY.max(MIN_GRANULARITY * nr_running);
+        let slice = period.as_nanos() as u64 * weight / total;
+        Duration::from_nanos(slice).max(MIN_GRANULARITY)
+    }
+
+    fn charge(&mut self, task: TaskId, runtime: Duration) {
+        let t = match self.tasks.get_mut(&task) {
+            Some(t) => t,
+            None => return,
+        };
+        let delta = runtime.as_nanos() as u64 * NICE_0_WEIGHT / t.weight;
+        t.vruntime += delta.max(1);
+        let vruntime = t.vruntime;
+        self.update_min_vruntime(vruntime);
+    }
+
+    fn tick(&mut self, task: TaskId, runtime: Duration) -> bool {
+        // alone, it goes on
+        !self.queue.is_empty() && runtime >= self.time_slice(task)
+    }
+
+    /// Go after all runnable tasks
+    fn yield_task(&mut self, task: TaskId) {
+        let last = match self.queue.iter().next_back() {
+            Some(&(last, _)) => last,
+            None => return,
+        };
+        if let Some(t) = self.tasks.get_mut(&task) {
+            if !t.queued {
+                t.vruntime = t.vruntime.max(last + 1);
+            }
+        }
+    }
+
+    fn set_nice(&mut self, task: TaskId, nice: i32) {
+        if let Some(t) = self.tasks.get_mut(&task) {
+            let weight = nice_to_weight(nice);
+            if t.queued {
+                self.queue_weight = self.queue_weight - t.weight + weight;
+            }
+            t.weight = weight;
+        }
+    }
+}
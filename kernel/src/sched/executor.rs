@@ -0,0 +1,367 @@
+//! The executor of the tasks, polling one at a time on each CPU
+//!
+//! Each CPU has its own run queue, so the hot path takes no global lock but that
+//! of the task table. A task is in the queue of its CPU only while it is runnable
+//! and not being polled, and the wakeups while it is polled enqueue it once it returns.
+//! An idle CPU steals a runnable task allowed to run on it from the busiest one,
+//! and a busy CPU pulls one from the busiest now and then if their queues are too far apart.
+
+use super::idle::kick;
+use super::preempt::clear_need_resched;
+use super::{Classes, SchedPolicy, TaskId, SCHEDULERS};
+use crate::arch::cpu;
+use crate::arch::timer::timer_now;
+use crate::consts::{MAX_CPU_NUM, USEC_PER_TICK};
+use crate::process::online_cpus;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::mem::size_of;
+use core::pin::Pin;
+use core::sync::atomic::{spin_loop_hint, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use core::task::Context;
+use core::time::Duration;
+use woke::{waker_ref, Woke};
+
+/// Waiting to be woken up
+const IDLE: u8 = 0;
+/// In the run queue of its CPU
+const QUEUED: u8 = 1;
+/// Being polled
+const RUNNING: u8 = 2;
+/// Woken up while being polled
+const WOKEN: u8 = 3;
+
+/// No CPU to move to
+const NO_CPU: usize = usize::max_value();
+
+/// How often a busy CPU looks for imbalance
+const BALANCE_INTERVAL: Duration = Duration::from_micros(4 * USEC_PER_TICK as u64);
+
+struct Task {
+    id: TaskId,
+    state: AtomicU8,
+    /// The CPU whose run queue the task is in, `NO_CPU` while it is moved,
+    /// only changed with the scheduler of that CPU locked
+    cpu: AtomicUsize,
+    /// The CPUs the task may run on, one bit for each
+    affinity: AtomicUsize,
+    /// The CPU to move to once it is polled
+    migrate_to: AtomicUsize,
+    /// Only locked by the CPU polling it
+    future: spin::Mutex<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl Woke for Task {
+    fn wake_by_ref(task: &Arc<Self>) {
+        loop {
+            let (new, enqueue) = match task.state.load(Ordering::SeqCst) {
+                IDLE => (QUEUED, true),
+                RUNNING => (WOKEN, false),
+                _ => return,
+            };
+            let old = if enqueue { IDLE } else { RUNNING };
+            if task
+                .state
+                .compare_exchange(old, new, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                if enqueue {
+                    enqueue_on(task.cpu.load(Ordering::SeqCst), task.id);
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Ids start from 1, so that 0 is never a task
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+lazy_static! {
+    static ref TASKS: Mutex<BTreeMap<TaskId, Arc<Task>>> = Mutex::new(BTreeMap::new());
+}
+
+percpu! {
+    /// The task polled by each CPU
+    static ref CURRENT: Mutex<Option<Current>> = Mutex::new(None);
+    /// Number of tasks in the run queue of each CPU, read without locking it
+    static ref NR_QUEUED: AtomicUsize = AtomicUsize::new(0);
+    /// When each CPU looked for imbalance last, in ns
+    static ref LAST_BALANCE: AtomicU64 = AtomicU64::new(0);
+}
+
+#[derive(Clone, Copy)]
+struct Current {
+    id: TaskId,
+    /// When it is picked
+    start: Duration,
+    /// When it should give the CPU to others
+    slice_end: Duration,
+}
+
+fn enqueue_on(cpu_id: usize, id: TaskId) {
+    SCHEDULERS.of(cpu_id).lock().enqueue(id);
+    NR_QUEUED.of(cpu_id).fetch_add(1, Ordering::SeqCst);
+    kick(cpu_id);
+}
+
+/// Whether any CPU has a runnable task, which this CPU may steal
+pub fn has_runnable() -> bool {
+    online_cpu_ids().any(|cpu_id| NR_QUEUED.of(cpu_id).load(Ordering::SeqCst) > 0)
+}
+
+fn online_cpu_ids() -> impl Iterator<Item = usize> {
+    let online = online_cpus();
+    let max = MAX_CPU_NUM.min(size_of::<usize>() * 8);
+    (0..max).filter(move |&cpu_id| online & (1 << cpu_id) != 0)
+}
+
+/// Tasks runnable or being polled on the online CPUs
+pub fn nr_running() -> usize {
+    online_cpu_ids()
+        .map(|cpu_id| {
+            let polled = CURRENT.of(cpu_id).lock().is_some() as usize;
+            NR_QUEUED.of(cpu_id).load(Ordering::SeqCst) + polled
+        })
+        .sum()
+}
+
+/// Tasks not finished yet
+pub fn nr_tasks() -> usize {
+    TASKS.lock().len()
+}
+
+/// Run `future` as a task of nice 0
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) -> TaskId {
+    spawn_with(future, 0)
+}
+
+/// Run `future` as a task of the nice value `nice` on this CPU, return its id
+pub fn spawn_with(future: impl Future<Output = ()> + Send + 'static, nice: i32) -> TaskId {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let cpu_id = cpu::id();
+    let task = Arc::new(Task {
+        id,
+        state: AtomicU8::new(QUEUED),
+        cpu: AtomicUsize::new(cpu_id),
+        affinity: AtomicUsize::new(usize::max_value()),
+        migrate_to: AtomicUsize::new(NO_CPU),
+        future: spin::Mutex::new(Box::pin(future)),
+    });
+    TASKS.lock().insert(id, task);
+    SCHEDULERS.of(cpu_id).lock().add(id, nice);
+    enqueue_on(cpu_id, id);
+    id
+}
+
+/// The task to poll next on this CPU
+fn pick_next(cpu_id: usize) -> Option<TaskId> {
+    let id = SCHEDULERS.of(cpu_id).lock().pick_next()?;
+    NR_QUEUED.of(cpu_id).fetch_sub(1, Ordering::SeqCst);
+    Some(id)
+}
+
+/// Take the next task of the busiest other CPU which may run on this one, if it has
+/// at least `threshold` tasks more than this one. The task is not enqueued.
+fn steal(cpu_id: usize, threshold: usize) -> Option<TaskId> {
+    let victim = online_cpu_ids()
+        .filter(|&other| other != cpu_id)
+        .max_by_key(|&other| NR_QUEUED.of(other).load(Ordering::SeqCst))?;
+    let mine = NR_QUEUED.of(cpu_id).load(Ordering::SeqCst);
+    if NR_QUEUED.of(victim).load(Ordering::SeqCst) < mine + threshold {
+        return None;
+    }
+    let (task, nice, policy) = {
+        let mut scheduler = SCHEDULERS.of(victim).lock();
+        // tasks which may not run here are put back
+        let mut skipped = Vec::new();
+        let mut found = None;
+        while let Some(id) = scheduler.pick_next() {
+            match TASKS.lock().get(&id) {
+                Some(task) if task.affinity.load(Ordering::SeqCst) & (1 << cpu_id) != 0 => {
+                    found = Some(task.clone());
+                    break;
+                }
+                _ => skipped.push(id),
+            }
+        }
+        for &id in skipped.iter() {
+            scheduler.enqueue(id);
+        }
+        let task = found?;
+        let (nice, policy) = match scheduler.take(task.id) {
+            Some(state) => state,
+            None => {
+                // not known to the scheduler: it stays queued there
+                scheduler.enqueue(task.id);
+                return None;
+            }
+        };
+        NR_QUEUED.of(victim).fetch_sub(1, Ordering::SeqCst);
+        task.cpu.store(NO_CPU, Ordering::SeqCst);
+        (task, nice, policy)
+    };
+    let mut scheduler = SCHEDULERS.of(cpu_id).lock();
+    scheduler.insert(task.id, nice, policy);
+    task.cpu.store(cpu_id, Ordering::SeqCst);
+    Some(task.id)
+}
+
+/// Poll the tasks picked by the scheduler of this CPU until none is runnable,
+/// even on other CPUs
+pub fn run_until_idle() {
+    let cpu_id = cpu::id();
+    loop {
+        let id = match pick_next(cpu_id).or_else(|| steal(cpu_id, 1)) {
+            Some(id) => id,
+            None => return,
+        };
+        let task = match TASKS.lock().get(&id) {
+            Some(task) => task.clone(),
+            None => continue,
+        };
+        let slice = SCHEDULERS.of(cpu_id).lock().time_slice(id);
+        clear_need_resched(cpu_id);
+        task.state.store(RUNNING, Ordering::SeqCst);
+        let start = timer_now();
+        *CURRENT.of(cpu_id).lock() = Some(Current {
+            id,
+            start,
+            slice_end: start + slice,
+        });
+        let ready = {
+            let waker = waker_ref(&task);
+            let mut cx = Context::from_waker(&*waker);
+            task.future.lock().as_mut().poll(&mut cx).is_ready()
+        };
+        *CURRENT.of(cpu_id).lock() = None;
+        if ready {
+            TASKS.lock().remove(&id);
+            SCHEDULERS.of(cpu_id).lock().remove(id);
+            continue;
+        }
+        let runtime = timer_now() - start;
+        let mut owner = cpu_id;
+        let mut scheduler = SCHEDULERS.of(cpu_id).lock();
+        scheduler.charge(id, runtime);
+        let target = task.migrate_to.swap(NO_CPU, Ordering::SeqCst);
+        if target != NO_CPU && target != cpu_id {
+            if let Some((nice, policy)) = scheduler.take(id) {
+                task.cpu.store(NO_CPU, Ordering::SeqCst);
+                drop(scheduler);
+                owner = target;
+                scheduler = SCHEDULERS.of(target).lock();
+                scheduler.insert(id, nice, policy);
+                task.cpu.store(target, Ordering::SeqCst);
+            }
+        }
+        if task
+            .state
+            .compare_exchange(RUNNING, IDLE, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            task.state.store(QUEUED, Ordering::SeqCst);
+            scheduler.enqueue(id);
+            NR_QUEUED.of(owner).fetch_add(1, Ordering::SeqCst);
+            drop(scheduler);
+            kick(owner);
+        }
+    }
+}
+
+/// Called at the timer interrupt of this CPU,
+/// return whether the task polled should give the CPU to others
+pub fn tick() -> bool {
+    let cpu_id = cpu::id();
+    let now = timer_now();
+    let last = LAST_BALANCE.of(cpu_id).load(Ordering::Relaxed);
+    if now.as_nanos() as u64 - last >= BALANCE_INTERVAL.as_nanos() as u64 {
+        LAST_BALANCE
+            .of(cpu_id)
+            .store(now.as_nanos() as u64, Ordering::Relaxed);
+        if let Some(id) = steal(cpu_id, 2) {
+            enqueue_on(cpu_id, id);
+        }
+    }
+    let current = *CURRENT.of(cpu_id).lock();
+    match current {
+        Some(current) => SCHEDULERS
+            .of(cpu_id)
+            .lock()
+            .tick(current.id, now - current.start),
+        None => false,
+    }
+}
+
+/// Tell the scheduler that the task polled by this CPU yields, before it wakes itself up
+pub fn yield_current() {
+    let cpu_id = cpu::id();
+    let current = *CURRENT.of(cpu_id).lock();
+    if let Some(current) = current {
+        SCHEDULERS.of(cpu_id).lock().yield_task(current.id);
+    }
+}
+
+/// Move the task polled by this CPU to the CPU `target`, once it returns
+pub fn migrate_current(target: usize) {
+    let current = *CURRENT.get().lock();
+    if let Some(current) = current {
+        if let Some(task) = TASKS.lock().get(&current.id) {
+            task.migrate_to.store(target, Ordering::SeqCst);
+        }
+    }
+}
+
+/// When the task polled by this CPU should give the CPU to others, if any
+pub fn slice_end() -> Option<Duration> {
+    CURRENT.get().lock().map(|current| current.slice_end)
+}
+
+/// The task polled by this CPU, if any
+pub fn current_task() -> Option<TaskId> {
+    CURRENT.get().lock().map(|current| current.id)
+}
+
+/// Change the scheduler state of the task `id` on the CPU it belongs to
+fn with_scheduler<R>(id: TaskId, f: impl FnOnce(&mut Classes) -> R) -> Option<R> {
+    let task = TASKS.lock().get(&id)?.clone();
+    loop {
+        let cpu_id = task.cpu.load(Ordering::SeqCst);
+        if cpu_id == NO_CPU {
+            spin_loop_hint();
+            continue;
+        }
+        let mut scheduler = SCHEDULERS.of(cpu_id).lock();
+        // unless moved to another CPU in the meantime
+        if task.cpu.load(Ordering::SeqCst) == cpu_id {
+            return Some(f(&mut scheduler));
+        }
+    }
+}
+
+/// Let the task `id` run only on the CPUs in `affinity`, one bit for each.
+/// It is not moved by itself, but no longer stolen by the others.
+pub fn set_affinity(id: TaskId, affinity: usize) {
+    if let Some(task) = TASKS.lock().get(&id) {
+        task.affinity.store(affinity, Ordering::SeqCst);
+    }
+}
+
+/// Change the nice value of the task `id`
+pub fn set_nice(id: TaskId, nice: i32) {
+    with_scheduler(id, |scheduler| scheduler.set_nice(id, nice));
+}
+
+/// Change the scheduling policy the task `id` runs with, regardless of inheritance
+pub(super) fn set_policy(id: TaskId, policy: SchedPolicy) {
+    with_scheduler(id, |scheduler| scheduler.set_policy(id, policy));
+}
+
+/// The scheduling policy the task `id` runs with
+pub fn policy(id: TaskId) -> Option<SchedPolicy> {
+    with_scheduler(id, |scheduler| scheduler.policy(id))
+}
@@ -0,0 +1,45 @@
+//! The idle loop of each CPU
+//!
+//! While no task is runnable, the CPU sleeps with hlt or wfi until an interrupt,
+//! instead of spinning. Interrupts are disabled from the check of the run queues to the
+//! sleep, so a task woken up in between wakes the CPU up right away. A task enqueued to
+//! a sleeping CPU by another one wakes it up by an IPI.
+
+use super::executor::{has_runnable, run_until_idle};
+use super::stat::{account, CpuTime};
+use crate::arch::{cpu, interrupt};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// CPUs sleeping in the idle loop, one bit for each
+static IDLE_CPUS: AtomicUsize = AtomicUsize::new(0);
+
+/// Run tasks on this CPU forever, sleeping while there is none
+pub fn idle() -> ! {
+    let cpu_id = cpu::id();
+    loop {
+        run_until_idle();
+        let flags = unsafe { interrupt::disable_and_store() };
+        IDLE_CPUS.fetch_or(1 << cpu_id, Ordering::SeqCst);
+        if !has_runnable() {
+            // woken up only for the next deadline
+            crate::timer::reprogram(false);
+            account(CpuTime::System);
+            interrupt::wait_for_interrupt();
+            account(CpuTime::Idle);
+        }
+        IDLE_CPUS.fetch_and(!(1 << cpu_id), Ordering::SeqCst);
+        unsafe { interrupt::restore(flags) };
+    }
+}
+
+/// Whether CPU `cpu_id` is sleeping in the idle loop
+pub fn is_idle(cpu_id: usize) -> bool {
+    IDLE_CPUS.load(Ordering::SeqCst) & (1 << cpu_id) != 0
+}
+
+/// Wake up CPU `cpu_id` if it is sleeping, after a task is enqueued to it
+pub fn kick(cpu_id: usize) {
+    if cpu_id != cpu::id() && is_idle(cpu_id) {
+        cpu::send_ipi(cpu_id);
+    }
+}
@@ -0,0 +1,149 @@
+//! Multi-level feedback queue
+//!
+//! New tasks start at the highest level. A task using up the quantum of its level,
+//! in one go or a bit at a time, goes down a level, where the quantum is twice as long.
+//! All tasks are moved back to the highest level every boost period, so that those
+//! at low levels never starve. Nice values are not used.
+//!
+//! Set by the kernel command line:
+//! - `mlfq.levels=<n>`: number of levels, 3 by default
+//! - `mlfq.quantum=<ms>`: quantum of the highest level, a time slice by default
+//! - `mlfq.boost=<ms>`: boost period, 1 s by default
+
+use super::{Scheduler, TaskId, TIME_SLICE};
+use crate::arch::timer::timer_now;
+use crate::drivers::CMDLINE;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+const MAX_LEVELS: usize = 8;
+
+pub struct Mlfq {
+    /// Runnable tasks of each level, the highest first
+    queues: Vec<VecDeque<TaskId>>,
+    tasks: BTreeMap<TaskId, MlfqTask>,
+    quantum: Duration,
+    boost_period: Duration,
+    last_boost: Duration,
+}
+
+struct MlfqTask {
+    level: usize,
+    /// Time run at the level so far
+    used: Duration,
+    queued: bool,
+}
+
+impl Default for Mlfq {
+    fn default() -> Self {
+        let mut levels = 3;
+        let mut quantum = TIME_SLICE;
+        let mut boost_period = Duration::from_secs(1);
+        for arg in CMDLINE.read().split_whitespace() {
+            let mut kv = arg.splitn(2, '=');
+            let (key, value) = match (kv.next(), kv.next().map(str::parse::<u64>)) {
+                (Some(key), Some(Ok(value))) => (key, value),
+                _ => continue,
+            };
+            match key {
+                "mlfq.levels" => levels = (value as usize).max(1).min(MAX_LEVELS),
+                "mlfq.quantum" if value > 0 => quantum = Duration::from_millis(value),
+                "mlfq.boost" if value > 0 => boost_period = Duration::from_millis(value),
+                _ => {}
+            }
+        }
+        Mlfq {
+            queues: (0..levels).map(|_| VecDeque::new()).collect(),
+            tasks: BTreeMap::new(),
+            quantum,
+            boost_period,
+            last_boost: timer_now(),
+        }
+    }
+}
+
+impl Mlfq {
+    fn quantum_of(&self, level: usize) -> Duration {
+        self.quantum * (1 << level)
+    }
+
+    /// Move all tasks to the highest level
+    fn boost(&mut self) {
+        for task in self.tasks.values_mut() {
+            task.level = 0;
+            task.used = Duration::default();
+        }
+        let (top, lower) = self.queues.split_at_mut(1);
+        for queue in lower {
+            top[0].extend(queue.drain(..));
+        }
+    }
+}
+
+impl Scheduler for Mlfq {
+    fn name(&self) -> &'static str {
+        "mlfq"
+    }
+
+    fn add(&mut self, task: TaskId, _nice: i32) {
+        let t = MlfqTask {
+            level: 0,
+            used: Duration::default(),
+            queued: false,
+        };
+        self.tasks.insert(task, t);
+    }
+
+    fn remove(&mut self, task: TaskId) -> bool {
+        match self.tasks.remove(&task) {
+            Some(t) if t.queued => {
+                self.queues[t.level].retain(|&other| other != task);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn enqueue(&mut self, task: TaskId) {
+        if let Some(t) = self.tasks.get_mut(&task) {
+            if !t.queued {
+                t.queued = true;
+                self.queues[t.level].push_back(task);
+            }
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<TaskId> {
+        let now = timer_now();
+        if now - self.last_boost >= self.boost_period {
+            self.last_boost = now;
+            self.boost();
+        }
+        let task = self.queues.iter_mut().find_map(VecDeque::pop_front)?;
+        self.tasks.get_mut(&task).unwrap().queued = false;
+        Some(task)
+    }
+
+    /// What is left of the quantum of its level
+    fn time_slice(&self, task: TaskId) -> Duration {
+        match self.tasks.get(&task) {
+            Some(t) => self.quantum_of(t.level) - t.used,
+            None => self.quantum,
+        }
+    }
+
+    fn charge(&mut self, task: TaskId, runtime: Duration) {
+        let last = self.queues.len() - 1;
+        let quantum = match self.tasks.get(&task) {
+            Some(t) => self.quantum_of(t.level),
+            None => return,
+        };
+        let t = self.tasks.get_mut(&task).unwrap();
+        t.used += runtime;
+        if t.used >= quantum {
+            t.level = (t.level + 1).min(last);
+            t.used = Duration::default();
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Scheduling of the kernel tasks, including the futures of user threads
+//!
+//! The executor polls the tasks in the order chosen by a `Scheduler`, so that
+//! the policy is replaced without touching the processes or the tasks.
+//! It is chosen by `sched=<name>` in the kernel command line, or by the
+//! `sched_*` cargo features if not given. The fair one is the default.
+//! Real-time tasks of SCHED_FIFO and SCHED_RR are in a class above the policy,
+//! running before any normal task.
+
+use crate::consts::MAX_CPU_NUM;
+use crate::drivers::CMDLINE;
+use crate::percpu::PerCpu;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+use log::*;
+
+mod cfs;
+mod executor;
+mod idle;
+mod mlfq;
+mod pi;
+mod preempt;
+mod rr;
+pub mod rt;
+pub mod stat;
+mod stride;
+
+use self::cfs::Cfs;
+pub use self::executor::{
+    current_task, migrate_current, policy, run_until_idle, set_affinity, set_nice, slice_end,
+    spawn, spawn_with, tick, yield_current,
+};
+pub use self::idle::{idle, is_idle};
+use self::mlfq::Mlfq;
+pub use self::pi::{inherit, set_policy, uninherit};
+pub use self::preempt::{
+    cond_resched, need_resched, preempt_disable, preempt_enable, preemptible, set_need_resched,
+    NoPreempt,
+};
+use self::rr::RoundRobin;
+use self::rt::Rt;
+use self::stride::Stride;
+
+/// Id of a task, never reused
+pub type TaskId = usize;
+
+/// Time a task runs before it is preempted, unless the policy tells otherwise
+pub const TIME_SLICE: Duration = Duration::from_micros(crate::consts::USEC_PER_TICK as u64);
+
+/// Scheduling policy, deciding which runnable task to poll next
+pub trait Scheduler: Send {
+    /// Name chosen by `sched=` in the kernel command line
+    fn name(&self) -> &'static str;
+    /// `task` is spawned with the nice value `nice`, before it is enqueued
+    fn add(&mut self, task: TaskId, nice: i32);
+    /// `task` leaves the policy, return whether it was runnable in the queue
+    fn remove(&mut self, task: TaskId) -> bool;
+    /// `task` becomes runnable
+    fn enqueue(&mut self, task: TaskId);
+    /// Take the runnable task to poll next
+    fn pick_next(&mut self) -> Option<TaskId>;
+    /// Time `task` just picked may run before it is preempted
+    fn time_slice(&self, _task: TaskId) -> Duration {
+        TIME_SLICE
+    }
+    /// `task` has been polled for `runtime`, before it is enqueued again if runnable
+    fn charge(&mut self, _task: TaskId, _runtime: Duration) {}
+    /// The timer interrupts `task` polled for `runtime` so far,
+    /// return whether it should give the CPU to others
+    fn tick(&mut self, task: TaskId, runtime: Duration) -> bool {
+        runtime >= self.time_slice(task)
+    }
+    /// `task` gives the CPU to others by itself, and is enqueued again soon
+    fn yield_task(&mut self, _task: TaskId) {}
+    /// The nice value of `task` is changed
+    fn set_nice(&mut self, _task: TaskId, _nice: i32) {}
+}
+
+/// Scheduling policy of a task, as of sched_setscheduler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// By the policy of normal tasks
+    Normal,
+    /// Real-time, with the static priority
+    Fifo(u8),
+    RoundRobin(u8),
+}
+
+impl Default for SchedPolicy {
+    fn default() -> Self {
+        SchedPolicy::Normal
+    }
+}
+
+impl SchedPolicy {
+    /// The priority to compare policies, 0 for normal tasks under any real-time one
+    pub fn rank(self) -> u8 {
+        match self {
+            SchedPolicy::Normal => 0,
+            SchedPolicy::Fifo(prio) | SchedPolicy::RoundRobin(prio) => prio,
+        }
+    }
+}
+
+/// The real-time class above the policy of normal tasks
+struct Classes {
+    rt: Rt,
+    normal: Box<dyn Scheduler>,
+    /// Nice values of all tasks, kept for real-time ones to go back to normal
+    nice: BTreeMap<TaskId, i32>,
+}
+
+impl Classes {
+    fn add(&mut self, task: TaskId, nice: i32) {
+        self.nice.insert(task, nice);
+        self.normal.add(task, nice);
+    }
+
+    fn remove(&mut self, task: TaskId) {
+        self.nice.remove(&task);
+        if !self.rt.remove(task) {
+            self.normal.remove(task);
+        }
+    }
+
+    fn enqueue(&mut self, task: TaskId) {
+        if self.rt.contains(task) {
+            self.rt.enqueue(task);
+        } else {
+            self.normal.enqueue(task);
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<TaskId> {
+        self.rt.pick_next().or_else(|| self.normal.pick_next())
+    }
+
+    fn time_slice(&self, task: TaskId) -> Duration {
+        if self.rt.contains(task) {
+            self.rt.time_slice(task)
+        } else {
+            self.normal.time_slice(task)
+        }
+    }
+
+    fn charge(&mut self, task: TaskId, runtime: Duration) {
+        if self.rt.contains(task) {
+            self.rt.charge(task, runtime);
+        } else {
+            self.normal.charge(task, runtime);
+        }
+    }
+
+    fn tick(&mut self, task: TaskId, runtime: Duration) -> bool {
+        if self.rt.contains(task) {
+            self.rt.tick(task, runtime)
+        } else {
+            // a real-time task is waiting
+            self.rt.highest_prio().is_some() || self.normal.tick(task, runtime)
+        }
+    }
+
+    fn yield_task(&mut self, task: TaskId) {
+        if !self.rt.contains(task) {
+            self.normal.yield_task(task);
+        }
+    }
+
+    fn set_nice(&mut self, task: TaskId, nice: i32) {
+        if let Some(old) = self.nice.get_mut(&task) {
+            *old = nice;
+            if !self.rt.contains(task) {
+                self.normal.set_nice(task, nice);
+            }
+        }
+    }
+
+    fn policy(&self, task: TaskId) -> SchedPolicy {
+        self.rt.policy(task).unwrap_or(SchedPolicy::Normal)
+    }
+
+    /// Take `task` which is not runnable in the queue, to move it to another CPU.
+    /// Return its nice value and policy.
+    fn take(&mut self, task: TaskId) -> Option<(i32, SchedPolicy)> {
+        let nice = self.nice.remove(&task)?;
+        let policy = self.policy(task);
+        if !self.rt.remove(task) {
+            self.normal.remove(task);
+        }
+        Some((nice, policy))
+    }
+
+    /// Put `task` taken from another CPU
+    fn insert(&mut self, task: TaskId, nice: i32, policy: SchedPolicy) {
+        self.add(task, nice);
+        self.set_policy(task, policy);
+    }
+
+    /// Move `task` to the class of `policy`, runnable as it was
+    fn set_policy(&mut self, task: TaskId, policy: SchedPolicy) {
+        let nice = match self.nice.get(&task) {
+            Some(&nice) => nice,
+            None => return,
+        };
+        let rt = self.rt.contains(task);
+        if !rt && policy == SchedPolicy::Normal {
+            return;
+        }
+        let queued = if rt {
+            self.rt.remove(task)
+        } else {
+            self.normal.remove(task)
+        };
+        match policy {
+            SchedPolicy::Normal => self.normal.add(task, nice),
+            _ => self.rt.add(task, policy),
+        }
+        if queued {
+            self.enqueue(task);
+        }
+    }
+}
+
+lazy_static! {
+    /// The scheduler of each CPU
+    static ref SCHEDULERS: PerCpu<Mutex<Classes>> = {
+        let name = CMDLINE
+            .read()
+            .split_whitespace()
+            .find(|arg| arg.starts_with("sched="))
+            .map(|arg| String::from(&arg["sched=".len()..]));
+        let schedulers = PerCpu::new(|| {
+            Mutex::new(Classes {
+                rt: Rt::default(),
+                normal: new_scheduler(name.as_ref().map(String::as_str)),
+                nice: BTreeMap::new(),
+            })
+        });
+        let policy = schedulers.of(0).lock().normal.name();
+        if name.map_or(false, |name| name != policy) {
+            warn!("sched: unknown policy, use the default");
+        }
+        info!("sched: policy {}", policy);
+        schedulers
+    };
+}
+
+/// The policy `name` of the command line, or the default one of the cargo features
+fn new_scheduler(name: Option<&str>) -> Box<dyn Scheduler> {
+    match name {
+        Some("cfs") => Box::new(Cfs::default()),
+        Some("mlfq") => Box::new(Mlfq::default()),
+        Some("rr") => Box::new(RoundRobin::default()),
+        Some("stride") => Box::new(Stride::default()),
+        _ => default_scheduler(),
+    }
+}
+
+#[cfg(feature = "sched_rr")]
+fn default_scheduler() -> Box<dyn Scheduler> {
+    Box::new(RoundRobin::default())
+}
+
+#[cfg(all(feature = "sched_stride", not(feature = "sched_rr")))]
+fn default_scheduler() -> Box<dyn Scheduler> {
+    Box::new(Stride::default())
+}
+
+#[cfg(all(
+    feature = "sched_mlfq",
+    not(any(feature = "sched_rr", feature = "sched_stride"))
+))]
+fn default_scheduler() -> Box<dyn Scheduler> {
+    Box::new(Mlfq::default())
+}
+
+#[cfg(not(any(feature = "sched_rr", feature = "sched_stride", feature = "sched_mlfq")))]
+fn default_scheduler() -> Box<dyn Scheduler> {
+    Box::new(Cfs::default())
+}
+
+/// Weights of nice values from -20 to 19 as in Linux, each about 1.25 times the next
+const NICE_TO_WEIGHT: [u32; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// Weight of nice 0
+pub const NICE_0_WEIGHT: u64 = 1024;
+
+/// The share of the CPU of a task with `nice`, relative to the others
+pub fn nice_to_weight(nice: i32) -> u64 {
+    NICE_TO_WEIGHT[(nice.max(-20).min(19) + 20) as usize] as u64
+}
@@ -0,0 +1,84 @@
+//! Priority inheritance, lending the priority of tasks blocked on a lock to its owner
+//!
+//! The owner runs with the highest of its own policy and those lent by the locks it holds,
+//! so that a real-time task is not kept waiting by normal tasks preempting the owner.
+//! The priority is not passed on along a chain of owners blocked on each other.
+
+use super::executor;
+use super::{SchedPolicy, TaskId};
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::collections::BTreeMap;
+
+struct Inherited {
+    /// The policy set for the task itself
+    base: SchedPolicy,
+    /// The highest policy of the tasks waiting for each lock held, by the lock
+    lent: BTreeMap<usize, SchedPolicy>,
+}
+
+impl Inherited {
+    fn effective(&self) -> SchedPolicy {
+        self.lent.values().copied().fold(self.base, |max, policy| {
+            if policy.rank() > max.rank() {
+                policy
+            } else {
+                max
+            }
+        })
+    }
+}
+
+lazy_static! {
+    /// Tasks running with a lent priority
+    static ref INHERITED: Mutex<BTreeMap<TaskId, Inherited>> = Mutex::new(BTreeMap::new());
+}
+
+/// Lend `policy` of the highest waiter of `lock` to its owner `task`, replacing what it lent before
+pub fn inherit(task: TaskId, lock: usize, policy: SchedPolicy) {
+    let mut inherited = INHERITED.lock();
+    if !inherited.contains_key(&task) {
+        let base = match executor::policy(task) {
+            Some(base) => base,
+            None => return,
+        };
+        inherited.insert(
+            task,
+            Inherited {
+                base,
+                lent: BTreeMap::new(),
+            },
+        );
+    }
+    let entry = inherited.get_mut(&task).unwrap();
+    entry.lent.insert(lock, policy);
+    executor::set_policy(task, entry.effective());
+}
+
+/// Take back what `lock` lent to `task`, when it is released or nobody waits for it
+pub fn uninherit(task: TaskId, lock: usize) {
+    let mut inherited = INHERITED.lock();
+    let entry = match inherited.get_mut(&task) {
+        Some(entry) => entry,
+        None => return,
+    };
+    if entry.lent.remove(&lock).is_none() {
+        return;
+    }
+    executor::set_policy(task, entry.effective());
+    if entry.lent.is_empty() {
+        inherited.remove(&task);
+    }
+}
+
+/// Change the scheduling policy of the task `id`.
+/// While it runs with a lent priority, that is kept if higher.
+pub fn set_policy(id: TaskId, policy: SchedPolicy) {
+    let mut inherited = INHERITED.lock();
+    match inherited.get_mut(&id) {
+        Some(entry) => {
+            entry.base = policy;
+            executor::set_policy(id, entry.effective());
+        }
+        None => executor::set_policy(id, policy),
+    }
+}
@@ -0,0 +1,69 @@
+//! Kernel preemption at safe points
+//!
+//! Tasks are futures sharing the stack of the CPU, so a task is only switched out
+//! where it awaits. The timer interrupt marks the CPU when the task polled should give
+//! the CPU to others, in the kernel as well as in user, and the task yields at the next
+//! preemption point unless preemption is disabled on the CPU.
+//! Threads check the mark before returning to user, and long loops in the kernel
+//! call `cond_resched` between their steps.
+
+use crate::arch::cpu;
+use crate::process::yield_now;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+percpu! {
+    static ref NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+    /// Preemption is disabled on a CPU while its count is not 0
+    static ref PREEMPT_COUNT: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Mark that the task polled by this CPU should give the CPU to others
+pub fn set_need_resched() {
+    NEED_RESCHED.get().store(true, Ordering::SeqCst);
+}
+
+/// Clear the mark when a task is picked
+pub fn clear_need_resched(cpu_id: usize) {
+    NEED_RESCHED.of(cpu_id).store(false, Ordering::SeqCst);
+}
+
+pub fn need_resched() -> bool {
+    NEED_RESCHED.get().load(Ordering::SeqCst)
+}
+
+/// Disable preemption on this CPU until `preempt_enable`, nested.
+/// The task must not await in between, or it may go on another CPU.
+pub fn preempt_disable() {
+    PREEMPT_COUNT.get().fetch_add(1, Ordering::SeqCst);
+}
+
+pub fn preempt_enable() {
+    PREEMPT_COUNT.get().fetch_sub(1, Ordering::SeqCst);
+}
+
+pub fn preemptible() -> bool {
+    PREEMPT_COUNT.get().load(Ordering::SeqCst) == 0
+}
+
+/// Disable preemption on this CPU while it lives
+pub struct NoPreempt(());
+
+impl NoPreempt {
+    pub fn new() -> Self {
+        preempt_disable();
+        NoPreempt(())
+    }
+}
+
+impl Drop for NoPreempt {
+    fn drop(&mut self) {
+        preempt_enable();
+    }
+}
+
+/// A preemption point: give the CPU to others if the time of the task is over
+pub async fn cond_resched() {
+    if need_resched() && preemptible() {
+        yield_now().await;
+    }
+}
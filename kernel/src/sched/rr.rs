@@ -0,0 +1,31 @@
+//! Round-robin: the runnable tasks in a queue, each running a time slice in turn
+
+use super::{Scheduler, TaskId};
+use alloc::collections::VecDeque;
+
+#[derive(Default)]
+pub struct RoundRobin {
+    queue: VecDeque<TaskId>,
+}
+
+impl Scheduler for RoundRobin {
+    fn name(&self) -> &'static str {
+        "rr"
+    }
+
+    fn add(&mut self, _task: TaskId, _nice: i32) {}
+
+    fn remove(&mut self, task: TaskId) -> bool {
+        let len = self.queue.len();
+        self.queue.retain(|&t| t != task);
+        self.queue.len() != len
+    }
+
+    fn enqueue(&mut self, task: TaskId) {
+        self.queue.push_back(task);
+    }
+
+    fn pick_next(&mut self) -> Option<TaskId> {
+        self.queue.pop_front()
+    }
+}
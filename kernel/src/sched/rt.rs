@@ -0,0 +1,146 @@
+//! Real-time class of SCHED_FIFO and SCHED_RR, above the policy of the normal tasks
+//!
+//! The runnable task of the highest static priority runs first. A SCHED_FIFO task runs
+//! until it gives up the CPU or one of a higher priority is runnable, and a SCHED_RR task
+//! also gives the CPU to those of the same priority every `RR_INTERVAL`.
+//! A task preempted by a higher priority stays at the head of its list, as in Linux.
+
+use super::{SchedPolicy, TaskId, TIME_SLICE};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// Static priorities of real-time tasks are 1..=99
+pub const MAX_RT_PRIO: u8 = 99;
+pub const MIN_RT_PRIO: u8 = 1;
+
+/// Time a SCHED_RR task runs before the others of the same priority
+pub const RR_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Rt {
+    /// Runnable tasks by priority
+    queues: Vec<VecDeque<TaskId>>,
+    tasks: BTreeMap<TaskId, RtTask>,
+}
+
+struct RtTask {
+    prio: u8,
+    round_robin: bool,
+    /// Time run since it took the CPU from those of the same priority
+    used: Duration,
+    queued: bool,
+    /// Preempted by a higher priority, to go back to the head of its list
+    preempted: bool,
+}
+
+impl Default for Rt {
+    fn default() -> Self {
+        Rt {
+            queues: (0..=MAX_RT_PRIO).map(|_| VecDeque::new()).collect(),
+            tasks: BTreeMap::new(),
+        }
+    }
+}
+
+impl Rt {
+    pub fn contains(&self, task: TaskId) -> bool {
+        self.tasks.contains_key(&task)
+    }
+
+    pub fn policy(&self, task: TaskId) -> Option<SchedPolicy> {
+        let t = self.tasks.get(&task)?;
+        Some(if t.round_robin {
+            SchedPolicy::RoundRobin(t.prio)
+        } else {
+            SchedPolicy::Fifo(t.prio)
+        })
+    }
+
+    /// The highest priority of the runnable tasks, if any
+    pub fn highest_prio(&self) -> Option<u8> {
+        (MIN_RT_PRIO..=MAX_RT_PRIO)
+            .rev()
+            .find(|&prio| !self.queues[prio as usize].is_empty())
+    }
+
+    /// `task` joins the class with `policy`, which is not SCHED_NORMAL
+    pub fn add(&mut self, task: TaskId, policy: SchedPolicy) {
+        let (prio, round_robin) = match policy {
+            SchedPolicy::Fifo(prio) => (prio, false),
+            SchedPolicy::RoundRobin(prio) => (prio, true),
+            SchedPolicy::Normal => unreachable!(),
+        };
+        let t = RtTask {
+            prio: prio.max(MIN_RT_PRIO).min(MAX_RT_PRIO),
+            round_robin,
+            used: Duration::default(),
+            queued: false,
+            preempted: false,
+        };
+        self.tasks.insert(task, t);
+    }
+
+    /// `task` leaves the class, return whether it was runnable in the queue
+    pub fn remove(&mut self, task: TaskId) -> bool {
+        match self.tasks.remove(&task) {
+            Some(t) if t.queued => {
+                self.queues[t.prio as usize].retain(|&other| other != task);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn enqueue(&mut self, task: TaskId) {
+        let t = match self.tasks.get_mut(&task) {
+            Some(t) if !t.queued => t,
+            _ => return,
+        };
+        t.queued = true;
+        let queue = &mut self.queues[t.prio as usize];
+        if core::mem::replace(&mut t.preempted, false) {
+            queue.push_front(task);
+        } else {
+            queue.push_back(task);
+        }
+    }
+
+    pub fn pick_next(&mut self) -> Option<TaskId> {
+        let prio = self.highest_prio()?;
+        let task = self.queues[prio as usize].pop_front().unwrap();
+        self.tasks.get_mut(&task).unwrap().queued = false;
+        Some(task)
+    }
+
+    /// Real-time tasks are checked for preemption at every time slice
+    pub fn time_slice(&self, _task: TaskId) -> Duration {
+        TIME_SLICE
+    }
+
+    pub fn charge(&mut self, task: TaskId, runtime: Duration) {
+        if let Some(t) = self.tasks.get_mut(&task) {
+            t.used += runtime;
+            if t.used >= RR_INTERVAL {
+                t.used = Duration::default();
+            }
+        }
+    }
+
+    /// Whether `task` polled for `runtime` should give the CPU
+    /// to a task of a higher priority, or of the same one for SCHED_RR
+    pub fn tick(&mut self, task: TaskId, runtime: Duration) -> bool {
+        let highest = self.highest_prio();
+        let t = match self.tasks.get_mut(&task) {
+            Some(t) => t,
+            None => return false,
+        };
+        match highest {
+            Some(prio) if prio > t.prio => {
+                t.preempted = true;
+                true
+            }
+            Some(prio) if prio == t.prio => t.round_robin && t.used + runtime >= RR_INTERVAL,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! CPU usage statistics and the load average
+//!
+//! The time of each CPU is charged to what it did since it was last charged, at the
+//! switches between user and kernel, in and out of the idle loop and at the timer
+//! interrupt, so that it is exact even if the idle CPU does not tick. The load average
+//! is sampled every 5 seconds by the first CPU ticking after that, and the samples
+//! missed while all CPUs are idle count as no load, as in Linux.
+
+use super::executor::{nr_running, nr_tasks};
+use crate::arch::cpu;
+use crate::arch::timer::timer_now;
+use crate::consts::{MAX_CPU_NUM, USEC_PER_TICK};
+use crate::process::{online_cpus, PROCESSES};
+use crate::syscall::realtime;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+/// What the time of a CPU is spent on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuTime {
+    User = 0,
+    System = 1,
+    Idle = 2,
+    /// In the timer interrupt
+    Irq = 3,
+}
+
+#[derive(Default)]
+struct CpuStat {
+    /// Nanoseconds spent, by `CpuTime`
+    times: [AtomicU64; 4],
+    /// When it was last charged in `timer_now`
+    last: AtomicU64,
+}
+
+/// Bits of the fraction of the load average
+const FSHIFT: u32 = 11;
+const FIXED_1: u64 = 1 << FSHIFT;
+/// exp(-5s/1min), exp(-5s/5min) and exp(-5s/15min) in fixed point
+const EXP: [u64; 3] = [1884, 2014, 2037];
+const LOAD_FREQ: Duration = Duration::from_secs(5);
+/// Samples missed to decay at most, after which the load is about zero anyway
+const MAX_MISSED: u64 = 2048;
+
+percpu! {
+    static ref STATS: CpuStat = CpuStat::default();
+}
+
+lazy_static! {
+    /// 1, 5 and 15 minutes load average in fixed point
+    static ref LOADS: Vec<AtomicU64> = (0..3).map(|_| AtomicU64::new(0)).collect();
+}
+
+/// When the load is sampled next in ns of `timer_now`, 0 before the first tick
+static NEXT_SAMPLE: AtomicU64 = AtomicU64::new(0);
+
+/// Charge the time of this CPU since it was last charged to `kind`
+pub fn account(kind: CpuTime) {
+    let stat = STATS.get();
+    let now = timer_now().as_nanos() as u64;
+    let last = stat.last.swap(now, Ordering::Relaxed);
+    stat.times[kind as usize].fetch_add(now.saturating_sub(last), Ordering::Relaxed);
+}
+
+/// The time spent by CPU `cpu_id`, by `CpuTime`
+pub fn cpu_times(cpu_id: usize) -> [Duration; 4] {
+    let stat = STATS.of(cpu_id);
+    let mut times = [Duration::default(); 4];
+    for (time, ns) in times.iter_mut().zip(stat.times.iter()) {
+        *time = Duration::from_nanos(ns.load(Ordering::Relaxed));
+    }
+    times
+}
+
+/// Sample the tasks runnable or running into the load average, if it is time to.
+/// Called at the timer interrupt.
+pub fn sample_load() {
+    let now = timer_now().as_nanos() as u64;
+    let freq = LOAD_FREQ.as_nanos() as u64;
+    let next = NEXT_SAMPLE.load(Ordering::Relaxed);
+    if next == 0 {
+        NEXT_SAMPLE.compare_and_swap(0, now + freq, Ordering::Relaxed);
+        return;
+    }
+    if now < next {
+        return;
+    }
+    let periods = (now - next) / freq + 1;
+    if NEXT_SAMPLE.compare_and_swap(next, next + periods * freq, Ordering::Relaxed) != next {
+        // sampled by another CPU
+        return;
+    }
+    let active = nr_running() as u64 * FIXED_1;
+    for (load, exp) in LOADS.iter().zip(EXP.iter()) {
+        let mut value = load.load(Ordering::Relaxed);
+        // no load while the CPUs were all idle
+        for _ in 1..periods.min(MAX_MISSED) {
+            value = value * exp / FIXED_1;
+        }
+        value = (value * exp + active * (FIXED_1 - exp)) / FIXED_1;
+        load.store(value, Ordering::Relaxed);
+    }
+}
+
+/// The 1, 5 and 15 minutes load average, with `shift` bits of fraction
+pub fn loadavg(shift: u32) -> [u64; 3] {
+    let mut loads = [0; 3];
+    for (value, load) in loads.iter_mut().zip(LOADS.iter()) {
+        let load = load.load(Ordering::Relaxed);
+        *value = if shift >= FSHIFT {
+            load << (shift - FSHIFT)
+        } else {
+            load >> (FSHIFT - shift)
+        };
+    }
+    loads
+}
+
+/// The time of the CPUs, formatted like `/proc/stat`
+pub struct Stat {
+    /// The time of each online CPU by `CpuTime`
+    cpus: Vec<(usize, [Duration; 4])>,
+    /// When it booted in seconds since epoch
+    boot_time: u64,
+    running: usize,
+}
+
+impl Stat {
+    pub fn get() -> Self {
+        let online = online_cpus();
+        let max = MAX_CPU_NUM.min(size_of::<usize>() * 8);
+        let cpus = (0..max)
+            .filter(|&cpu_id| online & (1 << cpu_id) != 0)
+            .map(|cpu_id| (cpu_id, cpu_times(cpu_id)))
+            .collect();
+        Stat {
+            cpus,
+            boot_time: (realtime().checked_sub(timer_now()).unwrap_or_default()).as_secs(),
+            running: nr_running(),
+        }
+    }
+}
+
+/// Time in USER_HZ, the tick of the kernel
+fn clock_ticks(time: Duration) -> u64 {
+    (time.as_micros() / USEC_PER_TICK as u128) as u64
+}
+
+impl fmt::Display for Stat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = |f: &mut fmt::Formatter, name: &str, times: &[Duration; 4]| {
+            // user nice system idle iowait irq softirq steal guest guest_nice
+            writeln!(
+                f,
+                "{} {} 0 {} {} 0 {} 0 0 0 0",
+                name,
+                clock_ticks(times[CpuTime::User as usize]),
+                clock_ticks(times[CpuTime::System as usize]),
+                clock_ticks(times[CpuTime::Idle as usize]),
+                clock_ticks(times[CpuTime::Irq as usize]),
+            )
+        };
+        let mut total = [Duration::default(); 4];
+        for (_, times) in self.cpus.iter() {
+            for (sum, time) in total.iter_mut().zip(times.iter()) {
+                *sum += *time;
+            }
+        }
+        line(f, "cpu ", &total)?;
+        for (cpu_id, times) in self.cpus.iter() {
+            line(f, &format!("cpu{}", cpu_id), times)?;
+        }
+        writeln!(f, "btime {}", self.boot_time)?;
+        writeln!(f, "procs_running {}", self.running)?;
+        writeln!(f, "procs_blocked 0")
+    }
+}
+
+/// The load average, formatted like `/proc/loadavg`
+pub struct LoadAvg {
+    /// With 2 decimals
+    loads: [u64; 3],
+    running: usize,
+    tasks: usize,
+    last_pid: usize,
+}
+
+impl LoadAvg {
+    pub fn get() -> Self {
+        let mut loads = loadavg(FSHIFT);
+        for load in loads.iter_mut() {
+            // rounded as in Linux
+            *load = ((*load + FIXED_1 / 200) * 100) >> FSHIFT;
+        }
+        LoadAvg {
+            loads,
+            running: nr_running(),
+            tasks: nr_tasks(),
+            last_pid: PROCESSES.read().keys().next_back().copied().unwrap_or(0),
+        }
+    }
+}
+
+impl fmt::Display for LoadAvg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for load in self.loads.iter() {
+            write!(f, "{}.{:02} ", load / 100, load % 100)?;
+        }
+        writeln!(f, "{}/{} {}", self.running, self.tasks, self.last_pid)
+    }
+}
@@ -0,0 +1,65 @@
+//! Stride scheduling: each time a task is picked, its pass grows by a stride
+//! inversely proportional to its weight, and the task of the least pass is picked.
+//! So tasks are picked in proportion to the weights of their nice values.
+
+use super::{nice_to_weight, Scheduler, TaskId};
+use alloc::collections::{BTreeMap, BTreeSet};
+
+/// Stride of the weight 1
+const BIG_STRIDE: u64 = 1 << 32;
+
+#[derive(Default)]
+pub struct Stride {
+    tasks: BTreeMap<TaskId, StrideTask>,
+    /// Runnable tasks by pass
+    queue: BTreeSet<(u64, TaskId)>,
+    /// Pass of the task picked last, given to new tasks so that they do not take over
+    pass: u64,
+}
+
+struct StrideTask {
+    pass: u64,
+    stride: u64,
+}
+
+impl Scheduler for Stride {
+    fn name(&self) -> &'static str {
+        "stride"
+    }
+
+    fn add(&mut self, task: TaskId, nice: i32) {
+        let stride = BIG_STRIDE / nice_to_weight(nice);
+        let pass = self.pass;
+        self.tasks.insert(task, StrideTask { pass, stride });
+    }
+
+    fn remove(&mut self, task: TaskId) -> bool {
+        match self.tasks.remove(&task) {
+            Some(t) => self.queue.remove(&(t.pass, task)),
+            None => false,
+        }
+    }
+
+    fn enqueue(&mut self, task: TaskId) {
+        if let Some(t) = self.tasks.get_mut(&task) {
+            // a task waiting long does not make up for the time all at once
+            t.pass = t.pass.max(self.pass);
+            self.queue.insert((t.pass, task));
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<TaskId> {
+        let &(pass, task) = self.queue.iter().next()?;
+        self.queue.remove(&(pass, task));
+        self.pass = pass;
+        let t = self.tasks.get_mut(&task).unwrap();
+        t.pass += t.stride;
+        Some(task)
+    }
+
+    fn set_nice(&mut self, task: TaskId, nice: i32) {
+        if let Some(t) = self.tasks.get_mut(&task) {
+            t.stride = BIG_STRIDE / nice_to_weight(nice);
+        }
+    }
+}
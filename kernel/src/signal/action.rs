@@ -17,6 +17,25 @@ pub const SI_QUEUE: i32 = -1;
 pub const SI_USER: i32 = 0;
 /// from user
 pub const SI_KERNEL: i32 = 128;
+
+/// si_code of SIGCHLD
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+
+/// si_code of signals for faults
+pub const ILL_ILLOPC: i32 = 1;
+pub const ILL_ILLOPN: i32 = 2;
+pub const ILL_COPROC: i32 = 7;
+pub const FPE_INTDIV: i32 = 1;
+pub const FPE_INTOVF: i32 = 2;
+pub const FPE_FLTINV: i32 = 7;
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+pub const BUS_ADRALN: i32 = 1;
+pub const BUS_ADRERR: i32 = 2;
 /// from kernel
 
 // yet there's a bug because of mismatching bits: https://sourceware.org/bugzilla/show_bug.cgi?id=25657
@@ -31,6 +50,10 @@ impl Sigset {
         Sigset(0)
     }
 
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
     pub fn contains(&self, sig: Signal) -> bool {
         (self.0 >> sig as u64 & 1) != 0
     }
@@ -47,6 +70,14 @@ impl Sigset {
     pub fn remove_set(&mut self, sigset: &Sigset) {
         self.0 ^= self.0 & sigset.0;
     }
+    pub fn intersect_set(&mut self, sigset: &Sigset) {
+        self.0 &= sigset.0;
+    }
+    /// Remove SIGKILL and SIGSTOP, which cannot be blocked
+    pub fn remove_unblockable(&mut self) {
+        self.remove(Signal::SIGKILL);
+        self.remove(Signal::SIGSTOP);
+    }
 }
 
 /// Linux struct sigaction
@@ -79,6 +110,63 @@ pub union SiginfoFields {
 
 impl SiginfoFields {
     const PAD_SIZE: usize = 128 - 2 * core::mem::size_of::<i32>() - core::mem::size_of::<usize>();
+
+    /// The union comes after padding to the alignment of pointers in Linux
+    const OFFSET: usize = core::mem::size_of::<usize>() - core::mem::size_of::<i32>();
+
+    /// Fields of signals sent by kill: pid and real user id of the sender
+    pub fn kill(pid: usize, uid: usize) -> Self {
+        let mut pad = [0u8; Self::PAD_SIZE];
+        let offset = Self::OFFSET;
+        pad[offset..offset + 4].copy_from_slice(&(pid as i32).to_ne_bytes());
+        pad[offset + 4..offset + 8].copy_from_slice(&(uid as u32).to_ne_bytes());
+        SiginfoFields { pad }
+    }
+
+    /// Fields of signals for faults: the address of the fault
+    pub fn fault(addr: usize) -> Self {
+        let mut pad = [0u8; Self::PAD_SIZE];
+        let offset = Self::OFFSET;
+        pad[offset..offset + core::mem::size_of::<usize>()].copy_from_slice(&addr.to_ne_bytes());
+        SiginfoFields { pad }
+    }
+
+    /// The 4 bytes at `offset` of the union
+    pub fn u32_at(&self, offset: usize) -> u32 {
+        let offset = Self::OFFSET + offset;
+        let mut bytes = [0u8; 4];
+        unsafe { bytes.copy_from_slice(&self.pad[offset..offset + 4]) };
+        u32::from_ne_bytes(bytes)
+    }
+
+    /// The 8 bytes at `offset` of the union
+    pub fn u64_at(&self, offset: usize) -> u64 {
+        let offset = Self::OFFSET + offset;
+        let mut bytes = [0u8; 8];
+        unsafe { bytes.copy_from_slice(&self.pad[offset..offset + 8]) };
+        u64::from_ne_bytes(bytes)
+    }
+
+    /// Fields of signals by POSIX timers: the timer id, the overrun and the value of sigevent
+    pub fn timer(id: usize, overrun: usize, value: usize) -> Self {
+        let mut fields = Self::kill(id, overrun);
+        let offset = Self::OFFSET + 8;
+        unsafe {
+            fields.pad[offset..offset + core::mem::size_of::<usize>()]
+                .copy_from_slice(&value.to_ne_bytes());
+        }
+        fields
+    }
+
+    /// Fields of SIGCHLD: pid and real user id of the child, and its exit code or signal
+    pub fn child(pid: usize, uid: usize, status: i32) -> Self {
+        let mut fields = Self::kill(pid, uid);
+        let offset = Self::OFFSET + 8;
+        unsafe {
+            fields.pad[offset..offset + 4].copy_from_slice(&status.to_ne_bytes());
+        }
+        fields
+    }
 }
 
 impl Default for SiginfoFields {
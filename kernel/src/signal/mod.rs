@@ -1,11 +1,19 @@
 use crate::arch::{
-    signal::{set_signal_handler, MachineContext, RET_CODE},
+    signal::{restart_syscall, set_signal_handler, MachineContext, RET_CODE},
     syscall::SYS_RT_SIGRETURN,
 };
-use crate::process::{process, process_of, Process, Thread};
+use crate::process::coredump::core_dump;
+use crate::process::ptrace::PtraceStop;
+use crate::process::{process, process_of, Process, Thread, THREADS};
 use crate::sync::{Event, MutexGuard, SpinNoIrq, SpinNoIrqLock as Mutex};
+use crate::syscall::SysError;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use bitflags::*;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
 use num::FromPrimitive;
 use trapframe::{TrapFrame, UserContext};
 
@@ -89,22 +97,320 @@ impl Signal {
     pub fn is_standard(self) -> bool {
         (self as usize) < Self::RTMIN
     }
+
+    /// Whether the signal stops the process by default
+    pub fn is_stop(self) -> bool {
+        self.default_action() == DefaultAction::Stop
+    }
+
+    /// What the signal does when its disposition is SIG_DFL, see signal(7)
+    pub fn default_action(self) -> DefaultAction {
+        use Signal::*;
+        match self {
+            SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU
+            | SIGXFSZ | SIGSYS => DefaultAction::Core,
+            SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ign,
+            SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+            SIGCONT => DefaultAction::Cont,
+            _ => DefaultAction::Term,
+        }
+    }
+}
+
+/// Default actions of signals
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub enum DefaultAction {
+    /// Terminate the process
+    Term,
+    /// Ignore the signal
+    Ign,
+    /// Terminate the process and dump core
+    Core,
+    /// Stop the process
+    Stop,
+    /// Continue the process if it is stopped
+    Cont,
 }
 
-// process and tid must be checked
-pub fn send_signal(process: Arc<Mutex<Process>>, tid: isize, info: Siginfo) {
+/// Most real-time signals queued in a process and its threads.
+/// Linux limits them for each user by RLIMIT_SIGPENDING instead.
+const MAX_QUEUED_SIGNALS: usize = 1024;
+
+/// Send a signal to the thread `tid` of `process`, or to any thread of it if `tid` is -1.
+/// process and tid must be checked.
+///
+/// A standard signal is pending at most once, while each real-time signal is queued
+/// with its siginfo. Return false if a real-time signal is dropped, as too many are queued.
+///
+/// SIGKILL and SIGCONT take effect at once: SIGKILL always goes to the whole process and
+/// wakes all threads, so that they are killed whatever they are waiting for, while SIGCONT
+/// continues the stopped process and discards pending stop signals, and the other way round.
+pub fn send_signal(process: Arc<Mutex<Process>>, tid: isize, info: Siginfo) -> bool {
     let signal: Signal = <Signal as FromPrimitive>::from_i32(info.signo).unwrap();
     let mut process = process.lock();
-    if signal.is_standard() && process.pending_sigset.contains(signal) {
-        return;
+    if process.zombie {
+        return true;
+    }
+    let threads = threads_of(&process);
+    if signal == Signal::SIGCONT {
+        if process.stopped {
+            process.stopped = false;
+            process.report_stop(None);
+        }
+        discard_signals(&mut process, &threads, Signal::is_stop);
+    } else if signal.is_stop() {
+        discard_signals(&mut process, &threads, |signal| signal == Signal::SIGCONT);
+    }
+
+    let target = if tid == -1 || signal == Signal::SIGKILL {
+        None
+    } else {
+        threads.iter().find(|thread| thread.tid == tid as usize)
+    };
+    // ignored signals are discarded, unless the tracer wants to see them, or they are
+    // blocked, as the disposition may change before they are unblocked, as in Linux
+    let blocked_by = |thread: &Arc<Thread>| is_blocked(&thread.inner.lock().sig_mask, signal);
+    let blocked = match target {
+        Some(thread) => blocked_by(thread),
+        None => !threads.is_empty() && threads.iter().all(blocked_by),
+    };
+    if is_ignored(&process, signal) && process.tracer.is_none() && !blocked {
+        return true;
+    }
+    if !signal.is_standard() {
+        let queued = process.sig_queue.len()
+            + threads
+                .iter()
+                .map(|thread| thread.inner.lock().sig_queue.len())
+                .sum::<usize>();
+        if queued >= MAX_QUEUED_SIGNALS {
+            return false;
+        }
+    }
+
+    match target {
+        Some(thread) => {
+            let mut inner = thread.inner.lock();
+            if signal.is_standard() && inner.pending_sigset.contains(signal) {
+                return true;
+            }
+            inner.sig_queue.push_back(info);
+            inner.pending_sigset.add(signal);
+        }
+        None => {
+            if signal.is_standard() && process.pending_sigset.contains(signal) {
+                return true;
+            }
+            process.sig_queue.push_back(info);
+            process.pending_sigset.add(signal);
+        }
     }
-    process.sig_queue.push_back((info, tid));
-    process.pending_sigset.add(signal);
-    process.eventbus.lock().set(Event::RECEIVE_SIGNAL);
+    // changed every time, so that those waiting for signals are woken up
+    let mut eventbus = process.eventbus.lock();
+    eventbus.clear(Event::RECEIVE_SIGNAL);
+    eventbus.set(Event::RECEIVE_SIGNAL);
+    drop(eventbus);
     info!(
         "send signal {} to pid {} tid {}",
         info.signo, process.pid, tid
-    )
+    );
+    drop(process);
+
+    if signal == Signal::SIGKILL || signal == Signal::SIGCONT {
+        for thread in threads {
+            thread.wake();
+        }
+    }
+    true
+}
+
+/// Whether `signal` does nothing to `process` when delivered
+pub fn is_ignored(process: &Process, signal: Signal) -> bool {
+    let handler = process.dispositions[signal as usize].handler;
+    handler == SIG_IGN
+        || (handler == SIG_DFL
+            && matches!(
+                signal.default_action(),
+                DefaultAction::Ign | DefaultAction::Cont
+            ))
+}
+
+/// Remove `signal` pending in `process` and its threads
+pub fn discard_pending(process: &mut Process, signal: Signal) {
+    let threads = threads_of(process);
+    discard_signals(process, &threads, |pending| pending == signal);
+}
+
+/// Send the signal of `info` for a fault of `thread`.
+/// It is unblocked and the default action is taken if ignored, or the thread would fault again.
+pub fn force_signal(thread: &Arc<Thread>, info: Siginfo) {
+    let signal: Signal = <Signal as FromPrimitive>::from_i32(info.signo).unwrap();
+    {
+        let mut process = thread.proc.lock();
+        let mut inner = thread.inner.lock();
+        let action = &mut process.dispositions[signal as usize];
+        if inner.sig_mask.contains(signal) || action.handler == SIG_IGN {
+            *action = SignalAction::default();
+            inner.sig_mask.remove(signal);
+        }
+    }
+    send_signal(thread.proc.clone(), thread.tid as isize, info);
+}
+
+fn threads_of(process: &Process) -> alloc::vec::Vec<Arc<Thread>> {
+    let table = THREADS.read();
+    process
+        .threads
+        .iter()
+        .filter_map(|tid| table.get(tid).cloned())
+        .collect()
+}
+
+/// Remove the pending signals matching `pred` from `process` and its `threads`
+fn discard_signals(process: &mut Process, threads: &[Arc<Thread>], pred: impl Fn(Signal) -> bool) {
+    let discarded = |info: &Siginfo| pred(<Signal as FromPrimitive>::from_i32(info.signo).unwrap());
+    process.sig_queue.retain(|info| !discarded(info));
+    process.pending_sigset = pending_set(process.sig_queue.iter());
+    for thread in threads {
+        let mut inner = thread.inner.lock();
+        inner.sig_queue.retain(|info| !discarded(info));
+        inner.pending_sigset = pending_set(inner.sig_queue.iter());
+    }
+}
+
+fn pending_set<'a>(queue: impl Iterator<Item = &'a Siginfo>) -> Sigset {
+    let mut set = Sigset::empty();
+    for info in queue {
+        set.add(<Signal as FromPrimitive>::from_i32(info.signo).unwrap());
+    }
+    set
+}
+
+/// Whether `signal` is blocked by `mask`. SIGKILL and SIGSTOP are never blocked.
+pub fn is_blocked(mask: &Sigset, signal: Signal) -> bool {
+    signal != Signal::SIGKILL && signal != Signal::SIGSTOP && mask.contains(signal)
+}
+
+/// Take the next signal to handle by `thread`.
+/// Signals sent to the thread come before those sent to the process.
+fn dequeue_signal(thread: &Thread, process: &mut Process) -> Option<Siginfo> {
+    let mask = thread.inner.lock().sig_mask;
+    dequeue_matching(thread, process, |signal| !is_blocked(&mask, signal))
+}
+
+/// Take the next signal in `set` pending for `thread`, whether blocked or not,
+/// as signalfd reads it
+pub fn take_signal(thread: &Thread, set: &Sigset) -> Option<Siginfo> {
+    let mut process = thread.proc.lock();
+    dequeue_matching(thread, &mut process, |signal| set.contains(signal))
+}
+
+/// Whether a signal in `set` is pending for `thread`
+pub fn has_signal_in(thread: &Thread, set: &Sigset) -> bool {
+    let process = thread.proc.lock();
+    let mut pending = thread.inner.lock().pending_sigset;
+    pending.add_set(&process.pending_sigset);
+    pending.intersect_set(set);
+    pending.bits() != 0
+}
+
+/// Take the next signal matching `pred` for `thread`, from the thread before the process
+fn dequeue_matching(
+    thread: &Thread,
+    process: &mut Process,
+    pred: impl Fn(Signal) -> bool,
+) -> Option<Siginfo> {
+    let mut inner = thread.inner.lock();
+    if let Some(info) = dequeue_from(&mut inner.sig_queue, &pred) {
+        inner.pending_sigset = pending_set(inner.sig_queue.iter());
+        return Some(info);
+    }
+    drop(inner);
+    let info = dequeue_from(&mut process.sig_queue, &pred)?;
+    process.pending_sigset = pending_set(process.sig_queue.iter());
+    Some(info)
+}
+
+/// Take the lowest numbered signal in `queue` matching `pred`,
+/// and the first queued one of it, as in Linux
+fn dequeue_from(queue: &mut VecDeque<Siginfo>, pred: impl Fn(Signal) -> bool) -> Option<Siginfo> {
+    let (idx, _) = queue
+        .iter()
+        .enumerate()
+        .filter(|(_, info)| pred(<Signal as FromPrimitive>::from_i32(info.signo).unwrap()))
+        .min_by_key(|(_, info)| info.signo)?;
+    queue.remove(idx)
+}
+
+/// Wait until one of `events` is set on the event bus of the process of `thread`,
+/// or the thread has a signal to handle. Return false if interrupted by a signal.
+pub fn wait_for_event_or_signal(thread: &Arc<Thread>, events: Event) -> impl Future<Output = bool> {
+    #[must_use = "future does nothing unless polled/`await`-ed"]
+    struct SignalFuture {
+        thread: Arc<Thread>,
+        events: Event,
+    }
+
+    impl Future for SignalFuture {
+        type Output = bool;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let eventbus = self.thread.proc.lock().eventbus.clone();
+            if self.thread.has_signal_to_handle() {
+                return Poll::Ready(false);
+            }
+            let mut bus = eventbus.lock();
+            if !(bus.events() & self.events).is_empty() {
+                return Poll::Ready(true);
+            }
+            let waker = cx.waker().clone();
+            let mask = self.events | Event::RECEIVE_SIGNAL;
+            bus.subscribe(Box::new(move |event| {
+                if (event & mask).is_empty() {
+                    return false;
+                }
+                waker.wake_by_ref();
+                true
+            }));
+            drop(bus);
+            // a signal may come before subscribing
+            if self.thread.has_signal_to_handle() {
+                return Poll::Ready(false);
+            }
+            Poll::Pending
+        }
+    }
+
+    SignalFuture {
+        thread: thread.clone(),
+        events,
+    }
+}
+
+/// Wait until the process of `thread`, stopped by a signal, is continued by SIGCONT.
+/// SIGKILL kills it meanwhile.
+pub fn wait_continue(thread: &Arc<Thread>) -> impl Future<Output = ()> {
+    #[must_use = "future does nothing unless polled/`await`-ed"]
+    struct ContinueFuture {
+        thread: Arc<Thread>,
+    }
+
+    impl Future for ContinueFuture {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if self.thread.proc.lock().stopped {
+                // woken up by SIGCONT through the waker of the thread
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        }
+    }
+
+    ContinueFuture {
+        thread: thread.clone(),
+    }
 }
 
 /// See musl struct __ucontext
@@ -128,145 +434,192 @@ pub struct SignalFrame {
     pub ret_code: [u8; 7],           // call sys_sigreturn
 }
 
+/// Where to put the signal frame below the stack top `sp`, which is aligned as the stack
+/// at the entry of a function. On x86_64 the red zone below `sp` is kept, and the return
+/// address, at the start of the frame, is pushed as if by a call.
+fn signal_frame_sp(sp: usize) -> usize {
+    let size = core::mem::size_of::<SignalFrame>();
+    if cfg!(target_arch = "x86_64") {
+        (sp.wrapping_sub(128 + size) & !0xf).wrapping_sub(8)
+    } else {
+        sp.wrapping_sub(size) & !0xf
+    }
+}
+
 /// return whether this thread exits
 pub fn handle_signal(thread: &Arc<Thread>, tf: &mut UserContext) -> bool {
     let mut process = thread.proc.lock();
-    while let Some((idx, info)) =
-        process
-            .sig_queue
-            .iter()
-            .enumerate()
-            .find_map(|(idx, &(info, tid))| {
-                if (tid == -1 || tid as usize == thread.tid)
-                    && !thread
-                        .inner
-                        .lock()
-                        .sig_mask
-                        .contains(FromPrimitive::from_i32(info.signo).unwrap())
-                {
-                    Some((idx, info))
-                } else {
-                    None
-                }
-            })
-    {
-        use crate::signal::SignalActionFlags;
-        use Signal::*;
-
+    while let Some(info) = dequeue_signal(thread, &mut process) {
         let signal: Signal = <Signal as FromPrimitive>::from_i32(info.signo).unwrap();
         info!(
             "process {} thread {} received signal: {:?}",
             process.pid, thread.tid, signal
         );
 
-        process.sig_queue.remove(idx);
-        process.pending_sigset.remove(signal);
-
-        let action = process.dispositions[info.signo as usize];
-        let action_flags = SignalActionFlags::from_bits_truncate(action.flags);
-
-        // enter signal handler
-        match action.handler {
-            // TODO: complete default actions
-            x if x == SIG_DFL => {
-                match signal {
-                    SIGALRM | SIGHUP | SIGINT => {
-                        info!("default action: Term");
-                        // TODO: exit code ref please?
-                        process.exit(info.signo as usize + 128);
-                        return true;
-                    }
-                    _ => (),
+        // a traced process stops for its tracer instead, which decides what to deliver
+        if process.tracer.is_some() && signal != Signal::SIGKILL {
+            info!("stop for tracer");
+            thread.inner.lock().ptrace_stop = Some(PtraceStop::new(info));
+            return false;
+        }
+        if deliver(thread, &mut process, tf, info) {
+            return true;
+        }
+        // the thread waits for SIGCONT before going on with other signals
+        if process.stopped {
+            return false;
+        }
+    }
+    // no handler runs, so the interrupted syscall goes on, with the mask before sigsuspend or ppoll
+    let mut inner = thread.inner.lock();
+    if let Some((num, args)) = inner.restart_syscall.take() {
+        restart_syscall(tf, num, &args);
+    }
+    if let Some(mask) = inner.saved_sig_mask.take() {
+        inner.sig_mask = mask;
+    }
+    return false;
+}
+
+/// Deliver the signal of `info` to `thread` at once, e.g. the one given by a tracer.
+/// Return whether this thread exits.
+pub fn deliver_signal(thread: &Arc<Thread>, tf: &mut UserContext, info: Siginfo) -> bool {
+    let mut process = thread.proc.lock();
+    deliver(thread, &mut process, tf, info)
+}
+
+fn deliver(
+    thread: &Arc<Thread>,
+    process: &mut Process,
+    tf: &mut UserContext,
+    info: Siginfo,
+) -> bool {
+    use crate::signal::SignalActionFlags;
+    use Signal::*;
+
+    let signal: Signal = <Signal as FromPrimitive>::from_i32(info.signo).unwrap();
+    let action = process.dispositions[info.signo as usize];
+    let action_flags = SignalActionFlags::from_bits_truncate(action.flags);
+
+    // enter signal handler
+    match action.handler {
+        x if x == SIG_DFL || signal == SIGKILL || signal == SIGSTOP => {
+            match signal.default_action() {
+                DefaultAction::Term => {
+                    info!("default action: Term");
+                    process.exit_by_signal(signal, false);
+                    return true;
                 }
+                DefaultAction::Core => {
+                    info!("default action: Core");
+                    let core_dumped = core_dump(&process, thread, tf, signal);
+                    process.exit_by_signal(signal, core_dumped);
+                    return true;
+                }
+                DefaultAction::Stop => {
+                    // all threads stop when they are about to return to user
+                    info!("default action: Stop");
+                    process.stopped = true;
+                    process.report_stop(Some(signal));
+                }
+                // SIGCONT has taken effect when sent
+                DefaultAction::Ign | DefaultAction::Cont => info!("default action: Ign"),
             }
-            x if x == SIG_IGN => {
-                // TODO: handle SIGCHLD
-                info!("ignore");
-            }
-            x if x == SIG_ERR => {
-                // TODO
-                unimplemented!();
+        }
+        x if x == SIG_IGN => {
+            // TODO: handle SIGCHLD
+            info!("ignore");
+        }
+        _ => {
+            info!("goto handler at {:#x}", action.handler);
+
+            // SA_RESETHAND: the handler runs only once
+            if action_flags.contains(SignalActionFlags::RESETHAND) {
+                process.dispositions[signal as usize] = SignalAction::default();
             }
-            _ => {
-                info!("goto handler at {:#x}", action.handler);
 
-                // save original sig mask
-                let mut inner = thread.inner.lock();
-                let sig_mask = inner.sig_mask;
+            // save original sig mask, which is the one before sigsuspend or ppoll if any
+            let mut inner = thread.inner.lock();
+            let sig_mask = inner.saved_sig_mask.take().unwrap_or(inner.sig_mask);
 
-                // update sig mask (see man sigaction(2))
-                // 1. block current
-                // 2. block mask in disposition
+            // update sig mask (see man sigaction(2))
+            // 1. block current, unless SA_NODEFER
+            // 2. block mask in disposition
+            if !action_flags.contains(SignalActionFlags::NODEFER) {
                 inner.sig_mask.add(signal);
-                inner.sig_mask.add_set(&action.mask);
-
-                // save original signal alternate stack
-                let stack = inner.signal_alternate_stack;
-                drop(inner);
-
-                let sig_sp = {
-                    // use signal alternate stack when SA_ONSTACK is set
-                    // fallback to default stack when unavailable
-                    // man sigaction(2)
-                    if action_flags.contains(SignalActionFlags::ONSTACK) {
-                        let stack_flags = SignalStackFlags::from_bits_truncate(stack.flags);
-                        if stack_flags.contains(SignalStackFlags::DISABLE) {
-                            tf.get_sp()
-                        } else {
-                            let mut inner = thread.inner.lock();
-                            inner.signal_alternate_stack.flags |= SignalStackFlags::ONSTACK.bits();
-
-                            // handle auto disarm
-                            if stack_flags.contains(SignalStackFlags::AUTODISARM) {
-                                inner.signal_alternate_stack.flags |=
-                                    SignalStackFlags::DISABLE.bits();
-                            }
-
-                            // top of stack
-                            stack.sp + stack.size
-                        }
-                    } else {
-                        tf.get_sp()
+            }
+            inner.sig_mask.add_set(&action.mask);
+
+            // the interrupted syscall runs again after the handler if SA_RESTART,
+            // or returns EINTR to the interrupted code
+            if let Some((num, args)) = inner.restart_syscall.take() {
+                if action_flags.contains(SignalActionFlags::RESTART) {
+                    restart_syscall(tf, num, &args);
+                }
+            }
+
+            // use the signal alternate stack when SA_ONSTACK is set, unless it is disabled
+            // or already in use by a handler interrupted, see man sigaction(2)
+            let sp = tf.get_sp();
+            let stack = inner.signal_alternate_stack;
+            let sig_sp = signal_frame_sp(
+                if action_flags.contains(SignalActionFlags::ONSTACK)
+                    && !stack.is_disabled()
+                    && !stack.contains(sp)
+                {
+                    // SS_AUTODISARM: the handler may switch away, e.g. by swapcontext,
+                    // and set up the stack again, until sigreturn restores it
+                    if stack.is_auto_disarm() {
+                        inner.signal_alternate_stack = SignalStack::default();
                     }
-                } - core::mem::size_of::<SignalFrame>();
-
-                let frame = if let Ok(frame) = unsafe {
-                    process
-                        .vm
-                        .lock()
-                        .check_write_ptr(sig_sp as *mut SignalFrame)
-                } {
-                    frame
-                } else {
-                    unimplemented!()
-                };
-                frame.info = info;
-                frame.ucontext = SignalUserContext {
-                    flags: 0,
-                    link: 0,
-                    stack,
-                    context: MachineContext::from_tf(tf),
-                    sig_mask,
-                };
-                if action_flags.contains(SignalActionFlags::RESTORER) {
-                    frame.ret_code_addr = action.restorer; // legacy
+                    stack.sp + stack.size
                 } else {
-                    frame.ret_code_addr = frame.ret_code.as_ptr() as usize;
-                    // mov SYS_RT_SIGRETURN, %eax
-                    frame.ret_code.copy_from_slice(&RET_CODE);
-                }
-                set_signal_handler(
-                    tf,
-                    sig_sp,
-                    action.handler,
-                    info.signo as usize,
-                    &frame.info as *const Siginfo,
-                    &frame.ucontext as *const SignalUserContext,
-                );
+                    sp
+                },
+            );
+            drop(inner);
+
+            let frame = if let Ok(frame) = unsafe {
+                process
+                    .vm
+                    .lock()
+                    .check_write_ptr(sig_sp as *mut SignalFrame)
+            } {
+                frame
+            } else {
+                // no room for the frame on the stack
+                warn!("bad signal frame at {:#x}, kill with SIGSEGV", sig_sp);
+                let core_dumped = core_dump(&process, thread, tf, SIGSEGV);
+                process.exit_by_signal(SIGSEGV, core_dumped);
+                return true;
+            };
+            frame.info = info;
+            frame.ucontext = SignalUserContext {
+                flags: 0,
+                link: 0,
+                stack: stack.status(sp),
+                context: MachineContext::from_tf(tf),
+                sig_mask,
+            };
+            if action_flags.contains(SignalActionFlags::RESTORER) {
+                frame.ret_code_addr = action.restorer; // legacy
+            } else {
+                frame.ret_code_addr = frame.ret_code.as_ptr() as usize;
+                // mov SYS_RT_SIGRETURN, %eax
+                frame.ret_code.copy_from_slice(&RET_CODE);
             }
+            // three arguments are given, for handlers with SA_SIGINFO
+            set_signal_handler(
+                tf,
+                sig_sp,
+                action.handler,
+                info.signo as usize,
+                &frame.info as *const Siginfo,
+                &frame.ucontext as *const SignalUserContext,
+            );
         }
     }
-    return false;
+    false
 }
 
 bitflags! {
@@ -286,6 +639,54 @@ pub struct SignalStack {
     pub size: usize,
 }
 
+/// Minimal size of a signal alternate stack
+const MINSIGSTKSZ: usize = 2048;
+
+impl SignalStack {
+    /// The stack to set from the one given by user, as sigaltstack does.
+    /// SS_ONSTACK is taken as 0 for old programs, as in Linux.
+    pub fn checked(&self) -> Result<SignalStack, SysError> {
+        let flags = SignalStackFlags::from_bits(self.flags).ok_or(SysError::EINVAL)?;
+        if flags.contains(SignalStackFlags::ONSTACK | SignalStackFlags::DISABLE) {
+            return Err(SysError::EINVAL);
+        }
+        if flags.contains(SignalStackFlags::DISABLE) {
+            return Ok(SignalStack::default());
+        }
+        if self.size < MINSIGSTKSZ {
+            return Err(SysError::ENOMEM);
+        }
+        Ok(SignalStack {
+            sp: self.sp,
+            flags: (flags & SignalStackFlags::AUTODISARM).bits(),
+            size: self.size,
+        })
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.flags & SignalStackFlags::DISABLE.bits() != 0
+    }
+
+    pub fn is_auto_disarm(&self) -> bool {
+        self.flags & SignalStackFlags::AUTODISARM.bits() != 0
+    }
+
+    /// Whether a thread running at `sp` is on the stack.
+    /// It is never so with SS_AUTODISARM, as the stack is disarmed while in use.
+    pub fn contains(&self, sp: usize) -> bool {
+        !self.is_disabled() && !self.is_auto_disarm() && sp > self.sp && sp - self.sp <= self.size
+    }
+
+    /// The stack as seen by a thread running at `sp`, with SS_ONSTACK if it is on it
+    pub fn status(&self, sp: usize) -> SignalStack {
+        let mut stack = *self;
+        if self.contains(sp) {
+            stack.flags |= SignalStackFlags::ONSTACK.bits();
+        }
+        stack
+    }
+}
+
 impl Default for SignalStack {
     fn default() -> Self {
         // default to disabled
@@ -1,22 +1,17 @@
 use super::*;
 use crate::consts::{INFORM_PER_MSEC, USEC_PER_TICK};
-use crate::process::{Process, Thread};
+use crate::process::Thread;
 use crate::syscall::TimeSpec;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-
-pub struct RegisteredProcess {
-    proc: Arc<SpinNoIrqLock<Process>>,
-    tid: usize,
-    epfd: usize,
-    fd: usize,
-}
+use core::task::Waker;
 
 #[derive(Default)]
 pub struct Condvar {
     wait_queue: SpinNoIrqLock<VecDeque<Arc<Thread>>>,
-    pub epoll_queue: SpinNoIrqLock<VecDeque<RegisteredProcess>>,
+    /// async tasks waiting for this condvar to be notified
+    waker_queue: SpinNoIrqLock<VecDeque<Waker>>,
 }
 
 impl Condvar {
@@ -136,10 +131,32 @@ impl Condvar {
         }
     }
 
+    /// Wake up `waker` the next time this condvar is notified.
+    pub fn register_waker(&self, waker: Waker) {
+        self.waker_queue.lock().push_back(waker);
+    }
+
+    /// Forget `waker` registered by `register_waker`, unless it has been woken up.
+    pub fn unregister_waker(&self, waker: &Waker) {
+        self.waker_queue
+            .lock()
+            .retain(|other| !other.will_wake(waker));
+    }
+
+    fn wake_n(&self, n: usize) {
+        let mut queue = self.waker_queue.lock();
+        for _ in 0..n {
+            match queue.pop_front() {
+                Some(waker) => waker.wake(),
+                None => break,
+            }
+        }
+    }
+
     pub fn notify_one(&self) {
+        self.wake_n(1);
         let mut queue = self.wait_queue.lock();
         if let Some(t) = queue.front() {
-            self.epoll_callback(t);
             // info!("nofity thread: {}", t.id());
             //t.unpark();
             queue.pop_front();
@@ -147,9 +164,9 @@ impl Condvar {
     }
 
     pub fn notify_all(&self) {
+        self.wake_n(usize::max_value());
         let mut queue = self.wait_queue.lock();
         for t in queue.iter() {
-            self.epoll_callback(t);
             //t.unpark();
         }
         queue.clear();
@@ -158,13 +175,13 @@ impl Condvar {
     /// Notify up to `n` waiters.
     /// Return the number of waiters that were woken up.
     pub fn notify_n(&self, n: usize) -> usize {
+        self.wake_n(n);
         let mut count = 0;
         let mut queue = self.wait_queue.lock();
         for t in queue.iter() {
             if count >= n {
                 break;
             }
-            self.epoll_callback(t);
             //t.unpark();
             count += 1;
         }
@@ -173,52 +190,4 @@ impl Condvar {
         }
         count
     }
-
-    pub fn register_epoll_list(
-        &self,
-        proc: Arc<SpinNoIrqLock<Process>>,
-        tid: usize,
-        epfd: usize,
-        fd: usize,
-    ) {
-        self.epoll_queue.lock().push_back(RegisteredProcess {
-            proc: proc,
-            tid: tid,
-            epfd: epfd,
-            fd: fd,
-        });
-    }
-
-    pub fn unregister_epoll_list(&self, tid: usize, epfd: usize, fd: usize) -> bool {
-        let mut epoll_list = self.epoll_queue.lock();
-        for idx in 0..epoll_list.len() {
-            if epoll_list[idx].tid == tid
-                && epoll_list[idx].epfd == epfd
-                && epoll_list[idx].fd == fd
-            {
-                epoll_list.remove(idx);
-                return true;
-            }
-        }
-        return false;
-    }
-
-    fn epoll_callback(&self, thread: &Arc<Thread>) {
-        let epoll_list = self.epoll_queue.lock();
-        for ist in epoll_list.iter() {
-            //if thread.id() == ist.tid {
-            if true {
-                let proc = ist.proc.lock();
-                match proc.get_epoll_instance(ist.epfd) {
-                    Ok(instacne) => {
-                        let mut ready_list = instacne.ready_list.lock();
-                        ready_list.insert(ist.fd);
-                    }
-                    Err(_) => {
-                        panic!("epoll instance not exist");
-                    }
-                }
-            }
-        }
-    }
 }
@@ -21,6 +21,7 @@ bitflags! {
         const PROCESS_QUIT                  = 1 << 10;
         const CHILD_PROCESS_QUIT            = 1 << 11;
         const RECEIVE_SIGNAL                = 1 << 12;
+        const VFORK_DONE                    = 1 << 13;
 
         /// Semaphore
         const SEMAPHORE_REMOVED             = 1 << 20;
@@ -33,7 +34,9 @@ pub type EventHandler = Box<dyn Fn(Event) -> bool + Send>;
 #[derive(Default)]
 pub struct EventBus {
     event: Event,
-    callbacks: Vec<EventHandler>,
+    /// Callbacks with the ids returned by `subscribe`
+    callbacks: Vec<(usize, EventHandler)>,
+    next_id: usize,
 }
 
 impl EventBus {
@@ -41,6 +44,11 @@ impl EventBus {
         Arc::new(Mutex::new(Self::default()))
     }
 
+    /// The events set now
+    pub fn events(&self) -> Event {
+        self.event
+    }
+
     pub fn set(&mut self, set: Event) {
         self.change(Event::empty(), set);
     }
@@ -56,12 +64,22 @@ impl EventBus {
         new.insert(set);
         self.event = new;
         if new != orig {
-            self.callbacks.retain(|f| !f(new));
+            self.callbacks.retain(|(_, f)| !f(new));
         }
     }
 
-    pub fn subscribe(&mut self, callback: EventHandler) {
-        self.callbacks.push(callback);
+    /// Call `callback` whenever the events change, until it returns true.
+    /// Return the id to unsubscribe it.
+    pub fn subscribe(&mut self, callback: EventHandler) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.callbacks.push((id, callback));
+        id
+    }
+
+    /// Remove the callback `id`, unless it has been removed by returning true
+    pub fn unsubscribe(&mut self, id: usize) {
+        self.callbacks.retain(|&(other, _)| other != id);
     }
 
     pub fn get_callback_len(&self) -> usize {
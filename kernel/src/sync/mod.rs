@@ -13,6 +13,10 @@
 //! * `condvar`: 条件变量。
 //!     依赖`thread`，为其它工具提供线程调度支持。
 //!
+//! * `pi_mutex`: 优先级继承的睡眠锁。
+//!     等待者睡眠而不忙等，按实时优先级获得锁；有实时任务等待时，持有者继承其优先级，
+//!     适用于持有时间较长的锁，避免无界的优先级反转。
+//!
 //! * `semaphore`: 信号量。
 //!     完全照搬`std::sync::Semaphore`，std中已经废弃。
 //!     貌似在Rust中并不常用，一般都用`Mutex`。
@@ -50,9 +54,11 @@
 pub use self::condvar::*;
 pub use self::event_bus::*;
 pub use self::mutex::*;
+pub use self::pi_mutex::*;
 pub use self::semaphore::*;
 
 mod condvar;
 mod event_bus;
 mod mutex;
+mod pi_mutex;
 mod semaphore;
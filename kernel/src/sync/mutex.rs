@@ -297,6 +297,9 @@ impl MutexSupport for SpinNoIrq {
         SpinNoIrq
     }
     fn cpu_relax(&self) {
+        // the CPU holding the lock may be waiting for this one to answer an IPI
+        #[cfg(target_arch = "x86_64")]
+        crate::arch::ipi::poll();
         core::sync::atomic::spin_loop_hint();
     }
     fn before_lock() -> Self::GuardData {
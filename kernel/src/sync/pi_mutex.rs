@@ -0,0 +1,221 @@
+//! A sleeping mutex with priority inheritance, for locks held for long
+//!
+//! The tasks waiting for it sleep instead of spinning, and get it in the order of
+//! their real-time priority, first come first served for the same one. The lock is
+//! handed over to the next waiter when released, so that it is not taken by others
+//! in between. While a real-time task waits, the owner runs with its priority, so
+//! that it is not preempted by the tasks in between and the real-time task is
+//! blocked no longer than the critical section.
+
+use super::SpinNoIrqLock as Mutex;
+use crate::sched::{current_task, inherit, policy, uninherit, SchedPolicy, TaskId};
+use alloc::collections::BTreeMap;
+use core::cell::UnsafeCell;
+use core::cmp::Reverse;
+use core::future::Future;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+pub struct PiMutex<T> {
+    state: Mutex<PiState>,
+    data: UnsafeCell<T>,
+}
+
+#[derive(Default)]
+struct PiState {
+    locked: bool,
+    /// The task holding it, None if not held by a task
+    owner: Option<TaskId>,
+    /// Tasks waiting, by their priority from the highest and the order they come
+    waiters: BTreeMap<(Reverse<u8>, usize), Waiter>,
+    seq: usize,
+}
+
+struct Waiter {
+    task: Option<TaskId>,
+    policy: SchedPolicy,
+    waker: Waker,
+}
+
+impl PiState {
+    /// Lend the priority of the highest waiter to the owner, or take it back if none
+    fn lend(&self, lock: usize) {
+        if let Some(owner) = self.owner {
+            match self.waiters.values().next() {
+                Some(waiter) if waiter.policy != SchedPolicy::Normal => {
+                    inherit(owner, lock, waiter.policy)
+                }
+                _ => uninherit(owner, lock),
+            }
+        }
+    }
+}
+
+/// A guard to which the protected data can be accessed
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct PiMutexGuard<'a, T> {
+    mutex: &'a PiMutex<T>,
+}
+
+// Same unsafe impls as `std::sync::Mutex`
+unsafe impl<T: Send> Sync for PiMutex<T> {}
+
+unsafe impl<T: Send> Send for PiMutex<T> {}
+
+impl<T> PiMutex<T> {
+    pub fn new(data: T) -> Self {
+        PiMutex {
+            state: Mutex::new(PiState::default()),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes this mutex, returning the underlying data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    /// Returns a mutable reference to the underlying data, as nobody else can hold it.
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    /// Acquires the mutex, sleeping until it is handed over to the current task.
+    pub fn lock(&self) -> PiMutexFuture<T> {
+        PiMutexFuture {
+            mutex: self,
+            ticket: None,
+            done: false,
+        }
+    }
+
+    /// Tries to acquire the mutex without sleeping.
+    pub fn try_lock(&self) -> Option<PiMutexGuard<T>> {
+        let mut state = self.state.lock();
+        if state.locked {
+            return None;
+        }
+        state.locked = true;
+        state.owner = current_task();
+        Some(PiMutexGuard { mutex: self })
+    }
+
+    /// The key of what it lends, unique while it is locked
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// Hand it over to the highest waiter, or release it if none
+    fn unlock(&self) {
+        let id = self.id();
+        let mut state = self.state.lock();
+        if let Some(owner) = state.owner {
+            uninherit(owner, id);
+        }
+        let next = state.waiters.keys().next().copied();
+        match next {
+            Some(key) => {
+                let waiter = state.waiters.remove(&key).unwrap();
+                state.owner = waiter.task;
+                waiter.waker.wake();
+                state.lend(id);
+            }
+            None => {
+                state.locked = false;
+                state.owner = None;
+            }
+        }
+    }
+}
+
+#[must_use = "future does nothing unless polled/`await`-ed"]
+pub struct PiMutexFuture<'a, T> {
+    mutex: &'a PiMutex<T>,
+    /// The place in the waiters once it waits
+    ticket: Option<(Reverse<u8>, usize)>,
+    /// Whether it has got the lock
+    done: bool,
+}
+
+impl<'a, T> Future for PiMutexFuture<'a, T> {
+    type Output = PiMutexGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+        let mut state = mutex.state.lock();
+        match self.ticket {
+            Some(key) => match state.waiters.get_mut(&key) {
+                Some(waiter) => {
+                    if !waiter.waker.will_wake(cx.waker()) {
+                        waiter.waker = cx.waker().clone();
+                    }
+                    return Poll::Pending;
+                }
+                // handed over by the owner
+                None => {}
+            },
+            None if !state.locked => {
+                state.locked = true;
+                state.owner = current_task();
+            }
+            None => {
+                let task = current_task();
+                let policy = task.and_then(policy).unwrap_or_default();
+                let key = (Reverse(policy.rank()), state.seq);
+                state.seq += 1;
+                state.waiters.insert(
+                    key,
+                    Waiter {
+                        task,
+                        policy,
+                        waker: cx.waker().clone(),
+                    },
+                );
+                state.lend(mutex.id());
+                self.ticket = Some(key);
+                return Poll::Pending;
+            }
+        }
+        self.done = true;
+        Poll::Ready(PiMutexGuard { mutex })
+    }
+}
+
+impl<'a, T> Drop for PiMutexFuture<'a, T> {
+    /// Stop waiting, or pass on the lock if it is handed over but not taken
+    fn drop(&mut self) {
+        let key = match self.ticket {
+            Some(key) if !self.done => key,
+            _ => return,
+        };
+        let mut state = self.mutex.state.lock();
+        if state.waiters.remove(&key).is_some() {
+            state.lend(self.mutex.id());
+        } else {
+            drop(state);
+            self.mutex.unlock();
+        }
+    }
+}
+
+impl<'a, T> Deref for PiMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for PiMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for PiMutexGuard<'a, T> {
+    /// The dropping of the guard will release the lock it was created from.
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
@@ -2,30 +2,33 @@
 
 #![allow(dead_code)]
 
-use core::cell::UnsafeCell;
 use core::cmp::min;
 use core::mem::size_of;
 #[cfg(not(target_arch = "mips"))]
-use rcore_fs::vfs::Timespec;
+use rcore_fs::vfs::{FsInfo, Timespec};
 
+use crate::arch::timer::timer_now;
 use crate::drivers::SOCKET_ACTIVITY;
 use crate::fs::*;
-use crate::memory::MemorySet;
-use crate::sync::Condvar;
-use crate::trap::TICK_ACTIVITY;
+use crate::memory::{MemInfo, MemorySet};
 use alloc::boxed::Box;
 use core::future::Future;
 use core::pin::Pin;
-use core::task::{Context, Poll};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
 
 use bitvec::prelude::{BitSlice, BitVec, Lsb0};
 
 use super::*;
-use crate::fs::epoll::EpollInstance;
-use crate::fs::fcntl::{FD_CLOEXEC, F_SETFD, O_CLOEXEC, O_NONBLOCK};
+use crate::fs::epoll::{EPollCtlOp, EpollInstance};
+use crate::fs::fcntl::{FD_CLOEXEC, F_GETFL, F_SETFD, F_SETFL, O_CLOEXEC, O_NONBLOCK};
 use crate::fs::FileLike;
 use crate::process::Process;
+use crate::sched::stat::{LoadAvg, Stat};
+use crate::signal::{send_signal, Siginfo, SI_USER};
+use crate::sync::EventBus;
 use crate::syscall::SysError::{EINTR, EINVAL, ESPIPE};
+use crate::timer::TimerId;
 use rcore_fs::vfs::PollStatus;
 
 impl Syscall<'_> {
@@ -37,21 +40,41 @@ impl Syscall<'_> {
         }
         let slice = unsafe { self.vm().check_write_array(base.ptr(), len)? };
 
-        let file_like = proc.get_file_like(fd)?;
+        // the process is not kept locked while reading, which may block or lock it,
+        // e.g. signalfd
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
         let len = file_like.read(slice).await?;
         Ok(len)
     }
 
-    pub fn sys_write(&mut self, fd: usize, base: *const u8, len: usize) -> SysResult {
+    pub async fn sys_write(&mut self, fd: usize, base: *const u8, len: usize) -> SysResult {
         let mut proc = self.process();
         if !proc.pid.is_init() {
             //we trust pid 0 process
             info!("write: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
         }
         let slice = unsafe { self.vm().check_read_array(base, len)? };
-        let file_like = proc.get_file_like(fd)?;
-        let len = file_like.write(slice)?;
-        Ok(len)
+
+        // the process is not kept locked while writing, which may block
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
+        let res = file_like.write(slice).await;
+        self.check_broken_pipe(&res);
+        res
+    }
+
+    /// Raise SIGPIPE on this thread if a write failed as its pipe is broken
+    fn check_broken_pipe(&self, res: &SysResult) {
+        if let Err(SysError::EPIPE) = res {
+            let info = Siginfo {
+                signo: Signal::SIGPIPE as i32,
+                errno: 0,
+                code: SI_USER,
+                field: Default::default(),
+            };
+            send_signal(self.thread.proc.clone(), self.thread.tid as isize, info);
+        }
     }
 
     pub async fn sys_pread(
@@ -65,9 +88,16 @@ impl Syscall<'_> {
             "pread: fd: {}, base: {:?}, len: {}, offset: {}",
             fd, base, len, offset
         );
+        if (offset as isize) < 0 {
+            return Err(SysError::EINVAL);
+        }
         let mut proc = self.process();
         let slice = unsafe { self.vm().check_write_array(base.ptr(), len)? };
-        let len = proc.get_file(fd)?.read_at(offset, slice).await?;
+        let file = proc.get_file(fd)?;
+        if file.pipe {
+            return Err(SysError::ESPIPE);
+        }
+        let len = file.read_at(offset, slice).await?;
         Ok(len)
     }
 
@@ -82,12 +112,79 @@ impl Syscall<'_> {
             "pwrite: fd: {}, base: {:?}, len: {}, offset: {}",
             fd, base, len, offset
         );
+        if (offset as isize) < 0 {
+            return Err(SysError::EINVAL);
+        }
         let mut proc = self.process();
         let slice = unsafe { self.vm().check_read_array(base, len)? };
-        let len = proc.get_file(fd)?.write_at(offset, slice)?;
+        let file = proc.get_file(fd)?;
+        if file.pipe {
+            return Err(SysError::ESPIPE);
+        }
+        let res = file.write_at(offset, slice).map_err(SysError::from);
+        drop(proc);
+        self.check_broken_pipe(&res);
+        res
+    }
+
+    pub async fn sys_preadv(
+        &mut self,
+        fd: usize,
+        iov_ptr: UserInPtr<IoVec>,
+        iov_count: usize,
+        offset: usize,
+    ) -> SysResult {
+        info!(
+            "preadv: fd: {}, iov: {:?}, count: {}, offset: {}",
+            fd, iov_ptr, iov_count, offset
+        );
+        if (offset as isize) < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let mut proc = self.process();
+        let mut iovs =
+            unsafe { IoVecs::check_and_new(iov_ptr.ptr(), iov_count, &self.vm(), true)? };
+
+        let file = proc.get_file(fd)?;
+        if file.pipe {
+            return Err(SysError::ESPIPE);
+        }
+        let mut buf = iovs.new_buf(true);
+        let len = file.read_at(offset, buf.as_mut_slice()).await?;
+        iovs.write_all_from_slice(&buf[..len]);
         Ok(len)
     }
 
+    pub fn sys_pwritev(
+        &mut self,
+        fd: usize,
+        iov_ptr: *const IoVec,
+        iov_count: usize,
+        offset: usize,
+    ) -> SysResult {
+        info!(
+            "pwritev: fd: {}, iov: {:?}, count: {}, offset: {}",
+            fd, iov_ptr, iov_count, offset
+        );
+        if (offset as isize) < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let mut proc = self.process();
+        let iovs = unsafe { IoVecs::check_and_new(iov_ptr, iov_count, &self.vm(), false)? };
+
+        let file = proc.get_file(fd)?;
+        if file.pipe {
+            return Err(SysError::ESPIPE);
+        }
+        let buf = iovs.read_all_to_vec();
+        let res = file
+            .write_at(offset, buf.as_slice())
+            .map_err(SysError::from);
+        drop(proc);
+        self.check_broken_pipe(&res);
+        res
+    }
+
     /// sys_ppoll function is for handling the third argument of sys_poll.
 
     pub async fn sys_ppoll(
@@ -95,44 +192,57 @@ impl Syscall<'_> {
         ufds: UserInOutPtr<PollFd>,
         nfds: usize,
         timeout: UserInPtr<TimeSpec>,
+        sigmask: UserInPtr<Sigset>,
+        sigsetsize: usize,
     ) -> SysResult {
         let proc = self.process();
         if !proc.pid.is_init() {
             info!(
-                "ppoll: ufds: {:?} nfds: {}, timeout: {:?}",
-                ufds, nfds, timeout
+                "ppoll: ufds: {:?} nfds: {}, timeout: {:?}, sigmask: {:?}",
+                ufds, nfds, timeout, sigmask
             );
         }
-        let timeout_msecs = if timeout.is_null() {
-            1 << 31 // infinity
+        drop(proc);
+        let timeout = if timeout.is_null() {
+            None // infinity
         } else {
-            let timeout = timeout.read().unwrap();
-            timeout.to_msec()
+            Some(timeout.read()?.checked_duration().ok_or(EINVAL)?)
         };
-        drop(proc);
+        let sigmask = self.read_wait_sig_mask(sigmask, sigsetsize)?;
 
-        self.sys_poll(ufds, nfds, timeout_msecs as usize).await
+        self.replace_sig_mask(sigmask);
+        let res = self.poll_impl(ufds, nfds, timeout).await;
+        self.restore_sig_mask(&res);
+        res
     }
 
     pub async fn sys_poll(
         &mut self,
-        mut ufds: UserInOutPtr<PollFd>,
+        ufds: UserInOutPtr<PollFd>,
         nfds: usize,
-        timeout_msecs: usize,
+        timeout_msecs: i32,
     ) -> SysResult {
         let proc = self.process();
         if !proc.pid.is_init() {
             // we trust pid 0 process
             info!(
-                "poll: ufds: {:?}, nfds: {}, timeout_msecs: {:#x}",
+                "poll: ufds: {:?}, nfds: {}, timeout_msecs: {}",
                 ufds, nfds, timeout_msecs
             );
         }
+        drop(proc);
+        self.poll_impl(ufds, nfds, msecs_to_timeout(timeout_msecs))
+            .await
+    }
 
-        // check whether the fds is valid and is owned by this process
-        let condvars = alloc::vec![&(*TICK_ACTIVITY), &(*SOCKET_ACTIVITY)];
-
-        let mut polls = ufds.read_array(nfds).unwrap();
+    async fn poll_impl(
+        &mut self,
+        mut ufds: UserInOutPtr<PollFd>,
+        nfds: usize,
+        timeout: Option<Duration>,
+    ) -> SysResult {
+        let proc = self.process();
+        let mut polls = ufds.read_array(nfds)?;
 
         if !proc.pid.is_init() {
             info!("poll: fds: {:?}", polls);
@@ -140,168 +250,123 @@ impl Syscall<'_> {
 
         drop(proc);
 
-        #[must_use = "future does nothing unless polled/`await`-ed"]
-        struct PollFuture<'a> {
-            polls: &'a mut Vec<PollFd>,
-            syscall: &'a Syscall<'a>,
-        }
-
-        impl<'a> Future for PollFuture<'a> {
-            type Output = SysResult;
-
-            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-                use PollEvents as PE;
-                let proc = self.syscall.process();
-                let mut events = 0;
-
-                // iterate each poll to check whether it is ready
-                for poll in self.as_mut().polls.iter_mut() {
-                    poll.revents = PE::empty();
-                    if let Some(file_like) = proc.files.get(&(poll.fd as usize)) {
-                        let mut fut = Box::pin(file_like.async_poll());
-                        let status = match fut.as_mut().poll(cx) {
-                            Poll::Ready(Ok(ret)) => ret,
-                            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
-                            Poll::Pending => continue,
-                        };
-                        if status.error {
-                            poll.revents |= PE::HUP;
-                            events += 1;
-                        }
-                        if status.read && poll.events.contains(PE::IN) {
-                            poll.revents |= PE::IN;
-                            events += 1;
-                        }
-                        if status.write && poll.events.contains(PE::OUT) {
-                            poll.revents |= PE::OUT;
-                            events += 1;
-                        }
-                    } else {
-                        poll.revents |= PE::ERR;
-                        events += 1;
-                    }
-                }
-                drop(proc);
-
-                // some event happens, so evoke the process
-                if events > 0 {
-                    return Poll::Ready(Ok(events));
-                }
-
-                return Poll::Pending;
-            }
-        }
+        let res = PollFuture::new(&mut polls, self, timeout).await;
+        ufds.write_array(&polls)?;
+        res
+    }
 
-        let future = PollFuture {
-            polls: &mut polls,
-            syscall: self,
+    pub async fn sys_pselect6(
+        &mut self,
+        nfds: usize,
+        read: *mut u32,
+        write: *mut u32,
+        err: *mut u32,
+        timeout: UserInPtr<TimeSpec>,
+        sigmask: UserInPtr<[usize; 2]>,
+    ) -> SysResult {
+        let timeout = if timeout.is_null() {
+            None // infinity
+        } else {
+            Some(timeout.read()?.checked_duration().ok_or(EINVAL)?)
         };
-        let res = future.await;
-        ufds.write_array(&polls)?;
+        // struct { const sigset_t *ss; size_t ss_len; }
+        let sigmask = if sigmask.is_null() {
+            None
+        } else {
+            let [ss, ss_len] = sigmask.read()?;
+            self.read_wait_sig_mask(UserInPtr::from(ss), ss_len)?
+        };
+
+        self.replace_sig_mask(sigmask);
+        let res = self.select_impl(nfds, read, write, err, timeout).await;
+        self.restore_sig_mask(&res);
         res
     }
 
-    pub fn sys_pselect6(
+    pub async fn sys_select(
         &mut self,
         nfds: usize,
         read: *mut u32,
         write: *mut u32,
         err: *mut u32,
-        timeout: *const TimeVal,
-        _sigset: *const u32,
+        timeout: UserInPtr<TimeVal>,
     ) -> SysResult {
-        self.sys_select(nfds, read, write, err, timeout)
+        let timeout = if timeout.is_null() {
+            None // infinity
+        } else {
+            Some(timeout.read()?.to_duration().ok_or(EINVAL)?)
+        };
+        self.select_impl(nfds, read, write, err, timeout).await
     }
 
-    pub fn sys_select(
+    async fn select_impl(
         &mut self,
         nfds: usize,
         read: *mut u32,
         write: *mut u32,
         err: *mut u32,
-        timeout: *const TimeVal,
+        timeout: Option<Duration>,
     ) -> SysResult {
         info!(
             "select: nfds: {}, read: {:?}, write: {:?}, err: {:?}, timeout: {:?}",
             nfds, read, write, err, timeout
         );
-        if nfds as u64 == 0 {
-            return Ok(0);
-        }
-        let proc = self.process();
         let mut read_fds = FdSet::new(&self.vm(), read, nfds)?;
         let mut write_fds = FdSet::new(&self.vm(), write, nfds)?;
         let mut err_fds = FdSet::new(&self.vm(), err, nfds)?;
-        let timeout_msecs = if !timeout.is_null() {
-            let timeout = unsafe { self.vm().check_read_ptr(timeout)? };
-            timeout.to_msec()
-        } else {
-            // infinity
-            1 << 31
-        };
-
-        let condvars = alloc::vec![&(*TICK_ACTIVITY), &(*SOCKET_ACTIVITY)];
 
-        // for debugging
-        if cfg!(debug_assertions) {
-            debug!("files before select {:#?}", proc.files);
+        // translate fd sets into poll requests
+        let mut polls = Vec::new();
+        for fd in 0..nfds {
+            let mut events = PollEvents::empty();
+            if read_fds.contains(fd) {
+                events |= PollEvents::IN;
+            }
+            if write_fds.contains(fd) {
+                events |= PollEvents::OUT;
+            }
+            if err_fds.contains(fd) {
+                events |= PollEvents::ERR;
+            }
+            if !events.is_empty() {
+                polls.push(PollFd {
+                    fd: fd as u32,
+                    events,
+                    revents: PollEvents::empty(),
+                });
+            }
         }
-        drop(proc);
 
-        let begin_time_ms = crate::trap::uptime_msec();
-        Condvar::wait_events(condvars.as_slice(), move || {
-            let proc = self.process();
-            let mut events = 0;
-            for (&fd, file_like) in proc.files.iter() {
-                //                if fd >= nfds {
-                //                    continue;
-                //                }
-                if !err_fds.contains(fd) && !read_fds.contains(fd) && !write_fds.contains(fd) {
-                    continue;
-                }
-                let status = match file_like.poll() {
-                    Ok(ret) => ret,
-                    Err(err) => return Some(Err(err)),
-                };
-                if status.error && err_fds.contains(fd) {
-                    err_fds.set(fd);
-                    events += 1;
-                }
-                if status.read && read_fds.contains(fd) {
-                    read_fds.set(fd);
-                    events += 1;
-                }
-                if status.write && write_fds.contains(fd) {
-                    write_fds.set(fd);
-                    events += 1;
-                }
-            }
-            drop(proc);
+        PollFuture::new(&mut polls, self, timeout).await?;
 
-            if events > 0 {
-                return Some(Ok(events));
+        let mut events = 0;
+        for poll in polls.iter() {
+            use PollEvents as PE;
+            let fd = poll.fd as usize;
+            if poll.revents.contains(PE::INVAL) {
+                return Err(SysError::EBADF);
             }
-
-            if timeout_msecs == 0 {
-                // no timeout, return now;
-                return Some(Ok(0));
+            // hang up is reported as readable and writable
+            if poll.revents.intersects(PE::IN | PE::HUP) && read_fds.contains(fd) {
+                read_fds.set(fd);
+                events += 1;
             }
-
-            let current_time_ms = crate::trap::uptime_msec();
-            // infinity check
-            if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs as usize
-            {
-                return Some(Ok(0));
+            if poll.revents.intersects(PE::OUT | PE::HUP) && write_fds.contains(fd) {
+                write_fds.set(fd);
+                events += 1;
             }
-
-            return None;
-        })
+            if poll.revents.contains(PE::HUP) && err_fds.contains(fd) {
+                err_fds.set(fd);
+                events += 1;
+            }
+        }
+        Ok(events)
     }
 
     pub fn sys_epoll_create(&mut self, size: usize) -> SysResult {
         info!("epoll_create: size: {:?}", size);
 
-        if (size as i32) < 0 {
+        if (size as i32) <= 0 {
             return Err(SysError::EINVAL);
         }
         self.sys_epoll_create1(0)
@@ -309,6 +374,9 @@ impl Syscall<'_> {
 
     pub fn sys_epoll_create1(&mut self, flags: usize) -> SysResult {
         info!("epoll_create1: flags: {:?}", flags);
+        if flags & !O_CLOEXEC != 0 {
+            return Err(SysError::EINVAL);
+        }
         let mut proc = self.process();
         let epoll_instance = EpollInstance::new(flags);
         let fd = proc.add_file(FileLike::EpollInstance(epoll_instance));
@@ -328,203 +396,154 @@ impl Syscall<'_> {
             info!("sys_epoll_ctl: epfd: {}, op: {:?}, fd: {:#x}", epfd, op, fd);
         }
 
-        let _event = unsafe { self.vm().check_read_ptr(event)? };
+        // the event is ignored by EPOLL_CTL_DEL
+        let event = if event.is_null() && op as i32 == EPollCtlOp::DEL {
+            EpollEvent::default()
+        } else {
+            unsafe { *self.vm().check_read_ptr(event)? }
+        };
 
         if proc.files.get(&fd).is_none() {
-            return Err(SysError::EPERM);
+            return Err(SysError::EBADF);
+        }
+        if fd == epfd {
+            return Err(SysError::EINVAL);
         }
 
-        let epoll_instance = match proc.get_epoll_instance_mut(epfd) {
-            Ok(ins) => ins,
-            Err(err) => {
-                return Err(err);
-            }
-        };
-
-        let ret = epoll_instance.control(op, fd, &_event)?;
-        return Ok(ret);
+        let epoll_instance = proc.get_epoll_instance_mut(epfd)?;
+        epoll_instance.control(op, fd, &event)
     }
 
-    pub fn sys_epoll_wait(
+    pub async fn sys_epoll_wait(
         &mut self,
         epfd: usize,
         events: *mut EpollEvent,
         maxevents: usize,
-        timeout: usize,
+        timeout: i32,
     ) -> SysResult {
-        self.sys_epoll_pwait(epfd, events, maxevents, timeout, 0)
+        self.sys_epoll_pwait(epfd, events, maxevents, timeout, UserInPtr::from(0), 0)
+            .await
     }
 
-    pub fn sys_epoll_pwait(
+    pub async fn sys_epoll_pwait(
         &mut self,
         epfd: usize,
         events: *mut EpollEvent,
         maxevents: usize,
-        timeout_msecs: usize,
-        _sigset_t: usize,
+        timeout_msecs: i32,
+        sigmask: UserInPtr<Sigset>,
+        sigsetsize: usize,
     ) -> SysResult {
-        info!("epoll_pwait: epfd: {}, timeout: {:?}", epfd, timeout_msecs);
-
-        let proc = self.process();
+        info!(
+            "epoll_pwait: epfd: {}, timeout: {:?}, sigmask: {:?}",
+            epfd, timeout_msecs, sigmask
+        );
+        if (maxevents as i32) <= 0 {
+            return Err(SysError::EINVAL);
+        }
         let events = unsafe { self.vm().check_write_array(events, maxevents)? };
-        let epoll_instance = proc.get_epoll_instance(epfd)?;
-
-        // add new fds which are registered by epoll_ctl after latest epoll_pwait
-        epoll_instance.ready_list.lock().clear();
-        epoll_instance
-            .ready_list
-            .lock()
-            .extend(epoll_instance.new_ctl_list.lock().clone());
-        epoll_instance.new_ctl_list.lock().clear();
-
-        // if registered fd has data to handle and its mode isn't epollet, we need
-        // to add it to the list.
-        let keys: Vec<_> = epoll_instance.events.keys().cloned().collect();
-        for (k, v) in epoll_instance.events.iter() {
-            if !v.contains(EpollEvent::EPOLLET) {
-                match &proc.files.get(k) {
+        let sigmask = self.read_wait_sig_mask(sigmask, sigsetsize)?;
+
+        let mut polls = Vec::new();
+        // edge-triggered fds reported ready before, with their files
+        let mut reported = Vec::new();
+        {
+            let mut proc = self.process();
+            let epoll_instance = proc.get_epoll_instance(epfd)?;
+            let mut closed = Vec::new();
+            for (&fd, event) in epoll_instance.events.iter() {
+                let file_like = match proc.files.get(&fd) {
+                    Some(file_like) => file_like,
                     None => {
-                        //      return Err(SysError::EINVAL);
-                    }
-                    Some(file_like) => {
-                        let status = file_like.poll()?;
-                        if status.write || status.read || status.error {
-                            let mut ready_list = epoll_instance.ready_list.lock();
-                            ready_list.insert(*k);
-                        }
+                        closed.push(fd);
+                        continue;
                     }
+                };
+                // disabled by EPOLLONESHOT
+                if event.events & !EpollEvent::EPOLL_INPUT_FLAGS == 0 {
+                    continue;
                 }
+                if event.contains(EpollEvent::EPOLLET)
+                    && epoll_instance.ready_list.lock().contains(&fd)
+                {
+                    reported.push((fd, file_like.clone()));
+                }
+                let mut poll_events = PollEvents::empty();
+                if event.contains(EpollEvent::EPOLLIN) {
+                    poll_events |= PollEvents::IN;
+                }
+                if event.contains(EpollEvent::EPOLLOUT) {
+                    poll_events |= PollEvents::OUT;
+                }
+                polls.push(PollFd {
+                    fd: fd as u32,
+                    events: poll_events,
+                    revents: PollEvents::empty(),
+                });
+            }
+            // closed fds are removed from the interest list
+            let epoll_instance = proc.get_epoll_instance_mut(epfd)?;
+            for fd in closed {
+                epoll_instance.events.remove(&fd);
             }
         }
-        drop(proc);
-
-        let mut callbacks = alloc::vec![];
-        for fd in &keys {
-            let proc = self.process();
-            match proc.files.get(&fd) {
-                Some(file_like) => {
-                    match file_like {
-                        FileLike::File(_file) => {
-                            callbacks.push((
-                                0, 0, // thread::current().id(),
-                                epfd, *fd,
-                            ));
-                        }
-                        FileLike::Socket(_socket) => {
-                            &(*crate::drivers::SOCKET_ACTIVITY).register_epoll_list(
-                                self.thread.proc.clone(),
-                                0,
-                                //thread::current().id(),
-                                epfd,
-                                *fd,
-                            );
-                            callbacks.push((
-                                1, 0, //thread::current().id(),
-                                epfd, *fd,
-                            ));
-                        }
-                        FileLike::EpollInstance(_) => {
-                            return Err(SysError::EINVAL);
-                        }
-                    };
-                }
-                None => {}
+        // an edge-triggered fd is reported again only after it has been idle.
+        // Files are polled with the process unlocked, as some lock it, e.g. signalfd.
+        for (fd, file_like) in reported {
+            let status = file_like.poll()?;
+            if status.read || status.write || status.error {
+                polls.retain(|poll| poll.fd as usize != fd);
+            } else {
+                let mut proc = self.process();
+                proc.get_epoll_instance(epfd)?.ready_list.lock().remove(&fd);
             }
-            drop(proc);
         }
 
-        let condvars = alloc::vec![&(*TICK_ACTIVITY), &(*SOCKET_ACTIVITY)];
-
-        let begin_time_ms = crate::trap::uptime_msec();
-        let condition = move || {
-            let mut proc = self.process();
+        self.replace_sig_mask(sigmask);
+        let res = PollFuture::new(&mut polls, self, msecs_to_timeout(timeout_msecs)).await;
+        self.restore_sig_mask(&res);
+        res?;
 
-            let epoll_instance = match proc.get_epoll_instance_mut(epfd) {
-                Ok(ins) => ins,
-                Err(err) => {
-                    return Some(Err(err));
-                }
+        let mut proc = self.process();
+        let epoll_instance = proc.get_epoll_instance_mut(epfd)?;
+        let mut events_num = 0;
+        for poll in polls.iter() {
+            use PollEvents as PE;
+            if events_num >= maxevents {
+                break;
+            }
+            let fd = poll.fd as usize;
+            let event = match epoll_instance.events.get_mut(&fd) {
+                Some(event) => event,
+                None => continue,
             };
-            let ready_list = epoll_instance.ready_list.lock().clone();
-            let mut events_num = 0;
-
-            for infd in ready_list.iter() {
-                let mut status: PollStatus = Default::default();
-                {
-                    if let Some(file_like) = proc.files.get(&infd) {
-                        let _status = match file_like.poll() {
-                            Ok(ret) => ret,
-                            Err(err) => return Some(Err(err)),
-                        };
-                        status.write = _status.write;
-                        status.read = _status.read;
-                        status.error = _status.error;
-                    }
-                }
-
-                {
-                    let epoll_instance = match proc.get_epoll_instance_mut(epfd) {
-                        Ok(ins) => ins,
-                        Err(err) => {
-                            return Some(Err(err));
-                        }
-                    };
-                    let epollevent = epoll_instance.events.get_mut(&infd)?;
-
-                    if status.error {
-                        events[events_num].events = EpollEvent::EPOLLERR;
-                        events[events_num].data = epollevent.data;
-
-                        events_num += 1;
-                    }
-                    if status.read && epollevent.contains(EpollEvent::EPOLLIN) {
-                        events[events_num].events = EpollEvent::EPOLLIN;
-                        events[events_num].data = epollevent.data;
-                        events_num += 1;
-                    }
-                    if status.write && epollevent.contains(EpollEvent::EPOLLOUT) {
-                        events[events_num].events = EpollEvent::EPOLLOUT;
-                        events[events_num].data = epollevent.data;
-
-                        events_num += 1;
-                    }
-                }
+            let mut revents = 0;
+            if poll.revents.contains(PE::IN) {
+                revents |= EpollEvent::EPOLLIN;
             }
-
-            {
-                let epoll_instance = match proc.get_epoll_instance_mut(epfd) {
-                    Ok(ins) => ins,
-                    Err(err) => {
-                        return Some(Err(err));
-                    }
-                };
-                epoll_instance.ready_list.lock().clear();
+            if poll.revents.contains(PE::OUT) {
+                revents |= EpollEvent::EPOLLOUT;
             }
-
-            drop(proc);
-
-            // some event happens, so evoke the process
-            if events_num > 0 {
-                return Some(Ok(events_num));
+            if poll.revents.contains(PE::HUP) {
+                revents |= EpollEvent::EPOLLHUP;
             }
-
-            let current_time_ms = crate::trap::uptime_msec();
-            // time runs out, so the evoke the process
-            if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs {
-                return Some(Ok(0));
+            if revents == 0 {
+                continue;
             }
-            return None;
-        };
-
-        let num = Condvar::wait_events(condvars.as_slice(), condition).unwrap();
-
-        for cb in callbacks.iter() {
-            match cb.0 {
-                1 => &(*crate::drivers::SOCKET_ACTIVITY).unregister_epoll_list(cb.1, cb.2, cb.3),
-                _ => panic!("cb error"),
+            events[events_num] = EpollEvent {
+                events: revents,
+                data: event.data,
             };
+            events_num += 1;
+            if event.contains(EpollEvent::EPOLLET) {
+                epoll_instance.ready_list.lock().insert(fd);
+            }
+            if event.contains(EpollEvent::EPOLLONESHOT) {
+                // disabled until rearmed by EPOLL_CTL_MOD
+                event.events &= EpollEvent::EPOLL_INPUT_FLAGS;
+            }
         }
-        Ok(num)
+        Ok(events_num)
     }
 
     pub async fn sys_readv(
@@ -541,8 +560,9 @@ impl Syscall<'_> {
         let mut iovs =
             unsafe { IoVecs::check_and_new(iov_ptr.ptr(), iov_count, &self.vm(), true)? };
 
-        // read all data to a buf
-        let file_like = proc.get_file_like(fd)?;
+        // read all data to a buf, with the process unlocked as read does
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
         let mut buf = iovs.new_buf(true);
         let len = file_like.read(buf.as_mut_slice()).await?;
         // copy data to user
@@ -550,7 +570,12 @@ impl Syscall<'_> {
         Ok(len)
     }
 
-    pub fn sys_writev(&mut self, fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResult {
+    pub async fn sys_writev(
+        &mut self,
+        fd: usize,
+        iov_ptr: *const IoVec,
+        iov_count: usize,
+    ) -> SysResult {
         let mut proc = self.process();
         if !proc.pid.is_init() {
             // we trust pid 0 process
@@ -567,9 +592,11 @@ impl Syscall<'_> {
         let iovs = unsafe { IoVecs::check_and_new(iov_ptr, iov_count, &self.vm(), false)? };
 
         let buf = iovs.read_all_to_vec();
-        let file_like = proc.get_file_like(fd)?;
-        let len = file_like.write(buf.as_slice())?;
-        Ok(len)
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
+        let res = file_like.write(buf.as_slice()).await;
+        self.check_broken_pipe(&res);
+        res
     }
 
     pub fn sys_open(&mut self, path: *const u8, flags: usize, mode: usize) -> SysResult {
@@ -591,38 +618,70 @@ impl Syscall<'_> {
             dir_fd as isize, path, flags, mode
         );
 
+        let lookup_flags = match flags.contains(OpenFlags::NOFOLLOW) {
+            true => AtFlags::SYMLINK_NOFOLLOW,
+            false => AtFlags::empty(),
+        };
+        let mut created = false;
         let inode = if flags.contains(OpenFlags::CREATE) {
             let (dir_path, file_name) = split_path(&path);
             // relative to cwd
             let dir_inode = proc.lookup_inode_at(dir_fd, dir_path, true)?;
             match dir_inode.find(file_name) {
-                Ok(file_inode) => {
-                    if flags.contains(OpenFlags::EXCLUSIVE) {
-                        return Err(SysError::EEXIST);
-                    }
-                    if flags.contains(OpenFlags::TRUNCATE) {
-                        if let Err(e) = file_inode.resize(0) {
-                            // TODO: do something? what about device file?
-                        }
-                    }
-                    file_inode
+                Ok(_) if flags.contains(OpenFlags::EXCLUSIVE) => {
+                    // even a dangling symlink counts as existing
+                    return Err(SysError::EEXIST);
                 }
+                Ok(_) => proc.resolve_path(dir_fd, &path, lookup_flags)?,
                 Err(FsError::EntryNotFound) => {
+                    let mode = mode & !proc.umask & 0o7777;
                     let inode = dir_inode.create(file_name, FileType::File, mode as u32)?;
+                    proc.set_owner(&inode)?;
                     TimeSpec::update(&inode);
-                    TimeSpec::update(&dir_inode);
+                    TimeSpec::update_mtime(&dir_inode);
+                    created = true;
                     inode
                 }
                 Err(e) => return Err(SysError::from(e)),
             }
         } else {
-            proc.lookup_inode_at(dir_fd, &path, true)?
+            proc.resolve_path(dir_fd, &path, lookup_flags)?
+        };
+
+        let metadata = inode.metadata()?;
+        match metadata.type_ {
+            FileType::SymLink => return Err(SysError::ELOOP),
+            FileType::Dir if flags.writable() || flags.contains(OpenFlags::CREATE) => {
+                return Err(SysError::EISDIR)
+            }
+            FileType::Dir => {}
+            _ if flags.contains(OpenFlags::DIRECTORY) => return Err(SysError::ENOTDIR),
+            _ => {}
+        }
+        // the creator may write a new file regardless of the mode it asked for
+        if !created {
+            proc.check_permission(&metadata, flags.access_mode())?;
+        }
+        if flags.contains(OpenFlags::TRUNCATE)
+            && flags.writable()
+            && metadata.type_ == FileType::File
+            && metadata.size != 0
+        {
+            inode.truncate(0)?;
+            TimeSpec::update_mtime(&inode);
+        }
+        // device nodes are backed by the registered driver, not the file system
+        let inode = match device_of(&metadata) {
+            Some(device) => device,
+            None => inode,
         };
 
+        // kept absolute, so that fchdir works after the cwd changes
+        let path = proc.absolute_path(dir_fd, &path)?;
         let file = FileHandle::new(
             inode,
             flags.to_options(),
-            String::from(path),
+            path,
             false,
             flags.contains(OpenFlags::CLOEXEC),
         );
@@ -645,7 +704,11 @@ impl Syscall<'_> {
             debug!("files before close {:#?}", proc.files);
         }
 
-        proc.files.remove(&fd).ok_or(SysError::EBADF)?;
+        let file_like = proc.files.remove(&fd).ok_or(SysError::EBADF)?;
+        if let FileLike::File(file) = file_like {
+            // closing any fd of a file releases the record locks of the process on it
+            release_range_locks(&file.inode(), proc.pid.get()).ok();
+        }
         Ok(0)
     }
 
@@ -660,7 +723,6 @@ impl Syscall<'_> {
         mode: usize,
         flags: usize,
     ) -> SysResult {
-        // TODO: check permissions based on uid/gid
         let proc = self.process();
         let path = check_and_clone_cstr(path)?;
         let flags = AtFlags::from_bits_truncate(flags);
@@ -671,8 +733,26 @@ impl Syscall<'_> {
                 dirfd as isize, path, mode, flags
             );
         }
-        let _inode =
-            proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?;
+        let inode = proc.resolve_path(dirfd, &path, flags)?;
+        let mode = AccessMode::from_bits(mode).ok_or(SysError::EINVAL)?;
+        proc.check_permission(&inode.metadata()?, mode)?;
+        Ok(0)
+    }
+
+    pub fn sys_statfs(&mut self, path: *const u8, mut buf: UserOutPtr<StatFs>) -> SysResult {
+        let proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        info!("statfs: path: {:?}, buf: {:?}", path, buf);
+        let inode = proc.lookup_inode(&path)?;
+        buf.write(StatFs::from(inode.fs().info()))?;
+        Ok(0)
+    }
+
+    pub fn sys_fstatfs(&mut self, fd: usize, mut buf: UserOutPtr<StatFs>) -> SysResult {
+        let proc = self.process();
+        info!("fstatfs: fd: {}, buf: {:?}", fd, buf);
+        let inode = proc.get_file_const(fd)?.inode();
+        buf.write(StatFs::from(inode.fs().info()))?;
         Ok(0)
     }
 
@@ -721,8 +801,7 @@ impl Syscall<'_> {
             dirfd as isize, path, stat_ptr, flags
         );
 
-        let inode =
-            proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?;
+        let inode = proc.resolve_path(dirfd, &path, flags)?;
         let stat = Stat::from(inode.metadata()?);
         *stat_ref = stat;
         Ok(0)
@@ -751,9 +830,12 @@ impl Syscall<'_> {
             dirfd as isize, path, base, len
         );
 
+        if len == 0 {
+            return Err(SysError::EINVAL);
+        }
+
         let inode = proc.lookup_inode_at(dirfd, &path, false)?;
         if inode.metadata()?.type_ == FileType::SymLink {
-            // TODO: recursive link resolution and loop detection
             let len = inode.read_at(0, slice)?;
             Ok(len)
         } else {
@@ -786,7 +868,7 @@ impl Syscall<'_> {
         Ok(0)
     }
 
-    pub fn sys_flock(&mut self, fd: usize, operation: usize) -> SysResult {
+    pub async fn sys_flock(&mut self, fd: usize, operation: usize) -> SysResult {
         bitflags! {
             struct Operation: u8 {
                 const LOCK_SH = 1;
@@ -795,12 +877,29 @@ impl Syscall<'_> {
                 const LOCK_UN = 8;
             }
         }
-        let operation = Operation::from_bits(operation as u8).unwrap();
+        let operation = Operation::from_bits(operation as u8).ok_or(SysError::EINVAL)?;
         info!("flock: fd: {}, operation: {:?}", fd, operation);
-        let mut proc = self.process();
-        // let file_like = proc.get_file_like(fd)?;
-        let file = proc.get_file(fd)?;
-        Ok(0)
+        let file = self.process().get_file_const(fd)?.clone();
+        let kind = match operation - Operation::LOCK_NB {
+            Operation::LOCK_SH => LockKind::Read,
+            Operation::LOCK_EX => LockKind::Write,
+            Operation::LOCK_UN => {
+                file.unlock_flock()?;
+                return Ok(0);
+            }
+            _ => return Err(SysError::EINVAL),
+        };
+        if operation.contains(Operation::LOCK_NB) {
+            return match file.try_flock(kind)? {
+                true => Ok(0),
+                false => Err(SysError::EAGAIN),
+            };
+        }
+        LockFuture::new(
+            || -> Result<bool, SysError> { Ok(file.try_flock(kind)?) },
+            self,
+        )
+        .await
     }
 
     pub fn sys_fdatasync(&mut self, fd: usize) -> SysResult {
@@ -813,12 +912,20 @@ impl Syscall<'_> {
         let proc = self.process();
         let path = check_and_clone_cstr(path)?;
         info!("truncate: path: {:?}, len: {}", path, len);
-        proc.lookup_inode(&path)?.resize(len)?;
+        if (len as isize) < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let inode = proc.lookup_inode(&path)?;
+        inode.truncate(len)?;
+        TimeSpec::update_mtime(&inode);
         Ok(0)
     }
 
     pub fn sys_ftruncate(&mut self, fd: usize, len: usize) -> SysResult {
         info!("ftruncate: fd: {}, len: {}", fd, len);
+        if (len as isize) < 0 {
+            return Err(SysError::EINVAL);
+        }
         self.process().get_file(fd)?.set_len(len as u64)?;
         Ok(0)
     }
@@ -846,37 +953,68 @@ impl Syscall<'_> {
                 Err(FsError::EntryNotFound) => break,
                 r => r,
             }?;
+            // d_off is the offset of the next entry, which can be passed to lseek
+            let next_offset = file.seek(SeekFrom::Current(0))?;
             let ok = writer.try_write(
                 info.inode as u64,
+                next_offset,
                 DirentType::from_type(&info.type_).bits(),
                 &name,
             );
             if !ok {
                 file.seek(SeekFrom::Current(-1))?;
+                if writer.written_size == 0 {
+                    // buffer is too small for a single entry
+                    return Err(SysError::EINVAL);
+                }
                 break;
             }
         }
         Ok(writer.written_size)
     }
 
+    pub fn sys_dup(&mut self, fd: usize) -> SysResult {
+        info!("dup: {}", fd);
+        let mut proc = self.process();
+        proc.get_file_like(fd)?;
+        let new_fd = proc.get_free_fd();
+        drop(proc);
+        if new_fd >= MAX_FILES {
+            return Err(SysError::EMFILE);
+        }
+        self.dup_impl(fd, new_fd, false)
+    }
+
     pub fn sys_dup2(&mut self, fd1: usize, fd2: usize) -> SysResult {
         info!("dup2: from {} to {}", fd1, fd2);
-        self.dup_impl(fd1, fd2, 0)
+        if fd2 >= MAX_FILES {
+            return Err(SysError::EBADF);
+        }
+        if fd1 == fd2 {
+            // do nothing but check the fd
+            self.process().get_file_like(fd1)?;
+            return Ok(fd2);
+        }
+        self.dup_impl(fd1, fd2, false)
     }
 
-    fn dup_impl(&mut self, fd1: usize, fd2: usize, flags: usize) -> SysResult {
+    /// Duplicate `fd1` to `fd2`, closing `fd2` first if it is opened
+    fn dup_impl(&mut self, fd1: usize, fd2: usize, fd_cloexec: bool) -> SysResult {
         let mut proc = self.process();
-        // close fd2 first if it is opened
-        proc.files.remove(&fd2);
-
-        let mut file_like = proc.get_file_like(fd1)?.dup(flags != 0);
+        let file_like = proc.get_file_like(fd1)?.dup(fd_cloexec);
         proc.files.insert(fd2, file_like);
         Ok(fd2)
     }
 
     pub fn sys_dup3(&mut self, fd1: usize, fd2: usize, flags: usize) -> SysResult {
         info!("dup3: from {} to {} with flags = {:#x}", fd1, fd2, flags);
-        self.dup_impl(fd1, fd2, flags)
+        if fd1 == fd2 || flags & !O_CLOEXEC != 0 {
+            return Err(SysError::EINVAL);
+        }
+        if fd2 >= MAX_FILES {
+            return Err(SysError::EBADF);
+        }
+        self.dup_impl(fd1, fd2, flags & O_CLOEXEC != 0)
     }
 
     pub fn sys_ioctl(
@@ -896,15 +1034,39 @@ impl Syscall<'_> {
             FIOCLEX => self.sys_fcntl(fd, F_SETFD, FD_CLOEXEC),
             FIONCLEX => self.sys_fcntl(fd, F_SETFD, 0),
             FIONBIO => {
-                let data = arg1 as *const i32;
-                let val = unsafe { *data };
+                let val = unsafe { *self.vm().check_read_ptr(arg1 as *const i32)? };
+                let flags = self.sys_fcntl(fd, F_GETFL, 0)?;
                 if val == 0 {
-                    self.sys_fcntl(fd, F_SETFD, 0)
+                    self.sys_fcntl(fd, F_SETFL, flags & !O_NONBLOCK)
                 } else {
-                    self.sys_fcntl(fd, F_SETFD, O_NONBLOCK)
+                    self.sys_fcntl(fd, F_SETFL, flags | O_NONBLOCK)
                 }
             }
             _ => {
+                // drivers access the argument directly, so check it for known requests
+                let vm = self.vm();
+                match request {
+                    TCGETS => unsafe {
+                        vm.check_write_ptr(arg1 as *mut Termios)?;
+                    },
+                    TCSETS => unsafe {
+                        vm.check_read_ptr(arg1 as *const Termios)?;
+                    },
+                    TIOCGWINSZ => unsafe {
+                        vm.check_write_ptr(arg1 as *mut Winsize)?;
+                    },
+                    TIOCSWINSZ => unsafe {
+                        vm.check_read_ptr(arg1 as *const Winsize)?;
+                    },
+                    TIOCGPGRP | FIONREAD => unsafe {
+                        vm.check_write_ptr(arg1 as *mut i32)?;
+                    },
+                    TIOCSPGRP => unsafe {
+                        vm.check_read_ptr(arg1 as *const i32)?;
+                    },
+                    _ => {}
+                };
+                drop(vm);
                 let mut proc = self.process();
                 let file_like = proc.get_file_like(fd)?;
                 file_like.ioctl(request, arg1, arg2, arg3)
@@ -919,6 +1081,9 @@ impl Syscall<'_> {
             // we trust pid 0 process
             info!("chdir: path: {:?}", path);
         }
+        if path.is_empty() {
+            return Err(SysError::ENOENT);
+        }
 
         let inode = proc.lookup_inode(&path)?;
         let info = inode.metadata()?;
@@ -926,35 +1091,101 @@ impl Syscall<'_> {
             return Err(SysError::ENOTDIR);
         }
 
-        // BUGFIX: '..' and '.'
-        if path.len() > 0 {
-            let cwd = match path.as_bytes()[0] {
-                b'/' => String::from("/"),
-                _ => proc.cwd.clone(),
-            };
-            let mut cwd_vec: Vec<_> = cwd.split("/").filter(|&x| x != "").collect();
-            let path_split = path.split("/").filter(|&x| x != "");
-            for seg in path_split {
-                if seg == ".." {
-                    cwd_vec.pop();
-                } else if seg == "." {
-                    // nothing to do here.
-                } else {
-                    cwd_vec.push(seg);
-                }
-            }
-            proc.cwd = String::from("");
-            for seg in cwd_vec {
-                proc.cwd.push_str("/");
-                proc.cwd.push_str(seg);
-            }
-            if proc.cwd == "" {
-                proc.cwd = String::from("/");
-            }
+        proc.change_cwd(&path);
+        Ok(0)
+    }
+
+    pub fn sys_fchdir(&mut self, fd: usize) -> SysResult {
+        let mut proc = self.process();
+        if !proc.pid.is_init() {
+            // we trust pid 0 process
+            info!("fchdir: fd: {}", fd);
+        }
+
+        let file = proc.get_file(fd)?;
+        let info = file.metadata()?;
+        if info.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
         }
+        // opened with an absolute path
+        let path = file.path.clone();
+        proc.change_cwd(&path);
         Ok(0)
     }
 
+    pub fn sys_umask(&mut self, mask: usize) -> SysResult {
+        let mut proc = self.process();
+        info!("umask: mask: {:#o}", mask);
+        let old_mask = proc.umask;
+        proc.umask = mask & 0o777;
+        Ok(old_mask)
+    }
+
+    pub fn sys_chmod(&mut self, path: *const u8, mode: usize) -> SysResult {
+        self.sys_fchmodat(AT_FDCWD, path, mode)
+    }
+
+    pub fn sys_fchmod(&mut self, fd: usize, mode: usize) -> SysResult {
+        let proc = self.process();
+        info!("fchmod: fd: {}, mode: {:#o}", fd, mode);
+        let inode = proc.get_file_const(fd)?.inode();
+        proc.chmod_inode(&inode, mode)
+    }
+
+    pub fn sys_fchmodat(&mut self, dirfd: usize, path: *const u8, mode: usize) -> SysResult {
+        let proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        info!(
+            "fchmodat: dirfd: {}, path: {:?}, mode: {:#o}",
+            dirfd as isize, path, mode
+        );
+        let inode = proc.resolve_path(dirfd, &path, AtFlags::empty())?;
+        proc.chmod_inode(&inode, mode)
+    }
+
+    pub fn sys_chown(&mut self, path: *const u8, owner: usize, group: usize) -> SysResult {
+        self.sys_fchownat(AT_FDCWD, path, owner, group, 0)
+    }
+
+    pub fn sys_lchown(&mut self, path: *const u8, owner: usize, group: usize) -> SysResult {
+        self.sys_fchownat(
+            AT_FDCWD,
+            path,
+            owner,
+            group,
+            AtFlags::SYMLINK_NOFOLLOW.bits(),
+        )
+    }
+
+    pub fn sys_fchown(&mut self, fd: usize, owner: usize, group: usize) -> SysResult {
+        let proc = self.process();
+        info!(
+            "fchown: fd: {}, owner: {}, group: {}",
+            fd, owner as i32, group as i32
+        );
+        let inode = proc.get_file_const(fd)?.inode();
+        proc.chown_inode(&inode, owner, group)
+    }
+
+    pub fn sys_fchownat(
+        &mut self,
+        dirfd: usize,
+        path: *const u8,
+        owner: usize,
+        group: usize,
+        flags: usize,
+    ) -> SysResult {
+        let proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        let flags = AtFlags::from_bits_truncate(flags);
+        info!(
+            "fchownat: dirfd: {}, path: {:?}, owner: {}, group: {}, flags: {:?}",
+            dirfd as isize, path, owner as i32, group as i32, flags
+        );
+        let inode = proc.resolve_path(dirfd, &path, flags)?;
+        proc.chown_inode(&inode, owner, group)
+    }
+
     pub fn sys_rename(&mut self, oldpath: *const u8, newpath: *const u8) -> SysResult {
         self.sys_renameat(AT_FDCWD, oldpath, AT_FDCWD, newpath)
     }
@@ -965,20 +1196,68 @@ impl Syscall<'_> {
         oldpath: *const u8,
         newdirfd: usize,
         newpath: *const u8,
+    ) -> SysResult {
+        self.sys_renameat2(olddirfd, oldpath, newdirfd, newpath, 0)
+    }
+
+    pub fn sys_renameat2(
+        &mut self,
+        olddirfd: usize,
+        oldpath: *const u8,
+        newdirfd: usize,
+        newpath: *const u8,
+        flags: usize,
     ) -> SysResult {
         let proc = self.process();
         let oldpath = check_and_clone_cstr(oldpath)?;
         let newpath = check_and_clone_cstr(newpath)?;
+        let flags = RenameFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
         info!(
-            "renameat: olddirfd: {}, oldpath: {:?}, newdirfd: {}, newpath: {:?}",
-            olddirfd as isize, oldpath, newdirfd as isize, newpath
+            "renameat2: olddirfd: {}, oldpath: {:?}, newdirfd: {}, newpath: {:?}, flags: {:?}",
+            olddirfd as isize, oldpath, newdirfd as isize, newpath, flags
         );
+        if flags.intersects(RenameFlags::EXCHANGE | RenameFlags::WHITEOUT) {
+            // not supported by rcore-fs
+            return Err(SysError::EINVAL);
+        }
+        if oldpath.is_empty() || newpath.is_empty() {
+            return Err(SysError::ENOENT);
+        }
 
         let (old_dir_path, old_file_name) = split_path(&oldpath);
         let (new_dir_path, new_file_name) = split_path(&newpath);
-        let old_dir_inode = proc.lookup_inode_at(olddirfd, old_dir_path, false)?;
-        let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, false)?;
+        let old_dir_inode = proc.lookup_inode_at(olddirfd, old_dir_path, true)?;
+        let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, true)?;
+        if !Arc::ptr_eq(&old_dir_inode.fs(), &new_dir_inode.fs()) {
+            return Err(SysError::EXDEV);
+        }
+        let old_inode = old_dir_inode.find(old_file_name)?;
+        match new_dir_inode.find(new_file_name) {
+            Ok(new_inode) => {
+                if flags.contains(RenameFlags::NOREPLACE) {
+                    return Err(SysError::EEXIST);
+                }
+                let old_info = old_inode.metadata()?;
+                let new_info = new_inode.metadata()?;
+                if old_info.inode == new_info.inode && old_info.dev == new_info.dev {
+                    // both refer to the same file, nothing to do
+                    return Ok(0);
+                }
+                match (old_info.type_, new_info.type_) {
+                    (FileType::Dir, FileType::Dir) => {}
+                    (FileType::Dir, _) => return Err(SysError::ENOTDIR),
+                    (_, FileType::Dir) => return Err(SysError::EISDIR),
+                    _ => {}
+                }
+                // replace the existing entry
+                new_dir_inode.unlink(new_file_name)?;
+            }
+            Err(FsError::EntryNotFound) => {}
+            Err(e) => return Err(e.into()),
+        }
         old_dir_inode.move_(old_file_name, &new_dir_inode, new_file_name)?;
+        TimeSpec::update_mtime(&old_dir_inode);
+        TimeSpec::update_mtime(&new_dir_inode);
         Ok(0)
     }
 
@@ -995,32 +1274,80 @@ impl Syscall<'_> {
             dirfd as isize, path, mode
         );
 
+        if path.is_empty() {
+            return Err(SysError::ENOENT);
+        }
         let (dir_path, file_name) = split_path(&path);
         let dir_inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
+        if dir_inode.metadata()?.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
+        }
         if dir_inode.find(file_name).is_ok() {
             return Err(SysError::EEXIST);
         }
+        let mode = mode & !proc.umask & 0o7777;
         let inode = dir_inode.create(file_name, FileType::Dir, mode as u32)?;
+        proc.set_owner(&inode)?;
         TimeSpec::update(&inode);
-        TimeSpec::update(&dir_inode);
+        TimeSpec::update_mtime(&dir_inode);
         Ok(0)
     }
 
-    pub fn sys_rmdir(&mut self, path: *const u8) -> SysResult {
+    pub fn sys_mknod(&mut self, path: *const u8, mode: usize, dev: usize) -> SysResult {
+        self.sys_mknodat(AT_FDCWD, path, mode, dev)
+    }
+
+    pub fn sys_mknodat(
+        &mut self,
+        dirfd: usize,
+        path: *const u8,
+        mode: usize,
+        dev: usize,
+    ) -> SysResult {
         let proc = self.process();
         let path = check_and_clone_cstr(path)?;
-        info!("rmdir: path: {:?}", path);
+        info!(
+            "mknodat: dirfd: {}, path: {:?}, mode: {:#o}, dev: {:#x}",
+            dirfd as isize, path, mode, dev
+        );
 
+        let mode = StatMode::from_bits_truncate(mode as u32);
+        let type_ = match mode & StatMode::TYPE_MASK {
+            StatMode::NULL | StatMode::FILE => FileType::File,
+            StatMode::CHAR => FileType::CharDevice,
+            StatMode::BLOCK => FileType::BlockDevice,
+            StatMode::FIFO => FileType::NamedPipe,
+            StatMode::SOCKET => FileType::Socket,
+            _ => return Err(SysError::EINVAL),
+        };
+        if path.is_empty() {
+            return Err(SysError::ENOENT);
+        }
         let (dir_path, file_name) = split_path(&path);
-        let dir_inode = proc.lookup_inode(dir_path)?;
-        let file_inode = dir_inode.find(file_name)?;
-        if file_inode.metadata()?.type_ != FileType::Dir {
+        let dir_inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
+        if dir_inode.metadata()?.type_ != FileType::Dir {
             return Err(SysError::ENOTDIR);
         }
-        dir_inode.unlink(file_name)?;
+        if dir_inode.find(file_name).is_ok() {
+            return Err(SysError::EEXIST);
+        }
+        let perm = (mode - StatMode::TYPE_MASK).bits() & !(proc.umask as u32);
+        let inode = dir_inode.create(file_name, type_, perm)?;
+        proc.set_owner(&inode)?;
+        if type_ == FileType::CharDevice || type_ == FileType::BlockDevice {
+            let mut metadata = inode.metadata()?;
+            metadata.rdev = dev;
+            inode.set_metadata(&metadata)?;
+        }
+        TimeSpec::update(&inode);
+        TimeSpec::update_mtime(&dir_inode);
         Ok(0)
     }
 
+    pub fn sys_rmdir(&mut self, path: *const u8) -> SysResult {
+        self.sys_unlinkat(AT_FDCWD, path, AtFlags::REMOVEDIR.bits())
+    }
+
     pub fn sys_link(&mut self, oldpath: *const u8, newpath: *const u8) -> SysResult {
         self.sys_linkat(AT_FDCWD, oldpath, AT_FDCWD, newpath, 0)
     }
@@ -1042,10 +1369,17 @@ impl Syscall<'_> {
             olddirfd as isize, oldpath, newdirfd as isize, newpath, flags
         );
 
+        // unlike other *at syscalls, linkat does not follow symlinks by default
+        let mut resolve_flags = flags & AtFlags::EMPTY_PATH;
+        if !flags.contains(AtFlags::SYMLINK_FOLLOW) {
+            resolve_flags |= AtFlags::SYMLINK_NOFOLLOW;
+        }
         let (new_dir_path, new_file_name) = split_path(&newpath);
-        let inode = proc.lookup_inode_at(olddirfd, &oldpath, true)?;
+        let inode = proc.resolve_path(olddirfd, &oldpath, resolve_flags)?;
         let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, true)?;
         new_dir_inode.link(new_file_name, &inode)?;
+        TimeSpec::update_ctime(&inode);
+        TimeSpec::update_mtime(&new_dir_inode);
         Ok(0)
     }
 
@@ -1081,7 +1415,7 @@ impl Syscall<'_> {
                     let symlink = dir_inode.create(filename, FileType::SymLink, 0o777)?;
                     symlink.write_at(0, target.as_bytes())?;
                     TimeSpec::update(&symlink);
-                    TimeSpec::update(&dir_inode);
+                    TimeSpec::update_mtime(&dir_inode);
                     Ok(0)
                 }
                 _ => Err(e.into()),
@@ -1098,13 +1432,28 @@ impl Syscall<'_> {
             dirfd as isize, path, flags
         );
 
+        if path.is_empty() {
+            return Err(SysError::ENOENT);
+        }
         let (dir_path, file_name) = split_path(&path);
         let dir_inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
         let file_inode = dir_inode.find(file_name)?;
-        if file_inode.metadata()?.type_ == FileType::Dir {
+        let is_dir = file_inode.metadata()?.type_ == FileType::Dir;
+        if flags.contains(AtFlags::REMOVEDIR) {
+            if !is_dir {
+                return Err(SysError::ENOTDIR);
+            }
+            match file_name {
+                "." => return Err(SysError::EINVAL),
+                ".." => return Err(SysError::ENOTEMPTY),
+                _ => {}
+            }
+        } else if is_dir {
             return Err(SysError::EISDIR);
         }
         dir_inode.unlink(file_name)?;
+        TimeSpec::update_ctime(&file_inode);
+        TimeSpec::update_mtime(&dir_inode);
         Ok(0)
     }
 
@@ -1114,6 +1463,9 @@ impl Syscall<'_> {
 
     pub fn sys_pipe2(&mut self, fds: *mut u32, flags: usize) -> SysResult {
         info!("pipe2: fds: {:?}, flags: {:#x}", fds, flags);
+        if flags & !(O_NONBLOCK | O_CLOEXEC) != 0 {
+            return Err(SysError::EINVAL);
+        }
 
         let mut proc = self.process();
         let fds = unsafe { self.vm().check_write_array(fds, 2)? };
@@ -1138,7 +1490,7 @@ impl Syscall<'_> {
                 read: false,
                 write: true,
                 append: false,
-                nonblock: false,
+                nonblock: (flags & O_NONBLOCK) != 0,
             },
             String::from("pipe_w:[]"),
             true,
@@ -1153,6 +1505,144 @@ impl Syscall<'_> {
         Ok(0)
     }
 
+    /// Create a file reading the signals in `mask` pending for the reader,
+    /// or change the mask of the signalfd `fd` if it is not -1
+    pub fn sys_signalfd4(
+        &mut self,
+        fd: usize,
+        mask: UserInPtr<Sigset>,
+        sizemask: usize,
+        flags: usize,
+    ) -> SysResult {
+        info!(
+            "signalfd4: fd: {}, mask: {:?}, sizemask: {}, flags: {:#x}",
+            fd as isize, mask, sizemask, flags
+        );
+        if sizemask != core::mem::size_of::<Sigset>() || flags & !(O_NONBLOCK | O_CLOEXEC) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let mut mask = mask.read()?;
+        // they are never queued
+        mask.remove_unblockable();
+
+        let mut proc = self.process();
+        if fd as isize != -1 {
+            let file = match proc.get_file_like(fd)? {
+                FileLike::File(file) => file,
+                _ => return Err(SysError::EINVAL),
+            };
+            let inode = file.inode();
+            let signalfd = inode
+                .as_any_ref()
+                .downcast_ref::<SignalFd>()
+                .ok_or(SysError::EINVAL)?;
+            signalfd.set_mask(mask);
+            return Ok(fd);
+        }
+        let fd = proc.add_file(FileLike::File(FileHandle::new(
+            Arc::new(SignalFd::new(mask)),
+            OpenOptions {
+                read: true,
+                write: false,
+                append: false,
+                nonblock: (flags & O_NONBLOCK) != 0,
+            },
+            String::from("anon_inode:[signalfd]"),
+            true,
+            (flags & O_CLOEXEC) != 0,
+        )));
+        Ok(fd)
+    }
+
+    /// Create a timer notifying by a file descriptor
+    pub fn sys_timerfd_create(&mut self, clock: usize, flags: usize) -> SysResult {
+        info!("timerfd_create: clock: {}, flags: {:#x}", clock, flags);
+        if flags & !(O_NONBLOCK | O_CLOEXEC) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let realtime = match clock {
+            CLOCK_REALTIME => true,
+            CLOCK_MONOTONIC | CLOCK_BOOTTIME => false,
+            _ => return Err(SysError::EINVAL),
+        };
+        let fd = self.process().add_file(FileLike::File(FileHandle::new(
+            Arc::new(TimerFd::new(realtime)),
+            OpenOptions {
+                read: true,
+                write: false,
+                append: false,
+                nonblock: (flags & O_NONBLOCK) != 0,
+            },
+            String::from("anon_inode:[timerfd]"),
+            true,
+            (flags & O_CLOEXEC) != 0,
+        )));
+        Ok(fd)
+    }
+
+    /// Arm or disarm the timer of `fd`, and get the old setting
+    pub fn sys_timerfd_settime(
+        &mut self,
+        fd: usize,
+        flags: usize,
+        new_value: UserInPtr<ITimerSpec>,
+        mut old_value: UserOutPtr<ITimerSpec>,
+    ) -> SysResult {
+        info!(
+            "timerfd_settime: fd: {}, flags: {:#x}, new_value: {:?}, old_value: {:?}",
+            fd, flags, new_value, old_value
+        );
+        if flags & !(TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let new = new_value.read()?;
+        let value = new.value.checked_duration().ok_or(SysError::EINVAL)?;
+        let interval = new.interval.checked_duration().ok_or(SysError::EINVAL)?;
+        let inode = self.timerfd_inode(fd)?;
+        let timerfd = inode.as_any_ref().downcast_ref::<TimerFd>().unwrap();
+        let now = timer_now();
+        // the deadline in `timer_now`
+        let deadline = if value == Duration::from_secs(0) {
+            None
+        } else if flags & TFD_TIMER_ABSTIME == 0 {
+            Some(now + value)
+        } else if timerfd.realtime {
+            Some(now + value.checked_sub(realtime()).unwrap_or_default())
+        } else {
+            Some(value)
+        };
+        let old = timerfd.set(deadline, interval);
+        if !old_value.is_null() {
+            old_value.write(old.into())?;
+        }
+        Ok(0)
+    }
+
+    /// Get the time left and the interval of the timer of `fd`
+    pub fn sys_timerfd_gettime(
+        &mut self,
+        fd: usize,
+        mut curr_value: UserOutPtr<ITimerSpec>,
+    ) -> SysResult {
+        info!("timerfd_gettime: fd: {}, curr_value: {:?}", fd, curr_value);
+        let inode = self.timerfd_inode(fd)?;
+        let timerfd = inode.as_any_ref().downcast_ref::<TimerFd>().unwrap();
+        curr_value.write(timerfd.get().into())?;
+        Ok(0)
+    }
+
+    /// The inode of `fd`, or EINVAL if it is not a timerfd
+    fn timerfd_inode(&self, fd: usize) -> Result<Arc<dyn INode>, SysError> {
+        let inode = match self.process().get_file_like(fd)? {
+            FileLike::File(file) => file.inode(),
+            _ => return Err(SysError::EINVAL),
+        };
+        if inode.as_any_ref().downcast_ref::<TimerFd>().is_none() {
+            return Err(SysError::EINVAL);
+        }
+        Ok(inode)
+    }
+
     pub fn sys_utimensat(
         &mut self,
         dirfd: usize,
@@ -1166,55 +1656,72 @@ impl Syscall<'_> {
         );
         const UTIME_NOW: usize = 0x3fffffff;
         const UTIME_OMIT: usize = 0x3ffffffe;
-        let mut proc = self.process();
-        let mut times = if times.is_null() {
-            let epoch = TimeSpec::get_epoch();
-            [epoch, epoch]
+        let proc = self.process();
+        let times = if times.is_null() {
+            let now = TimeSpec {
+                sec: 0,
+                nsec: UTIME_NOW,
+            };
+            [now, now]
         } else {
             let times = unsafe { self.vm().check_read_array(times, 2)? };
             [times[0], times[1]]
         };
-        let mut inode = if pathname.is_null() {
+        for time in times.iter() {
+            if time.nsec != UTIME_NOW && time.nsec != UTIME_OMIT && time.nsec >= 1_000_000_000 {
+                return Err(EINVAL);
+            }
+        }
+        let inode = if pathname.is_null() {
             let fd = dirfd;
             info!("futimens: fd: {}, times: {:?}", fd, times);
-            proc.get_file(fd)?.inode()
+            proc.get_file_const(fd)?.inode()
         } else {
             let pathname = check_and_clone_cstr(pathname)?;
             info!(
                 "utimensat: dirfd: {}, pathname: {}, times: {:?}, flags: {:#x}",
                 dirfd as i64, pathname, times, flags
             );
-            let follow = match flags {
-                0 => true,
-                fcntl::AT_SYMLINK_NOFOLLOW => false,
-                _ => return Err(EINVAL),
-            };
-            proc.lookup_inode_at(dirfd, &pathname, follow)?
+            let flags = AtFlags::from_bits(flags).ok_or(EINVAL)?;
+            if flags.intersects(!(AtFlags::SYMLINK_NOFOLLOW | AtFlags::EMPTY_PATH)) {
+                return Err(EINVAL);
+            }
+            proc.resolve_path(dirfd, &pathname, flags)?
         };
+        if times[0].nsec == UTIME_OMIT && times[1].nsec == UTIME_OMIT {
+            return Ok(0);
+        }
+
         let mut metadata = inode.metadata()?;
-        if times[0].nsec != UTIME_OMIT {
-            if times[0].nsec == UTIME_NOW {
-                times[0] = TimeSpec::get_epoch();
+        // setting both times to now only requires write permission,
+        // anything else requires ownership
+        if proc.uid != 0 && proc.uid != metadata.uid {
+            if times[0].nsec != UTIME_NOW || times[1].nsec != UTIME_NOW {
+                return Err(SysError::EPERM);
             }
-            metadata.atime = rcore_fs::vfs::Timespec {
-                sec: times[0].sec as i64,
-                nsec: times[0].nsec as i32,
-            };
+            proc.check_permission(&metadata, AccessMode::WRITE)?;
         }
-        if times[1].nsec != UTIME_OMIT {
-            if times[1].nsec == UTIME_NOW {
-                times[1] = TimeSpec::get_epoch();
-            }
-            metadata.mtime = rcore_fs::vfs::Timespec {
-                sec: times[1].sec as i64,
-                nsec: times[1].nsec as i32,
-            };
+        let now = TimeSpec::get_epoch();
+        let resolve = |time: &TimeSpec| match time.nsec {
+            UTIME_NOW => Some(now),
+            UTIME_OMIT => None,
+            _ => Some(*time),
+        };
+        if let Some(atime) = resolve(&times[0]) {
+            metadata.atime = atime.into();
         }
+        if let Some(mtime) = resolve(&times[1]) {
+            metadata.mtime = mtime.into();
+        }
+        metadata.ctime = now.into();
         inode.set_metadata(&metadata)?;
         Ok(0)
     }
 
     pub fn sys_sync(&mut self) -> SysResult {
+        info!("sync");
+        // write back cached pages, then dirty blocks of the root file system
+        page_cache::sync_all()?;
         ROOT_INODE.fs().sync()?;
         Ok(0)
     }
@@ -1223,11 +1730,66 @@ impl Syscall<'_> {
         &mut self,
         out_fd: usize,
         in_fd: usize,
-        offset_ptr: UserInOutPtr<usize>,
+        mut offset_ptr: UserInOutPtr<usize>,
         count: usize,
     ) -> SysResult {
-        self.sys_copy_file_range(in_fd, offset_ptr, out_fd, UserInOutPtr::from(0), count, 0)
-            .await
+        info!(
+            "sendfile: out: {}, in: {}, offset_ptr: {:?}, count: {}",
+            out_fd, in_fd, offset_ptr, count
+        );
+        let proc = self.process();
+        // the input must be a file, while the output can be anything writable, e.g. a socket
+        let mut in_file = proc.get_file_const(in_fd)?.clone();
+        let mut out_file = proc.files.get(&out_fd).ok_or(SysError::EBADF)?.clone();
+        drop(proc);
+        if in_file.pipe {
+            return Err(SysError::EINVAL);
+        }
+
+        // null offset_ptr means reading from and updating the file offset
+        let mut read_offset = if !offset_ptr.is_null() {
+            offset_ptr.read()?
+        } else {
+            in_file.seek(SeekFrom::Current(0))? as usize
+        };
+
+        let mut buffer = vec![0u8; SENDFILE_BUF_SIZE];
+        let mut total_written = 0;
+        'outer: while total_written < count {
+            let len = min(buffer.len(), count - total_written);
+            let read_len = in_file.read_at(read_offset, &mut buffer[..len]).await?;
+            if read_len == 0 {
+                break;
+            }
+            let mut bytes_written = 0;
+            while bytes_written < read_len {
+                match out_file.write(&buffer[bytes_written..read_len]).await {
+                    Ok(0) => break 'outer,
+                    Ok(write_len) => bytes_written += write_len,
+                    // return what has been done so far
+                    Err(_) if total_written + bytes_written > 0 => {
+                        total_written += bytes_written;
+                        read_offset += bytes_written;
+                        break 'outer;
+                    }
+                    Err(err) => {
+                        let res = Err(err);
+                        self.check_broken_pipe(&res);
+                        return res;
+                    }
+                }
+            }
+            total_written += bytes_written;
+            read_offset += bytes_written;
+            crate::sched::cond_resched().await;
+        }
+
+        if !offset_ptr.is_null() {
+            offset_ptr.write(read_offset)?;
+        } else {
+            in_file.seek(SeekFrom::Start(read_offset as u64))?;
+        }
+        Ok(total_written)
     }
 
     pub async fn sys_copy_file_range(
@@ -1244,11 +1806,10 @@ impl Syscall<'_> {
             in_fd, out_fd, in_offset, out_offset, count, flags
         );
         let proc = self.process();
-        // We know it's save, pacify the borrow checker
-        let proc_cell = UnsafeCell::new(proc);
-        let in_file = unsafe { (*proc_cell.get()).get_file(in_fd)? };
-        let out_file = unsafe { (*proc_cell.get()).get_file(out_fd)? };
-        drop(proc_cell);
+        // the handles share the open file descriptions with the fds
+        let mut in_file = proc.get_file_const(in_fd)?.clone();
+        let mut out_file = proc.get_file_const(out_fd)?.clone();
+        drop(proc);
         let mut buffer = [0u8; 1024];
 
         // for in_offset and out_offset
@@ -1283,7 +1844,9 @@ impl Syscall<'_> {
             let mut bytes_written = 0;
             let mut rlen = read_len;
             while bytes_written < read_len {
-                let write_len = out_file.write(&buffer[bytes_written..(bytes_written + rlen)])?;
+                let write_len = out_file
+                    .write(&buffer[bytes_written..(bytes_written + rlen)])
+                    .await?;
                 if write_len == 0 {
                     info!(
                         "copy_file_range:END_ERR in: {}, out: {}, in_offset: {:?}, out_offset: {:?}, count: {} = bytes_read {}, bytes_written {}, write_len {}",
@@ -1295,6 +1858,7 @@ impl Syscall<'_> {
                 rlen -= write_len;
             }
             total_written += bytes_written;
+            crate::sched::cond_resched().await;
         }
 
         if !in_offset.is_null() {
@@ -1316,43 +1880,387 @@ impl Syscall<'_> {
 
     pub fn sys_fcntl(&mut self, fd: usize, cmd: usize, arg: usize) -> SysResult {
         info!("fcntl: fd: {}, cmd: {:#x}, arg: {}", fd, cmd, arg);
+        use crate::fs::fcntl::*;
         let mut proc = self.process();
         let file_like = proc.get_file_like(fd)?;
+        match cmd {
+            F_DUPFD | F_DUPFD_CLOEXEC => {
+                if arg >= MAX_FILES {
+                    return Err(SysError::EINVAL);
+                }
+                let new_fd = proc.get_free_fd_from(arg);
+                if new_fd >= MAX_FILES {
+                    return Err(SysError::EMFILE);
+                }
+                core::mem::drop(proc);
+                return self.dup_impl(fd, new_fd, cmd == F_DUPFD_CLOEXEC);
+            }
+            _ => {}
+        }
         match file_like {
-            FileLike::File(file) => {
-                use crate::fs::fcntl::*;
-                match cmd {
-                    F_SETFD => {
-                        file.fd_cloexec = (arg & 1) != 0;
-                        Ok(0)
-                    }
-                    F_GETFD => Ok(file.fd_cloexec as usize),
-                    F_SETFL => {
-                        file.set_options(arg);
-                        Ok(0)
-                    }
-                    F_GETFL => self.unimplemented("F_GETFL", Ok(0)),
-                    F_DUPFD_CLOEXEC => {
-                        info!("fcntl: dupfd_cloexec: arg: {:#x}", arg);
-                        // let file_like = proc.get_file_like(fd1)?.clone();
-                        let new_fd = proc.get_free_fd_from(arg);
-                        core::mem::drop(proc);
-                        self.dup_impl(fd, new_fd, 1)
-                    }
-                    _ => Ok(0),
+            FileLike::File(file) => match cmd {
+                F_SETFD => {
+                    file.fd_cloexec = (arg & FD_CLOEXEC) != 0;
+                    Ok(0)
+                }
+                F_GETFD => Ok(file.fd_cloexec as usize),
+                F_SETFL => {
+                    file.set_options(arg);
+                    Ok(0)
+                }
+                F_GETFL => Ok(file.get_options()),
+                _ => Err(SysError::EINVAL),
+            },
+            // sockets always block and are kept open across exec
+            FileLike::Socket(_) => match cmd {
+                F_SETFD if (arg & FD_CLOEXEC) == 0 => Ok(0),
+                F_GETFD => Ok(0),
+                F_SETFL if (arg & O_NONBLOCK) == 0 => Ok(0),
+                F_GETFL => Ok(O_RDWR),
+                _ => Err(SysError::EINVAL),
+            },
+            FileLike::EpollInstance(epoll) => match cmd {
+                F_SETFD => {
+                    epoll.fd_cloexec = (arg & FD_CLOEXEC) != 0;
+                    Ok(0)
+                }
+                F_GETFD => Ok(epoll.fd_cloexec as usize),
+                F_SETFL if (arg & O_NONBLOCK) == 0 => Ok(0),
+                F_GETFL => Ok(O_RDWR),
+                _ => Err(SysError::EINVAL),
+            },
+        }
+    }
+
+    /// The record locking commands of fcntl: F_GETLK, F_SETLK and F_SETLKW
+    pub async fn sys_fcntl_lock(
+        &mut self,
+        fd: usize,
+        cmd: usize,
+        mut flock_ptr: UserInOutPtr<Flock>,
+    ) -> SysResult {
+        use crate::fs::fcntl::*;
+        info!(
+            "fcntl lock: fd: {}, cmd: {}, flock: {:?}",
+            fd, cmd, flock_ptr
+        );
+        let proc = self.process();
+        let mut file = proc.get_file_const(fd)?.clone();
+        let pid = proc.pid.get();
+        drop(proc);
+        let mut flock = flock_ptr.read()?;
+        info!("fcntl lock: {:?}", flock);
+
+        let base = match flock.whence as u8 {
+            SEEK_SET => 0,
+            SEEK_CUR => file.seek(SeekFrom::Current(0))? as i64,
+            SEEK_END => file.metadata()?.size as i64,
+            _ => return Err(SysError::EINVAL),
+        };
+        let start = base
+            .checked_add(flock.start as i64)
+            .ok_or(SysError::EOVERFLOW)?;
+        let (start, end) = match flock.len as i64 {
+            0 => (start, i64::max_value()),
+            len if len > 0 => (start, start.checked_add(len).ok_or(SysError::EOVERFLOW)?),
+            len => (start.checked_add(len).ok_or(SysError::EINVAL)?, start),
+        };
+        if start < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let kind = match flock.type_ as usize {
+            F_RDLCK => Some(LockKind::Read),
+            F_WRLCK => Some(LockKind::Write),
+            F_UNLCK => None,
+            _ => return Err(SysError::EINVAL),
+        };
+        let inode = file.inode();
+
+        if cmd == F_GETLK {
+            let lock = RangeLock {
+                kind: kind.ok_or(SysError::EINVAL)?,
+                start: start as u64,
+                end: end as u64,
+                pid,
+            };
+            match test_range_lock(&inode, &lock)? {
+                Some(other) => {
+                    flock.type_ = match other.kind {
+                        LockKind::Read => F_RDLCK,
+                        LockKind::Write => F_WRLCK,
+                    } as i16;
+                    flock.whence = SEEK_SET as i16;
+                    flock.start = other.start as isize;
+                    flock.len = match other.end {
+                        end if end == i64::max_value() as u64 => 0,
+                        end => (end - other.start) as isize,
+                    };
+                    flock.pid = other.pid as i32;
+                }
+                None => flock.type_ = F_UNLCK as i16,
+            }
+            flock_ptr.write(flock)?;
+            return Ok(0);
+        }
+
+        let kind = match kind {
+            Some(kind) => kind,
+            None => {
+                release_range_lock(&inode, pid, start as u64, end as u64)?;
+                return Ok(0);
+            }
+        };
+        let options = file.get_options() & 0b11;
+        let permitted = match kind {
+            LockKind::Read => options != O_WRONLY,
+            LockKind::Write => options != O_RDONLY,
+        };
+        if !permitted {
+            return Err(SysError::EBADF);
+        }
+        let lock = RangeLock {
+            kind,
+            start: start as u64,
+            end: end as u64,
+            pid,
+        };
+        if cmd == F_SETLK {
+            return match try_range_lock(&inode, lock)? {
+                true => Ok(0),
+                false => Err(SysError::EAGAIN),
+            };
+        }
+        LockFuture::new(
+            || -> Result<bool, SysError> { Ok(try_range_lock(&inode, lock)?) },
+            self,
+        )
+        .await
+    }
+}
+
+/// The timeout of `msecs` in milliseconds, negative for infinity
+fn msecs_to_timeout(msecs: i32) -> Option<Duration> {
+    if msecs < 0 {
+        None
+    } else {
+        Some(Duration::from_millis(msecs as u64))
+    }
+}
+
+#[must_use = "future does nothing unless polled/`await`-ed"]
+/// Wait until any of `polls` is ready, the `deadline` passes, or a signal arrives.
+/// Shared by poll, select and epoll.
+struct PollFuture<'a> {
+    polls: &'a mut Vec<PollFd>,
+    syscall: &'a Syscall<'a>,
+    deadline: Option<Duration>,
+    /// Registered to wake it up the first time it waits, until it is dropped
+    waker: Option<Waker>,
+    timer: Option<TimerId>,
+    subscription: Option<(Arc<Mutex<EventBus>>, usize)>,
+}
+
+impl<'a> PollFuture<'a> {
+    /// Wait no longer than `timeout`, or for ever if None
+    fn new(
+        polls: &'a mut Vec<PollFd>,
+        syscall: &'a Syscall<'a>,
+        timeout: Option<Duration>,
+    ) -> Self {
+        PollFuture {
+            polls,
+            syscall,
+            // too far to be reached
+            deadline: timeout.and_then(|timeout| timer_now().checked_add(timeout)),
+            waker: None,
+            timer: None,
+            subscription: None,
+        }
+    }
+}
+
+impl<'a> Drop for PollFuture<'a> {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            crate::timer::cancel(timer);
+        }
+        if let Some(waker) = self.waker.take() {
+            SOCKET_ACTIVITY.unregister_waker(&waker);
+        }
+        if let Some((eventbus, id)) = self.subscription.take() {
+            eventbus.lock().unsubscribe(id);
+        }
+    }
+}
+
+impl<'a> Future for PollFuture<'a> {
+    type Output = SysResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        use PollEvents as PE;
+        let syscall = self.syscall;
+        // files are polled with the process unlocked, as some lock it, e.g. signalfd
+        let (files, eventbus) = {
+            let proc = syscall.process();
+            let files: Vec<_> = self
+                .polls
+                .iter()
+                .map(|poll| proc.files.get(&(poll.fd as usize)).cloned())
+                .collect();
+            (files, proc.eventbus.clone())
+        };
+        let mut events = 0;
+
+        // iterate each poll to check whether it is ready
+        for (poll, file_like) in self.as_mut().polls.iter_mut().zip(files.iter()) {
+            poll.revents = PE::empty();
+            if (poll.fd as i32) < 0 {
+                // negative fd is ignored
+                continue;
+            }
+            if let Some(file_like) = file_like {
+                let mut fut = Box::pin(file_like.async_poll());
+                let status = match fut.as_mut().poll(cx) {
+                    Poll::Ready(Ok(ret)) => ret,
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => continue,
+                };
+                if status.error {
+                    poll.revents |= PE::HUP;
+                }
+                if status.read && poll.events.contains(PE::IN) {
+                    poll.revents |= PE::IN;
                 }
+                if status.write && poll.events.contains(PE::OUT) {
+                    poll.revents |= PE::OUT;
+                }
+            } else {
+                poll.revents |= PE::INVAL;
             }
-            FileLike::Socket(_) => {
-                Ok(0)
-                //TODO
+            if !poll.revents.is_empty() {
+                events += 1;
+            }
+        }
+
+        // some event happens, so evoke the process
+        if events > 0 {
+            return Poll::Ready(Ok(events));
+        }
+        if let Some(deadline) = self.deadline {
+            if timer_now() >= deadline {
+                return Poll::Ready(Ok(0));
+            }
+        }
+        if syscall.thread.has_signal_to_handle() {
+            return Poll::Ready(Err(EINTR));
+        }
+
+        // wake up on timeout, socket activity or signal.
+        // Socket activity wakes up those registered once, so it is registered again.
+        let waker = cx.waker().clone();
+        if let Some(old) = self.waker.replace(waker.clone()) {
+            SOCKET_ACTIVITY.unregister_waker(&old);
+        }
+        SOCKET_ACTIVITY.register_waker(waker.clone());
+        if self.timer.is_none() {
+            if let Some(deadline) = self.deadline {
+                let waker = waker.clone();
+                self.timer = Some(crate::timer::add(deadline, Box::new(move |_| waker.wake())));
             }
-            FileLike::EpollInstance(_) => Ok(0),
         }
+        if self.subscription.is_none() {
+            let id = eventbus.lock().subscribe(Box::new(move |_| {
+                waker.wake_by_ref();
+                false
+            }));
+            self.subscription = Some((eventbus, id));
+        }
+        return Poll::Pending;
+    }
+}
+
+#[must_use = "future does nothing unless polled/`await`-ed"]
+/// Retry `try_lock` whenever a file lock is released, until it succeeds or a signal arrives.
+/// Shared by flock and fcntl F_SETLKW.
+struct LockFuture<'a, F> {
+    try_lock: F,
+    syscall: &'a Syscall<'a>,
+    /// Registered to wake it up on a release, until it is dropped
+    waker: Option<Waker>,
+    subscription: Option<(Arc<Mutex<EventBus>>, usize)>,
+}
+
+impl<'a, F> LockFuture<'a, F> {
+    fn new(try_lock: F, syscall: &'a Syscall<'a>) -> Self {
+        LockFuture {
+            try_lock,
+            syscall,
+            waker: None,
+            subscription: None,
+        }
+    }
+}
+
+impl<'a, F> Drop for LockFuture<'a, F> {
+    fn drop(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            LOCK_RELEASED.unregister_waker(&waker);
+        }
+        if let Some((eventbus, id)) = self.subscription.take() {
+            eventbus.lock().unsubscribe(id);
+        }
+    }
+}
+
+impl<'a, F> Future for LockFuture<'a, F>
+where
+    F: FnMut() -> Result<bool, SysError> + Unpin,
+{
+    type Output = SysResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        // register before trying, so that a release in between is not missed.
+        // A release wakes up those registered once, so it is registered again.
+        let waker = cx.waker().clone();
+        if let Some(old) = this.waker.replace(waker.clone()) {
+            LOCK_RELEASED.unregister_waker(&old);
+        }
+        LOCK_RELEASED.register_waker(waker.clone());
+        match (this.try_lock)() {
+            Ok(true) => return Poll::Ready(Ok(0)),
+            Ok(false) => {}
+            Err(err) => return Poll::Ready(Err(err)),
+        }
+        if this.syscall.thread.has_signal_to_handle() {
+            return Poll::Ready(Err(EINTR));
+        }
+
+        // wake up on signal
+        if this.subscription.is_none() {
+            let eventbus = this.syscall.process().eventbus.clone();
+            let id = eventbus.lock().subscribe(Box::new(move |_| {
+                waker.wake_by_ref();
+                false
+            }));
+            this.subscription = Some((eventbus, id));
+        }
+        Poll::Pending
     }
 }
 
 impl Process {
     pub fn get_file_like(&mut self, fd: usize) -> Result<&mut FileLike, SysError> {
+        if !self.files.contains_key(&fd) {
+            return Err(SysError::EBADF);
+        }
+        // using the fd may consume its readiness, so edge-triggered epoll instances
+        // report it on the next readiness event, even if it is not seen idle
+        for file_like in self.files.values() {
+            if let FileLike::EpollInstance(epoll) = file_like {
+                epoll.ready_list.lock().remove(&fd);
+            }
+        }
         self.files.get_mut(&fd).ok_or(SysError::EBADF)
     }
     pub fn get_file(&mut self, fd: usize) -> Result<&mut FileHandle, SysError> {
@@ -1393,6 +2301,29 @@ impl Process {
             "/proc/self/exe" => {
                 return Ok(Arc::new(Pseudo::new(&self.exec_path, FileType::SymLink)));
             }
+            "/proc/meminfo" => {
+                let content = format!("{}", MemInfo::get());
+                return Ok(Arc::new(Pseudo::new(&content, FileType::File)));
+            }
+            "/proc/stat" => {
+                let content = format!("{}", Stat::get());
+                return Ok(Arc::new(Pseudo::new(&content, FileType::File)));
+            }
+            "/proc/loadavg" => {
+                let content = format!("{}", LoadAvg::get());
+                return Ok(Arc::new(Pseudo::new(&content, FileType::File)));
+            }
+            "/proc/self/status" => {
+                let mut vm = self.vm.lock();
+                let content = format!(
+                    "Pid:\t{}\nVmSize:\t{:>8} kB\nVmLck:\t{:>8} kB\nVmRSS:\t{:>8} kB\n",
+                    self.pid.get(),
+                    vm.total_pages() * PAGE_SIZE / 1024,
+                    vm.total_locked_pages() * PAGE_SIZE / 1024,
+                    vm.resident_pages() * PAGE_SIZE / 1024,
+                );
+                return Ok(Arc::new(Pseudo::new(&content, FileType::File)));
+            }
             _ => {}
         }
         let (fd_dir_path, fd_name) = split_path(&path);
@@ -1405,23 +2336,161 @@ impl Process {
             _ => {}
         }
 
-        let follow_max_depth = if follow { FOLLOW_MAX_DEPTH } else { 0 };
         if dirfd == AT_FDCWD {
-            Ok(ROOT_INODE
-                .lookup(&self.cwd)?
-                .lookup_follow(path, follow_max_depth)?)
+            let cwd = ROOT_INODE.lookup(&self.cwd)?;
+            Ok(lookup_follow_at(&cwd, path, follow)?)
         } else {
             let file = match self.files.get(&dirfd).ok_or(SysError::EBADF)? {
                 FileLike::File(file) => file,
                 _ => return Err(SysError::EBADF),
             };
-            Ok(file.lookup_follow(path, follow_max_depth)?)
+            Ok(file.lookup_follow(path, follow)?)
         }
     }
 
     pub fn lookup_inode(&self, path: &str) -> Result<Arc<dyn INode>, SysError> {
         self.lookup_inode_at(AT_FDCWD, path, true)
     }
+
+    /// Resolve `path` for the *at family of syscalls.
+    ///
+    /// - If `flags` contains `AT_SYMLINK_NOFOLLOW`, the last symlink is not followed.
+    ///
+    /// - If `path` is empty, it refers to `dirfd` itself when `flags` contains
+    ///   `AT_EMPTY_PATH`, or is an error otherwise.
+    fn resolve_path(
+        &self,
+        dirfd: usize,
+        path: &str,
+        flags: AtFlags,
+    ) -> Result<Arc<dyn INode>, SysError> {
+        if path.is_empty() {
+            if !flags.contains(AtFlags::EMPTY_PATH) {
+                return Err(SysError::ENOENT);
+            }
+            if dirfd == AT_FDCWD {
+                return Ok(ROOT_INODE.lookup(&self.cwd)?);
+            }
+            return Ok(self.get_file_const(dirfd)?.inode());
+        }
+        self.lookup_inode_at(dirfd, path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))
+    }
+
+    /// Check whether the process is allowed to access a file in `mode`
+    ///
+    /// Root is granted read and write access to everything, and execute
+    /// access to directories and files with any execute bit set.
+    fn check_permission(&self, metadata: &Metadata, mode: AccessMode) -> Result<(), SysError> {
+        let perm = metadata.mode as usize;
+        if self.uid == 0 {
+            if mode.contains(AccessMode::EXEC)
+                && metadata.type_ != FileType::Dir
+                && perm & 0o111 == 0
+            {
+                return Err(SysError::EACCES);
+            }
+            return Ok(());
+        }
+        let granted = if self.uid == metadata.uid {
+            perm >> 6
+        } else if self.gid == metadata.gid {
+            perm >> 3
+        } else {
+            perm
+        } & 0o7;
+        if mode.bits() & !granted != 0 {
+            return Err(SysError::EACCES);
+        }
+        Ok(())
+    }
+
+    /// Make the process the owner of a newly created `inode`
+    pub fn set_owner(&self, inode: &Arc<dyn INode>) -> Result<(), SysError> {
+        let mut metadata = inode.metadata()?;
+        if metadata.uid != self.uid || metadata.gid != self.gid {
+            metadata.uid = self.uid;
+            metadata.gid = self.gid;
+            inode.set_metadata(&metadata)?;
+        }
+        Ok(())
+    }
+
+    /// Change the permission bits of `inode`, which must be owned by the process
+    fn chmod_inode(&self, inode: &Arc<dyn INode>, mode: usize) -> SysResult {
+        let mut metadata = inode.metadata()?;
+        if self.uid != 0 && self.uid != metadata.uid {
+            return Err(SysError::EPERM);
+        }
+        metadata.mode = (mode & 0o7777) as _;
+        inode.set_metadata(&metadata)?;
+        TimeSpec::update_ctime(inode);
+        Ok(0)
+    }
+
+    /// Change the owner and group of `inode`, where -1 leaves an id unchanged
+    ///
+    /// Only root may change the owner. The owner may change the group to
+    /// its own group.
+    fn chown_inode(&self, inode: &Arc<dyn INode>, owner: usize, group: usize) -> SysResult {
+        let mut metadata = inode.metadata()?;
+        if owner as i32 != -1 && owner as u32 as usize != metadata.uid {
+            if self.uid != 0 {
+                return Err(SysError::EPERM);
+            }
+            metadata.uid = owner as u32 as usize;
+        }
+        if group as i32 != -1 && group as u32 as usize != metadata.gid {
+            if self.uid != 0 && (self.uid != metadata.uid || self.gid != group as u32 as usize) {
+                return Err(SysError::EPERM);
+            }
+            metadata.gid = group as u32 as usize;
+        }
+        inode.set_metadata(&metadata)?;
+        TimeSpec::update_ctime(inode);
+        Ok(0)
+    }
+
+    /// Change the working directory to `path`, resolving '.' and '..'
+    pub fn change_cwd(&mut self, path: &str) {
+        self.cwd = join_path(&self.cwd, path);
+    }
+
+    /// Get the absolute path of `path` relative to `dirfd`, resolving '.' and '..'
+    pub fn absolute_path(&self, dirfd: usize, path: &str) -> Result<String, SysError> {
+        if dirfd == AT_FDCWD || path.starts_with('/') {
+            Ok(join_path(&self.cwd, path))
+        } else {
+            Ok(join_path(&self.get_file_const(dirfd)?.path, path))
+        }
+    }
+}
+
+/// Join `path` to the absolute path `base`, resolving '.' and '..'
+fn join_path(base: &str, path: &str) -> String {
+    let base = match path.as_bytes().first() {
+        Some(b'/') => "/",
+        _ => base,
+    };
+    let mut base_vec: Vec<_> = base.split("/").filter(|&x| x != "").collect();
+    let path_split = path.split("/").filter(|&x| x != "");
+    for seg in path_split {
+        if seg == ".." {
+            base_vec.pop();
+        } else if seg == "." {
+            // nothing to do here.
+        } else {
+            base_vec.push(seg);
+        }
+    }
+    let mut new_path = String::from("");
+    for seg in base_vec {
+        new_path.push_str("/");
+        new_path.push_str(seg);
+    }
+    if new_path == "" {
+        new_path = String::from("/");
+    }
+    new_path
 }
 
 /// Split a `path` str to `(base_path, file_name)`
@@ -1446,7 +2515,7 @@ impl From<FsError> for SysError {
             FsError::EntryExist => SysError::EEXIST,
             FsError::NotSameFs => SysError::EXDEV,
             FsError::InvalidParam => SysError::EINVAL,
-            FsError::NoDeviceSpace => SysError::ENOMEM,
+            FsError::NoDeviceSpace => SysError::ENOSPC,
             FsError::DirRemoved => SysError::ENOENT,
             FsError::DirNotEmpty => SysError::ENOTEMPTY,
             FsError::WrongFs => SysError::EINVAL,
@@ -1461,10 +2530,34 @@ impl From<FsError> for SysError {
     }
 }
 
+bitflags! {
+    struct RenameFlags: usize {
+        /// Don't overwrite newpath of the rename
+        const NOREPLACE = 1;
+        /// Atomically exchange oldpath and newpath
+        const EXCHANGE = 2;
+        /// Whiteout oldpath
+        const WHITEOUT = 4;
+    }
+}
+
 bitflags! {
     struct AtFlags: usize {
         const EMPTY_PATH = 0x1000;
         const SYMLINK_NOFOLLOW = 0x100;
+        const REMOVEDIR = 0x200;
+        const SYMLINK_FOLLOW = 0x400;
+    }
+}
+
+bitflags! {
+    struct AccessMode: usize {
+        /// test for execute or search permission
+        const EXEC = 1;
+        /// test for write permission
+        const WRITE = 2;
+        /// test for read permission
+        const READ = 4;
     }
 }
 
@@ -1482,8 +2575,26 @@ bitflags! {
         const EXCLUSIVE = 1 << 7;
         /// truncate file upon open
         const TRUNCATE = 1 << 9;
+        /// do not make the file the controlling terminal
+        const NOCTTY = 1 << 8;
         /// append on each write
         const APPEND = 1 << 10;
+        /// non-blocking I/O
+        const NONBLOCK = 1 << 11;
+        /// synchronized I/O data integrity
+        const DSYNC = 1 << 12;
+        /// signal-driven I/O
+        const ASYNC = 1 << 13;
+        /// bypass the page cache
+        const DIRECT = 1 << 14;
+        /// allow files whose size exceeds off_t
+        const LARGEFILE = 1 << 15;
+        /// fail if the file is not a directory
+        const DIRECTORY = 1 << 16;
+        /// fail if the file is a symbolic link
+        const NOFOLLOW = 1 << 17;
+        /// do not update the access time
+        const NOATIME = 1 << 18;
         /// close on exec
         const CLOEXEC = 1 << 19;
     }
@@ -1498,12 +2609,22 @@ impl OpenFlags {
         let b = self.bits() & 0b11;
         b == OpenFlags::WRONLY.bits() || b == OpenFlags::RDWR.bits()
     }
+    fn access_mode(&self) -> AccessMode {
+        let mut mode = AccessMode::empty();
+        if self.readable() {
+            mode |= AccessMode::READ;
+        }
+        if self.writable() || self.contains(OpenFlags::TRUNCATE) {
+            mode |= AccessMode::WRITE;
+        }
+        mode
+    }
     fn to_options(&self) -> OpenOptions {
         OpenOptions {
             read: self.readable(),
             write: self.writable(),
             append: self.contains(OpenFlags::APPEND),
-            nonblock: false,
+            nonblock: self.contains(OpenFlags::NONBLOCK),
         }
     }
 }
@@ -1538,7 +2659,7 @@ impl<'a> DirentBufWriter<'a> {
             buf,
         }
     }
-    fn try_write(&mut self, inode: u64, type_: u8, name: &str) -> bool {
+    fn try_write(&mut self, inode: u64, offset: u64, type_: u8, name: &str) -> bool {
         let len = ::core::mem::size_of::<LinuxDirent64>() + name.len() + 1;
         let len = (len + 7) / 8 * 8; // align up
         if self.rest_size < len {
@@ -1546,7 +2667,7 @@ impl<'a> DirentBufWriter<'a> {
         }
         let dent = LinuxDirent64 {
             ino: inode,
-            offset: 0,
+            offset,
             reclen: len as u16,
             type_,
             name: [],
@@ -1874,10 +2995,102 @@ impl From<Metadata> for Stat {
     }
 }
 
+#[cfg(not(target_arch = "mips"))]
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct StatFs {
+    /// type of file system
+    type_: usize,
+    /// optimal transfer block size
+    bsize: usize,
+    /// total data blocks in file system
+    blocks: usize,
+    /// free blocks in file system
+    bfree: usize,
+    /// free blocks available to unprivileged user
+    bavail: usize,
+    /// total file nodes in file system
+    files: usize,
+    /// free file nodes in file system
+    ffree: usize,
+    /// file system id
+    fsid: [u32; 2],
+    /// maximum length of filenames
+    namelen: usize,
+    /// fragment size
+    frsize: usize,
+    /// mount flags of file system
+    flags: usize,
+    /// padding
+    __spare: [usize; 4],
+}
+
+#[cfg(target_arch = "mips")]
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct StatFs {
+    /// type of file system
+    type_: usize,
+    /// optimal transfer block size
+    bsize: usize,
+    /// fragment size
+    frsize: usize,
+    /// total data blocks in file system
+    blocks: usize,
+    /// free blocks in file system
+    bfree: usize,
+    /// total file nodes in file system
+    files: usize,
+    /// free file nodes in file system
+    ffree: usize,
+    /// free blocks available to unprivileged user
+    bavail: usize,
+    /// file system id
+    fsid: [u32; 2],
+    /// maximum length of filenames
+    namelen: usize,
+    /// mount flags of file system
+    flags: usize,
+    /// padding
+    __spare: [usize; 5],
+}
+
+impl From<FsInfo> for StatFs {
+    fn from(info: FsInfo) -> Self {
+        StatFs {
+            bsize: info.bsize,
+            blocks: info.blocks,
+            bfree: info.bfree,
+            bavail: info.bavail,
+            files: info.files,
+            ffree: info.ffree,
+            namelen: info.namemax,
+            frsize: info.frsize,
+            ..StatFs::default()
+        }
+    }
+}
+
 const SEEK_SET: u8 = 0;
 const SEEK_CUR: u8 = 1;
 const SEEK_END: u8 = 2;
 
+/// Record lock description used by fcntl
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Flock {
+    /// type of lock: F_RDLCK, F_WRLCK or F_UNLCK
+    type_: i16,
+    /// how to interpret start: SEEK_SET, SEEK_CUR or SEEK_END
+    whence: i16,
+    /// starting offset for lock
+    start: isize,
+    /// number of bytes to lock, 0 means until EOF
+    len: isize,
+    /// pid of the process blocking our lock, for F_GETLK only
+    pid: i32,
+}
+
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct IoVec {
@@ -2034,3 +3247,11 @@ impl FdSet {
 
 /// Pathname is interpreted relative to the current working directory(CWD)
 const AT_FDCWD: usize = -100isize as usize;
+
+/// Size of the kernel buffer used by sendfile
+const SENDFILE_BUF_SIZE: usize = 0x4000;
+
+/// Flags of timerfd_settime
+const TFD_TIMER_ABSTIME: usize = 1;
+/// Setting the real time does not cancel timerfds for now, so it is accepted and ignored
+const TFD_TIMER_CANCEL_ON_SET: usize = 2;
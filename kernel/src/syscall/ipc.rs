@@ -138,7 +138,7 @@ impl Syscall<'_> {
         self.vm().push(
             addr,
             addr + size,
-            MemoryAttr::default().user().execute().writable(),
+            MemoryAttr::default().user().writable(),
             Shared::new_with_guard(GlobalFrameAlloc, shm_identifier.shared_guard.clone()),
             "shmat",
         );
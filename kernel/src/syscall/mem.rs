@@ -1,10 +1,15 @@
 use rcore_fs::vfs::MMapArea;
-use rcore_memory::memory_set::handler::{Delay, File, Linear, Shared};
+use rcore_memory::memory_set::handler::{Delay, File, Huge, Linear, Shared};
 use rcore_memory::memory_set::MemoryAttr;
-use rcore_memory::PAGE_SIZE;
+use rcore_memory::{HUGE_PAGE_SIZE, PAGE_SIZE};
 
 use super::*;
-use crate::memory::GlobalFrameAlloc;
+use crate::consts::USER_HEAP_SIZE;
+use crate::drivers::CMDLINE;
+use crate::fs::fcntl::{O_RDWR, O_WRONLY};
+use crate::fs::FileLike;
+use crate::memory::{swap, GlobalFrameAlloc, GlobalSwapper};
+use crate::process::Process;
 
 impl Syscall<'_> {
     pub fn sys_mmap(
@@ -23,20 +28,54 @@ impl Syscall<'_> {
             addr, len, prot, flags, fd as isize, offset
         );
 
+        if len == 0 {
+            return Err(SysError::EINVAL);
+        }
+        if prot.is_wx_denied() {
+            return Err(SysError::EACCES);
+        }
+        if !flags.contains(MmapFlags::ANONYMOUS) && offset % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+
+        // anonymous private mappings may be backed by huge pages
+        let huge = flags.contains(MmapFlags::HUGETLB)
+            && flags.contains(MmapFlags::ANONYMOUS)
+            && !flags.contains(MmapFlags::SHARED);
+        let align = if huge { HUGE_PAGE_SIZE } else { PAGE_SIZE };
+        let len = len.checked_add(align - 1).ok_or(SysError::ENOMEM)? & !(align - 1);
+        if huge && flags.contains(MmapFlags::FIXED) && addr % align != 0 {
+            return Err(SysError::EINVAL);
+        }
+
         let mut proc = self.process();
         let mut addr = addr;
         if addr == 0 {
             // although NULL can be a valid address
             // but in C, NULL is regarded as allocation failure
-            // so just skip it
-            addr = PAGE_SIZE;
+            // so just skip it.
+            // also leave room for the heap to grow.
+            addr = proc.mmap_base;
+        }
+
+        // new mappings are locked after mlockall(MCL_FUTURE)
+        if self.vm().lock_future() {
+            let locked = self.vm().total_locked_pages() * PAGE_SIZE + len;
+            if !memlock_allowed(&proc, locked) {
+                return Err(SysError::EAGAIN);
+            }
         }
 
         if flags.contains(MmapFlags::FIXED) {
             // we have to map it to addr, so remove the old mapping first
+            if !self.vm().can_split(addr, addr + len) {
+                return Err(SysError::EINVAL);
+            }
             self.vm().pop_with_split(addr, addr + len);
         } else {
-            addr = self.vm().find_free_area(addr, len);
+            // leave room to align the start
+            addr = self.vm().find_free_area(addr, len + align - PAGE_SIZE);
+            addr = (addr + align - 1) & !(align - 1);
         }
 
         if flags.contains(MmapFlags::ANONYMOUS) {
@@ -49,18 +88,37 @@ impl Syscall<'_> {
                     "mmap_anon_shared",
                 );
                 return Ok(addr);
+            } else if huge {
+                self.vm().push(
+                    addr,
+                    addr + len,
+                    prot.to_attr(),
+                    Huge::new(GlobalFrameAlloc),
+                    "mmap_anon_huge",
+                );
+                return Ok(addr);
             } else {
                 self.vm().push(
                     addr,
                     addr + len,
                     prot.to_attr(),
-                    Delay::new(GlobalFrameAlloc),
+                    Delay::with_swapper(GlobalFrameAlloc, GlobalSwapper),
                     "mmap_anon",
                 );
                 return Ok(addr);
             }
         } else {
             let file_like = proc.get_file_like(fd)?;
+            if let FileLike::File(file) = &*file_like {
+                // the file must be readable, and also writable
+                // if changes are carried through to it
+                let mode = file.get_options() & 0b11;
+                let write_back =
+                    flags.contains(MmapFlags::SHARED) && prot.contains(MmapProt::WRITE);
+                if mode == O_WRONLY || (write_back && mode != O_RDWR) {
+                    return Err(SysError::EACCES);
+                }
+            }
             let area = MMapArea {
                 start_vaddr: addr,
                 end_vaddr: addr + len,
@@ -73,33 +131,358 @@ impl Syscall<'_> {
         }
     }
 
+    pub fn sys_brk(&mut self, addr: usize) -> SysResult {
+        info!("brk: addr={:#x}", addr);
+        let mut proc = self.process();
+        // an invalid break, e.g. 0, just queries the current one
+        if addr < proc.brk_start || addr > proc.brk_start + USER_HEAP_SIZE {
+            return Ok(proc.brk);
+        }
+        let round_up = |addr: usize| (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let old_end = round_up(proc.brk);
+        let new_end = round_up(addr);
+        let mut vm = self.vm();
+        if new_end > old_end {
+            // fail like Linux by keeping the old break if the heap runs into a mapping
+            if !vm.test_free_area(old_end, new_end) {
+                return Ok(proc.brk);
+            }
+            vm.push(
+                old_end,
+                new_end,
+                MemoryAttr::default().user(),
+                Delay::with_swapper(GlobalFrameAlloc, GlobalSwapper),
+                "heap",
+            );
+        } else if new_end < old_end {
+            vm.pop_with_split(new_end, old_end);
+        }
+        proc.brk = addr;
+        Ok(addr)
+    }
+
     pub fn sys_mprotect(&mut self, addr: usize, len: usize, prot: usize) -> SysResult {
         let prot = MmapProt::from_bits_truncate(prot);
         info!(
             "mprotect: addr={:#x}, size={:#x}, prot={:?}",
             addr, len, prot
         );
-        let _attr = prot.to_attr();
-
-        // TODO: properly set the attribute of the area
-        //        now some mut ptr check is fault
-        let vm = self.vm();
-        let memory_area = vm
-            .iter()
-            .find(|area| area.is_overlap_with(addr, addr + len));
-        if memory_area.is_none() {
+        if addr % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        if prot.is_wx_denied() {
+            return Err(SysError::EACCES);
+        }
+        let end = addr
+            .checked_add(len)
+            .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+            .ok_or(SysError::ENOMEM)?
+            & !(PAGE_SIZE - 1);
+        // huge pages can not be protected in part
+        if !self.vm().can_split(addr, end) {
+            return Err(SysError::EINVAL);
+        }
+        // the whole range must be mapped
+        if !self.vm().protect(addr, end, prot.to_attr()) {
+            return Err(SysError::ENOMEM);
+        }
+        Ok(0)
+    }
+
+    pub fn sys_mremap(
+        &mut self,
+        old_addr: usize,
+        old_size: usize,
+        new_size: usize,
+        flags: usize,
+        new_addr: usize,
+    ) -> SysResult {
+        let flags = MremapFlags::from_bits_truncate(flags);
+        info!(
+            "mremap: old_addr={:#x}, old_size={:#x}, new_size={:#x}, flags={:?}, new_addr={:#x}",
+            old_addr, old_size, new_size, flags, new_addr
+        );
+        if old_addr % PAGE_SIZE != 0 || old_size == 0 || new_size == 0 {
+            return Err(SysError::EINVAL);
+        }
+        if flags.contains(MremapFlags::FIXED) && !flags.contains(MremapFlags::MAYMOVE) {
+            return Err(SysError::EINVAL);
+        }
+        let round_up = |size: usize| {
+            size.checked_add(PAGE_SIZE - 1)
+                .map(|size| size & !(PAGE_SIZE - 1))
+                .ok_or(SysError::ENOMEM)
+        };
+        let old_size = round_up(old_size)?;
+        let new_size = round_up(new_size)?;
+        let old_end = old_addr.checked_add(old_size).ok_or(SysError::EFAULT)?;
+        let fixed_end = if flags.contains(MremapFlags::FIXED) {
+            let new_end = new_addr.checked_add(new_size).ok_or(SysError::EINVAL)?;
+            if new_addr % PAGE_SIZE != 0 || (new_addr < old_end && old_addr < new_end) {
+                return Err(SysError::EINVAL);
+            }
+            Some(new_end)
+        } else {
+            None
+        };
+        let hint = self.process().mmap_base;
+        let mut vm = self.vm();
+        if !vm.is_mapped(old_addr, old_end) {
+            return Err(SysError::EFAULT);
+        }
+        if !vm.can_split(old_addr, old_end) || !vm.can_split(old_addr, old_addr + new_size) {
+            return Err(SysError::EINVAL);
+        }
+
+        // shrinking always happens in place
+        let size = old_size.min(new_size);
+        // nothing is unmapped before the move is known to succeed
+        if let Some(new_end) = fixed_end {
+            if !vm.can_split(new_addr, new_end) {
+                return Err(SysError::EINVAL);
+            }
+            if !vm.can_relocate(old_addr, old_addr + size, new_addr) {
+                return Err(SysError::ENOMEM);
+            }
+        }
+        if new_size < old_size {
+            vm.pop_with_split(old_addr + new_size, old_end);
+        }
+        if !flags.contains(MremapFlags::FIXED) {
+            if new_size <= old_size || vm.grow(old_addr, old_end, old_addr + new_size) {
+                return Ok(old_addr);
+            }
+            if !flags.contains(MremapFlags::MAYMOVE) {
+                return Err(SysError::ENOMEM);
+            }
+        }
+
+        let new_addr = if let Some(new_end) = fixed_end {
+            vm.pop_with_split(new_addr, new_end);
+            new_addr
+        } else {
+            vm.find_free_area(hint, new_size)
+        };
+        // the pages are moved instead of copied
+        if !vm.relocate(old_addr, old_addr + size, new_addr) {
+            return Err(SysError::ENOMEM);
+        }
+        if new_size > size {
+            vm.grow(new_addr, new_addr + size, new_addr + new_size);
+        }
+        Ok(new_addr)
+    }
+
+    pub fn sys_madvise(&mut self, addr: usize, len: usize, advice: usize) -> SysResult {
+        info!(
+            "madvise: addr={:#x}, size={:#x}, advice={}",
+            addr, len, advice
+        );
+        if addr % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let end = addr
+            .checked_add(len)
+            .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+            .ok_or(SysError::EINVAL)?
+            & !(PAGE_SIZE - 1);
+        let mut vm = self.vm();
+        if !vm.is_mapped(addr, end) {
             return Err(SysError::ENOMEM);
         }
+        match advice {
+            MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL => {}
+            // pages are freed at once instead of when memory runs short
+            // locked pages must stay in memory
+            MADV_DONTNEED | MADV_FREE if vm.locked_pages(addr, end) > 0 => {
+                return Err(SysError::EINVAL)
+            }
+            MADV_DONTNEED | MADV_FREE => vm.discard(addr, end),
+            MADV_WILLNEED => vm.prefault(addr, end),
+            _ => return Err(SysError::EINVAL),
+        }
+        Ok(0)
+    }
+
+    pub fn sys_mlock(&mut self, addr: usize, len: usize) -> SysResult {
+        self.sys_mlock2(addr, len, 0)
+    }
+
+    pub fn sys_mlock2(&mut self, addr: usize, len: usize, flags: usize) -> SysResult {
+        info!(
+            "mlock2: addr={:#x}, size={:#x}, flags={:#x}",
+            addr, len, flags
+        );
+        if flags & !MLOCK_ONFAULT != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let (start, end) = page_range(addr, len)?;
+        let proc = self.process();
+        let mut vm = self.vm();
+        if !vm.is_mapped(start, end) {
+            return Err(SysError::ENOMEM);
+        }
+        let pages =
+            vm.total_locked_pages() - vm.locked_pages(start, end) + (end - start) / PAGE_SIZE;
+        if !memlock_allowed(&proc, pages * PAGE_SIZE) {
+            return Err(SysError::ENOMEM);
+        }
+        vm.lock(start, end, flags & MLOCK_ONFAULT != 0);
+        Ok(0)
+    }
+
+    pub fn sys_munlock(&mut self, addr: usize, len: usize) -> SysResult {
+        info!("munlock: addr={:#x}, size={:#x}", addr, len);
+        let (start, end) = page_range(addr, len)?;
+        let mut vm = self.vm();
+        if !vm.is_mapped(start, end) {
+            return Err(SysError::ENOMEM);
+        }
+        vm.unlock(start, end);
+        Ok(0)
+    }
+
+    pub fn sys_mlockall(&mut self, flags: usize) -> SysResult {
+        info!("mlockall: flags={:#x}", flags);
+        let on_fault = flags & MCL_ONFAULT != 0;
+        let flags = flags & !MCL_ONFAULT;
+        if flags == 0 || flags & !(MCL_CURRENT | MCL_FUTURE) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let proc = self.process();
+        let mut vm = self.vm();
+        if flags & MCL_CURRENT != 0 {
+            if !memlock_allowed(&proc, vm.total_pages() * PAGE_SIZE) {
+                return Err(SysError::ENOMEM);
+            }
+            vm.lock_all(on_fault);
+        }
+        vm.set_lock_future(flags & MCL_FUTURE != 0);
+        Ok(0)
+    }
+
+    pub fn sys_munlockall(&mut self) -> SysResult {
+        info!("munlockall");
+        self.vm().unlock_all();
+        Ok(0)
+    }
+
+    pub fn sys_swapon(&mut self, path: *const u8, flags: usize) -> SysResult {
+        let proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        info!("swapon: path: {:?}, flags: {:#x}", path, flags);
+        if proc.uid != 0 {
+            return Err(SysError::EPERM);
+        }
+        let inode = proc.lookup_inode(&path)?;
+        swap::swap_on(inode)?;
+        Ok(0)
+    }
+
+    pub fn sys_swapoff(&mut self, path: *const u8) -> SysResult {
+        let proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        info!("swapoff: path: {:?}", path);
+        if proc.uid != 0 {
+            return Err(SysError::EPERM);
+        }
+        let inode = proc.lookup_inode(&path)?;
+        // the pages of all processes are read back
+        drop(proc);
+        swap::swap_off(&inode)?;
+        Ok(0)
+    }
+
+    pub fn sys_msync(&mut self, addr: usize, len: usize, flags: usize) -> SysResult {
+        info!(
+            "msync: addr={:#x}, len={:#x}, flags={:#x}",
+            addr, len, flags
+        );
+        let flags = MsyncFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        if addr % PAGE_SIZE != 0 || flags.contains(MsyncFlags::ASYNC | MsyncFlags::SYNC) {
+            return Err(SysError::EINVAL);
+        }
+        let (start, end) = page_range(addr, len)?;
+        let mut vm = self.vm();
+        if !vm.is_mapped(start, end) {
+            return Err(SysError::ENOMEM);
+        }
+        // shared mappings map the page cache, so there is nothing to invalidate
+        if !vm.sync(start, end, flags.contains(MsyncFlags::SYNC)) {
+            return Err(SysError::EIO);
+        }
         Ok(0)
     }
 
     pub fn sys_munmap(&mut self, addr: usize, len: usize) -> SysResult {
         info!("munmap addr={:#x}, size={:#x}", addr, len);
+        if !self.vm().can_split(addr, addr + len) {
+            return Err(SysError::EINVAL);
+        }
         self.vm().pop_with_split(addr, addr + len);
         Ok(0)
     }
 }
 
+bitflags! {
+    pub struct MsyncFlags: usize {
+        /// Schedule the write back and return
+        const ASYNC = 1 << 0;
+        /// Invalidate other mappings of the same file
+        const INVALIDATE = 1 << 1;
+        /// Write back and wait for it to complete
+        const SYNC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MremapFlags: usize {
+        /// The mapping can be moved to a new address
+        const MAYMOVE = 1 << 0;
+        /// Move the mapping to the exact address
+        const FIXED = 1 << 1;
+    }
+}
+
+/// Round `[addr, addr + len)` to whole pages, as mlock does
+fn page_range(addr: usize, len: usize) -> Result<(usize, usize), SysError> {
+    let start = addr & !(PAGE_SIZE - 1);
+    let end = addr
+        .checked_add(len)
+        .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+        .ok_or(SysError::ENOMEM)?
+        & !(PAGE_SIZE - 1);
+    Ok((start, end))
+}
+
+/// Whether RLIMIT_MEMLOCK of `proc` allows `size` bytes to be locked.
+/// Root is not limited, as with CAP_IPC_LOCK.
+fn memlock_allowed(proc: &Process, size: usize) -> bool {
+    proc.uid == 0 || size as u64 <= proc.memlock_limit.cur
+}
+
+const MLOCK_ONFAULT: usize = 1;
+
+const MCL_CURRENT: usize = 1;
+const MCL_FUTURE: usize = 2;
+const MCL_ONFAULT: usize = 4;
+
+const MADV_NORMAL: usize = 0;
+const MADV_RANDOM: usize = 1;
+const MADV_SEQUENTIAL: usize = 2;
+const MADV_WILLNEED: usize = 3;
+const MADV_DONTNEED: usize = 4;
+const MADV_FREE: usize = 8;
+
+lazy_static! {
+    /// Mappings both writable and executable are allowed by `wxallowed`
+    /// in the kernel command line, for programs generating code in place
+    static ref WX_ALLOWED: bool = CMDLINE
+        .read()
+        .split_whitespace()
+        .any(|arg| arg == "wxallowed");
+}
+
 bitflags! {
     pub struct MmapProt: usize {
         /// Data cannot be accessed
@@ -124,6 +507,8 @@ bitflags! {
         const FIXED = 1 << 4;
         /// The mapping is not backed by any file. (non-POSIX)
         const ANONYMOUS = 0x800;
+        /// Back the mapping with huge pages
+        const HUGETLB = 0x80000;
     }
 }
 
@@ -138,17 +523,29 @@ bitflags! {
         const FIXED = 1 << 4;
         /// The mapping is not backed by any file. (non-POSIX)
         const ANONYMOUS = 1 << 5;
+        /// Back the mapping with huge pages
+        const HUGETLB = 0x40000;
     }
 }
 
 impl MmapProt {
     pub fn to_attr(self) -> MemoryAttr {
+        // PROT_NONE is not accessible by the user, so that any access faults
+        if !self.intersects(MmapProt::READ | MmapProt::WRITE | MmapProt::EXEC) {
+            return MemoryAttr::default().readonly();
+        }
         let mut attr = MemoryAttr::default().user();
         if self.contains(MmapProt::EXEC) {
             attr = attr.execute();
         }
-        // TODO: see sys_mprotect
-        //        if !self.contains(MmapProt::WRITE) { attr = attr.readonly(); }
+        if !self.contains(MmapProt::WRITE) {
+            attr = attr.readonly();
+        }
         attr
     }
+
+    /// Writable and executable at once, refused for W^X
+    fn is_wx_denied(self) -> bool {
+        self.contains(MmapProt::WRITE | MmapProt::EXEC) && !*WX_ALLOWED
+    }
 }
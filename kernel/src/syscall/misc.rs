@@ -2,22 +2,89 @@
 
 use super::*;
 use crate::arch::cpu;
-use crate::consts::{ARCH, USER_STACK_SIZE};
+use crate::arch::timer::timer_now;
+use crate::consts::ARCH;
+use crate::memory::MemInfo;
+use crate::sched::rt::{MAX_RT_PRIO, MIN_RT_PRIO, RR_INTERVAL};
+use crate::sched::stat::loadavg;
+use crate::sched::{SchedPolicy, TIME_SLICE};
 use crate::syscall::SysError::ETIMEDOUT;
 use crate::trap::TICK_ACTIVITY;
 use core::mem::size_of;
 use core::sync::atomic::{AtomicI32, Ordering};
+use core::time::Duration;
 
 impl Syscall<'_> {
+    /// Set or get the FS and GS base of the current thread, kept in its user context,
+    /// through which threads find their TLS
     #[cfg(target_arch = "x86_64")]
     pub fn sys_arch_prctl(&mut self, code: i32, addr: usize) -> SysResult {
+        const ARCH_SET_GS: i32 = 0x1001;
         const ARCH_SET_FS: i32 = 0x1002;
+        const ARCH_GET_FS: i32 = 0x1003;
+        const ARCH_GET_GS: i32 = 0x1004;
         match code {
+            ARCH_SET_FS | ARCH_SET_GS if addr >= crate::consts::KERNEL_OFFSET => {
+                Err(SysError::EPERM)
+            }
             ARCH_SET_FS => {
                 info!("sys_arch_prctl: set FSBASE to {:#x}", addr);
                 self.context.general.fsbase = addr;
                 Ok(0)
             }
+            ARCH_SET_GS => {
+                info!("sys_arch_prctl: set GSBASE to {:#x}", addr);
+                self.context.general.gsbase = addr;
+                Ok(0)
+            }
+            ARCH_GET_FS | ARCH_GET_GS => {
+                let base = if code == ARCH_GET_FS {
+                    self.context.general.fsbase
+                } else {
+                    self.context.general.gsbase
+                };
+                info!("sys_arch_prctl: get base {:#x}", base);
+                let ptr = unsafe { self.vm().check_write_ptr(addr as *mut usize)? };
+                *ptr = base;
+                Ok(0)
+            }
+            _ => Err(SysError::EINVAL),
+        }
+    }
+
+    pub fn sys_prctl(&mut self, option: usize, arg2: usize) -> SysResult {
+        const PR_GET_DUMPABLE: usize = 3;
+        const PR_SET_DUMPABLE: usize = 4;
+        const PR_SET_NAME: usize = 15;
+        const PR_GET_NAME: usize = 16;
+        info!("prctl: option: {}, arg2: {:#x}", option, arg2);
+        match option {
+            PR_GET_DUMPABLE => Ok(self.process().dumpable as usize),
+            PR_SET_DUMPABLE => {
+                if arg2 > 1 {
+                    return Err(SysError::EINVAL);
+                }
+                self.process().dumpable = arg2 == 1;
+                Ok(0)
+            }
+            PR_SET_NAME => {
+                let name = check_and_clone_cstr(arg2 as *const u8)?;
+                self.thread.inner.lock().name = thread_name(&name);
+                Ok(0)
+            }
+            PR_GET_NAME => {
+                let buf = unsafe {
+                    self.vm()
+                        .check_write_array(arg2 as *mut u8, THREAD_NAME_LEN + 1)?
+                };
+                let inner = self.thread.inner.lock();
+                let len = inner.name.len();
+                buf[..len].copy_from_slice(inner.name.as_bytes());
+                for byte in buf[len..].iter_mut() {
+                    *byte = 0;
+                }
+                Ok(0)
+            }
             _ => Err(SysError::EINVAL),
         }
     }
@@ -37,27 +104,200 @@ impl Syscall<'_> {
         Ok(0)
     }
 
-    pub fn sys_sched_getaffinity(&mut self, pid: usize, size: usize, mask: *mut u32) -> SysResult {
+    /// Get the CPUs thread `pid` may run on, 0 for the current thread.
+    /// Return the size of the mask written.
+    pub fn sys_sched_getaffinity(
+        &mut self,
+        pid: usize,
+        size: usize,
+        mask: *mut usize,
+    ) -> SysResult {
         info!(
             "sched_getaffinity: pid: {}, size: {}, mask: {:?}",
             pid, size, mask
         );
-        let mask = unsafe { self.vm().check_write_array(mask, size / size_of::<u32>())? };
-
-        // we only have 4 cpu at most.
-        // so just set it.
-        mask[0] = 0b1111;
-        mask[1] = 0;
-        mask[2] = 0;
-        mask[3] = 0;
+        if size < size_of::<usize>() || size % size_of::<usize>() != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let thread = self.affinity_target(pid)?;
+        let mask = unsafe { self.vm().check_write_ptr(mask)? };
+        *mask = thread.inner.lock().affinity & online_cpus();
+        Ok(size_of::<usize>())
+    }
+
+    /// Set the CPUs thread `pid` may run on, 0 for the current thread.
+    /// CPUs not online are ignored, and bytes beyond `size` are taken as zeros.
+    pub async fn sys_sched_setaffinity(
+        &mut self,
+        pid: usize,
+        size: usize,
+        mask: usize,
+    ) -> SysResult {
+        info!(
+            "sched_setaffinity: pid: {}, size: {}, mask: {:#x}",
+            pid, size, mask
+        );
+        let mut bytes = [0u8; size_of::<usize>()];
+        let len = size.min(bytes.len());
+        let user_mask = unsafe { self.vm().check_read_array(mask as *const u8, len)? };
+        bytes[..len].copy_from_slice(user_mask);
+        let affinity = usize::from_ne_bytes(bytes) & online_cpus();
+        if affinity == 0 {
+            return Err(SysError::EINVAL);
+        }
+        let thread = self.affinity_target(pid)?;
+        let uid = self.process().uid;
+        if uid != 0 {
+            let target = thread.proc.lock();
+            if uid != target.uid && uid != target.ruid {
+                return Err(SysError::EPERM);
+            }
+        }
+        let mut inner = thread.inner.lock();
+        inner.affinity = affinity;
+        if let Some(task) = inner.sched_task {
+            crate::sched::set_affinity(task, affinity);
+        }
+        drop(inner);
+        if Arc::ptr_eq(&thread, self.thread) && affinity & (1 << cpu::id()) == 0 {
+            // move to an allowed CPU now
+            yield_now().await;
+        }
+        Ok(0)
+    }
+
+    fn affinity_target(&self, pid: usize) -> Result<Arc<Thread>, SysError> {
+        if pid == 0 {
+            return Ok(self.thread.clone());
+        }
+        THREADS.read().get(&pid).cloned().ok_or(SysError::ESRCH)
+    }
+
+    /// Set the scheduling policy and the static priority of thread `pid`,
+    /// 0 for the current thread. Only root can make it real-time.
+    pub fn sys_sched_setscheduler(
+        &mut self,
+        pid: usize,
+        policy: usize,
+        param: UserInPtr<SchedParam>,
+    ) -> SysResult {
+        info!(
+            "sched_setscheduler: pid: {}, policy: {}, param: {:?}",
+            pid, policy, param
+        );
+        let param = param.read()?;
+        // not kept by fork anyway
+        let policy = sched_policy(policy & !SCHED_RESET_ON_FORK, param.priority)?;
+        let thread = self.affinity_target(pid)?;
+        self.set_sched_policy(&thread, policy)
+    }
+
+    pub fn sys_sched_getscheduler(&mut self, pid: usize) -> SysResult {
+        info!("sched_getscheduler: pid: {}", pid);
+        let thread = self.affinity_target(pid)?;
+        let policy = thread.inner.lock().sched_policy;
+        Ok(policy_number(policy).0)
+    }
+
+    /// Set the static priority of thread `pid`, keeping its policy
+    pub fn sys_sched_setparam(&mut self, pid: usize, param: UserInPtr<SchedParam>) -> SysResult {
+        info!("sched_setparam: pid: {}, param: {:?}", pid, param);
+        let param = param.read()?;
+        let thread = self.affinity_target(pid)?;
+        let policy = thread.inner.lock().sched_policy;
+        let policy = sched_policy(policy_number(policy).0, param.priority)?;
+        self.set_sched_policy(&thread, policy)
+    }
+
+    pub fn sys_sched_getparam(
+        &mut self,
+        pid: usize,
+        mut param: UserOutPtr<SchedParam>,
+    ) -> SysResult {
+        info!("sched_getparam: pid: {}, param: {:?}", pid, param);
+        let thread = self.affinity_target(pid)?;
+        let policy = thread.inner.lock().sched_policy;
+        param.write(SchedParam {
+            priority: policy_number(policy).1,
+        })?;
+        Ok(0)
+    }
+
+    pub fn sys_sched_get_priority_max(&mut self, policy: usize) -> SysResult {
+        info!("sched_get_priority_max: policy: {}", policy);
+        match policy {
+            SCHED_FIFO | SCHED_RR => Ok(MAX_RT_PRIO as usize),
+            SCHED_OTHER => Ok(0),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+
+    pub fn sys_sched_get_priority_min(&mut self, policy: usize) -> SysResult {
+        info!("sched_get_priority_min: policy: {}", policy);
+        match policy {
+            SCHED_FIFO | SCHED_RR => Ok(MIN_RT_PRIO as usize),
+            SCHED_OTHER => Ok(0),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+
+    /// Get the time slice of thread `pid`, 0 for SCHED_FIFO which runs until it gives up
+    pub fn sys_sched_rr_get_interval(
+        &mut self,
+        pid: usize,
+        mut interval: UserOutPtr<TimeSpec>,
+    ) -> SysResult {
+        info!(
+            "sched_rr_get_interval: pid: {}, interval: {:?}",
+            pid, interval
+        );
+        let thread = self.affinity_target(pid)?;
+        let policy = thread.inner.lock().sched_policy;
+        let slice = match policy {
+            SchedPolicy::Fifo(_) => Duration::from_secs(0),
+            SchedPolicy::RoundRobin(_) => RR_INTERVAL,
+            SchedPolicy::Normal => TIME_SLICE,
+        };
+        interval.write(slice.into())?;
+        Ok(0)
+    }
+
+    fn set_sched_policy(&self, thread: &Arc<Thread>, policy: SchedPolicy) -> SysResult {
+        let uid = self.process().uid;
+        if uid != 0 {
+            if policy != SchedPolicy::Normal {
+                return Err(SysError::EPERM);
+            }
+            let target = thread.proc.lock();
+            if uid != target.uid && uid != target.ruid {
+                return Err(SysError::EPERM);
+            }
+        }
+        let mut inner = thread.inner.lock();
+        inner.sched_policy = policy;
+        if let Some(task) = inner.sched_task {
+            crate::sched::set_policy(task, policy);
+        }
         Ok(0)
     }
 
     pub fn sys_sysinfo(&mut self, sys_info: *mut SysInfo) -> SysResult {
+        info!("sysinfo: sys_info: {:?}", sys_info);
         let sys_info = unsafe { self.vm().check_write_ptr(sys_info)? };
 
-        let sysinfo = SysInfo::default();
-        *sys_info = sysinfo;
+        let mem = MemInfo::get();
+        *sys_info = SysInfo {
+            uptime: timer_now().as_secs(),
+            loads: loadavg(SI_LOAD_SHIFT),
+            totalram: mem.total as u64,
+            freeram: mem.free as u64,
+            bufferram: mem.cached as u64,
+            totalswap: mem.swap_total as u64,
+            freeswap: mem.swap_free as u64,
+            procs: PROCESSES.read().len() as u16,
+            mem_unit: 1,
+            ..SysInfo::default()
+        };
         Ok(0)
     }
 
@@ -66,54 +306,72 @@ impl Syscall<'_> {
         uaddr: usize,
         op: u32,
         val: i32,
-        timeout: UserInPtr<TimeSpec>,
+        timeout: usize,
+        uaddr2: usize,
+        val3: i32,
     ) -> SysResult {
         info!(
-            "futex: [{}] uaddr: {:#x}, op: {:#x}, val: {}, timeout_ptr: {:?}",
-            self.thread.tid, uaddr, op, val, timeout
+            "futex: [{}] uaddr: {:#x}, op: {:#x}, val: {}, timeout: {:#x}, uaddr2: {:#x}, val3: {}",
+            self.thread.tid, uaddr, op, val, timeout, uaddr2, val3
         );
+        const OP_WAIT: u32 = 0;
+        const OP_WAKE: u32 = 1;
+        const OP_REQUEUE: u32 = 3;
+        const OP_CMP_REQUEUE: u32 = 4;
+        const OP_PRIVATE: u32 = 0x80;
+        const OP_CLOCK_REALTIME: u32 = 0x100;
+
         if op & OP_PRIVATE == 0 {
             warn!("process-shared futex is unimplemented");
         }
-        if uaddr % size_of::<u32>() != 0 {
+        let cmd = op & !(OP_PRIVATE | OP_CLOCK_REALTIME);
+        let requeue = cmd == OP_REQUEUE || cmd == OP_CMP_REQUEUE;
+        if uaddr % size_of::<u32>() != 0 || (requeue && uaddr2 % size_of::<u32>() != 0) {
             return Err(SysError::EINVAL);
         }
         let atomic = unsafe { self.vm().check_write_ptr(uaddr as *mut AtomicI32)? };
+        if requeue {
+            unsafe { self.vm().check_write_ptr(uaddr2 as *mut AtomicI32)? };
+        }
+        // the timeout of wait is relative
+        let wait_timeout = if cmd == OP_WAIT && timeout != 0 {
+            let timeout = UserInPtr::<TimeSpec>::from(timeout).read()?;
+            info!("futex wait timeout: {:?}", timeout);
+            Some(timeout.to_duration())
+        } else {
+            None
+        };
+        let key = futex_key(&self.thread.vm, uaddr);
+        let queue = get_futex(key);
 
-        const OP_WAIT: u32 = 0;
-        const OP_WAKE: u32 = 1;
-        const OP_PRIVATE: u32 = 0x80;
-
-        let mut proc = self.process();
-        let queue = proc.get_futex(uaddr);
-
-        match op & 0xf {
-            OP_WAIT => {
-                if atomic.load(Ordering::Acquire) != val {
-                    return Err(SysError::EAGAIN);
-                }
-                // avoid deadlock
-                drop(proc);
-                if timeout.is_null() {
-                    queue.wait(None).await?;
-                    Ok(0)
+        let res = match cmd {
+            OP_WAIT => match queue.wait(atomic, val, wait_timeout) {
+                Ok(future) => future.await,
+                Err(err) => Err(err),
+            },
+            OP_WAKE => Ok(queue.wake(val as usize)),
+            OP_CMP_REQUEUE if atomic.load(Ordering::Acquire) != val3 => Err(SysError::EAGAIN),
+            OP_REQUEUE | OP_CMP_REQUEUE => {
+                let key2 = futex_key(&self.thread.vm, uaddr2);
+                let target = get_futex(key2);
+                // the fourth argument is the number of waiters to requeue
+                let (woken, requeued) = queue.requeue(val as usize, &target, timeout);
+                drop(target);
+                put_futex(key2);
+                if cmd == OP_CMP_REQUEUE {
+                    Ok(woken + requeued)
                 } else {
-                    // TODO: timeout
-                    let timeout = timeout.read()?;
-                    info!("futex wait timeout: {:?}", timeout);
-                    queue.wait(Some(timeout.to_duration())).await?;
-                    Ok(0)
+                    Ok(woken)
                 }
             }
-            OP_WAKE => {
-                let woken_up_count = queue.wake(val as usize);
-                Ok(woken_up_count)
-            }
             _ => {
                 warn!("unsupported futex operation: {}", op);
                 Err(SysError::ENOSYS)
             }
-        }
+        };
+        drop(queue);
+        put_futex(key);
+        res
     }
 
     pub fn sys_reboot(
@@ -123,8 +381,9 @@ impl Syscall<'_> {
         cmd: u32,
         _arg: *const u8,
     ) -> SysResult {
+        info!("reboot: cmd={:#x}", cmd);
         // we will skip verifying magic
-        if cmd == LINUX_REBOOT_CMD_HALT {
+        if cmd == LINUX_REBOOT_CMD_HALT || cmd == LINUX_REBOOT_CMD_POWER_OFF {
             unsafe {
                 cpu::exit_in_qemu(0);
             }
@@ -149,12 +408,25 @@ impl Syscall<'_> {
         );
         match resource {
             RLIMIT_STACK => {
+                let mut proc = self.process();
+                let new_limit = match new_limit.is_null() {
+                    true => None,
+                    false => Some(unsafe { *self.vm().check_read_ptr(new_limit)? }),
+                };
+                if let Some(new_limit) = new_limit {
+                    if new_limit.cur > new_limit.max {
+                        return Err(SysError::EINVAL);
+                    }
+                    if new_limit.max > proc.stack_limit.max && proc.uid != 0 {
+                        return Err(SysError::EPERM);
+                    }
+                }
                 if !old_limit.is_null() {
                     let old_limit = unsafe { self.vm().check_write_ptr(old_limit)? };
-                    *old_limit = RLimit {
-                        cur: USER_STACK_SIZE as u64,
-                        max: USER_STACK_SIZE as u64,
-                    };
+                    *old_limit = proc.stack_limit;
+                }
+                if let Some(new_limit) = new_limit {
+                    proc.stack_limit = new_limit;
                 }
                 Ok(0)
             }
@@ -162,12 +434,58 @@ impl Syscall<'_> {
                 if !old_limit.is_null() {
                     let old_limit = unsafe { self.vm().check_write_ptr(old_limit)? };
                     *old_limit = RLimit {
-                        cur: 1024,
-                        max: 1024,
+                        cur: MAX_FILES as u64,
+                        max: MAX_FILES as u64,
                     };
                 }
                 Ok(0)
             }
+            RLIMIT_MEMLOCK => {
+                let mut proc = self.process();
+                let new_limit = match new_limit.is_null() {
+                    true => None,
+                    false => Some(unsafe { *self.vm().check_read_ptr(new_limit)? }),
+                };
+                if let Some(new_limit) = new_limit {
+                    if new_limit.cur > new_limit.max {
+                        return Err(SysError::EINVAL);
+                    }
+                    if new_limit.max > proc.memlock_limit.max && proc.uid != 0 {
+                        return Err(SysError::EPERM);
+                    }
+                }
+                if !old_limit.is_null() {
+                    let old_limit = unsafe { self.vm().check_write_ptr(old_limit)? };
+                    *old_limit = proc.memlock_limit;
+                }
+                if let Some(new_limit) = new_limit {
+                    proc.memlock_limit = new_limit;
+                }
+                Ok(0)
+            }
+            RLIMIT_CORE => {
+                let mut proc = self.process();
+                let new_limit = match new_limit.is_null() {
+                    true => None,
+                    false => Some(unsafe { *self.vm().check_read_ptr(new_limit)? }),
+                };
+                if let Some(new_limit) = new_limit {
+                    if new_limit.cur > new_limit.max {
+                        return Err(SysError::EINVAL);
+                    }
+                    if new_limit.max > proc.core_limit.max && proc.uid != 0 {
+                        return Err(SysError::EPERM);
+                    }
+                }
+                if !old_limit.is_null() {
+                    let old_limit = unsafe { self.vm().check_write_ptr(old_limit)? };
+                    *old_limit = proc.core_limit;
+                }
+                if let Some(new_limit) = new_limit {
+                    proc.core_limit = new_limit;
+                }
+                Ok(0)
+            }
             RLIMIT_RSS | RLIMIT_AS => {
                 if !old_limit.is_null() {
                     let old_limit = unsafe { self.vm().check_write_ptr(old_limit)? };
@@ -208,6 +526,45 @@ const LINUX_REBOOT_CMD_RESTART2: u32 = 0xA1B2C3D4;
 const LINUX_REBOOT_CMD_SW_SUSPEND: u32 = 0xD000FCE2;
 const LINUX_REBOOT_CMD_KEXEC: u32 = 0x45584543;
 
+const SCHED_OTHER: usize = 0;
+const SCHED_FIFO: usize = 1;
+const SCHED_RR: usize = 2;
+const SCHED_RESET_ON_FORK: usize = 0x4000_0000;
+
+/// Linux struct sched_param
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SchedParam {
+    priority: i32,
+}
+
+/// The policy of sched_setscheduler with the static priority `priority`
+fn sched_policy(policy: usize, priority: i32) -> Result<SchedPolicy, SysError> {
+    let rt_prio = if priority >= MIN_RT_PRIO as i32 && priority <= MAX_RT_PRIO as i32 {
+        Some(priority as u8)
+    } else {
+        None
+    };
+    match (policy, rt_prio) {
+        (SCHED_OTHER, _) if priority == 0 => Ok(SchedPolicy::Normal),
+        (SCHED_FIFO, Some(prio)) => Ok(SchedPolicy::Fifo(prio)),
+        (SCHED_RR, Some(prio)) => Ok(SchedPolicy::RoundRobin(prio)),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+/// The policy number and the static priority of `policy`
+fn policy_number(policy: SchedPolicy) -> (usize, i32) {
+    match policy {
+        SchedPolicy::Normal => (SCHED_OTHER, 0),
+        SchedPolicy::Fifo(prio) => (SCHED_FIFO, prio as i32),
+        SchedPolicy::RoundRobin(prio) => (SCHED_RR, prio as i32),
+    }
+}
+
+/// Bits of the fraction of the load average in sysinfo
+const SI_LOAD_SHIFT: u32 = 16;
+
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct SysInfo {
@@ -226,13 +583,21 @@ pub struct SysInfo {
 }
 
 const RLIMIT_STACK: usize = 3;
+const RLIMIT_CORE: usize = 4;
 const RLIMIT_RSS: usize = 5;
 const RLIMIT_NOFILE: usize = 7;
+const RLIMIT_MEMLOCK: usize = 8;
 const RLIMIT_AS: usize = 9;
 
+/// No limit of a resource
+pub const RLIM_INFINITY: u64 = !0;
+
+/// Default limit of locked memory, as in Linux
+pub const DEFAULT_MEMLOCK_LIMIT: u64 = 8 * 1024 * 1024;
+
 #[repr(C)]
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct RLimit {
-    cur: u64, // soft limit
-    max: u64, // hard limit
+    pub cur: u64, // soft limit
+    pub max: u64, // hard limit
 }
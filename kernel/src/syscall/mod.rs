@@ -3,6 +3,7 @@
 use crate::arch::cpu;
 use crate::arch::syscall::*;
 use crate::fs::epoll::EpollEvent;
+use crate::fs::fcntl::{F_GETLK, F_SETLK, F_SETLKW};
 use crate::memory::{copy_from_user, MemorySet};
 use crate::process::*;
 use crate::signal::{Signal, SignalAction, SignalFrame, SignalStack, SignalUserContext, Sigset};
@@ -75,9 +76,24 @@ pub async fn handle_syscall(thread: &Arc<Thread>, context: &mut UserContext) ->
     let ret = syscall.syscall(num, args).await;
     let exit = syscall.exit;
     context.set_syscall_ret(ret as usize);
+    if ret == -(SysError::EINTR as isize) && is_restartable(num) {
+        // restarted after the signal unless a handler without SA_RESTART runs
+        thread.inner.lock().restart_syscall = Some((num, args));
+    }
     exit
 }
 
+/// Whether the syscall is run again when interrupted by a signal whose handler has
+/// SA_RESTART, or which runs no handler. Others fail with EINTR, as in signal(7).
+fn is_restartable(num: usize) -> bool {
+    match num {
+        SYS_READ | SYS_WRITE | SYS_READV | SYS_WRITEV | SYS_PREAD64 | SYS_PWRITE64 | SYS_IOCTL
+        | SYS_OPENAT | SYS_FCNTL | SYS_FLOCK | SYS_WAIT4 | SYS_WAITID | SYS_FUTEX | SYS_ACCEPT
+        | SYS_ACCEPT4 | SYS_CONNECT | SYS_RECVFROM | SYS_SENDTO | SYS_RECVMSG | SYS_SENDMSG => true,
+        _ => false,
+    }
+}
+
 /// All context needed for syscall
 struct Syscall<'a> {
     pub thread: &'a Arc<Thread>,
@@ -122,7 +138,7 @@ impl Syscall<'_> {
                 self.sys_read(args[0], UserOutPtr::from(args[1]), args[2])
                     .await
             }
-            SYS_WRITE => self.sys_write(args[0], args[1] as *const u8, args[2]),
+            SYS_WRITE => self.sys_write(args[0], args[1] as *const u8, args[2]).await,
             SYS_OPENAT => self.sys_openat(args[0], args[1] as *const u8, args[2], args[3]),
             SYS_CLOSE => self.sys_close(args[0]),
             SYS_FSTAT => self.sys_fstat(args[0], args[1] as *mut Stat),
@@ -136,17 +152,31 @@ impl Syscall<'_> {
                     .await
             }
             SYS_PWRITE64 => self.sys_pwrite(args[0], args[1] as *const u8, args[2], args[3]),
+            SYS_PREADV => {
+                self.sys_preadv(args[0], UserInPtr::from(args[1]), args[2], args[3])
+                    .await
+            }
+            SYS_PWRITEV => self.sys_pwritev(args[0], args[1] as *const IoVec, args[2], args[3]),
             SYS_READV => {
                 self.sys_readv(args[0], UserInPtr::from(args[1]), args[2])
                     .await
             }
-            SYS_WRITEV => self.sys_writev(args[0], args[1] as *const IoVec, args[2]),
+            SYS_WRITEV => {
+                self.sys_writev(args[0], args[1] as *const IoVec, args[2])
+                    .await
+            }
             SYS_SENDFILE => {
                 self.sys_sendfile(args[0], args[1], UserInOutPtr::from(args[2]), args[3])
                     .await
             }
-            SYS_FCNTL => self.sys_fcntl(args[0], args[1], args[2]),
-            SYS_FLOCK => self.sys_flock(args[0], args[1]),
+            SYS_FCNTL => match args[1] {
+                F_GETLK | F_SETLK | F_SETLKW => {
+                    self.sys_fcntl_lock(args[0], args[1], UserInOutPtr::from(args[2]))
+                        .await
+                }
+                _ => self.sys_fcntl(args[0], args[1], args[2]),
+            },
+            SYS_FLOCK => self.sys_flock(args[0], args[1]).await,
             SYS_FSYNC => self.sys_fsync(args[0]),
             SYS_FDATASYNC => self.sys_fdatasync(args[0]),
             SYS_TRUNCATE => self.sys_truncate(args[0] as *const u8, args[1]),
@@ -154,10 +184,19 @@ impl Syscall<'_> {
             SYS_GETDENTS64 => self.sys_getdents64(args[0], args[1] as *mut LinuxDirent64, args[2]),
             SYS_GETCWD => self.sys_getcwd(args[0] as *mut u8, args[1]),
             SYS_CHDIR => self.sys_chdir(args[0] as *const u8),
+            SYS_FCHDIR => self.sys_fchdir(args[0]),
             SYS_RENAMEAT => {
                 self.sys_renameat(args[0], args[1] as *const u8, args[2], args[3] as *const u8)
             }
+            SYS_RENAMEAT2 => self.sys_renameat2(
+                args[0],
+                args[1] as *const u8,
+                args[2],
+                args[3] as *const u8,
+                args[4],
+            ),
             SYS_MKDIRAT => self.sys_mkdirat(args[0], args[1] as *const u8, args[2]),
+            SYS_MKNODAT => self.sys_mknodat(args[0], args[1] as *const u8, args[2], args[3]),
             SYS_LINKAT => self.sys_linkat(
                 args[0],
                 args[1] as *const u8,
@@ -172,13 +211,16 @@ impl Syscall<'_> {
             SYS_READLINKAT => {
                 self.sys_readlinkat(args[0], args[1] as *const u8, args[2] as *mut u8, args[3])
             }
-            SYS_FCHMOD => self.unimplemented("fchmod", Ok(0)),
-            SYS_FCHMODAT => self.unimplemented("fchmodat", Ok(0)),
-            SYS_FCHOWN => self.unimplemented("fchown", Ok(0)),
-            SYS_FCHOWNAT => self.unimplemented("fchownat", Ok(0)),
+            SYS_FCHMOD => self.sys_fchmod(args[0], args[1]),
+            SYS_FCHMODAT => self.sys_fchmodat(args[0], args[1] as *const u8, args[2]),
+            SYS_FCHOWN => self.sys_fchown(args[0], args[1], args[2]),
+            SYS_FCHOWNAT => {
+                self.sys_fchownat(args[0], args[1] as *const u8, args[2], args[3], args[4])
+            }
             SYS_FACCESSAT => self.sys_faccessat(args[0], args[1] as *const u8, args[2], args[3]),
+            SYS_DUP => self.sys_dup(args[0]),
             SYS_DUP3 => self.sys_dup3(args[0], args[1], args[2]),
-            SYS_PIPE2 => self.sys_pipe2(args[0] as *mut u32, args[1]), // TODO: handle `flags`
+            SYS_PIPE2 => self.sys_pipe2(args[0] as *mut u32, args[1]),
             SYS_SET_ROBUST_LIST => self.unimplemented("set_robuts_list", Ok(0)),
             SYS_GET_ROBUST_LIST => self.unimplemented("get_robust_list", Ok(0)),
             SYS_UTIMENSAT => self.sys_utimensat(
@@ -200,49 +242,78 @@ impl Syscall<'_> {
             }
 
             // io multiplexing
-            SYS_PSELECT6 => self.sys_pselect6(
-                args[0],
-                args[1] as *mut u32,
-                args[2] as *mut u32,
-                args[3] as *mut u32,
-                args[4] as *const TimeVal,
-                args[5] as *const u32,
-            ),
+            SYS_PSELECT6 => {
+                self.sys_pselect6(
+                    args[0],
+                    args[1] as *mut u32,
+                    args[2] as *mut u32,
+                    args[3] as *mut u32,
+                    UserInPtr::from(args[4]),
+                    UserInPtr::from(args[5]),
+                )
+                .await
+            }
             SYS_PPOLL => {
                 self.sys_ppoll(
                     UserInOutPtr::from(args[0]),
                     args[1],
                     UserInPtr::from(args[2]),
+                    UserInPtr::from(args[3]),
+                    args[4],
                 )
                 .await
-            } // ignore sigmask
+            }
             SYS_EPOLL_CREATE1 => self.sys_epoll_create1(args[0]),
             SYS_EPOLL_CTL => {
                 self.sys_epoll_ctl(args[0], args[1], args[2], args[3] as *mut EpollEvent)
             }
-            SYS_EPOLL_PWAIT => self.sys_epoll_pwait(
+            SYS_EPOLL_PWAIT => {
+                self.sys_epoll_pwait(
+                    args[0],
+                    args[1] as *mut EpollEvent,
+                    args[2],
+                    args[3] as i32,
+                    UserInPtr::from(args[4]),
+                    args[5],
+                )
+                .await
+            }
+            SYS_EVENTFD2 => self.unimplemented("eventfd2", Err(SysError::EACCES)),
+            SYS_SIGNALFD4 => {
+                self.sys_signalfd4(args[0], UserInPtr::from(args[1]), args[2], args[3])
+            }
+            SYS_TIMERFD_CREATE => self.sys_timerfd_create(args[0], args[1]),
+            SYS_TIMERFD_SETTIME => self.sys_timerfd_settime(
                 args[0],
-                args[1] as *mut EpollEvent,
-                args[2],
-                args[3],
-                args[4],
+                args[1],
+                UserInPtr::from(args[2]),
+                UserOutPtr::from(args[3]),
             ),
-            SYS_EVENTFD2 => self.unimplemented("eventfd2", Err(SysError::EACCES)),
+            SYS_TIMERFD_GETTIME => self.sys_timerfd_gettime(args[0], UserOutPtr::from(args[1])),
 
             SYS_SOCKETPAIR => self.unimplemented("socketpair", Err(SysError::EACCES)),
             // file system
-            SYS_STATFS => self.unimplemented("statfs", Err(SysError::EACCES)),
-            SYS_FSTATFS => self.unimplemented("fstatfs", Err(SysError::EACCES)),
+            SYS_STATFS => self.sys_statfs(args[0] as *const u8, UserOutPtr::from(args[1])),
+            SYS_FSTATFS => self.sys_fstatfs(args[0], UserOutPtr::from(args[1])),
             SYS_SYNC => self.sys_sync(),
             SYS_MOUNT => self.unimplemented("mount", Err(SysError::EACCES)),
             SYS_UMOUNT2 => self.unimplemented("umount2", Err(SysError::EACCES)),
 
             // memory
-            SYS_BRK => self.unimplemented("brk", Err(SysError::ENOMEM)),
+            SYS_BRK => self.sys_brk(args[0]),
             SYS_MMAP => self.sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
             SYS_MPROTECT => self.sys_mprotect(args[0], args[1], args[2]),
             SYS_MUNMAP => self.sys_munmap(args[0], args[1]),
-            SYS_MADVISE => self.unimplemented("madvise", Ok(0)),
+            SYS_MSYNC => self.sys_msync(args[0], args[1], args[2]),
+            SYS_SWAPON => self.sys_swapon(args[0] as *const u8, args[1]),
+            SYS_SWAPOFF => self.sys_swapoff(args[0] as *const u8),
+            SYS_MREMAP => self.sys_mremap(args[0], args[1], args[2], args[3], args[4]),
+            SYS_MADVISE => self.sys_madvise(args[0], args[1], args[2]),
+            SYS_MLOCK => self.sys_mlock(args[0], args[1]),
+            SYS_MLOCK2 => self.sys_mlock2(args[0], args[1], args[2]),
+            SYS_MUNLOCK => self.sys_munlock(args[0], args[1]),
+            SYS_MLOCKALL => self.sys_mlockall(args[0]),
+            SYS_MUNLOCKALL => self.sys_munlockall(),
 
             // signal
             SYS_RT_SIGACTION => self.sys_rt_sigaction(
@@ -258,15 +329,32 @@ impl Syscall<'_> {
                 UserOutPtr::from(args[2]),
                 args[3],
             ),
+            SYS_RT_SIGPENDING => self.sys_rt_sigpending(UserOutPtr::from(args[0]), args[1]),
+            SYS_RT_SIGSUSPEND => {
+                self.sys_rt_sigsuspend(UserInPtr::from(args[0]), args[1])
+                    .await
+            }
             SYS_SIGALTSTACK => {
                 self.sys_sigaltstack(UserInPtr::from(args[0]), UserOutPtr::from(args[1]))
             }
             SYS_KILL => self.sys_kill(args[0] as isize, args[1]),
 
             // schedule
-            SYS_SCHED_YIELD => self.sys_yield(),
+            SYS_SCHED_YIELD => self.sys_yield().await,
+            SYS_SCHED_SETSCHEDULER => {
+                self.sys_sched_setscheduler(args[0], args[1], UserInPtr::from(args[2]))
+            }
+            SYS_SCHED_GETSCHEDULER => self.sys_sched_getscheduler(args[0]),
+            SYS_SCHED_SETPARAM => self.sys_sched_setparam(args[0], UserInPtr::from(args[1])),
+            SYS_SCHED_GETPARAM => self.sys_sched_getparam(args[0], UserOutPtr::from(args[1])),
+            SYS_SCHED_GET_PRIORITY_MAX => self.sys_sched_get_priority_max(args[0]),
+            SYS_SCHED_GET_PRIORITY_MIN => self.sys_sched_get_priority_min(args[0]),
+            SYS_SCHED_RR_GET_INTERVAL => {
+                self.sys_sched_rr_get_interval(args[0], UserOutPtr::from(args[1]))
+            }
+            SYS_SCHED_SETAFFINITY => self.sys_sched_setaffinity(args[0], args[1], args[2]).await,
             SYS_SCHED_GETAFFINITY => {
-                self.sys_sched_getaffinity(args[0], args[1], args[2] as *mut u32)
+                self.sys_sched_getaffinity(args[0], args[1], args[2] as *mut usize)
             }
 
             // socket
@@ -313,13 +401,17 @@ impl Syscall<'_> {
             ),
 
             // process
-            SYS_CLONE => self.sys_clone(
-                args[0],
-                args[1],
-                args[2] as *mut u32,
-                args[3] as *mut u32,
-                args[4],
-            ),
+            #[cfg(target_arch = "x86_64")]
+            SYS_CLONE => {
+                self.sys_clone(args[0], args[1], args[2], args[3], args[4])
+                    .await
+            }
+            // the thread pointer comes before the child tid on other architectures
+            #[cfg(not(target_arch = "x86_64"))]
+            SYS_CLONE => {
+                self.sys_clone(args[0], args[1], args[2], args[4], args[3])
+                    .await
+            }
             SYS_EXECVE => self.sys_exec(
                 args[0] as *const u8,
                 args[1] as *const *const u8,
@@ -328,28 +420,64 @@ impl Syscall<'_> {
             SYS_EXIT => self.sys_exit(args[0] as usize),
             SYS_EXIT_GROUP => self.sys_exit_group(args[0]),
             SYS_WAIT4 => {
-                self.sys_wait4(args[0] as isize, UserInOutPtr::from(args[1]))
+                self.sys_wait4(args[0] as isize, UserInOutPtr::from(args[1]), args[2])
                     .await
-            } // TODO: wait4
+            }
             SYS_SET_TID_ADDRESS => self.sys_set_tid_address(args[0] as *mut u32),
+            SYS_PTRACE => self.sys_ptrace(args[0], args[1], args[2], args[3]),
             SYS_FUTEX => {
                 self.sys_futex(
                     args[0],
                     args[1] as u32,
                     args[2] as i32,
-                    UserInPtr::from(args[3]),
+                    args[3],
+                    args[4],
+                    args[5] as i32,
                 )
                 .await
             }
             SYS_TKILL => self.sys_tkill(args[0], args[1]),
+            SYS_TGKILL => self.sys_tgkill(args[0], args[1], args[2]),
 
             // time
-            SYS_NANOSLEEP => self.sys_nanosleep(UserInPtr::from(args[0])).await,
-            SYS_SETITIMER => self.unimplemented("setitimer", Ok(0)),
+            SYS_NANOSLEEP => {
+                self.sys_nanosleep(UserInPtr::from(args[0]), UserOutPtr::from(args[1]))
+                    .await
+            }
+            SYS_CLOCK_NANOSLEEP => {
+                self.sys_clock_nanosleep(
+                    args[0],
+                    args[1],
+                    UserInPtr::from(args[2]),
+                    UserOutPtr::from(args[3]),
+                )
+                .await
+            }
+            SYS_SETITIMER => {
+                self.sys_setitimer(args[0], UserInPtr::from(args[1]), UserOutPtr::from(args[2]))
+            }
+            SYS_GETITIMER => self.sys_getitimer(args[0], UserOutPtr::from(args[1])),
+            SYS_TIMER_CREATE => {
+                self.sys_timer_create(args[0], UserInPtr::from(args[1]), UserOutPtr::from(args[2]))
+            }
+            SYS_TIMER_SETTIME => self.sys_timer_settime(
+                args[0],
+                args[1],
+                UserInPtr::from(args[2]),
+                UserOutPtr::from(args[3]),
+            ),
+            SYS_TIMER_GETTIME => self.sys_timer_gettime(args[0], UserOutPtr::from(args[1])),
+            SYS_TIMER_GETOVERRUN => self.sys_timer_getoverrun(args[0]),
+            SYS_TIMER_DELETE => self.sys_timer_delete(args[0]),
             SYS_GETTIMEOFDAY => {
-                self.sys_gettimeofday(UserOutPtr::from(args[0]), UserInPtr::from(args[1]))
+                self.sys_gettimeofday(UserOutPtr::from(args[0]), UserOutPtr::from(args[1]))
             }
             SYS_CLOCK_GETTIME => self.sys_clock_gettime(args[0], UserOutPtr::from(args[1])),
+            SYS_SETTIMEOFDAY => {
+                self.sys_settimeofday(UserInPtr::from(args[0]), UserInPtr::from(args[1]))
+            }
+            SYS_CLOCK_SETTIME => self.sys_clock_settime(args[0], UserInPtr::from(args[1])),
+            SYS_CLOCK_GETRES => self.sys_clock_getres(args[0], UserOutPtr::from(args[1])),
 
             // sem
             #[cfg(not(target_arch = "mips"))]
@@ -379,29 +507,43 @@ impl Syscall<'_> {
             SYS_GETPID => self.sys_getpid(),
             SYS_GETTID => self.sys_gettid(),
             SYS_UNAME => self.sys_uname(args[0] as *mut u8),
-            SYS_UMASK => self.unimplemented("umask", Ok(0o777)),
+            SYS_UMASK => self.sys_umask(args[0]),
             //        SYS_GETRLIMIT => self.sys_getrlimit(),
             SYS_SETRLIMIT => self.unimplemented("setrlimit", Ok(0)),
             SYS_GETRUSAGE => self.sys_getrusage(args[0], args[1] as *mut RUsage),
             SYS_SYSINFO => self.sys_sysinfo(args[0] as *mut SysInfo),
             SYS_TIMES => self.sys_times(args[0] as *mut Tms),
-            SYS_GETUID => self.unimplemented("getuid", Ok(0)),
-            SYS_GETGID => self.unimplemented("getgid", Ok(0)),
-            SYS_SETUID => self.unimplemented("setuid", Ok(0)),
-            SYS_GETEUID => self.unimplemented("geteuid", Ok(0)),
-            SYS_GETEGID => self.unimplemented("getegid", Ok(0)),
+            SYS_GETUID => self.sys_getuid(),
+            SYS_GETGID => self.sys_getgid(),
+            SYS_SETUID => self.sys_setuid(args[0]),
+            SYS_GETEUID => self.sys_geteuid(),
+            SYS_GETEGID => self.sys_getegid(),
+            SYS_SETREUID => self.sys_setreuid(args[0], args[1]),
+            SYS_SETREGID => self.sys_setregid(args[0], args[1]),
+            SYS_GETRESUID => self.sys_getresuid(
+                UserOutPtr::from(args[0]),
+                UserOutPtr::from(args[1]),
+                UserOutPtr::from(args[2]),
+            ),
+            SYS_GETRESGID => self.sys_getresgid(
+                UserOutPtr::from(args[0]),
+                UserOutPtr::from(args[1]),
+                UserOutPtr::from(args[2]),
+            ),
             SYS_GETPPID => self.sys_getppid(),
-            SYS_SETSID => self.unimplemented("setsid", Ok(0)),
+            SYS_SETSID => self.sys_setsid(),
+            SYS_GETSID => self.sys_getsid(args[0]),
             SYS_GETPGID => self.sys_getpgid(args[0]),
             SYS_SETPGID => self.sys_setpgid(args[0], args[1]),
             SYS_GETGROUPS => self.unimplemented("getgroups", Ok(0)),
             SYS_RT_SIGTIMEDWAIT => self.unimplemented("rt_sigtimedwait", Ok(0)),
             SYS_SETGROUPS => self.unimplemented("setgroups", Ok(0)),
-            SYS_SETRESUID => self.unimplemented("setresuid", Ok(0)),
-            SYS_SETRESGID => self.unimplemented("setresgid", Ok(0)),
-            SYS_SETGID => self.unimplemented("setgid", Ok(0)),
-            SYS_SETPRIORITY => self.sys_set_priority(args[0]),
-            SYS_PRCTL => self.unimplemented("prctl", Ok(0)),
+            SYS_SETRESUID => self.sys_setresuid(args[0], args[1], args[2]),
+            SYS_SETRESGID => self.sys_setresgid(args[0], args[1], args[2]),
+            SYS_SETGID => self.sys_setgid(args[0]),
+            SYS_GETPRIORITY => self.sys_getpriority(args[0], args[1]),
+            SYS_SETPRIORITY => self.sys_setpriority(args[0], args[1], args[2]),
+            SYS_PRCTL => self.sys_prctl(args[0], args[1]),
             SYS_MEMBARRIER => self.unimplemented("membarrier", Ok(0)),
             SYS_PRLIMIT64 => self.sys_prlimit64(
                 args[0],
@@ -418,7 +560,12 @@ impl Syscall<'_> {
             SYS_GETRANDOM => {
                 self.sys_getrandom(args[0] as *mut u8, args[1] as usize, args[2] as u32)
             }
-            SYS_RT_SIGQUEUEINFO => self.unimplemented("rt_sigqueueinfo", Ok(0)),
+            SYS_RT_SIGQUEUEINFO => {
+                self.sys_rt_sigqueueinfo(args[0], args[1], UserInPtr::from(args[2]))
+            }
+            SYS_RT_TGSIGQUEUEINFO => {
+                self.sys_rt_tgsigqueueinfo(args[0], args[1], args[2], UserInPtr::from(args[3]))
+            }
 
             // kernel module
             SYS_INIT_MODULE => {
@@ -486,11 +633,13 @@ impl Syscall<'_> {
         let ret = match id {
             SYS_OPEN => self.sys_open(args[0] as *const u8, args[1], args[2]),
             SYS_POLL => {
-                self.sys_poll(UserInOutPtr::from(args[0]), args[1], args[2])
+                self.sys_poll(UserInOutPtr::from(args[0]), args[1], args[2] as i32)
                     .await
             }
             SYS_DUP2 => self.sys_dup2(args[0], args[1]),
             SYS_FORK => self.sys_fork(),
+            SYS_GETPGRP => self.sys_getpgrp(),
+            SYS_SIGNALFD => self.sys_signalfd4(args[0], UserInPtr::from(args[1]), args[2], 0),
             SYS_MMAP2 => self.sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5] * 4096),
             SYS_FSTAT64 => self.sys_fstat(args[0], args[1] as *mut Stat),
             SYS_LSTAT64 => self.sys_lstat(args[0] as *const u8, args[1] as *mut Stat),
@@ -525,7 +674,8 @@ impl Syscall<'_> {
             },
             SYS_EPOLL_CREATE => self.sys_epoll_create(args[0]),
             SYS_EPOLL_WAIT => {
-                self.sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3])
+                self.sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3] as i32)
+                    .await
             }
 
             _ => return None,
@@ -540,36 +690,44 @@ impl Syscall<'_> {
             SYS_STAT => self.sys_stat(args[0] as *const u8, args[1] as *mut Stat),
             SYS_LSTAT => self.sys_lstat(args[0] as *const u8, args[1] as *mut Stat),
             SYS_POLL => {
-                self.sys_poll(UserInOutPtr::from(args[0]), args[1], args[2])
+                self.sys_poll(UserInOutPtr::from(args[0]), args[1], args[2] as i32)
                     .await
             }
             SYS_ACCESS => self.sys_access(args[0] as *const u8, args[1]),
             SYS_PIPE => self.sys_pipe(args[0] as *mut u32),
-            SYS_SELECT => self.sys_select(
-                args[0],
-                args[1] as *mut u32,
-                args[2] as *mut u32,
-                args[3] as *mut u32,
-                args[4] as *const TimeVal,
-            ),
+            SYS_SIGNALFD => self.sys_signalfd4(args[0], UserInPtr::from(args[1]), args[2], 0),
+            SYS_SELECT => {
+                self.sys_select(
+                    args[0],
+                    args[1] as *mut u32,
+                    args[2] as *mut u32,
+                    args[3] as *mut u32,
+                    UserInPtr::from(args[4]),
+                )
+                .await
+            }
             SYS_DUP2 => self.sys_dup2(args[0], args[1]),
-            SYS_ALARM => self.unimplemented("alarm", Ok(0)),
+            SYS_ALARM => self.sys_alarm(args[0]),
             SYS_FORK => self.sys_fork(),
-            SYS_VFORK => self.sys_vfork(),
+            SYS_VFORK => self.sys_vfork().await,
+            SYS_GETPGRP => self.sys_getpgrp(),
             SYS_RENAME => self.sys_rename(args[0] as *const u8, args[1] as *const u8),
             SYS_MKDIR => self.sys_mkdir(args[0] as *const u8, args[1]),
+            SYS_MKNOD => self.sys_mknod(args[0] as *const u8, args[1], args[2]),
             SYS_RMDIR => self.sys_rmdir(args[0] as *const u8),
             SYS_LINK => self.sys_link(args[0] as *const u8, args[1] as *const u8),
             SYS_UNLINK => self.sys_unlink(args[0] as *const u8),
             SYS_SYMLINK => self.sys_symlink(args[0] as *const u8, args[1] as *const u8),
             SYS_READLINK => self.sys_readlink(args[0] as *const u8, args[1] as *mut u8, args[2]),
-            SYS_CHMOD => self.unimplemented("chmod", Ok(0)),
-            SYS_CHOWN => self.unimplemented("chown", Ok(0)),
+            SYS_CHMOD => self.sys_chmod(args[0] as *const u8, args[1]),
+            SYS_CHOWN => self.sys_chown(args[0] as *const u8, args[1], args[2]),
+            SYS_LCHOWN => self.sys_lchown(args[0] as *const u8, args[1], args[2]),
             SYS_ARCH_PRCTL => self.sys_arch_prctl(args[0] as i32, args[1]),
             SYS_TIME => self.sys_time(args[0] as *mut u64),
             SYS_EPOLL_CREATE => self.sys_epoll_create(args[0]),
             SYS_EPOLL_WAIT => {
-                self.sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3])
+                self.sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3] as i32)
+                    .await
             }
             _ => return None,
         };
@@ -625,6 +783,7 @@ pub enum SysError {
     ENOTEMPTY = 39,
     ELOOP = 40,
     EIDRM = 43,
+    EOVERFLOW = 75,
     ENOTSOCK = 80,
     ENOPROTOOPT = 92,
     EPFNOSUPPORT = 96,
@@ -684,6 +843,7 @@ impl fmt::Display for SysError {
                 ENOSYS => "Function not implemented",
                 ENOTEMPTY => "Directory not empty",
                 ELOOP => "Too many symbolic links encountered",
+                EOVERFLOW => "Value too large for defined data type",
                 ENOTSOCK => "Socket operation on non-socket",
                 ENOPROTOOPT => "Protocol not available",
                 EPFNOSUPPORT => "Protocol family not supported",
@@ -1,19 +1,20 @@
 //! Syscalls for process
 
 use super::*;
-use crate::arch::timer::timer_now;
+use crate::arch::timer::{timer_now, timer_resolution};
 use crate::fs::FileLike;
-use crate::signal::{send_signal, Signal};
+use crate::process::ptrace;
+use crate::signal::{send_signal, wait_for_event_or_signal, Siginfo, Signal, SI_USER};
 use crate::{
     sync::{wait_for_event, Event, EventBus, SpinNoIrqLock as Mutex},
     syscall::SysError::{EINTR, ESRCH},
-    trap::NAIVE_TIMER,
 };
 use alloc::boxed::Box;
 use alloc::sync::Weak;
 use core::{
     future::Future,
     pin::Pin,
+    sync::atomic::spin_loop_hint,
     task::{Context, Poll},
     time::Duration,
 };
@@ -28,160 +29,277 @@ impl Syscall<'_> {
         Ok(pid)
     }
 
+    /// Create a child sharing the address space, and wait until it execs or exits
     #[cfg(target_arch = "x86_64")]
-    pub fn sys_vfork(&mut self) -> SysResult {
-        self.sys_fork()
+    pub async fn sys_vfork(&mut self) -> SysResult {
+        let flags = CloneFlags::VM | CloneFlags::VFORK;
+        self.sys_clone(flags.bits() | Signal::SIGCHLD as usize, 0, 0, 0, 0)
+            .await
     }
 
-    /// Create a new thread in the current process.
-    /// The new thread's stack pointer will be set to `newsp`,
-    /// and thread pointer will be set to `newtls`.
-    /// The child tid will be stored at both `parent_tid` and `child_tid`.
-    /// This is partially implemented for musl only.
-    pub fn sys_clone(
+    /// Create a new process or thread.
+    /// With CLONE_THREAD, the new thread shares the whole process,
+    /// so CLONE_VM, CLONE_FS, CLONE_FILES and CLONE_SIGHAND are required as well.
+    /// Otherwise the child is a forked process, and the address space is only
+    /// shared by vfork, when the caller waits until the child execs or exits.
+    /// The child starts with stack pointer `newsp` if it is not 0,
+    /// and thread pointer `newtls` with CLONE_SETTLS.
+    pub async fn sys_clone(
         &mut self,
         flags: usize,
         newsp: usize,
-        parent_tid: *mut u32,
-        child_tid: *mut u32,
+        parent_tid: usize,
+        child_tid: usize,
         newtls: usize,
     ) -> SysResult {
         let clone_flags = CloneFlags::from_bits_truncate(flags);
         info!(
-            "clone: flags: {:?} == {:#x}, newsp: {:#x}, parent_tid: {:?}, child_tid: {:?}, newtls: {:#x}",
+            "clone: flags: {:?} == {:#x}, newsp: {:#x}, parent_tid: {:#x}, child_tid: {:#x}, newtls: {:#x}",
             clone_flags, flags, newsp, parent_tid, child_tid, newtls
         );
-        if flags == 0x4111 || flags == 0x11 {
-            warn!("sys_clone is calling sys_fork instead, ignoring other args");
-            return self.sys_fork();
-        }
-        if (flags != 0x7d0f00) && (flags != 0x5d0f00) {
-            // 0x5d0f00 is the args from gcc of alpine linux
-            warn!(
-                "sys_clone only support sys_fork or musl pthread_create without flags{:x}",
-                flags
-            );
+        if clone_flags.contains(CloneFlags::THREAD) && !clone_flags.contains(CloneFlags::SIGHAND)
+            || clone_flags.contains(CloneFlags::SIGHAND) && !clone_flags.contains(CloneFlags::VM)
+        {
+            return Err(SysError::EINVAL);
+        }
+        let thread_flags = CloneFlags::VM
+            | CloneFlags::FS
+            | CloneFlags::FILES
+            | CloneFlags::SIGHAND
+            | CloneFlags::THREAD;
+        let is_thread = clone_flags.contains(CloneFlags::THREAD);
+        if is_thread && !clone_flags.contains(thread_flags)
+            || !is_thread
+                && (clone_flags.intersects(CloneFlags::FS | CloneFlags::FILES)
+                    || clone_flags.contains(CloneFlags::VM)
+                        && !clone_flags.contains(CloneFlags::VFORK))
+        {
+            warn!("sys_clone: sharing only part of a process is not supported");
             return Err(SysError::ENOSYS);
         }
-        let parent_tid_ref = unsafe { self.vm().check_write_ptr(parent_tid)? };
-        // child_tid buffer should not be set because CLONE_CHILD_SETTID flag is not specified in the current implementation
-        let child_tid_ref = unsafe { self.vm().check_write_ptr(child_tid)? };
-        let mut new_thread = self
-            .thread
-            .new_clone(self.context, newsp, newtls, child_tid as usize);
-        if clone_flags.contains(CloneFlags::CHILD_CLEARTID) {
-            new_thread.inner.lock().clear_child_tid = child_tid as usize;
+
+        // check the pointers before creating the child
+        let parent_tid_ref = if clone_flags.contains(CloneFlags::PARENT_SETTID) {
+            Some(unsafe { self.vm().check_write_ptr(parent_tid as *mut u32)? })
+        } else {
+            None
+        };
+        if clone_flags.intersects(CloneFlags::CHILD_SETTID | CloneFlags::CHILD_CLEARTID) {
+            unsafe { self.vm().check_write_ptr(child_tid as *mut u32)? };
+        }
+
+        let mut context = self.context.clone();
+        if newsp != 0 {
+            context.set_sp(newsp);
+        }
+        if clone_flags.contains(CloneFlags::SETTLS) {
+            context.set_tls(newtls);
+        }
+        let vfork = !is_thread && clone_flags.contains(CloneFlags::VM | CloneFlags::VFORK);
+        let new_thread = if is_thread {
+            self.thread.new_clone(&context)
+        } else if vfork {
+            self.thread.vfork(&context)
+        } else {
+            self.thread.fork(&context)
+        };
+        {
+            let mut inner = new_thread.inner.lock();
+            if clone_flags.contains(CloneFlags::CHILD_SETTID) {
+                inner.set_child_tid = child_tid;
+            }
+            if clone_flags.contains(CloneFlags::CHILD_CLEARTID) {
+                inner.clear_child_tid = child_tid;
+            }
         }
         let tid: usize = new_thread.tid;
         info!("clone: {} -> {}", self.thread.tid, tid);
-        *parent_tid_ref = tid as u32;
-        *child_tid_ref = tid as u32;
+        if let Some(parent_tid_ref) = parent_tid_ref {
+            *parent_tid_ref = tid as u32;
+        }
+        let child = new_thread.proc.clone();
         spawn(new_thread);
+
+        if vfork {
+            let eventbus = self.process().eventbus.clone();
+            while child.lock().vfork_vm.is_some() {
+                wait_for_event(eventbus.clone(), Event::VFORK_DONE).await;
+                eventbus.lock().clear(Event::VFORK_DONE);
+            }
+        }
         Ok(tid)
     }
 
-    /// Wait for the process exit.
-    /// Return the PID. Store exit code to `wstatus` if it's not null.
-    pub async fn sys_wait4(&mut self, pid: isize, wstatus: UserInOutPtr<i32>) -> SysResult {
-        info!("wait4: pid: {}, code: {:?}", pid, wstatus);
-        let wstatus = if !wstatus.is_null() {
-            Some(wstatus)
-        } else {
-            None
-        };
-        #[derive(Debug)]
+    /// Wait for a child process to exit.
+    /// `pid` selects the child: -1 for any child, 0 for any child in the same process group,
+    /// a negative value for any child in the process group `-pid`, or the child of pid `pid`.
+    /// Return the PID, or 0 if no child has exited yet with WNOHANG.
+    /// Store the status word to `wstatus` if it's not null.
+    /// Children are reported only once they exit, since processes are never stopped.
+    pub async fn sys_wait4(
+        &mut self,
+        pid: isize,
+        mut wstatus: UserInOutPtr<i32>,
+        options: usize,
+    ) -> SysResult {
+        info!(
+            "wait4: pid: {}, code: {:?}, options: {:#x}",
+            pid, wstatus, options
+        );
+        let options = WaitOptions::from_bits(options).ok_or(SysError::EINVAL)?;
+        #[derive(Debug, Clone, Copy)]
         enum WaitFor {
             AnyChild,
-            AnyChildInGroup,
+            AnyChildInGroup(Pgid),
             Pid(usize),
         }
         let target = match pid {
             -1 => WaitFor::AnyChild,
-            0 => WaitFor::AnyChildInGroup,
+            0 => WaitFor::AnyChildInGroup(self.process().pgid),
             p if p > 0 => WaitFor::Pid(p as usize),
-            _ => unimplemented!(),
+            p => WaitFor::AnyChildInGroup(-p as Pgid),
         };
         loop {
             info!("wait4 loop: pid: {}, code: {:?}", pid, wstatus);
+
+            // stops of tracees are reported, whether they are children or not.
+            // tracees are locked without the process, which they lock to notify it
+            let (tracer, tracees) = {
+                let proc = self.process();
+                (proc.pid, proc.tracees.clone())
+            };
+            let mut has_tracee = false;
+            let mut stopped = None;
+            for &tracee in tracees.iter() {
+                let tracee = match process(tracee.get()) {
+                    Some(tracee) => tracee,
+                    None => continue,
+                };
+                let p = tracee.lock();
+                if p.tracer != Some(tracer) {
+                    continue;
+                }
+                let matched = match target {
+                    WaitFor::AnyChild => true,
+                    WaitFor::AnyChildInGroup(pgid) => p.pgid == pgid,
+                    WaitFor::Pid(pid) => p.pid.get() == pid || p.threads.contains(&pid),
+                };
+                if !matched {
+                    continue;
+                }
+                has_tracee = true;
+                let thread_table = THREADS.read();
+                stopped = p
+                    .threads
+                    .iter()
+                    .filter_map(|tid| thread_table.get(tid))
+                    .find_map(|thread| match &thread.inner.lock().ptrace_stop {
+                        Some(stop) if !stop.reported && !stop.resumed => {
+                            Some((thread.clone(), stop.status()))
+                        }
+                        _ => None,
+                    });
+                if stopped.is_some() {
+                    break;
+                }
+            }
+            if let Some((thread, status)) = stopped {
+                info!("wait: found stopped tracee {}", thread.tid);
+                if !wstatus.is_null() {
+                    wstatus.write(status)?;
+                }
+                if let Some(stop) = thread.inner.lock().ptrace_stop.as_mut() {
+                    stop.reported = true;
+                }
+                return Ok(thread.tid);
+            }
+
             let mut proc = self.process();
 
             // check child state
-            let find = match target {
-                WaitFor::AnyChild | WaitFor::AnyChildInGroup => {
-                    let mut res = None;
-                    for (pid, child) in &proc.children {
-                        if let Some(c) = child.upgrade() {
-                            let p = c.lock();
-                            if p.exited() {
-                                res = Some((p.pid, p.exit_code));
-                                break;
-                            }
-                        } else {
-                            info!("wait: pid {} is missing", pid);
-                        }
+            let mut found = None;
+            let mut changed = None;
+            let mut has_child = false;
+            for (child_pid, child) in &proc.children {
+                let child = match child.upgrade() {
+                    Some(child) => child,
+                    None => {
+                        info!("wait: pid {} is missing", child_pid);
+                        continue;
                     }
-                    res
+                };
+                let p = child.lock();
+                let matched = match target {
+                    WaitFor::AnyChild => true,
+                    WaitFor::AnyChildInGroup(pgid) => p.pgid == pgid,
+                    WaitFor::Pid(pid) => p.pid.get() == pid,
+                };
+                if !matched {
+                    continue;
                 }
-                WaitFor::Pid(pid) => {
-                    let mut res = None;
-                    if let Some(c) = process(pid) {
-                        let p = c.lock();
-                        if p.exited() {
-                            res = Some((p.pid, p.exit_code));
-                        }
+                has_child = true;
+                if p.exited() {
+                    let mut usage = p.thread_usage();
+                    usage += p.children_usage;
+                    found = Some((p.pid, p.exit_code, usage));
+                    break;
+                }
+                // stopped or continued by job control
+                if let Some(status) = p.stop_report {
+                    let wanted = if status == 0xffff {
+                        WaitOptions::CONTINUED
+                    } else {
+                        WaitOptions::UNTRACED
+                    };
+                    if options.contains(wanted) {
+                        changed = Some((child.clone(), p.pid, status));
+                        break;
                     }
-                    res
                 }
-            };
+            }
+            if let Some((child, pid, status)) = changed {
+                info!("wait: pid {} changed state: {:#x}", pid, status);
+                if !wstatus.is_null() {
+                    wstatus.write(status)?;
+                }
+                child.lock().stop_report = None;
+                return Ok(pid.get());
+            }
             // if found, return
-            if let Some((pid, exit_code)) = find {
+            if let Some((pid, status, usage)) = found {
                 info!("wait: found pid {}", pid);
 
                 // write before removing to handle EFAULT
-                if let Some(mut wstatus) = wstatus {
-                    wstatus.write(exit_code as i32)?;
+                if !wstatus.is_null() {
+                    wstatus.write(status as i32)?;
                 }
 
                 // remove from process table
-                if true {
-                    let mut process_table = PROCESSES.write();
-                    process_table.remove(&pid.get());
-                }
+                PROCESSES.write().remove(&pid.get());
 
                 // remove from children
                 proc.children.retain(|(p, _)| *p != pid);
+                proc.children_usage += usage;
 
                 return Ok(pid.get());
             }
-            // if not, check pid
-            let invalid = {
-                let children = proc
-                    .children
-                    .iter()
-                    .filter_map(|(pid, weak)| {
-                        if weak.upgrade().is_none() {
-                            None
-                        } else {
-                            Some(pid)
-                        }
-                    })
-                    .collect::<Vec<_>>();
-                match target {
-                    WaitFor::AnyChild | WaitFor::AnyChildInGroup => children.len() == 0,
-                    WaitFor::Pid(pid) => children.iter().find(|p| p.get() == pid).is_none(),
-                }
-            };
-            if invalid {
+            if !has_child && !has_tracee {
                 info!("wait: no valid child proc");
                 return Err(SysError::ECHILD);
             }
+            if options.contains(WaitOptions::NOHANG) {
+                return Ok(0);
+            }
 
             info!("wait: thread {} -> {:?}, sleep", self.thread.tid, target);
 
             let eventbus = proc.eventbus.clone();
             drop(proc);
 
-            wait_for_event(eventbus.clone(), Event::CHILD_PROCESS_QUIT).await;
+            // interrupted by a signal, but restarted for SA_RESTART
+            if !wait_for_event_or_signal(self.thread, Event::CHILD_PROCESS_QUIT).await {
+                return Err(EINTR);
+            }
             eventbus.lock().clear(Event::CHILD_PROCESS_QUIT);
         }
     }
@@ -206,8 +324,8 @@ impl Syscall<'_> {
     ) -> SysResult {
         info!("exec: path: {:?}, argv: {:?}, envp: {:?}", path, argv, envp);
         let mut proc = self.process();
-        let path = check_and_clone_cstr(path)?;
-        let args = check_and_clone_cstr_array(argv)?;
+        let mut path = check_and_clone_cstr(path)?;
+        let mut args = check_and_clone_cstr_array(argv)?;
         let envs = check_and_clone_cstr_array(envp)?;
 
         if args.is_empty() {
@@ -218,32 +336,72 @@ impl Syscall<'_> {
         info!("exec: path: {:?}, args: {:?}, envs: {:?}", path, args, envs);
 
         // Read program file
-        let inode = proc.lookup_inode(&path)?;
+        let mut inode = proc.lookup_inode(&path)?;
+        let name = program_name(&path);
+        // run scripts by their interpreters, with the script path in place of argv[0]
+        let mut depth = 0;
+        while let Some((interp, arg)) = read_shebang(&inode)? {
+            depth += 1;
+            if depth > SHEBANG_MAX_DEPTH {
+                return Err(SysError::ELOOP);
+            }
+            info!("exec: interpreter: {:?}, arg: {:?}", interp, arg);
+            args[0] = path;
+            if let Some(arg) = arg {
+                args.insert(0, arg);
+            }
+            args.insert(0, interp.clone());
+            inode = proc.lookup_inode(&interp)?;
+            path = interp;
+        }
+        let metadata = inode.metadata()?;
 
         // Make new Thread
         // Re-create vm
         let mut vm = self.vm();
-        let (entry_addr, ustack_top) =
-            Thread::new_user_vm(&inode, args, envs, &mut vm).map_err(|_| SysError::EINVAL)?;
+        let (entry_addr, ustack_top, brk_start, stack, tls) = if proc.vfork_vm.is_some() {
+            // the address space is the parent's, so the program is loaded into a new one
+            let mut new_vm = MemorySet::new();
+            let res = Thread::new_user_vm(&inode, args, envs, &mut new_vm)
+                .map_err(|_| SysError::EINVAL)?;
+            let parent_vm = core::mem::replace(&mut *vm, new_vm);
+            proc.end_vfork(parent_vm);
+            res
+        } else {
+            Thread::new_user_vm(&inode, args, envs, &mut vm).map_err(|_| SysError::EINVAL)?
+        };
+        proc.stack = stack;
+        proc.brk_start = brk_start;
+        proc.brk = brk_start;
+        proc.mmap_base = aslr::mmap_base(brk_start);
+
+        // run set-user-ID and set-group-ID programs as their owners,
+        // unless they are traced, which would let the tracer take the privilege
+        let traced = proc.tracer.is_some();
+        if metadata.mode & 0o4000 != 0 && !traced {
+            proc.uid = metadata.uid;
+        }
+        if metadata.mode & 0o2010 == 0o2010 && !traced {
+            proc.gid = metadata.gid;
+        }
+        proc.suid = proc.uid;
+        proc.sgid = proc.gid;
+        // set-user-ID and set-group-ID programs must not leak their memory
+        proc.dumpable = proc.uid == proc.ruid && proc.gid == proc.rgid;
+        self.thread.inner.lock().name = name;
 
         // Kill other threads
-        // TODO: stop and wait until they are finished
-        proc.threads.retain(|&tid| tid == self.thread.tid);
+        // TODO: wait until the ones running on other CPUs trap into the kernel
+        proc.kill_threads(Some(self.thread.tid));
 
         // close file that FD_CLOEXEC is set
         let close_fds = proc
             .files
             .iter()
-            .filter_map(|(fd, file_like)| {
-                if let FileLike::File(file) = file_like {
-                    if file.fd_cloexec {
-                        Some(*fd)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
+            .filter_map(|(fd, file_like)| match file_like {
+                FileLike::File(file) if file.fd_cloexec => Some(*fd),
+                FileLike::EpollInstance(epoll) if epoll.fd_cloexec => Some(*fd),
+                _ => None,
             })
             .collect::<Vec<_>>();
         for fd in close_fds {
@@ -263,18 +421,33 @@ impl Syscall<'_> {
         for d in proc.dispositions.iter_mut() {
             *d = SignalAction::default();
         }
+        proc.posix_timers.clear();
         drop(proc);
+        self.thread.inner.lock().signal_alternate_stack = SignalStack::default();
 
         // Modify the TrapFrame
         self.context.set_ip(entry_addr);
         self.context.set_sp(ustack_top);
+        self.context.set_tls(tls);
+
+        // a traced process stops at the new program, so that the tracer may set breakpoints
+        if traced {
+            let info = Siginfo {
+                signo: Signal::SIGTRAP as i32,
+                errno: 0,
+                code: SI_USER,
+                field: Default::default(),
+            };
+            send_signal(self.thread.proc.clone(), self.thread.tid as isize, info);
+        }
 
         info!("exec:END: path: {:?}", path);
         Ok(0)
     }
 
-    pub fn sys_yield(&mut self) -> SysResult {
-        //thread::yield_now();
+    pub async fn sys_yield(&mut self) -> SysResult {
+        info!("sched_yield");
+        yield_now().await;
         Ok(0)
     }
 
@@ -284,15 +457,14 @@ impl Syscall<'_> {
         Ok(self.process().pid.get())
     }
 
+    /// Get the process group id of process `pid`, or the current process if 0
     pub fn sys_getpgid(&self, mut pid: usize) -> SysResult {
         if pid == 0 {
             pid = self.process().pid.get();
         }
         info!("getpgid: get pgid of process {}", pid);
 
-        let process_table = PROCESSES.read();
-        let proc = process_table.get(&pid);
-        if let Some(proc) = proc {
+        if let Some(proc) = process(pid) {
             let proc = proc.lock();
             Ok(proc.pgid as usize)
         } else {
@@ -300,24 +472,213 @@ impl Syscall<'_> {
         }
     }
 
-    pub fn sys_setpgid(&self, mut pid: usize, pgid: usize) -> SysResult {
+    /// Get the process group id of the current process
+    pub fn sys_getpgrp(&self) -> SysResult {
+        info!("getpgrp");
+        Ok(self.process().pgid as usize)
+    }
+
+    /// Move process `pid` into the process group `pgid` of the same session.
+    /// `pid` must be the current process or a child of it,
+    /// and 0 for either of them stands for the pid of the current process or `pid`.
+    pub fn sys_setpgid(&self, pid: usize, pgid: usize) -> SysResult {
+        let (caller, sid) = {
+            let proc = self.process();
+            (proc.pid.get(), proc.sid)
+        };
+        let pid = if pid == 0 { caller } else { pid };
+        info!("setpgid: set pgid of process {} to {}", pid, pgid);
+        if (pgid as isize) < 0 || pgid > Pgid::max_value() as usize {
+            return Err(SysError::EINVAL);
+        }
+        let pgid = if pgid == 0 { pid as Pgid } else { pgid as Pgid };
+
+        let target = process(pid).ok_or(ESRCH)?;
+        if pid != caller && !self.process().children.iter().any(|(p, _)| p.get() == pid) {
+            return Err(ESRCH);
+        }
+        // an existing group must be joined within the session
+        if pgid != pid as Pgid
+            && !process_group(pgid)
+                .iter()
+                .any(|proc| proc.lock().sid == sid)
+        {
+            return Err(SysError::EPERM);
+        }
+        let mut target = target.lock();
+        if target.sid != sid || target.sid == pid as Pgid {
+            // in another session, or a session leader
+            return Err(SysError::EPERM);
+        }
+        target.pgid = pgid;
+        Ok(0)
+    }
+
+    /// Get the session id of process `pid`, or the current process if 0
+    pub fn sys_getsid(&self, pid: usize) -> SysResult {
+        info!("getsid: pid: {}", pid);
         if pid == 0 {
-            pid = self.process().pid.get();
+            return Ok(self.process().sid as usize);
         }
-        info!("setpgid: set pgid of process {} to {}", pid, pgid);
+        let proc = process(pid).ok_or(ESRCH)?;
+        let sid = proc.lock().sid;
+        Ok(sid as usize)
+    }
 
-        let process_table = PROCESSES.read();
-        let proc = process_table.get(&pid);
-        if let Some(proc) = proc {
-            // TODO: check process pid is the child of calling process
-            let mut proc = proc.lock();
-            proc.pgid = pgid as Pgid;
-            Ok(0)
+    /// Create a new session led by the current process, in a new process group.
+    /// Fail if the process already leads a process group.
+    pub fn sys_setsid(&self) -> SysResult {
+        let pid = self.process().pid.get();
+        info!("setsid: pid: {}", pid);
+        if !process_group(pid as Pgid).is_empty() {
+            return Err(SysError::EPERM);
+        }
+        let mut proc = self.process();
+        proc.pgid = pid as Pgid;
+        proc.sid = pid as Pgid;
+        Ok(pid)
+    }
+
+    /// Get the real user id
+    pub fn sys_getuid(&self) -> SysResult {
+        info!("getuid");
+        Ok(self.process().ruid)
+    }
+
+    /// Get the effective user id
+    pub fn sys_geteuid(&self) -> SysResult {
+        info!("geteuid");
+        Ok(self.process().uid)
+    }
+
+    /// Get the real group id
+    pub fn sys_getgid(&self) -> SysResult {
+        info!("getgid");
+        Ok(self.process().rgid)
+    }
+
+    /// Get the effective group id
+    pub fn sys_getegid(&self) -> SysResult {
+        info!("getegid");
+        Ok(self.process().gid)
+    }
+
+    pub fn sys_getresuid(
+        &self,
+        mut ruid: UserOutPtr<u32>,
+        mut euid: UserOutPtr<u32>,
+        mut suid: UserOutPtr<u32>,
+    ) -> SysResult {
+        info!("getresuid");
+        let (r, e, s) = {
+            let proc = self.process();
+            (proc.ruid, proc.uid, proc.suid)
+        };
+        ruid.write(r as u32)?;
+        euid.write(e as u32)?;
+        suid.write(s as u32)?;
+        Ok(0)
+    }
+
+    pub fn sys_getresgid(
+        &self,
+        mut rgid: UserOutPtr<u32>,
+        mut egid: UserOutPtr<u32>,
+        mut sgid: UserOutPtr<u32>,
+    ) -> SysResult {
+        info!("getresgid");
+        let (r, e, s) = {
+            let proc = self.process();
+            (proc.rgid, proc.gid, proc.sgid)
+        };
+        rgid.write(r as u32)?;
+        egid.write(e as u32)?;
+        sgid.write(s as u32)?;
+        Ok(0)
+    }
+
+    /// Set all user ids if privileged, or only the effective one otherwise
+    pub fn sys_setuid(&self, uid: usize) -> SysResult {
+        info!("setuid: {}", uid);
+        self.set_id(uids, uid)
+    }
+
+    /// Set all group ids if privileged, or only the effective one otherwise
+    pub fn sys_setgid(&self, gid: usize) -> SysResult {
+        info!("setgid: {}", gid);
+        self.set_id(gids, gid)
+    }
+
+    pub fn sys_setreuid(&self, ruid: usize, euid: usize) -> SysResult {
+        info!("setreuid: ruid: {:#x}, euid: {:#x}", ruid, euid);
+        self.set_reids(uids, id_arg(ruid), id_arg(euid))
+    }
+
+    pub fn sys_setregid(&self, rgid: usize, egid: usize) -> SysResult {
+        info!("setregid: rgid: {:#x}, egid: {:#x}", rgid, egid);
+        self.set_reids(gids, id_arg(rgid), id_arg(egid))
+    }
+
+    pub fn sys_setresuid(&self, ruid: usize, euid: usize, suid: usize) -> SysResult {
+        info!(
+            "setresuid: ruid: {:#x}, euid: {:#x}, suid: {:#x}",
+            ruid, euid, suid
+        );
+        self.set_ids(uids, [id_arg(ruid), id_arg(euid), id_arg(suid)])
+    }
+
+    pub fn sys_setresgid(&self, rgid: usize, egid: usize, sgid: usize) -> SysResult {
+        info!(
+            "setresgid: rgid: {:#x}, egid: {:#x}, sgid: {:#x}",
+            rgid, egid, sgid
+        );
+        self.set_ids(gids, [id_arg(rgid), id_arg(egid), id_arg(sgid)])
+    }
+
+    fn set_id(&self, which: fn(&mut Process) -> [&mut usize; 3], id: usize) -> SysResult {
+        let privileged = self.process().uid == 0;
+        if privileged {
+            self.set_ids(which, [Some(id); 3])
         } else {
-            Err(ESRCH)
+            self.set_ids(which, [None, Some(id), None])
         }
     }
 
+    /// Set the real and effective ids like setreuid.
+    /// The saved id follows the effective one if the real one is set,
+    /// or the effective one is set to a value other than the real one.
+    fn set_reids(
+        &self,
+        which: fn(&mut Process) -> [&mut usize; 3],
+        real: Option<usize>,
+        effective: Option<usize>,
+    ) -> SysResult {
+        let mut proc = self.process();
+        let privileged = proc.uid == 0;
+        let ids = which(&mut proc);
+        let (old_real, old_effective) = (*ids[0], *ids[1]);
+        if !privileged && real.map_or(false, |id| id != old_real && id != old_effective) {
+            return Err(SysError::EPERM);
+        }
+        let saved = if real.is_some() || effective.map_or(false, |id| id != old_real) {
+            Some(effective.unwrap_or(old_effective))
+        } else {
+            None
+        };
+        change_ids(ids, [real, effective, saved], privileged)
+    }
+
+    /// Set the real, effective and saved ids, where None keeps one unchanged
+    fn set_ids(
+        &self,
+        which: fn(&mut Process) -> [&mut usize; 3],
+        new: [Option<usize>; 3],
+    ) -> SysResult {
+        let mut proc = self.process();
+        let privileged = proc.uid == 0;
+        change_ids(which(&mut proc), new, privileged)
+    }
+
     /// Get the current thread id
     pub fn sys_gettid(&mut self) -> SysResult {
         info!("gettid");
@@ -340,29 +701,31 @@ impl Syscall<'_> {
         let tid = self.thread.tid;
         info!("exit: {}, code: {}", tid, exit_code);
 
-        let mut proc = self.process();
-        proc.threads.retain(|&id| id != tid);
-
-        // for last thread, exit the process
-        if proc.threads.len() == 0 {
-            proc.exit(exit_code);
-        }
-
         // perform futex wake 1
         // ref: http://man7.org/linux/man-pages/man2/set_tid_address.2.html
         // TODO: do it in all possible ways a thread can exit
         //        it has memory access so we can't move it to Thread::drop?
+        // before the process exits, which frees the memory
         let clear_child_tid = self.thread.inner.lock().clear_child_tid as *mut u32;
         if !clear_child_tid.is_null() {
             info!("exit: futex {:#?} wake 1", clear_child_tid);
             if let Ok(clear_child_tid_ref) = unsafe { self.vm().check_write_ptr(clear_child_tid) } {
                 *clear_child_tid_ref = 0;
-                let futex = proc.get_futex(clear_child_tid as usize);
-                futex.wake(1);
+                let key = futex_key(&self.thread.vm, clear_child_tid as usize);
+                get_futex(key).wake(1);
+                put_futex(key);
             }
         }
 
+        let mut proc = self.process();
+        proc.threads.retain(|&id| id != tid);
+
+        // for last thread, exit the process
+        if proc.threads.len() == 0 {
+            proc.exit(exit_code);
+        }
         drop(proc);
+        THREADS.write().remove(&tid);
         self.exit = true;
         Ok(0)
     }
@@ -374,27 +737,323 @@ impl Syscall<'_> {
 
         proc.exit(exit_code);
         drop(proc);
-        // TODO: quit other threads
         self.exit = true;
         Ok(0)
     }
 
-    pub async fn sys_nanosleep(&mut self, req: UserInPtr<TimeSpec>) -> SysResult {
+    /// Sleep for the time in `req`. If interrupted by a signal,
+    /// return EINTR and write the time left to `rem`.
+    pub async fn sys_nanosleep(
+        &mut self,
+        req: UserInPtr<TimeSpec>,
+        rem: UserOutPtr<TimeSpec>,
+    ) -> SysResult {
         let time = req.read()?;
-        info!("nanosleep: time: {:#?},", time);
-        if !time.is_zero() {
-            self.sleep_for(time.to_duration()).await?;
-            if self.thread.has_signal_to_handle() {
-                return Err(EINTR);
+        info!("nanosleep: time: {:#?}, rem: {:?}", time, rem);
+        let duration = time.checked_duration().ok_or(SysError::EINVAL)?;
+        self.sleep_interruptible(timer_now() + duration, rem).await
+    }
+
+    /// Sleep by the clock for the time in `req`, or until the time if TIMER_ABSTIME.
+    /// If interrupted by a signal, return EINTR and write the time left to `rem`,
+    /// unless the time is absolute.
+    pub async fn sys_clock_nanosleep(
+        &mut self,
+        clock: usize,
+        flags: usize,
+        req: UserInPtr<TimeSpec>,
+        rem: UserOutPtr<TimeSpec>,
+    ) -> SysResult {
+        let time = req.read()?;
+        info!(
+            "clock_nanosleep: clock: {}, flags: {:#x}, time: {:#?}, rem: {:?}",
+            clock, flags, time, rem
+        );
+        let time = time.checked_duration().ok_or(SysError::EINVAL)?;
+        if flags & !TIMER_ABSTIME != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let now = timer_now();
+        let absolute = flags & TIMER_ABSTIME != 0;
+        let deadline = match clock {
+            CLOCK_REALTIME | CLOCK_REALTIME_COARSE if absolute => {
+                now + time.checked_sub(realtime()).unwrap_or_default()
+            }
+            CLOCK_REALTIME | CLOCK_REALTIME_COARSE => now + time,
+            CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+                if absolute {
+                    time
+                } else {
+                    now + time
+                }
+            }
+            // sleeping on CPU time clocks is not supported
+            _ => return Err(SysError::EINVAL),
+        };
+        let rem = if absolute { UserOutPtr::from(0) } else { rem };
+        self.sleep_interruptible(deadline, rem).await
+    }
+
+    /// Sleep until `deadline` of `timer_now`. If interrupted, return EINTR
+    /// and write the time left to `rem` if it is not null.
+    async fn sleep_interruptible(
+        &mut self,
+        deadline: Duration,
+        mut rem: UserOutPtr<TimeSpec>,
+    ) -> SysResult {
+        if self.sleep_until(deadline).await.is_ok() {
+            return Ok(0);
+        }
+        if !rem.is_null() {
+            let left = deadline.checked_sub(timer_now()).unwrap_or_default();
+            rem.write(left.into())?;
+        }
+        Err(EINTR)
+    }
+
+    /// Get the highest priority of the processes selected by `which` and `who`,
+    /// as `20 - nice` so that the result is always positive
+    pub fn sys_getpriority(&mut self, which: usize, who: usize) -> SysResult {
+        info!("getpriority: which: {}, who: {}", which, who);
+        let nice = self
+            .priority_targets(which, who)?
+            .iter()
+            .map(|proc| proc.lock().nice)
+            .min()
+            .unwrap();
+        Ok((20 - nice) as usize)
+    }
+
+    /// Set the nice value of the processes selected by `which` and `who`,
+    /// clamped to -20..=19. Only root can lower it or change processes of others.
+    pub fn sys_setpriority(&mut self, which: usize, who: usize, nice: usize) -> SysResult {
+        let nice = (nice as i32).max(-20).min(19);
+        info!(
+            "setpriority: which: {}, who: {}, nice: {}",
+            which, who, nice
+        );
+        let (uid, ruid) = {
+            let proc = self.process();
+            (proc.uid, proc.ruid)
+        };
+        for target in self.priority_targets(which, who)? {
+            let mut target = target.lock();
+            if uid != 0 {
+                if uid != target.uid && uid != target.ruid && ruid != target.ruid {
+                    return Err(SysError::EPERM);
+                }
+                if nice < target.nice {
+                    return Err(SysError::EACCES);
+                }
+            }
+            target.set_nice(nice);
+        }
+        Ok(0)
+    }
+
+    /// Processes selected by `which` and `who` of getpriority and setpriority,
+    /// where `who` 0 stands for the current process, process group or user
+    fn priority_targets(
+        &mut self,
+        which: usize,
+        who: usize,
+    ) -> Result<Vec<Arc<Mutex<Process>>>, SysError> {
+        const PRIO_PROCESS: usize = 0;
+        const PRIO_PGRP: usize = 1;
+        const PRIO_USER: usize = 2;
+        let targets = match which {
+            PRIO_PROCESS if who == 0 => vec![self.thread.proc.clone()],
+            PRIO_PROCESS => process(who).into_iter().collect(),
+            PRIO_PGRP => {
+                let pgid = if who == 0 {
+                    self.process().pgid
+                } else {
+                    who as Pgid
+                };
+                process_group(pgid)
+            }
+            PRIO_USER => {
+                let uid = if who == 0 { self.process().ruid } else { who };
+                PROCESSES
+                    .read()
+                    .values()
+                    .filter(|proc| proc.lock().ruid == uid)
+                    .cloned()
+                    .collect()
+            }
+            _ => return Err(SysError::EINVAL),
+        };
+        if targets.is_empty() {
+            return Err(ESRCH);
+        }
+        Ok(targets)
+    }
+
+    /// Trace processes for debuggers.
+    /// Requests other than TRACEME and ATTACH act on the tracee thread `pid`,
+    /// which must be stopped except for KILL.
+    pub fn sys_ptrace(
+        &mut self,
+        request: usize,
+        pid: usize,
+        addr: usize,
+        data: usize,
+    ) -> SysResult {
+        info!(
+            "ptrace: request: {}, pid: {}, addr: {:#x}, data: {:#x}",
+            request, pid, addr, data
+        );
+        match request {
+            PTRACE_TRACEME => return self.ptrace_traceme(),
+            PTRACE_ATTACH => return self.ptrace_attach(pid),
+            _ => {}
+        }
+        let thread = self.ptrace_tracee(pid, request != PTRACE_KILL)?;
+        // the signal to deliver when resuming
+        let signal = || match data {
+            0 => Ok(None),
+            signum => Signal::from_usize(signum).map(Some).ok_or(SysError::EIO),
+        };
+        match request {
+            PTRACE_PEEKTEXT | PTRACE_PEEKDATA => {
+                let mut word = [0u8; core::mem::size_of::<usize>()];
+                if !thread.vm.lock().read_force(addr, &mut word) {
+                    return Err(SysError::EIO);
+                }
+                // the word is stored at `data`, where the C library takes it from
+                let mut out = UserOutPtr::<usize>::from(data);
+                out.write(usize::from_ne_bytes(word))?;
+                Ok(0)
+            }
+            PTRACE_POKETEXT | PTRACE_POKEDATA => {
+                if !thread.vm.lock().write_force(addr, &data.to_ne_bytes()) {
+                    return Err(SysError::EIO);
+                }
+                Ok(0)
             }
+            #[cfg(target_arch = "x86_64")]
+            PTRACE_GETREGS => {
+                let regs = thread
+                    .with_user_context(|context| ptrace::user_regs(context))
+                    .ok_or(ESRCH)?;
+                let mut out = UserOutPtr::<[usize; ptrace::USER_REGS_LEN]>::from(data);
+                out.write(regs)?;
+                Ok(0)
+            }
+            #[cfg(target_arch = "x86_64")]
+            PTRACE_SETREGS => {
+                let regs = UserInPtr::<[usize; ptrace::USER_REGS_LEN]>::from(data).read()?;
+                match thread.with_user_context(|context| ptrace::set_user_regs(context, &regs)) {
+                    Some(true) => Ok(0),
+                    Some(false) => Err(SysError::EIO),
+                    None => Err(ESRCH),
+                }
+            }
+            PTRACE_CONT | PTRACE_SINGLESTEP => {
+                let signal = signal()?;
+                let step = request == PTRACE_SINGLESTEP;
+                match thread.with_user_context(|context| ptrace::set_single_step(context, step)) {
+                    Some(true) => {}
+                    Some(false) => return Err(SysError::EIO),
+                    None => return Err(ESRCH),
+                }
+                ptrace::resume(&thread, signal);
+                Ok(0)
+            }
+            PTRACE_KILL => {
+                let info = Siginfo {
+                    signo: Signal::SIGKILL as i32,
+                    errno: 0,
+                    code: SI_USER,
+                    field: Default::default(),
+                };
+                send_signal(thread.proc.clone(), -1, info);
+                ptrace::resume(&thread, None);
+                Ok(0)
+            }
+            PTRACE_DETACH => {
+                let signal = signal()?;
+                let tracee = {
+                    let mut proc = thread.proc.lock();
+                    proc.tracer = None;
+                    proc.pid
+                };
+                self.process().tracees.retain(|&pid| pid != tracee);
+                thread.with_user_context(|context| ptrace::set_single_step(context, false));
+                ptrace::resume(&thread, signal);
+                Ok(0)
+            }
+            _ => Err(SysError::EIO),
+        }
+    }
+
+    /// Let the parent trace the current process
+    fn ptrace_traceme(&mut self) -> SysResult {
+        let (pid, parent) = {
+            let mut proc = self.process();
+            let parent = proc.parent.clone();
+            if proc.tracer.is_some() || parent.1.upgrade().is_none() {
+                return Err(SysError::EPERM);
+            }
+            proc.tracer = Some(parent.0);
+            (proc.pid, parent.1)
+        };
+        // the parent is locked without the process, as the parent locks its tracees
+        if let Some(parent) = parent.upgrade() {
+            parent.lock().tracees.push(pid);
         }
         Ok(0)
     }
 
-    pub fn sys_set_priority(&mut self, priority: usize) -> SysResult {
+    /// Trace the process of thread `tid`, which is stopped by SIGSTOP.
+    /// Only processes of the same user which are dumpable can be traced, unless by root.
+    fn ptrace_attach(&mut self, tid: usize) -> SysResult {
+        let (tracer, uid, gid) = {
+            let proc = self.process();
+            (proc.pid, proc.ruid, proc.rgid)
+        };
+        let target = THREADS.read().get(&tid).cloned().ok_or(ESRCH)?;
+        let tracee = {
+            let mut proc = target.proc.lock();
+            if proc.pid == tracer || proc.tracer.is_some() {
+                return Err(SysError::EPERM);
+            }
+            let same_user = [proc.uid, proc.ruid, proc.suid].iter().all(|&id| id == uid)
+                && [proc.gid, proc.rgid, proc.sgid].iter().all(|&id| id == gid);
+            if uid != 0 && !(same_user && proc.dumpable) {
+                return Err(SysError::EPERM);
+            }
+            proc.tracer = Some(tracer);
+            proc.pid
+        };
+        self.process().tracees.push(tracee);
+        let info = Siginfo {
+            signo: Signal::SIGSTOP as i32,
+            errno: 0,
+            code: SI_USER,
+            field: Default::default(),
+        };
+        send_signal(target.proc.clone(), -1, info);
         Ok(0)
     }
 
+    /// The thread `tid` traced by the current process,
+    /// stopped for it if `stopped`
+    fn ptrace_tracee(&self, tid: usize, stopped: bool) -> Result<Arc<Thread>, SysError> {
+        let tracer = self.process().pid;
+        let thread = THREADS.read().get(&tid).cloned().ok_or(ESRCH)?;
+        if thread.proc.lock().tracer != Some(tracer) {
+            return Err(ESRCH);
+        }
+        if stopped {
+            match &thread.inner.lock().ptrace_stop {
+                Some(stop) if !stop.resumed => {}
+                _ => return Err(ESRCH),
+            }
+        }
+        Ok(thread)
+    }
+
     pub fn sys_set_tid_address(&mut self, tidptr: *mut u32) -> SysResult {
         info!("set_tid_address: {:?}", tidptr);
         self.thread.inner.lock().clear_child_tid = tidptr as usize;
@@ -403,19 +1062,28 @@ impl Syscall<'_> {
 
     // sleeping
     pub fn sleep_for(&mut self, duration: Duration) -> impl Future<Output = SysResult> {
+        self.sleep_until(timer_now() + duration)
+    }
+
+    /// Sleep until `deadline` of `timer_now`, or a signal to handle
+    pub fn sleep_until(&mut self, deadline: Duration) -> impl Future<Output = SysResult> {
         SleepFuture {
-            deadline: timer_now() + duration,
-            duration,
+            deadline,
+            timer_added: false,
             thread: self.thread.clone(),
             eventbus: self.thread.proc.lock().eventbus.clone(),
         }
     }
 }
 
+/// The last part of a sleep shorter than this is spun instead,
+/// as the timer only expires at timer interrupts
+const SLEEP_SPIN: Duration = Duration::from_micros(50);
+
 #[must_use = "future does nothing unless polled/`await`-ed"]
 pub struct SleepFuture {
     deadline: Duration,
-    duration: Duration,
+    timer_added: bool,
     thread: Arc<Thread>,
     eventbus: Arc<Mutex<EventBus>>,
 }
@@ -423,20 +1091,28 @@ pub struct SleepFuture {
 impl Future for SleepFuture {
     type Output = SysResult;
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         // check
-        if timer_now() >= self.deadline {
+        let now = timer_now();
+        if now >= self.deadline {
             return Poll::Ready(Ok(0));
         } else if self.thread.has_signal_to_handle() {
             return Poll::Ready(Err(EINTR));
         }
 
+        // close enough to wait here, if the timer is precise
+        if self.deadline - now <= SLEEP_SPIN && timer_resolution() < SLEEP_SPIN {
+            while timer_now() < self.deadline {
+                spin_loop_hint();
+            }
+            return Poll::Ready(Ok(0));
+        }
+
         // handle infinity
-        if self.duration.as_nanos() < i64::max_value() as u128 {
+        if !self.timer_added && self.deadline.as_nanos() < i64::max_value() as u128 {
+            self.timer_added = true;
             let waker = cx.waker().clone();
-            NAIVE_TIMER
-                .lock()
-                .add(self.deadline, Box::new(move |_| waker.wake()));
+            crate::timer::add(self.deadline, Box::new(move |_| waker.wake()));
         }
 
         let waker = cx.waker().clone();
@@ -451,6 +1127,99 @@ impl Future for SleepFuture {
     }
 }
 
+/// Real, effective and saved user ids of `proc`
+fn uids(proc: &mut Process) -> [&mut usize; 3] {
+    [&mut proc.ruid, &mut proc.uid, &mut proc.suid]
+}
+
+/// Real, effective and saved group ids of `proc`
+fn gids(proc: &mut Process) -> [&mut usize; 3] {
+    [&mut proc.rgid, &mut proc.gid, &mut proc.sgid]
+}
+
+/// An id argument, where -1 stands for unchanged
+fn id_arg(id: usize) -> Option<usize> {
+    if id as u32 == u32::max_value() {
+        None
+    } else {
+        Some(id as u32 as usize)
+    }
+}
+
+/// Change `ids` to `new`, where None keeps one unchanged.
+/// Unprivileged processes can only switch among their current ids.
+fn change_ids(mut ids: [&mut usize; 3], new: [Option<usize>; 3], privileged: bool) -> SysResult {
+    let current = [*ids[0], *ids[1], *ids[2]];
+    if !privileged && new.iter().flatten().any(|id| !current.contains(id)) {
+        return Err(SysError::EPERM);
+    }
+    for (id, new) in ids.iter_mut().zip(new.iter()) {
+        if let Some(new) = new {
+            **id = *new;
+        }
+    }
+    Ok(0)
+}
+
+bitflags! {
+    pub struct WaitOptions: usize {
+        /// Return immediately if no child has exited
+        const NOHANG = 1;
+        /// Also report stopped children
+        const UNTRACED = 2;
+        /// Also report continued children
+        const CONTINUED = 8;
+        /// Do not wait for children of other threads in the group
+        const NOTHREAD = 0x2000_0000;
+        /// Wait for all children, whatever the exit signal is
+        const ALL = 0x4000_0000;
+        /// Wait for children which deliver no or other signals on exit
+        const CLONE = 0x8000_0000;
+    }
+}
+
+/// Interpreters of scripts may be scripts themselves, up to this depth
+const SHEBANG_MAX_DEPTH: usize = 4;
+
+const PTRACE_TRACEME: usize = 0;
+const PTRACE_PEEKTEXT: usize = 1;
+const PTRACE_PEEKDATA: usize = 2;
+const PTRACE_POKETEXT: usize = 4;
+const PTRACE_POKEDATA: usize = 5;
+const PTRACE_CONT: usize = 7;
+const PTRACE_KILL: usize = 8;
+const PTRACE_SINGLESTEP: usize = 9;
+#[cfg(target_arch = "x86_64")]
+const PTRACE_GETREGS: usize = 12;
+#[cfg(target_arch = "x86_64")]
+const PTRACE_SETREGS: usize = 13;
+const PTRACE_ATTACH: usize = 16;
+const PTRACE_DETACH: usize = 17;
+
+/// Parse the `#!interpreter [arg]` line at the start of the file `inode`.
+/// Return None if it is not a script.
+fn read_shebang(inode: &Arc<dyn INode>) -> Result<Option<(String, Option<String>)>, SysError> {
+    let mut buf = [0u8; 256];
+    let len = crate::fs::page_cache::read_at(inode, 0, &mut buf)?;
+    if !buf[..len].starts_with(b"#!") {
+        return Ok(None);
+    }
+    let line = buf[2..len].split(|&c| c == b'\n').next().unwrap();
+    let line = str::from_utf8(line).map_err(|_| SysError::ENOEXEC)?.trim();
+    // like Linux, whatever follows the interpreter is a single argument
+    let mut parts = line.splitn(2, |c: char| c == ' ' || c == '\t');
+    let interp = parts.next().unwrap();
+    if interp.is_empty() {
+        return Err(SysError::ENOEXEC);
+    }
+    let arg = parts
+        .next()
+        .map(|arg| arg.trim())
+        .filter(|arg| !arg.is_empty())
+        .map(String::from);
+    Ok(Some((String::from(interp), arg)))
+}
+
 bitflags! {
     pub struct CloneFlags: usize {
         const CSIGNAL =         0x000000ff;
@@ -1,8 +1,10 @@
 use super::{UserInPtr, UserOutPtr};
 use crate::process::*;
 use crate::signal::*;
-use crate::syscall::SysError::{EINVAL, ENOMEM, EPERM, ESRCH};
-use crate::syscall::{SysResult, Syscall};
+use crate::sync::{Event, SpinNoIrqLock as Mutex};
+use crate::syscall::SysError::{EAGAIN, EINTR, EINVAL, EPERM, ESRCH};
+use crate::syscall::{SysError, SysResult, Syscall};
+use alloc::sync::Arc;
 use num::FromPrimitive;
 
 impl Syscall<'_> {
@@ -19,20 +21,32 @@ impl Syscall<'_> {
                 signal, act, oldact, sigsetsize
             );
             use Signal::*;
-            if signal == SIGKILL
-                || signal == SIGSTOP
+            // the actions of SIGKILL and SIGSTOP can only be queried
+            if (!act.is_null() && (signal == SIGKILL || signal == SIGSTOP))
                 || sigsetsize != core::mem::size_of::<Sigset>()
             {
                 Err(EINVAL)
             } else {
+                let new_act = if act.is_null() {
+                    None
+                } else {
+                    let act = act.read()?;
+                    if act.handler == SIG_ERR {
+                        return Err(EINVAL);
+                    }
+                    Some(act)
+                };
                 let mut proc = self.process();
                 if !oldact.is_null() {
                     oldact.write(proc.dispositions[signum])?;
                 }
-                if !act.is_null() {
-                    let act = act.read()?;
+                if let Some(act) = new_act {
                     info!("new action: {:?} -> {:x?}", signal, act);
                     proc.dispositions[signum] = act;
+                    // pending signals are discarded when they become ignored
+                    if is_ignored(&proc, signal) {
+                        discard_pending(&mut proc, signal);
+                    }
                 }
                 Ok(0)
             }
@@ -51,9 +65,14 @@ impl Syscall<'_> {
         let ptr: UserInPtr<SignalFrame> = UserInPtr::from(self.context.get_sp() - 8);
         let frame: SignalFrame = ptr.read()?;
 
-        // restore signal alternate stack
+        // restore signal alternate stack and signal mask,
+        // ignoring a bad stack as in Linux
         let mut inner = self.thread.inner.lock();
-        inner.signal_alternate_stack = frame.ucontext.stack;
+        if let Ok(stack) = frame.ucontext.stack.checked() {
+            inner.signal_alternate_stack = stack;
+        }
+        inner.sig_mask = frame.ucontext.sig_mask;
+        inner.sig_mask.remove_unblockable();
         drop(inner);
 
         // restore context
@@ -74,7 +93,10 @@ impl Syscall<'_> {
             "rt_sigprocmask: how: {}, set: {:?}, oldset: {:?}, sigsetsize: {}",
             how, set, oldset, sigsetsize
         );
-        if sigsetsize != 8 {
+        const BLOCK: usize = 0;
+        const UNBLOCK: usize = 1;
+        const SETMASK: usize = 2;
+        if sigsetsize != 8 || (!set.is_null() && how > SETMASK) {
             return Err(EINVAL);
         }
         if !oldset.is_null() {
@@ -82,9 +104,6 @@ impl Syscall<'_> {
         }
         if !set.is_null() {
             let set = set.read()?;
-            const BLOCK: usize = 0;
-            const UNBLOCK: usize = 1;
-            const SETMASK: usize = 2;
             let mut inner = self.thread.inner.lock();
             match how {
                 BLOCK => {
@@ -101,86 +120,264 @@ impl Syscall<'_> {
                 }
                 _ => return Err(EINVAL),
             }
+            // pending signals unblocked now are delivered on return to user
+            inner.sig_mask.remove_unblockable();
         }
         return Ok(0);
     }
 
-    /// sending signal sig to process pid
+    /// Get the signals pending for the thread while blocked
+    pub fn sys_rt_sigpending(&self, mut set: UserOutPtr<Sigset>, sigsetsize: usize) -> SysResult {
+        info!("rt_sigpending: set: {:?}, sigsetsize: {}", set, sigsetsize);
+        if sigsetsize != core::mem::size_of::<Sigset>() {
+            return Err(EINVAL);
+        }
+        let pending = {
+            let proc = self.process();
+            let inner = self.thread.inner.lock();
+            let mut pending = inner.pending_sigset;
+            pending.add_set(&proc.pending_sigset);
+            pending.intersect_set(&inner.sig_mask);
+            pending
+        };
+        set.write(pending)?;
+        Ok(0)
+    }
+
+    /// Replace the signal mask by `mask` and wait for a signal.
+    /// The old mask is restored when the signal is handled, after the handler if any.
+    pub async fn sys_rt_sigsuspend(
+        &mut self,
+        mask: UserInPtr<Sigset>,
+        sigsetsize: usize,
+    ) -> SysResult {
+        info!(
+            "rt_sigsuspend: mask: {:?}, sigsetsize: {}",
+            mask, sigsetsize
+        );
+        if sigsetsize != core::mem::size_of::<Sigset>() {
+            return Err(EINVAL);
+        }
+        let mut mask = mask.read()?;
+        mask.remove_unblockable();
+        {
+            let mut inner = self.thread.inner.lock();
+            let old_mask = core::mem::replace(&mut inner.sig_mask, mask);
+            inner.saved_sig_mask = Some(old_mask);
+        }
+        wait_for_event_or_signal(self.thread, Event::empty()).await;
+        Err(EINTR)
+    }
+
+    /// Read the signal mask given to a wait of pselect6, ppoll or epoll_pwait, if any
+    pub fn read_wait_sig_mask(
+        &self,
+        mask: UserInPtr<Sigset>,
+        sigsetsize: usize,
+    ) -> Result<Option<Sigset>, SysError> {
+        if mask.is_null() {
+            return Ok(None);
+        }
+        if sigsetsize != core::mem::size_of::<Sigset>() {
+            return Err(EINVAL);
+        }
+        let mut mask = mask.read()?;
+        mask.remove_unblockable();
+        Ok(Some(mask))
+    }
+
+    /// Replace the signal mask by `mask` if any for a wait, like sigsuspend
+    pub fn replace_sig_mask(&self, mask: Option<Sigset>) {
+        if let Some(mask) = mask {
+            let mut inner = self.thread.inner.lock();
+            let old_mask = core::mem::replace(&mut inner.sig_mask, mask);
+            inner.saved_sig_mask = Some(old_mask);
+        }
+    }
+
+    /// Put back the mask replaced for a wait which returns `result`.
+    /// If a signal interrupts it, the mask is put back when the signal is handled,
+    /// after the handler if any.
+    pub fn restore_sig_mask(&self, result: &SysResult) {
+        if let Err(EINTR) = result {
+            return;
+        }
+        let mut inner = self.thread.inner.lock();
+        if let Some(mask) = inner.saved_sig_mask.take() {
+            inner.sig_mask = mask;
+        }
+    }
+
+    /// sending signal sig to process pid, to the process group -pid if negative,
+    /// to the own process group if 0, or to every process it may signal if -1.
+    /// Signal 0 only checks that a target exists and may be signalled.
     pub fn sys_kill(&mut self, pid: isize, signum: usize) -> SysResult {
-        if let Some(signal) = <Signal as FromPrimitive>::from_usize(signum) {
-            info!("kill: pid: {}, signal: {:?}", pid, signal);
-            let info = Siginfo {
-                signo: signum as i32,
-                errno: 0,
-                code: SI_USER,
-                field: Default::default(),
-            };
-            match pid {
-                pid if pid > 0 => {
-                    if let Some(process) = process(pid as usize) {
-                        send_signal(process, -1, info);
-                        Ok(0)
-                    } else {
-                        Err(ESRCH)
-                    }
-                }
-                0 => {
-                    // to current process group
-                    let pgid = self.process().pgid;
-                    for process in process_group(pgid) {
-                        send_signal(process, -1, info);
-                    }
-                    Ok(0)
-                }
-                -1 => {
-                    // TODO: check permissions
-                    // sig is sent to every process for which the calling process
-                    // has permission to send signals, except for process 1 (init)
-                    for process in PROCESSES.read().values() {
-                        send_signal(process.clone(), -1, info);
-                    }
-                    Ok(0)
-                }
-                _ => {
-                    let process_group = process_group((-pid) as Pgid);
-                    if process_group.is_empty() {
-                        Err(ESRCH)
-                    } else {
-                        for process in process_group {
-                            send_signal(process, -1, info);
-                        }
-                        Ok(0)
-                    }
-                }
+        info!("kill: pid: {}, signum: {}", pid, signum);
+        if signum != 0 && <Signal as FromPrimitive>::from_usize(signum).is_none() {
+            return Err(EINVAL);
+        }
+        let info = Siginfo {
+            signo: signum as i32,
+            errno: 0,
+            code: SI_USER,
+            field: self.sender_fields(),
+        };
+        let (self_pid, pgid) = {
+            let proc = self.process();
+            (proc.pid.clone(), proc.pgid)
+        };
+        let targets = match pid {
+            pid if pid > 0 => vec![process(pid as usize).ok_or(ESRCH)?],
+            0 => process_group(pgid),
+            // except for init and itself
+            -1 => all_processes()
+                .into_iter()
+                .filter(|target| {
+                    let pid = target.lock().pid.clone();
+                    !pid.is_init() && pid != self_pid
+                })
+                .collect(),
+            _ => process_group((-pid) as Pgid),
+        };
+        if targets.is_empty() {
+            return Err(ESRCH);
+        }
+        // it succeeds if any target is signalled
+        let mut result = Err(EPERM);
+        for target in targets {
+            if !self.may_signal(&target, signum) {
+                continue;
             }
-        } else {
-            info!("kill: pid: {}, signal: UNKNOWN", pid);
-            Err(EINVAL)
+            if signum != 0 && !send_signal(target, -1, info) {
+                result = result.or(Err(EAGAIN));
+                continue;
+            }
+            result = Ok(0);
         }
+        result
+    }
+
+    /// Whether the current process may send signal `signum` to `target`, see kill(2).
+    /// The real or effective user id of the sender must be the real or saved one
+    /// of the target, unless privileged. SIGCONT may be sent in the same session.
+    fn may_signal(&self, target: &Arc<Mutex<Process>>, signum: usize) -> bool {
+        let (uid, ruid, sid) = {
+            let proc = self.process();
+            (proc.uid, proc.ruid, proc.sid)
+        };
+        let target = target.lock();
+        uid == 0
+            || [uid, ruid]
+                .iter()
+                .any(|&id| id == target.ruid || id == target.suid)
+            || (signum == Signal::SIGCONT as usize && sid == target.sid)
     }
 
+    /// sending signal sig to thread tid
     pub fn sys_tkill(&mut self, tid: usize, signum: usize) -> SysResult {
-        if let Some(signal) = <Signal as FromPrimitive>::from_usize(signum) {
-            info!("tkill: tid: {}, signal: {:?}", tid, signal);
-            if let Some(process) = process_of(tid) {
-                send_signal(
-                    process,
-                    tid as isize,
-                    Siginfo {
-                        signo: signum as i32,
-                        errno: 0,
-                        code: SI_TKILL,
-                        field: Default::default(),
-                    },
-                );
-                Ok(0)
-            } else {
-                Err(ESRCH)
+        info!("tkill: tid: {}, signum: {}", tid, signum);
+        let info = self.tkill_info(signum);
+        self.send_to_thread(None, tid, info)
+    }
+
+    /// sending signal sig to thread tid, which must be in thread group tgid
+    pub fn sys_tgkill(&mut self, tgid: usize, tid: usize, signum: usize) -> SysResult {
+        info!("tgkill: tgid: {}, tid: {}, signum: {}", tgid, tid, signum);
+        let info = self.tkill_info(signum);
+        self.send_to_thread(Some(tgid), tid, info)
+    }
+
+    /// Queue a signal with the siginfo given by user to process pid
+    pub fn sys_rt_sigqueueinfo(
+        &mut self,
+        pid: usize,
+        signum: usize,
+        info: UserInPtr<Siginfo>,
+    ) -> SysResult {
+        info!("rt_sigqueueinfo: pid: {}, signum: {}", pid, signum);
+        let info = self.queued_info(pid, signum, info)?;
+        let process = process(pid).ok_or(ESRCH)?;
+        if !self.may_signal(&process, signum) {
+            return Err(EPERM);
+        }
+        if signum != 0 && !send_signal(process, -1, info) {
+            return Err(EAGAIN);
+        }
+        Ok(0)
+    }
+
+    /// Queue a signal with the siginfo given by user to thread tid in thread group tgid
+    pub fn sys_rt_tgsigqueueinfo(
+        &mut self,
+        tgid: usize,
+        tid: usize,
+        signum: usize,
+        info: UserInPtr<Siginfo>,
+    ) -> SysResult {
+        info!(
+            "rt_tgsigqueueinfo: tgid: {}, tid: {}, signum: {}",
+            tgid, tid, signum
+        );
+        let info = self.queued_info(tgid, signum, info)?;
+        self.send_to_thread(Some(tgid), tid, info)
+    }
+
+    fn tkill_info(&self, signum: usize) -> Siginfo {
+        Siginfo {
+            signo: signum as i32,
+            errno: 0,
+            code: SI_TKILL,
+            field: self.sender_fields(),
+        }
+    }
+
+    /// The siginfo given by user to queue a signal to process pid.
+    /// Others may not pretend to be the kernel or kill.
+    fn queued_info(
+        &self,
+        pid: usize,
+        signum: usize,
+        info: UserInPtr<Siginfo>,
+    ) -> Result<Siginfo, SysError> {
+        if signum != 0 && <Signal as FromPrimitive>::from_usize(signum).is_none() {
+            return Err(EINVAL);
+        }
+        let mut info = info.read()?;
+        if (info.code >= 0 || info.code == SI_TKILL) && pid != self.process().pid.get() {
+            return Err(EPERM);
+        }
+        info.signo = signum as i32;
+        Ok(info)
+    }
+
+    /// Signal 0 only checks that the thread exists
+    fn send_to_thread(&mut self, tgid: Option<usize>, tid: usize, info: Siginfo) -> SysResult {
+        let signum = info.signo as usize;
+        if signum != 0 && <Signal as FromPrimitive>::from_usize(signum).is_none() {
+            return Err(EINVAL);
+        }
+        if tid as isize <= 0 || tgid.map_or(false, |tgid| tgid as isize <= 0) {
+            return Err(EINVAL);
+        }
+        let process = process_of(tid).ok_or(ESRCH)?;
+        if let Some(tgid) = tgid {
+            if process.lock().pid.get() != tgid {
+                return Err(ESRCH);
             }
-        } else {
-            info!("tkill: tid: {}, signum: {}", tid, signum);
-            Err(EINVAL)
         }
+        if !self.may_signal(&process, signum) {
+            return Err(EPERM);
+        }
+        if signum != 0 && !send_signal(process, tid as isize, info) {
+            return Err(EAGAIN);
+        }
+        Ok(0)
+    }
+
+    /// Fields of siginfo telling the receiver who sends the signal
+    fn sender_fields(&self) -> SiginfoFields {
+        let proc = self.process();
+        SiginfoFields::kill(proc.pid.get(), proc.ruid)
     }
 
     pub fn sys_sigaltstack(
@@ -189,36 +386,21 @@ impl Syscall<'_> {
         mut old_ss: UserOutPtr<SignalStack>,
     ) -> SysResult {
         info!("sigaltstack: ss: {:?}, old_ss: {:?}", ss, old_ss);
-        if !old_ss.is_null() {
-            old_ss.write(self.thread.inner.lock().signal_alternate_stack)?;
-        }
+        let sp = self.context.get_sp();
+        let old = self.thread.inner.lock().signal_alternate_stack;
         if !ss.is_null() {
             let ss = ss.read()?;
             info!("new stack: {:?}", ss);
-
-            // check stack size when not disable
-            const MINSIGSTKSZ: usize = 2048;
-            if ss.flags & SignalStackFlags::DISABLE.bits() != 0 && ss.size < MINSIGSTKSZ {
-                return Err(ENOMEM);
-            }
-
-            // only allow SS_AUTODISARM and SS_DISABLE
-            if ss.flags
-                != ss.flags
-                    & (SignalStackFlags::AUTODISARM.bits() | SignalStackFlags::DISABLE.bits())
-            {
-                return Err(EINVAL);
-            }
-
-            let mut inner = self.thread.inner.lock();
-            let old_ss = &mut inner.signal_alternate_stack;
-            let flags = SignalStackFlags::from_bits_truncate(old_ss.flags);
-            if flags.contains(SignalStackFlags::ONSTACK) {
-                // cannot change signal alternate stack when we are on it
-                // see man sigaltstack(2)
+            let new = ss.checked()?;
+            // cannot change signal alternate stack when we are on it
+            // see man sigaltstack(2)
+            if old.contains(sp) {
                 return Err(EPERM);
             }
-            *old_ss = ss;
+            self.thread.inner.lock().signal_alternate_stack = new;
+        }
+        if !old_ss.is_null() {
+            old_ss.write(old.status(sp))?;
         }
         Ok(0)
     }
@@ -1,7 +1,10 @@
 //! Syscalls for time
 
 use super::*;
+use crate::arch::timer::{timer_now, timer_resolution};
 use crate::consts::USEC_PER_TICK;
+use crate::process::itimer::{set_timer, IntervalTimer, ITIMER_REAL};
+use crate::process::posix_timer::{self, TimerNotify};
 use core::time::Duration;
 use lazy_static::lazy_static;
 use rcore_fs::vfs::Timespec;
@@ -10,30 +13,98 @@ impl Syscall<'_> {
     pub fn sys_gettimeofday(
         &mut self,
         mut tv: UserOutPtr<TimeVal>,
-        tz: UserInPtr<u8>,
+        mut tz: UserOutPtr<TimeZone>,
     ) -> SysResult {
         info!("gettimeofday: tv: {:?}, tz: {:?}", tv, tz);
-        // don't support tz
+        if !tv.is_null() {
+            tv.write(TimeVal::get_epoch())?;
+        }
+        // the time zone is obsolete and always UTC
         if !tz.is_null() {
-            return Err(SysError::EINVAL);
+            tz.write(TimeZone::default())?;
+        }
+        Ok(0)
+    }
+
+    /// Set the real time, only by root
+    pub fn sys_settimeofday(
+        &mut self,
+        tv: UserInPtr<TimeVal>,
+        tz: UserInPtr<TimeZone>,
+    ) -> SysResult {
+        info!("settimeofday: tv: {:?}, tz: {:?}", tv, tz);
+        if self.process().uid != 0 {
+            return Err(SysError::EPERM);
         }
+        // the time zone is ignored
+        if !tv.is_null() {
+            let time = tv.read()?.to_duration().ok_or(SysError::EINVAL)?;
+            set_realtime(time);
+        }
+        Ok(0)
+    }
 
-        let timeval = TimeVal::get_epoch();
-        tv.write(timeval)?;
+    /// Set the clock, only CLOCK_REALTIME by root
+    pub fn sys_clock_settime(&mut self, clock: usize, ts: UserInPtr<TimeSpec>) -> SysResult {
+        info!("clock_settime: clock: {:?}, ts: {:?}", clock, ts);
+        let time = ts.read()?.checked_duration().ok_or(SysError::EINVAL)?;
+        if clock != CLOCK_REALTIME {
+            return Err(SysError::EINVAL);
+        }
+        if self.process().uid != 0 {
+            return Err(SysError::EPERM);
+        }
+        set_realtime(time);
         Ok(0)
     }
 
     pub fn sys_clock_gettime(&mut self, clock: usize, mut ts: UserOutPtr<TimeSpec>) -> SysResult {
         info!("clock_gettime: clock: {:?}, ts: {:?}", clock, ts);
 
-        let timespec = TimeSpec::get_epoch();
-        ts.write(timespec)?;
+        let time = self.clock_time(clock)?;
+        ts.write(time.into())?;
         Ok(0)
     }
 
+    /// Get the resolution of the clock
+    pub fn sys_clock_getres(&mut self, clock: usize, mut res: UserOutPtr<TimeSpec>) -> SysResult {
+        info!("clock_getres: clock: {:?}, res: {:?}", clock, res);
+
+        self.clock_time(clock)?;
+        let resolution = match clock {
+            CLOCK_REALTIME_COARSE | CLOCK_MONOTONIC_COARSE => {
+                Duration::from_micros(USEC_PER_TICK as u64)
+            }
+            _ => timer_resolution(),
+        };
+        if !res.is_null() {
+            res.write(resolution.into())?;
+        }
+        Ok(0)
+    }
+
+    /// The time of the clock, or EINVAL if it is unknown
+    fn clock_time(&self, clock: usize) -> Result<Duration, SysError> {
+        match clock {
+            CLOCK_REALTIME | CLOCK_REALTIME_COARSE => Ok(realtime()),
+            CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+                Ok(timer_now())
+            }
+            CLOCK_PROCESS_CPUTIME_ID => {
+                let usage = self.process().thread_usage();
+                Ok(usage.utime + usage.stime)
+            }
+            CLOCK_THREAD_CPUTIME_ID => {
+                let usage = self.thread.inner.lock().usage;
+                Ok(usage.utime + usage.stime)
+            }
+            _ => Err(SysError::EINVAL),
+        }
+    }
+
     #[cfg(target_arch = "x86_64")]
     pub fn sys_time(&mut self, time: *mut u64) -> SysResult {
-        let sec = get_epoch_usec() / USEC_PER_SEC;
+        let sec = realtime().as_secs();
         if time as usize != 0 {
             let time = unsafe { self.vm().check_write_ptr(time)? };
             *time = sec as u64;
@@ -41,25 +112,175 @@ impl Syscall<'_> {
         Ok(sec as usize)
     }
 
+    /// Get the resource usage of the current process, its waited children or the current thread
     pub fn sys_getrusage(&mut self, who: usize, rusage: *mut RUsage) -> SysResult {
         info!("getrusage: who: {}, rusage: {:?}", who, rusage);
         let rusage = unsafe { self.vm().check_write_ptr(rusage)? };
 
-        let tick_base = *TICK_BASE;
-        let tick = unsafe { crate::trap::wall_tick() as u64 };
+        let usage = match who as isize {
+            RUSAGE_SELF => self.process().thread_usage(),
+            RUSAGE_CHILDREN => self.process().children_usage,
+            RUSAGE_THREAD => self.thread.inner.lock().usage,
+            _ => return Err(SysError::EINVAL),
+        };
+        *rusage = RUsage {
+            utime: usage.utime.into(),
+            stime: usage.stime.into(),
+            minflt: usage.minflt,
+            majflt: usage.majflt,
+            ..RUsage::default()
+        };
+        Ok(0)
+    }
 
-        let usec = (tick - tick_base) * USEC_PER_TICK as u64;
-        let new_rusage = RUsage {
-            utime: TimeVal {
-                sec: (usec / USEC_PER_SEC) as usize,
-                usec: (usec % USEC_PER_SEC) as usize,
-            },
-            stime: TimeVal {
-                sec: (usec / USEC_PER_SEC) as usize,
-                usec: (usec % USEC_PER_SEC) as usize,
-            },
+    /// Arm or disarm an interval timer of the current process, and get the old setting
+    pub fn sys_setitimer(
+        &mut self,
+        which: usize,
+        new_value: UserInPtr<ITimerVal>,
+        mut old_value: UserOutPtr<ITimerVal>,
+    ) -> SysResult {
+        info!(
+            "setitimer: which: {}, new_value: {:?}, old_value: {:?}",
+            which, new_value, old_value
+        );
+        // a null new value disarms the timer, as in Linux
+        let new = if new_value.is_null() {
+            ITimerVal::default()
+        } else {
+            new_value.read()?
         };
-        *rusage = new_rusage;
+        let value = new.value.to_duration().ok_or(SysError::EINVAL)?;
+        let interval = new.interval.to_duration().ok_or(SysError::EINVAL)?;
+        let old = set_timer(&self.thread.proc, which, value, interval)?;
+        if !old_value.is_null() {
+            old_value.write(old.into())?;
+        }
+        Ok(0)
+    }
+
+    pub fn sys_getitimer(&mut self, which: usize, mut value: UserOutPtr<ITimerVal>) -> SysResult {
+        info!("getitimer: which: {}, value: {:?}", which, value);
+        let timer = self.process().itimers.get(which)?;
+        value.write(timer.into())?;
+        Ok(0)
+    }
+
+    /// Send SIGALRM after `seconds`, or cancel it if 0.
+    /// Return the seconds left of the old alarm, rounded.
+    #[cfg(target_arch = "x86_64")]
+    pub fn sys_alarm(&mut self, seconds: usize) -> SysResult {
+        info!("alarm: seconds: {}", seconds);
+        let value = Duration::from_secs(seconds as u64);
+        let old = set_timer(&self.thread.proc, ITIMER_REAL, value, Duration::default())?;
+        let mut left = old.value.as_secs() as usize;
+        // an alarm about to go off is still reported
+        if old.value.subsec_micros() >= 500_000 || (left == 0 && old.value.subsec_nanos() != 0) {
+            left += 1;
+        }
+        Ok(left)
+    }
+
+    /// Create a POSIX timer of the current process, notifying as `sevp` says,
+    /// or by SIGALRM if it is null
+    pub fn sys_timer_create(
+        &mut self,
+        clock: usize,
+        sevp: UserInPtr<SigEvent>,
+        mut timerid: UserOutPtr<i32>,
+    ) -> SysResult {
+        info!(
+            "timer_create: clock: {}, sevp: {:?}, timerid: {:?}",
+            clock, sevp, timerid
+        );
+        let realtime = match clock {
+            CLOCK_REALTIME => true,
+            CLOCK_MONOTONIC | CLOCK_BOOTTIME => false,
+            _ => return Err(SysError::EINVAL),
+        };
+        let event = if sevp.is_null() {
+            None
+        } else {
+            Some(sevp.read()?)
+        };
+        let mut proc = self.process();
+        let notify = match event {
+            None => None,
+            Some(event) => {
+                let tid = match event.notify {
+                    SIGEV_NONE => None,
+                    SIGEV_SIGNAL => Some(None),
+                    SIGEV_THREAD_ID if proc.threads.contains(&(event.tid as usize)) => {
+                        Some(Some(event.tid as usize))
+                    }
+                    _ => return Err(SysError::EINVAL),
+                };
+                Some(match tid {
+                    None => TimerNotify::None,
+                    Some(_) if event.signo <= 0 || event.signo as usize > Signal::RTMAX => {
+                        return Err(SysError::EINVAL);
+                    }
+                    Some(tid) => TimerNotify::Signal {
+                        signo: event.signo,
+                        value: event.value,
+                        tid,
+                    },
+                })
+            }
+        };
+        let id = proc.posix_timers.create(realtime, notify)?;
+        drop(proc);
+        if let Err(err) = timerid.write(id as i32) {
+            self.process().posix_timers.delete(id).ok();
+            return Err(err);
+        }
+        Ok(0)
+    }
+
+    /// Arm or disarm the POSIX timer `id`, and get the old setting
+    pub fn sys_timer_settime(
+        &mut self,
+        id: usize,
+        flags: usize,
+        new_value: UserInPtr<ITimerSpec>,
+        mut old_value: UserOutPtr<ITimerSpec>,
+    ) -> SysResult {
+        info!(
+            "timer_settime: id: {}, flags: {:#x}, new_value: {:?}, old_value: {:?}",
+            id, flags, new_value, old_value
+        );
+        let new = new_value.read()?;
+        let value = new.value.checked_duration().ok_or(SysError::EINVAL)?;
+        let interval = new.interval.checked_duration().ok_or(SysError::EINVAL)?;
+        let absolute = flags & TIMER_ABSTIME != 0;
+        let old = posix_timer::set_timer(&self.thread.proc, id, value, interval, absolute)?;
+        if !old_value.is_null() {
+            old_value.write(old.into())?;
+        }
+        Ok(0)
+    }
+
+    /// Get the time left and the interval of the POSIX timer `id`
+    pub fn sys_timer_gettime(
+        &mut self,
+        id: usize,
+        mut curr_value: UserOutPtr<ITimerSpec>,
+    ) -> SysResult {
+        info!("timer_gettime: id: {}, curr_value: {:?}", id, curr_value);
+        let timer = self.process().posix_timers.get(id)?;
+        curr_value.write(timer.into())?;
+        Ok(0)
+    }
+
+    /// Get the expirations missed before the last signal of the POSIX timer `id`
+    pub fn sys_timer_getoverrun(&mut self, id: usize) -> SysResult {
+        info!("timer_getoverrun: id: {}", id);
+        self.process().posix_timers.overrun(id)
+    }
+
+    pub fn sys_timer_delete(&mut self, id: usize) -> SysResult {
+        info!("timer_delete: id: {}", id);
+        self.process().posix_timers.delete(id)?;
         Ok(0)
     }
 
@@ -67,14 +288,18 @@ impl Syscall<'_> {
         info!("times: buf: {:?}", buf);
         let buf = unsafe { self.vm().check_write_ptr(buf)? };
 
-        let _tick_base = *TICK_BASE;
         let tick = unsafe { crate::trap::wall_tick() as u64 };
+        let (usage, children) = {
+            let proc = self.process();
+            (proc.thread_usage(), proc.children_usage)
+        };
+        let ticks = |time: Duration| time.as_micros() as u64 / USEC_PER_TICK as u64;
 
         let new_buf = Tms {
-            tms_utime: 0,
-            tms_stime: 0,
-            tms_cutime: 0,
-            tms_cstime: 0,
+            tms_utime: ticks(usage.utime),
+            tms_stime: ticks(usage.stime),
+            tms_cutime: ticks(children.utime),
+            tms_cstime: ticks(children.stime),
         };
 
         *buf = new_buf;
@@ -82,10 +307,39 @@ impl Syscall<'_> {
     }
 }
 
-// should be initialized together
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+pub const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+pub const CLOCK_THREAD_CPUTIME_ID: usize = 3;
+pub const CLOCK_MONOTONIC_RAW: usize = 4;
+pub const CLOCK_REALTIME_COARSE: usize = 5;
+pub const CLOCK_MONOTONIC_COARSE: usize = 6;
+pub const CLOCK_BOOTTIME: usize = 7;
+
+/// Flags of clock_nanosleep and timer_settime
+pub const TIMER_ABSTIME: usize = 1;
+
+/// sigev_notify of struct sigevent
+const SIGEV_SIGNAL: i32 = 0;
+const SIGEV_NONE: i32 = 1;
+const SIGEV_THREAD_ID: i32 = 4;
+
 lazy_static! {
-    pub static ref EPOCH_BASE: u64 = crate::drivers::rtc::read_epoch();
-    pub static ref TICK_BASE: u64 = unsafe { crate::trap::wall_tick() as u64 };
+    /// The time since epoch when `timer_now` is zero
+    static ref REALTIME_OFFSET: Mutex<Duration> = Mutex::new(Duration::default());
+}
+
+/// Seed the real time from RTC, at boot
+pub fn init_realtime() {
+    let epoch = crate::drivers::rtc::read_epoch();
+    let mut offset = REALTIME_OFFSET.lock();
+    *offset = Duration::from_secs(epoch)
+        .checked_sub(timer_now())
+        .unwrap_or_default();
+    #[cfg(target_arch = "x86_64")]
+    crate::arch::vdso::set_realtime_offset(*offset);
+    drop(offset);
+    info!("time: {} seconds since epoch", epoch);
 }
 
 // 1ms msec
@@ -95,35 +349,109 @@ lazy_static! {
 const USEC_PER_SEC: u64 = 1_000_000;
 const MSEC_PER_SEC: u64 = 1_000;
 const USEC_PER_MSEC: u64 = 1_000;
-const NSEC_PER_USEC: u64 = 1_000;
 const NSEC_PER_MSEC: u64 = 1_000_000;
+const NSEC_PER_SEC: u64 = 1_000_000_000;
 
-/// Get time since epoch in usec
-fn get_epoch_usec() -> u64 {
-    let tick_base = *TICK_BASE;
-    let epoch_base = *EPOCH_BASE;
-    let tick = unsafe { crate::trap::wall_tick() as u64 };
+/// Get time since epoch, the time of RTC advanced by the monotonic timer
+pub fn realtime() -> Duration {
+    *REALTIME_OFFSET.lock() + timer_now()
+}
 
-    (tick - tick_base) * USEC_PER_TICK as u64 + epoch_base * USEC_PER_SEC
+/// Set the time since epoch, and write it back to RTC
+pub fn set_realtime(time: Duration) {
+    let mut offset = REALTIME_OFFSET.lock();
+    *offset = time.checked_sub(timer_now()).unwrap_or_default();
+    #[cfg(target_arch = "x86_64")]
+    crate::arch::vdso::set_realtime_offset(*offset);
+    drop(offset);
+    crate::drivers::rtc::write_epoch(time.as_secs());
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone)]
 pub struct TimeVal {
     sec: usize,
     usec: usize,
 }
 
+impl From<Duration> for TimeVal {
+    fn from(duration: Duration) -> Self {
+        TimeVal {
+            sec: duration.as_secs() as usize,
+            usec: duration.subsec_micros() as usize,
+        }
+    }
+}
+
 impl TimeVal {
     pub fn to_msec(&self) -> u64 {
         (self.sec as u64) * MSEC_PER_SEC + (self.usec as u64) / USEC_PER_MSEC
     }
 
+    /// The duration, or None if the microseconds are out of range
+    pub fn to_duration(&self) -> Option<Duration> {
+        if self.usec >= USEC_PER_SEC as usize || self.sec > isize::max_value() as usize {
+            return None;
+        }
+        Some(Duration::new(self.sec as u64, self.usec as u32 * 1000))
+    }
+
     pub fn get_epoch() -> Self {
-        let usec = get_epoch_usec();
-        TimeVal {
-            sec: (usec / USEC_PER_SEC) as usize,
-            usec: (usec % USEC_PER_SEC) as usize,
+        realtime().into()
+    }
+}
+
+/// struct timezone
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TimeZone {
+    minuteswest: i32,
+    dsttime: i32,
+}
+
+/// struct itimerval
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ITimerVal {
+    interval: TimeVal,
+    value: TimeVal,
+}
+
+impl From<IntervalTimer> for ITimerVal {
+    fn from(timer: IntervalTimer) -> Self {
+        ITimerVal {
+            interval: timer.interval.into(),
+            value: timer.value.into(),
+        }
+    }
+}
+
+/// struct sigevent, without the fields for SIGEV_THREAD used only by libc
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SigEvent {
+    value: usize,
+    signo: i32,
+    notify: i32,
+    tid: i32,
+    _pad: [u8; SIGEV_PAD_SIZE],
+}
+
+const SIGEV_PAD_SIZE: usize = 64 - core::mem::size_of::<usize>() - 3 * 4;
+
+/// struct itimerspec
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ITimerSpec {
+    pub interval: TimeSpec,
+    pub value: TimeSpec,
+}
+
+impl From<IntervalTimer> for ITimerSpec {
+    fn from(timer: IntervalTimer) -> Self {
+        ITimerSpec {
+            interval: timer.interval.into(),
+            value: timer.value.into(),
         }
     }
 }
@@ -135,6 +463,15 @@ pub struct TimeSpec {
     pub nsec: usize,
 }
 
+impl From<Duration> for TimeSpec {
+    fn from(duration: Duration) -> Self {
+        TimeSpec {
+            sec: duration.as_secs() as usize,
+            nsec: duration.subsec_nanos() as usize,
+        }
+    }
+}
+
 impl TimeSpec {
     pub fn to_msec(&self) -> u64 {
         (self.sec as u64) * MSEC_PER_SEC + (self.nsec as u64) / NSEC_PER_MSEC
@@ -144,21 +481,51 @@ impl TimeSpec {
         Duration::new(self.sec as u64, self.nsec as u32)
     }
 
-    pub fn get_epoch() -> Self {
-        let usec = get_epoch_usec();
-        TimeSpec {
-            sec: (usec / USEC_PER_SEC) as usize,
-            nsec: (usec % USEC_PER_SEC * NSEC_PER_USEC) as usize,
+    /// The duration, or None if the nanoseconds are out of range
+    pub fn checked_duration(&self) -> Option<Duration> {
+        if self.nsec >= NSEC_PER_SEC as usize || self.sec > isize::max_value() as usize {
+            return None;
         }
+        Some(self.to_duration())
+    }
+
+    pub fn get_epoch() -> Self {
+        realtime().into()
     }
 
-    // TODO: more precise; update when write
+    /// Set all timestamps of `inode` to now, e.g. after it is created
     pub fn update(inode: &Arc<dyn INode>) {
+        Self::touch(inode, true, true, true);
+    }
+
+    /// Set the access time of `inode` to now, e.g. after it is read
+    pub fn update_atime(inode: &Arc<dyn INode>) {
+        Self::touch(inode, true, false, false);
+    }
+
+    /// Set the modification and change time of `inode` to now,
+    /// e.g. after its content or a directory entry in it changes
+    pub fn update_mtime(inode: &Arc<dyn INode>) {
+        Self::touch(inode, false, true, true);
+    }
+
+    /// Set the change time of `inode` to now, e.g. after its metadata changes
+    pub fn update_ctime(inode: &Arc<dyn INode>) {
+        Self::touch(inode, false, false, true);
+    }
+
+    fn touch(inode: &Arc<dyn INode>, atime: bool, mtime: bool, ctime: bool) {
         let now = TimeSpec::get_epoch().into();
         if let Ok(mut metadata) = inode.metadata() {
-            metadata.atime = now;
-            metadata.mtime = now;
-            metadata.ctime = now;
+            if atime {
+                metadata.atime = now;
+            }
+            if mtime {
+                metadata.mtime = now;
+            }
+            if ctime {
+                metadata.ctime = now;
+            }
             // silently fail for device file
             inode.set_metadata(&metadata).ok();
         }
@@ -178,11 +545,29 @@ impl Into<Timespec> for TimeSpec {
     }
 }
 
-// ignore other fields for now
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+const RUSAGE_THREAD: isize = 1;
+
 #[repr(C)]
+#[derive(Default)]
 pub struct RUsage {
     utime: TimeVal,
     stime: TimeVal,
+    maxrss: usize,
+    ixrss: usize,
+    idrss: usize,
+    isrss: usize,
+    minflt: usize,
+    majflt: usize,
+    nswap: usize,
+    inblock: usize,
+    oublock: usize,
+    msgsnd: usize,
+    msgrcv: usize,
+    nsignals: usize,
+    nvcsw: usize,
+    nivcsw: usize,
 }
 
 #[repr(C)]
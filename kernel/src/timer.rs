@@ -0,0 +1,156 @@
+//! Timers of absolute deadlines in `timer_now`, shared by sleeps, timeouts and scheduling
+//!
+//! The deadlines are kept in order. The one-shot timer of each CPU is programmed to the
+//! earliest one, or to the end of the time slice if a thread runs on it, so that it is
+//! preempted. An idle CPU is only woken up for deadlines, instead of at every tick.
+//! On architectures without a one-shot timer, the periodic tick goes on as before.
+
+use crate::arch::cpu;
+use crate::arch::timer::{set_next_event, timer_now};
+use crate::sched::TIME_SLICE;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+type Callback = Box<dyn FnOnce(Duration) + Send + Sync>;
+
+/// A timer added, to cancel it before it expires
+#[derive(Debug, Clone, Copy)]
+pub struct TimerId(Duration, usize);
+
+#[derive(Default)]
+struct Timer {
+    /// Callbacks by deadline, first added first for the same deadline
+    events: BTreeMap<(Duration, usize), Callback>,
+    seq: usize,
+}
+
+lazy_static! {
+    static ref TIMER: Mutex<Timer> = Mutex::new(Timer::default());
+}
+
+percpu! {
+    /// The deadline the timer of each CPU is programmed to in ns, 0 if none
+    static ref PROGRAMMED: AtomicU64 = AtomicU64::new(0);
+}
+
+/// Call `callback` with the time when `deadline` is reached, in interrupt context
+pub fn add(deadline: Duration, callback: Callback) -> TimerId {
+    let seq = {
+        let mut timer = TIMER.lock();
+        let seq = timer.seq;
+        timer.seq += 1;
+        timer.events.insert((deadline, seq), callback);
+        seq
+    };
+    // the CPU adding it is woken up in time
+    let programmed = PROGRAMMED.get().load(Ordering::Relaxed);
+    if programmed == 0 || deadline.as_nanos() < programmed as u128 {
+        program(deadline);
+    }
+    TimerId(deadline, seq)
+}
+
+/// Remove the timer `id` unless it has expired
+pub fn cancel(id: TimerId) {
+    // dropped with the timers unlocked
+    let callback = TIMER.lock().events.remove(&(id.0, id.1));
+    drop(callback);
+}
+
+/// Call the callbacks of deadlines reached by `now`
+pub fn expire(now: Duration) {
+    let expired = {
+        let mut timer = TIMER.lock();
+        let later = timer.events.split_off(&(now, usize::max_value()));
+        core::mem::replace(&mut timer.events, later)
+    };
+    for (_, callback) in expired {
+        callback(now);
+    }
+}
+
+/// Program the timer of this CPU to the earliest deadline,
+/// and no later than the end of the time slice if a thread is to run
+pub fn reprogram(running: bool) {
+    let next = TIMER
+        .lock()
+        .events
+        .keys()
+        .next()
+        .map(|&(deadline, _)| deadline);
+    let slice_end = if running {
+        Some(current_slice_end())
+    } else {
+        None
+    };
+    match (next, slice_end) {
+        (Some(next), Some(slice_end)) => program(next.min(slice_end)),
+        (Some(deadline), None) | (None, Some(deadline)) => program(deadline),
+        (None, None) => {
+            PROGRAMMED.get().store(0, Ordering::Relaxed);
+            set_next_event(None);
+        }
+    }
+}
+
+/// Make sure the thread about to run on this CPU is preempted at the end of its time slice
+pub fn arm_time_slice() {
+    let programmed = PROGRAMMED.get().load(Ordering::Relaxed);
+    let slice_end = current_slice_end();
+    if programmed == 0 || slice_end.as_nanos() < programmed as u128 {
+        reprogram(true);
+    }
+}
+
+/// The end of the time slice of the task polled by this CPU.
+/// Once it is over, the task is interrupted every `TIME_SLICE` until it gives up the CPU.
+fn current_slice_end() -> Duration {
+    let now = timer_now();
+    crate::sched::slice_end()
+        .filter(|&end| end > now)
+        .unwrap_or(now + TIME_SLICE)
+}
+
+fn program(deadline: Duration) {
+    // 0 is for none
+    let nanos = deadline.as_nanos().min(u64::max_value() as u128).max(1) as u64;
+    PROGRAMMED.get().store(nanos, Ordering::Relaxed);
+    set_next_event(Some(deadline));
+}
+
+/// Wait until `deadline` of `timer_now`, woken up by the timer interrupt
+pub fn sleep_until(deadline: Duration) -> impl Future<Output = ()> {
+    #[must_use = "future does nothing unless polled/`await`-ed"]
+    struct TimerFuture {
+        deadline: Duration,
+        timer_added: bool,
+    }
+
+    impl Future for TimerFuture {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if timer_now() >= self.deadline {
+                return Poll::Ready(());
+            }
+            if !self.timer_added {
+                self.timer_added = true;
+                let waker = cx.waker().clone();
+                add(self.deadline, Box::new(move |_| waker.wake()));
+            }
+            Poll::Pending
+        }
+    }
+
+    TimerFuture {
+        deadline,
+        timer_added: false,
+    }
+}
@@ -1,11 +1,8 @@
 use crate::arch::cpu;
 use crate::consts::INFORM_PER_MSEC;
 use crate::process::*;
-use crate::sync::SpinNoIrqLock as Mutex;
 use crate::{signal::SignalUserContext, sync::Condvar};
 use core::sync::atomic::{AtomicUsize, Ordering};
-use core::time::Duration;
-use naive_timer::Timer;
 use trapframe::TrapFrame;
 use trapframe::UserContext;
 pub static TICK: AtomicUsize = AtomicUsize::new(0);
@@ -28,19 +25,30 @@ lazy_static! {
 }
 
 pub fn uptime_msec() -> usize {
-    unsafe { crate::trap::wall_tick() * crate::consts::USEC_PER_TICK / 1000 }
-}
-
-lazy_static! {
-    pub static ref NAIVE_TIMER: Mutex<Timer> = Mutex::new(Timer::default());
+    crate::arch::timer::timer_now().as_millis() as usize
 }
 
 pub fn timer() {
+    use crate::sched::stat::{account, sample_load, CpuTime};
+    // interrupted in the idle loop or in the kernel, but not in user
+    // which is charged once back in the kernel
+    account(if crate::sched::is_idle(cpu::id()) {
+        CpuTime::Idle
+    } else {
+        CpuTime::System
+    });
     do_tick();
     //let ret=unsafe{wall_tick()};
 
     let now = crate::arch::timer::timer_now();
-    NAIVE_TIMER.lock().expire(now);
+    crate::timer::expire(now);
+    crate::timer::reprogram(true);
+    // in user or in the kernel, the task yields at the next preemption point
+    if crate::sched::tick() {
+        crate::sched::set_need_resched();
+    }
+    sample_load();
+    account(CpuTime::Irq);
 }
 
 pub fn serial(c: u8) {